@@ -0,0 +1,55 @@
+//! Writes workbooks of 1,250 to 10,000 sheets of two rows each, as `--split-by` does
+//! with many groups, to check that the time per sheet stays flat as sheets are added.
+//!
+//! ```bash
+//! $ cargo run --release -p excel-rs-xlsx --example many-sheets
+//! ```
+
+use std::{io::Cursor, time::Instant};
+
+use anyhow::Result;
+use excel_rs_xlsx::{
+    typed_sheet::{TYPE_NUMBER, TYPE_STRING},
+    WorkBook,
+};
+
+const RUNS: usize = 3;
+
+fn write_workbook(sheets: usize) -> Result<usize> {
+    let types = vec![TYPE_STRING, TYPE_NUMBER];
+    let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+
+    for i in 0..sheets {
+        let mut worksheet = workbook.get_typed_worksheet(format!("Group {i}"));
+        worksheet.write_row(vec![b"Name", b"Total"], &types)?;
+        worksheet.write_row(vec![b"Ada", b"36"], &types)?;
+        worksheet.close()?;
+    }
+
+    Ok(workbook.finish()?.into_inner().len())
+}
+
+fn main() -> Result<()> {
+    println!("| Sheets | Time (best of {RUNS}) | Per sheet | xlsx size |");
+    println!("|---|---|---|---|");
+
+    for sheets in [1_250, 2_500, 5_000, 10_000] {
+        let mut best = f64::MAX;
+        let mut size = 0;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            size = write_workbook(sheets)?;
+            best = best.min(start.elapsed().as_secs_f64());
+        }
+
+        println!(
+            "| {} | {:.3}s | {:.1}µs | {:.1}MB |",
+            sheets,
+            best,
+            best / sheets as f64 * 1e6,
+            size as f64 / 1e6
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Counts the allocations made writing a sheet of known size with and without
+//! `TypedSheet::reserve_rows`, as the NumPy export does, along with the time taken.
+//!
+//! ```bash
+//! $ cargo run --release -p excel-rs-xlsx --example reserve-rows
+//! ```
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::Cursor,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use anyhow::Result;
+use excel_rs_xlsx::{typed_sheet::TYPE_NUMBER, WorkBook};
+
+const ROWS: u32 = 200_000;
+const COLS: usize = 30;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+static REALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        REALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn write_sheet(reserve: bool) -> Result<()> {
+    let header: Vec<Vec<u8>> = (0..COLS)
+        .map(|x| format!("Column {x}").into_bytes())
+        .collect();
+    let types = vec![TYPE_NUMBER; COLS];
+    let cells: Vec<Vec<u8>> = (0..COLS)
+        .map(|x| (x * 1_000_003).to_string().into_bytes())
+        .collect();
+
+    // The cells and the output are allocated up front, so only the sheet's own
+    // buffers are counted
+    let mut workbook = WorkBook::new(Cursor::new(Vec::with_capacity(256 << 20)));
+    let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+    if reserve {
+        worksheet.reserve_rows(ROWS + 1, COLS);
+    }
+
+    worksheet.write_row(header.iter().map(|x| x.as_slice()).collect(), &types)?;
+    for _ in 0..ROWS {
+        worksheet.write_row(cells.iter().map(|x| x.as_slice()).collect(), &types)?;
+    }
+
+    worksheet.close()?;
+    workbook.finish()?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    println!("{ROWS} rows by {COLS} columns of numbers");
+    println!("| | Time | Allocations | Reallocations |");
+    println!("|---|---|---|---|");
+
+    for reserve in [false, true] {
+        ALLOCS.store(0, Ordering::Relaxed);
+        REALLOCS.store(0, Ordering::Relaxed);
+        let start = Instant::now();
+        write_sheet(reserve)?;
+        let elapsed = start.elapsed().as_secs_f64();
+
+        println!(
+            "| {} | {:.2}s | {} | {} |",
+            if reserve {
+                "`reserve_rows`"
+            } else {
+                "No reserve"
+            },
+            elapsed,
+            ALLOCS.load(Ordering::Relaxed),
+            REALLOCS.load(Ordering::Relaxed)
+        );
+    }
+
+    Ok(())
+}
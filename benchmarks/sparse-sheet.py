@@ -0,0 +1,62 @@
+"""Size and speed of cli-excel-rs on a wide csv that is mostly empty fields.
+
+Writes a csv of 200,000 rows and 50 columns where 9 fields in 10 are empty, then
+converts it with and without --skip-empty-cells. The sheet XML is the size of
+xl/worksheets/sheet1.xml unzipped, where the empty cells are left out.
+
+    $ cargo build --release -p cli-excel-rs
+    $ python3 benchmarks/sparse-sheet.py
+"""
+
+import os
+import random
+import subprocess
+import tempfile
+import time
+import zipfile
+
+ROWS = 200_000
+COLS = 50
+FILLED = 0.1
+BINARY = os.path.join(os.path.dirname(__file__), "..", "target", "release", "cli-excel-rs")
+
+
+def write_csv(path):
+    # Seeded, so every run converts the same file
+    rng = random.Random(0)
+    with open(path, "w") as f:
+        f.write(",".join(f"col{j}" for j in range(COLS)) + "\n")
+        for i in range(ROWS):
+            fields = (str(i + j) if rng.random() < FILLED else "" for j in range(COLS))
+            f.write(",".join(fields) + "\n")
+
+
+def main():
+    with tempfile.TemporaryDirectory() as dir:
+        csv = os.path.join(dir, "in.csv")
+        xlsx = os.path.join(dir, "out.xlsx")
+        write_csv(csv)
+        print(f"A csv of {ROWS:,} rows and {COLS} columns ({os.path.getsize(csv) / 1024**2:.1f}MB)")
+        print()
+        print("| | Time (best of 3) | xlsx size | Sheet XML |")
+        print("|---|---|---|---|")
+
+        for flags in [[], ["--skip-empty-cells"]]:
+            args = [BINARY, "csv", "--in", csv, "--out", xlsx, "--quiet", *flags]
+            times = []
+            for _ in range(3):
+                start = time.perf_counter()
+                subprocess.run(args, check=True)
+                times.append(time.perf_counter() - start)
+
+            with zipfile.ZipFile(xlsx) as zip:
+                sheet = zip.getinfo("xl/worksheets/sheet1.xml").file_size
+            name = " ".join(["`cli-excel-rs csv", *flags]) + "`"
+            print(
+                f"| {name} | {min(times):.2f}s | {os.path.getsize(xlsx) / 1024**2:.1f}MB "
+                f"| {sheet / 1024**2:.1f}MB |"
+            )
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,81 @@
+"""Peak memory of cli-excel-rs converting csvs of growing size.
+
+Each csv has as many rows as a sheet holds, 1,000,000, and is made wider to reach its
+size. It is converted with --infer-types to an xlsx file, which is streamed, and to
+stdout, which is built in memory. Every conversion runs under a child of its own, so
+the peak RSS getrusage reports for its children is that conversion's alone.
+
+    $ cargo build --release -p cli-excel-rs
+    $ python3 benchmarks/streaming-memory.py 0.25 0.5 1 2
+"""
+
+import os
+import resource
+import subprocess
+import sys
+import tempfile
+import time
+
+ROWS = 1_000_000
+BINARY = os.path.join(os.path.dirname(__file__), "..", "target", "release", "cli-excel-rs")
+
+
+def write_csv(path, size):
+    # A number and a word per pair of columns, about 10 bytes a field
+    cols = max(2, size // ROWS // 10)
+    with open(path, "w") as f:
+        f.write(",".join(f"col{j}" for j in range(cols)) + "\n")
+        for i in range(ROWS):
+            fields = (str(i * 7 + j) if j % 2 else f"row{i}" for j in range(cols))
+            f.write(",".join(fields) + "\n")
+    return cols
+
+
+def measure(args):
+    """Runs the binary once, printing its time and peak RSS in bytes."""
+    start = time.perf_counter()
+    subprocess.run(args, stdout=subprocess.DEVNULL, check=True)
+    elapsed = time.perf_counter() - start
+    # Linux reports ru_maxrss in kilobytes
+    peak = resource.getrusage(resource.RUSAGE_CHILDREN).ru_maxrss * 1024
+    print(elapsed, peak)
+
+
+def run(args):
+    out = subprocess.run(
+        [sys.executable, __file__, "--measure", *args],
+        capture_output=True,
+        text=True,
+        check=True,
+    ).stdout
+    elapsed, peak = out.split()
+    return float(elapsed), int(peak)
+
+
+def main(sizes):
+    print("| csv | Rows x columns | To a file | To stdout |")
+    print("|---|---|---|---|")
+    with tempfile.TemporaryDirectory() as dir:
+        csv = os.path.join(dir, "in.csv")
+        xlsx = os.path.join(dir, "out.xlsx")
+        for gb in sizes:
+            cols = write_csv(csv, int(gb * 1024**3))
+            size = os.path.getsize(csv) / 1024**3
+
+            convert = [BINARY, "csv", "--in", csv, "--infer-types", "--quiet", "--out"]
+            file_time, file_peak = run(convert + [xlsx])
+            stdout_time, stdout_peak = run(convert + ["-"])
+
+            print(
+                f"| {size:.2f}GB | {ROWS:,} x {cols} "
+                f"| {file_peak / 1024**2:.1f}MB peak, {file_time:.1f}s "
+                f"| {stdout_peak / 1024**2:.1f}MB peak, {stdout_time:.1f}s |",
+                flush=True,
+            )
+
+
+if __name__ == "__main__":
+    if sys.argv[1:2] == ["--measure"]:
+        measure(sys.argv[2:])
+    else:
+        main([float(x) for x in sys.argv[1:]] or [0.25, 0.5, 1])
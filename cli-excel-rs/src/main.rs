@@ -1,64 +1,1582 @@
-use std::{fs::File, io::{Cursor, Read, Write}};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufWriter, Cursor, Read, Seek, Write},
+    path::Path,
+    process,
+    rc::Rc,
+    time::Instant,
+};
 
-use clap::{arg, Command};
-use excel_rs_csv::{bytes_to_csv, get_headers, get_next_record};
-use excel_rs_xlsx::WorkBook;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{arg, ArgAction, ArgMatches, Command};
+use excel_rs_csv::{ByteRecord, CsvReader, WriterBuilder};
+use excel_rs_xls::XlsWriter;
+use excel_rs_xlsx::{
+    reader::{read_encrypted_workbook, read_workbook, CalamineBackedReader, ParsedCell},
+    typed_sheet::{
+        from_excel_serial, infer_column_types, infer_row_types, NumberLocale, TypedSheet,
+        TYPE_BLANK, TYPE_BOOLEAN, TYPE_NUMBER, TYPE_STRING,
+    },
+    Fill, HeaderStyle, HorizontalAlign, WorkBook,
+};
+use sha2::{Digest, Sha256};
+
+const SHEET_NAME: &str = "Sheet 1";
+const MAX_SHEET_NAME_LEN: usize = 31;
+const SOURCE_HASH_PROPERTY: &str = "SourceSHA256";
 
 fn cli() -> Command {
     Command::new("excel-rs")
         .about("A collection of tools to work with XLSX files")
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(arg!(-q --quiet "print nothing but errors").global(true))
+        .arg(
+            arg!(-v --verbose "report each file's rows, the inferred types and timing")
+                .global(true)
+                .conflicts_with("quiet"),
+        )
         .subcommand(
             Command::new("csv")
                 .about("Convert a csv file to xlsx")
-                .arg(arg!(--in <FILE> "csv file to convert"))
-                .arg(arg!(--out <FILE> "xlsx output file name")),
+                .arg(
+                    arg!(--in <FILE> "csv file to convert, or - to read from stdin (default)")
+                        .action(ArgAction::Append),
+                )
+                .arg(arg!(--concat "append the rows of every --in file to one sheet"))
+                .arg(arg!(--delimiter <CHAR> "field separator of the csv, e.g. ; or tab (default ,)"))
+                .arg(
+                    arg!(--out <FILE> "xlsx output file name, or - to write to stdout")
+                        .required_unless_present("split-by"),
+                )
+                .arg(
+                    arg!(--"split-by" <COLUMN> "write one xlsx file per distinct value of this column")
+                        .requires("out-dir")
+                        .conflicts_with("out"),
+                )
+                .arg(arg!(--"out-dir" <DIR> "directory to write the --split-by files to"))
+                .arg(
+                    arg!(--"group-banner" "start each --split-by sheet with a merged title row, freezing it and the header and filtering the header")
+                        .requires("split-by"),
+                )
+                .arg(
+                    arg!(--"sheet-name-template" <TEMPLATE> "name each --split-by file's sheet, with {value} replaced by the column value")
+                        .requires("split-by"),
+                )
+                .arg(
+                    arg!(--format <FORMAT> "output format, taken from the --out extension if not given")
+                        .value_parser(["xlsx", "xls"]),
+                )
+                .arg(
+                    arg!(--"no-header" "the csv has no header row, so the first row is data")
+                        .conflicts_with_all(["split-by", "fit-headers", "dedup-headers", "trim-headers", "pad-rows"]),
+                )
+                .arg(arg!(--"fit-headers" "size each column to fit its header"))
+                .arg(
+                    arg!(--autofit "size each column to fit its longest value, holding the sheet in memory")
+                        .conflicts_with("fit-headers"),
+                )
+                .arg(arg!(--"freeze-first-col" "keep the first column in view when scrolling right"))
+                .arg(
+                    arg!(--autofilter "add filter dropdowns to the header row")
+                        .conflicts_with("no-header"),
+                )
+                .arg(
+                    arg!(--"filter-header-style" "make the filtered header bold on a light fill")
+                        .requires("autofilter"),
+                )
+                .arg(
+                    arg!(--"bold-header" "make the header bold on a light gray fill")
+                        .conflicts_with_all(["no-header", "filter-header-style"]),
+                )
+                .arg(arg!(--"add-row-numbers" "number the csv rows from 1 in a new first column"))
+                .arg(
+                    arg!(--"row-number-header" <NAME> "header of the --add-row-numbers column (default Row)")
+                        .requires("add-row-numbers")
+                        .conflicts_with("no-header"),
+                )
+                .arg(arg!(--"dedup-headers" "rename repeated headers to Name (2), Name (3), ..."))
+                .arg(arg!(--"trim-headers" "strip spaces around header names, leaving data as it is"))
+                .arg(arg!(--"keep-bom" "keep a leading UTF-8 BOM in the first header"))
+                .arg(arg!(--"skip-blank-lines" "drop blank lines instead of writing them as empty rows"))
+                .arg(arg!(--"pad-rows" "pad rows shorter than the header with empty cells"))
+                .arg(
+                    arg!(--"pad-blank" "pad with cells that have no value instead of empty text")
+                        .requires("pad-rows"),
+                )
+                .arg(arg!(--"skip-empty-cells" "leave out empty fields instead of writing cells with empty text"))
+                .arg(arg!(--"trim-trailing-empty" "drop empty fields at the end of each row, before --pad-rows pads it"))
+                .arg(
+                    arg!(--"max-columns" <N> "write only the first N columns of each row, dropping the rest")
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(arg!(--"sanitize-formulas" "prefix text starting with = + - or @ with an apostrophe"))
+                .arg(arg!(--durable "fsync each output file and its directory before exiting"))
+                .arg(arg!(--"embed-source-hash" "record the SHA-256 of the input in a custom document property"))
+                .arg(arg!(--"infer-types" "write fields that look like numbers, dates or true/false as numbers, dates and booleans"))
+                .arg(
+                    arg!(--"decimal-sep" <CHAR> "decimal separator of numbers in the csv (default .)")
+                        .requires("infer-types"),
+                )
+                .arg(
+                    arg!(--"thousands-sep" <CHAR> "thousands separator of numbers in the csv")
+                        .requires("infer-types"),
+                )
+                .arg(
+                    arg!(--"scan-all" "give each column one type, inferred from every row, holding the csv in memory")
+                        .requires("infer-types"),
+                )
+                .arg(
+                    arg!(--"day-first" "read dates like 01/02/2023 as 1 February rather than January 2")
+                        .requires("infer-types"),
+                )
+                .arg(arg!(--align <SPEC> "align columns by index, e.g. 0:left,1:right,2:center"))
+                .arg(arg!(--schema <FILE> "JSON file styling columns by name or index: width, bold, numFmt, align and fill"))
+                .arg(arg!(--"bool-columns" <COLS> "write these columns by index as booleans, e.g. 0,3"))
+                .arg(
+                    arg!(--"true-values" <LIST> "comma-separated text --bool-columns read as true (default true)")
+                        .requires("bool-columns"),
+                )
+                .arg(
+                    arg!(--"false-values" <LIST> "comma-separated text --bool-columns read as false (default false)")
+                        .requires("bool-columns"),
+                )
+                .arg(
+                    arg!(--"number-format" <CODE> "Excel number format for numbers, e.g. #,##0.00;[Red](#,##0.00)")
+                        .requires("infer-types"),
+                )
+                .arg(arg!(--"currency-columns" <COLS> "write these columns by index as numbers in a currency format, failing on any other text, e.g. 2,3"))
+                .arg(
+                    arg!(--"currency-format" <CODE> "Excel number format for --currency-columns (default $#,##0.00)")
+                        .requires("currency-columns"),
+                ),
         )
+        .subcommand(
+            Command::new("xlsx2csv")
+                .about("Convert a sheet of an xlsx file to csv")
+                .arg(arg!(--in <FILE> "xlsx file to convert").required(true))
+                .arg(arg!(--out <FILE> "csv output file name, or - to write to stdout (default)"))
+                .arg(arg!(--sheet <NAME> "sheet to convert (default the first)"))
+                .arg(
+                    arg!(--sheets <LIST> "sheets to convert by position from 1 or name, e.g. 1-3,5, each to its own csv named like out.sheet1.csv")
+                        .requires("out")
+                        .conflicts_with("sheet"),
+                )
+                .arg(arg!(--delimiter <CHAR> "field separator of the csv, e.g. ; or tab (default ,)"))
+                .arg(arg!(--password <PASSWORD> "password of an encrypted xlsx file")),
+        )
+}
+
+fn separator(sub_matches: &ArgMatches, id: &str) -> Result<Option<u8>> {
+    let Some(sep) = sub_matches.get_one::<String>(id) else {
+        return Ok(None);
+    };
+    match sep.as_bytes() {
+        [sep] if !sep.is_ascii_alphanumeric() && !matches!(sep, b'-' | b'+') => Ok(Some(*sep)),
+        _ => bail!("--{id} must be a single ASCII punctuation or space character"),
+    }
+}
+
+fn delimiter(sub_matches: &ArgMatches) -> Result<u8> {
+    let Some(delimiter) = sub_matches.get_one::<String>("delimiter") else {
+        return Ok(b',');
+    };
+    match delimiter.as_bytes() {
+        b"tab" | b"\\t" => Ok(b'\t'),
+        [b'"' | b'\r' | b'\n'] => bail!("--delimiter can't be a quote or a line break"),
+        [x] if x.is_ascii() => Ok(*x),
+        _ => bail!("--delimiter must be a single ASCII character, or tab"),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Xlsx,
+    Xls,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Xlsx => "xlsx",
+            Format::Xls => "xls",
+        }
+    }
+}
+
+// What write_sheet needs from either output format
+trait SheetWriter {
+    fn set_no_header(&mut self);
+    fn fit_headers(&mut self, headers: &[&[u8]]);
+    // Same signature as TypedSheet::write_row, so rows are passed straight through
+    #[allow(clippy::ptr_arg)]
+    fn write_row(&mut self, data: Vec<&[u8]>, types: &Vec<&str>) -> Result<()>;
+    // Writes the header below a title merged across its columns
+    fn write_banner(&mut self, title: &str, headers: Vec<&[u8]>) -> Result<()>;
+}
+
+impl<W: Write + Seek> SheetWriter for TypedSheet<'_, W> {
+    fn set_no_header(&mut self) {
+        TypedSheet::set_no_header(self)
+    }
+
+    fn fit_headers(&mut self, headers: &[&[u8]]) {
+        TypedSheet::fit_headers(self, headers)
+    }
+
+    fn write_row(&mut self, data: Vec<&[u8]>, types: &Vec<&str>) -> Result<()> {
+        TypedSheet::write_row(self, data, types)
+    }
+
+    fn write_banner(&mut self, title: &str, headers: Vec<&[u8]>) -> Result<()> {
+        let headers: Vec<Cow<str>> = headers.iter().map(|x| String::from_utf8_lossy(x)).collect();
+        let headers: Vec<&str> = headers.iter().map(|x| x.as_ref()).collect();
+        self.write_grouped_header(&[(title, headers.len())], &headers)
+    }
+}
+
+impl SheetWriter for XlsWriter {
+    fn set_no_header(&mut self) {
+        XlsWriter::set_no_header(self)
+    }
+
+    fn fit_headers(&mut self, headers: &[&[u8]]) {
+        XlsWriter::fit_headers(self, headers)
+    }
+
+    fn write_row(&mut self, data: Vec<&[u8]>, types: &Vec<&str>) -> Result<()> {
+        XlsWriter::write_row(self, data, types)
+    }
+
+    fn write_banner(&mut self, _title: &str, _headers: Vec<&[u8]>) -> Result<()> {
+        bail!("--group-banner isn't supported for xls output")
+    }
+}
+
+// What is printed to stderr, so it never mixes with an xlsx on stdout
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Verbosity {
+    Quiet,
+    Summary,
+    Verbose,
+}
+
+// Counts for the summary
+#[derive(Default)]
+struct Written {
+    rows: usize,
+    numbers: usize,
+    dates: usize,
+}
+
+struct Options {
+    format: Format,
+    delimiter: u8,
+    no_header: bool,
+    fit_headers: bool,
+    number_format: Option<String>,
+    align: Vec<(usize, HorizontalAlign)>,
+    schema: Vec<ColumnSchema>,
+    bool_columns: Vec<usize>,
+    bool_literals: BoolLiterals,
+    currency_columns: Vec<usize>,
+    currency_format: String,
+    freeze_first_col: bool,
+    autofit: bool,
+    autofilter: bool,
+    filter_header_style: bool,
+    bold_header: bool,
+    dedup_headers: bool,
+    sanitize_formulas: bool,
+    locale: Option<NumberLocale>,
+    scan_all: bool,
+    // The cell type short rows are padded with
+    pad_rows: Option<&'static str>,
+    trim_trailing_empty: bool,
+    skip_empty_cells: bool,
+    // Counts the csv's columns, not the row number column
+    max_columns: Option<usize>,
+    durable: bool,
+    group_banner: bool,
+    // Fed every input byte as it is read, when the source hash is embedded
+    source_hash: Option<Rc<RefCell<Sha256>>>,
+    // The header of the row number column, when rows are numbered
+    row_numbers: Option<String>,
+    verbosity: Verbosity,
+}
+
+// The text --bool-columns reads as true and false, compared ignoring ASCII case
+struct BoolLiterals {
+    truthy: Vec<String>,
+    falsy: Vec<String>,
+}
+
+impl BoolLiterals {
+    fn parse(&self, datum: &[u8]) -> Option<&'static [u8]> {
+        let matches = |x: &String| x.as_bytes().eq_ignore_ascii_case(datum);
+        if self.truthy.iter().any(matches) {
+            Some(b"1")
+        } else if self.falsy.iter().any(matches) {
+            Some(b"0")
+        } else {
+            None
+        }
+    }
+}
+
+// A column's entry in a --schema file
+struct ColumnSchema {
+    column: SchemaColumn,
+    width: Option<f64>,
+    // Only the fields the entry sets: bold, fill, number format and alignment
+    format: excel_rs_xlsx::Format,
+}
+
+enum SchemaColumn {
+    Name(String),
+    // Counted from 0, like --align
+    Index(usize),
+}
+
+fn write_sheet(
+    worksheet: &mut impl SheetWriter,
+    title: Option<&str>,
+    headers: Option<&ByteRecord>,
+    mut records: impl Iterator<Item = Result<ByteRecord>>,
+    options: &Options,
+) -> Result<Written> {
+    let mut written = Written::default();
+    let mut types: Vec<&str> = Vec::new();
+    // Column options count the csv's columns, which the row number column pushes right
+    let shift = options.row_numbers.is_some() as usize;
+    let max_cells = options.max_columns.map_or(usize::MAX, |x| x + shift);
+
+    for (col, option) in [
+        (&options.bool_columns, "--bool-columns"),
+        (&options.currency_columns, "--currency-columns"),
+    ]
+    .into_iter()
+    .flat_map(|(cols, option)| cols.iter().map(move |x| (x, option)))
+    {
+        let cols = headers
+            .map_or(usize::MAX, |x| x.len() - shift)
+            .min(options.max_columns.unwrap_or(usize::MAX));
+        if *col >= cols {
+            bail!("{option} column {col} is out of range, the csv has {cols} columns");
+        }
+    }
+
+    if let Some(headers) = headers {
+        let deduped = options.dedup_headers.then(|| dedup_headers(headers));
+        let mut headers_to_bytes: Vec<&[u8]> = match &deduped {
+            Some(deduped) => deduped.iter().map(|x| x.as_bytes()).collect(),
+            None => headers.iter().to_owned().collect(),
+        };
+        headers_to_bytes.truncate(max_cells);
+        types = vec![TYPE_STRING; headers_to_bytes.len()];
+        if options.row_numbers.is_some() {
+            types[0] = TYPE_NUMBER;
+        }
+
+        let sanitized: Vec<Cow<[u8]>>;
+        if options.sanitize_formulas {
+            sanitized = headers_to_bytes
+                .iter()
+                .map(|x| sanitize_formula(x))
+                .collect();
+            headers_to_bytes = sanitized.iter().map(|x| x.as_ref()).collect();
+        }
+
+        if options.fit_headers {
+            worksheet.fit_headers(&headers_to_bytes);
+        }
+
+        match title {
+            Some(title) => worksheet.write_banner(title, headers_to_bytes)?,
+            None => worksheet.write_row(headers_to_bytes, &types)?,
+        }
+    } else {
+        worksheet.set_no_header();
+        if options.row_numbers.is_some() {
+            types = vec![TYPE_NUMBER];
+        }
+    }
+
+    // Every row is read before any is written, so each column gets a single type
+    let mut held = Vec::new();
+    let mut column_types = None;
+    if let (Some(locale), true) = (&options.locale, options.scan_all) {
+        held = records.by_ref().collect::<Result<_>>()?;
+        let rows: Vec<Vec<&[u8]>> = held
+            .iter()
+            .map(|x: &ByteRecord| x.iter().take(max_cells).collect())
+            .collect();
+        column_types = Some(infer_column_types(&rows, locale));
+    }
+
+    for record in held.into_iter().map(Ok).chain(records) {
+        let record = record?;
+        written.rows += 1;
+        let mut row_data: Vec<&[u8]> = record.iter().take(max_cells).collect();
+        // Trimmed first, so --pad-rows pads back up to the header with its own cells
+        if options.trim_trailing_empty {
+            while row_data.last().is_some_and(|x| x.is_empty()) {
+                row_data.pop();
+            }
+        }
+
+        let padding = match options.pad_rows {
+            Some(_) => types.len().saturating_sub(row_data.len()),
+            None => 0,
+        };
+        row_data.resize(row_data.len() + padding, b"");
+
+        // Padding with empty text needs no types of its own
+        let blank_padding = padding > 0 && options.pad_rows == Some(TYPE_BLANK);
+
+        if options.locale.is_none()
+            && !options.sanitize_formulas
+            && !blank_padding
+            && options.bool_columns.is_empty()
+            && options.currency_columns.is_empty()
+        {
+            // A row shorter than the header only takes the types of the cells it has
+            match types.get(..row_data.len()) {
+                Some(row_types) if row_types.len() < types.len() => {
+                    worksheet.write_row(row_data, &row_types.to_vec())?
+                }
+                _ => worksheet.write_row(row_data, &types)?,
+            }
+            continue;
+        }
+
+        let mut row_types = match (&options.locale, &column_types) {
+            (_, Some(column_types)) => row_data
+                .iter()
+                .zip(column_types.iter().chain(std::iter::repeat(&TYPE_STRING)))
+                .map(|(datum, t)| if datum.is_empty() { TYPE_STRING } else { *t })
+                .collect(),
+            (Some(locale), None) => infer_row_types(&row_data, locale),
+            (None, None) => vec![TYPE_STRING; row_data.len()],
+        };
+        if options.locale.is_some() {
+            written.numbers += row_types.iter().filter(|x| **x == TYPE_NUMBER).count();
+            written.dates += row_types.iter().filter(|x| x.starts_with("date")).count();
+        }
+        if options.row_numbers.is_some() {
+            row_types[0] = TYPE_NUMBER;
+        }
+        if blank_padding {
+            let start = row_types.len() - padding;
+            row_types[start..].fill(TYPE_BLANK);
+        }
+        // Text that isn't one of the literals keeps the type it had
+        for col in options.bool_columns.iter().map(|x| x + shift) {
+            let Some(datum) = row_data.get(col) else {
+                continue;
+            };
+            if options.bool_literals.parse(datum).is_some() {
+                row_types[col] = TYPE_BOOLEAN;
+            }
+        }
+
+        let mut cells: Vec<Cow<[u8]>> = row_data
+            .iter()
+            .zip(&row_types)
+            .map(|(datum, t)| match (*t, &options.locale) {
+                (TYPE_NUMBER, Some(locale)) => locale.parse(datum).unwrap_or(Cow::Borrowed(datum)),
+                (TYPE_BOOLEAN, _) => {
+                    Cow::Borrowed(options.bool_literals.parse(datum).unwrap_or(datum))
+                }
+                _ if options.sanitize_formulas => sanitize_formula(datum),
+                _ => Cow::Borrowed(*datum),
+            })
+            .collect();
+
+        // Parsed whether or not --infer-types read them as numbers, so the format applies
+        let locale = options.locale.unwrap_or_default();
+        for col in &options.currency_columns {
+            let Some(datum) = row_data.get(col + shift).filter(|x| !x.is_empty()) else {
+                continue;
+            };
+            let Some(number) = locale.parse(datum) else {
+                bail!(
+                    "--currency-columns column {col} holds {:?} in data row {}, which isn't a number",
+                    String::from_utf8_lossy(datum),
+                    written.rows
+                );
+            };
+            cells[col + shift] = number;
+            row_types[col + shift] = TYPE_NUMBER;
+        }
+
+        worksheet.write_row(cells.iter().map(|x| x.as_ref()).collect(), &row_types)?;
+    }
+
+    Ok(written)
+}
+
+fn parse_align(spec: &str) -> Result<Vec<(usize, HorizontalAlign)>> {
+    spec.split(',')
+        .map(|x| {
+            let Some((col, align)) = x.split_once(':') else {
+                bail!("--align takes column:alignment pairs, got {x:?}");
+            };
+            let col = col
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("--align column {col:?} is not a column index"))?;
+            let align = match align.trim() {
+                "left" => HorizontalAlign::Left,
+                "center" => HorizontalAlign::Center,
+                "right" => HorizontalAlign::Right,
+                _ => bail!("--align alignment {align:?} must be left, center or right"),
+            };
+            Ok((col, align))
+        })
+        .collect()
+}
+
+fn parse_columns(spec: &str, option: &str) -> Result<Vec<usize>> {
+    spec.split(',')
+        .map(|x| {
+            x.trim()
+                .parse()
+                .map_err(|_| anyhow!("{option} column {x:?} is not a column index"))
+        })
+        .collect()
+}
+
+// A --schema file lists the columns to style, each picked by header name or by index:
+// {"columns": [{"name": "Total", "width": 14, "bold": true, "numFmt": "#,##0.00",
+// "align": "right", "fill": "FFF2CC"}]}
+fn parse_schema(path: &str) -> Result<Vec<ColumnSchema>> {
+    let text = fs::read_to_string(path).with_context(|| format!("can't read --schema {path}"))?;
+    let schema: serde_json::Value =
+        serde_json::from_str(&text).with_context(|| format!("--schema {path} isn't valid JSON"))?;
+    let Some(columns) = schema.get("columns").and_then(|x| x.as_array()) else {
+        bail!("--schema {path} has no \"columns\" list");
+    };
+    columns
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            parse_column_schema(x).with_context(|| format!("--schema {path} column {}", i + 1))
+        })
+        .collect()
+}
+
+fn parse_column_schema(entry: &serde_json::Value) -> Result<ColumnSchema> {
+    let Some(entry) = entry.as_object() else {
+        bail!("must be an object, got {entry}");
+    };
+
+    let mut column = None;
+    let mut width = None;
+    let mut format = excel_rs_xlsx::Format::default();
+    for (key, value) in entry {
+        let invalid = || anyhow!("{key:?} can't be {value}");
+        match key.as_str() {
+            "name" | "index" if column.is_some() => bail!("has both a name and an index"),
+            "name" => {
+                let name = value.as_str().ok_or_else(invalid)?;
+                column = Some(SchemaColumn::Name(String::from(name)));
+            }
+            "index" => {
+                let index = value.as_u64().ok_or_else(invalid)?;
+                column = Some(SchemaColumn::Index(index as usize));
+            }
+            "width" => {
+                let valid = value.as_f64().filter(|x| (0.0..=255.0).contains(x));
+                width = Some(valid.ok_or_else(invalid)?);
+            }
+            "bold" => format.bold = value.as_bool().ok_or_else(invalid)?,
+            "numFmt" => {
+                let code = value.as_str().filter(|x| !x.is_empty());
+                format.num_format = Some(String::from(code.ok_or_else(invalid)?));
+            }
+            "align" => {
+                format.align = Some(match value.as_str() {
+                    Some("left") => HorizontalAlign::Left,
+                    Some("center") => HorizontalAlign::Center,
+                    Some("right") => HorizontalAlign::Right,
+                    _ => bail!("\"align\" must be left, center or right, got {value}"),
+                })
+            }
+            "fill" => {
+                let color = value.as_str().and_then(parse_color).ok_or_else(|| {
+                    anyhow!("\"fill\" must be an RGB color like \"FFF2CC\", got {value}")
+                })?;
+                format.fill = Some(Fill::solid(color));
+            }
+            _ => bail!("has an unknown key {key:?}"),
+        }
+    }
+
+    let Some(column) = column else {
+        bail!("needs a \"name\" or an \"index\"");
+    };
+    Ok(ColumnSchema {
+        column,
+        width,
+        format,
+    })
+}
+
+// Six hex digits, with or without a leading #
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+// Text starting with one of these can be run as a formula once the data is opened or
+// exported again as csv, so it is prefixed with an apostrophe to keep it literal
+fn sanitize_formula(datum: &[u8]) -> Cow<'_, [u8]> {
+    match datum.first() {
+        Some(b'=' | b'+' | b'-' | b'@' | b'\t' | b'\r') => Cow::Owned([b"'", datum].concat()),
+        _ => Cow::Borrowed(datum),
+    }
+}
+
+// Renames repeats the way Excel's import does: Amount, Amount (2), Amount (3)
+fn dedup_headers(headers: &ByteRecord) -> Vec<String> {
+    let headers: Vec<String> = headers
+        .iter()
+        .map(|x| String::from_utf8_lossy(x).into_owned())
+        .collect();
+
+    // Excel compares column names case-insensitively
+    let mut used: HashSet<String> = HashSet::new();
+    let mut deduped = Vec::with_capacity(headers.len());
+
+    for (col, header) in headers.iter().enumerate() {
+        let is_repeat = headers[..col]
+            .iter()
+            .any(|x| x.to_lowercase() == header.to_lowercase());
+
+        let mut name = header.clone();
+        let mut n = 1;
+        while (is_repeat && n == 1) || used.contains(&name.to_lowercase()) {
+            n += 1;
+            name = format!("{header} ({n})");
+        }
+
+        used.insert(name.to_lowercase());
+        deduped.push(name);
+    }
+
+    deduped
+}
+
+fn to_xlsx<W: Write + Seek>(
+    writer: W,
+    sheet_name: &str,
+    title: Option<&str>,
+    headers: Option<&ByteRecord>,
+    records: impl Iterator<Item = Result<ByteRecord>>,
+    options: &Options,
+) -> Result<(W, Written)> {
+    let mut workbook = WorkBook::new(writer);
+    workbook.skip_empty_cells(options.skip_empty_cells);
+    let mut worksheet = workbook.get_typed_worksheet(String::from(sheet_name));
+
+    if let Some(code) = &options.number_format {
+        worksheet.set_default_number_format(code);
+    }
+
+    // Column options count the csv's columns, which the row number column pushes right
+    let shift = options.row_numbers.is_some() as usize;
+
+    if options.autofit {
+        worksheet.auto_fit_columns();
+    }
+
+    if options.freeze_first_col {
+        worksheet.freeze_panes(0, 1 + shift);
+    }
+
+    if options.autofilter || title.is_some() {
+        worksheet.set_autofilter();
+        if options.filter_header_style {
+            worksheet.style_header(HeaderStyle::filter());
+        }
+    }
+
+    if options.bold_header {
+        worksheet.style_header(HeaderStyle {
+            bottom_border: None,
+            freeze: false,
+            ..Default::default()
+        });
+    }
+
+    for col in &options.currency_columns {
+        let existing = worksheet.column_format(*col + shift).unwrap_or_default();
+        let format = excel_rs_xlsx::Format {
+            num_format: Some(options.currency_format.clone()),
+            ..existing
+        };
+        worksheet.set_column_format(*col + shift, &format);
+    }
+
+    // Before --align, so an alignment given on the command line wins
+    for schema in &options.schema {
+        let cols = headers
+            .map_or(usize::MAX, |x| x.len() - shift)
+            .min(options.max_columns.unwrap_or(usize::MAX));
+        let col = match &schema.column {
+            SchemaColumn::Index(col) if *col >= cols => {
+                bail!("--schema column {col} is out of range, the csv has {cols} columns")
+            }
+            SchemaColumn::Index(col) => *col,
+            SchemaColumn::Name(name) => {
+                let Some(headers) = headers else {
+                    bail!("--schema names column {name:?}, but the csv has no header");
+                };
+                headers
+                    .iter()
+                    .skip(shift)
+                    .take(cols)
+                    .position(|x| x == name.as_bytes())
+                    .ok_or_else(|| anyhow!("--schema column {name:?} isn't in the csv's header"))?
+            }
+        };
+
+        if let Some(width) = schema.width {
+            worksheet.set_column_width(col + shift, width);
+        }
+        if schema.format == excel_rs_xlsx::Format::default() {
+            continue;
+        }
+        // Whatever the entry leaves out stays as it was, like the default number format
+        let existing =
+            worksheet
+                .column_format(col + shift)
+                .unwrap_or_else(|| excel_rs_xlsx::Format {
+                    num_format: options.number_format.clone(),
+                    ..Default::default()
+                });
+        let format = excel_rs_xlsx::Format {
+            fill: schema.format.fill.clone().or(existing.fill),
+            num_format: schema.format.num_format.clone().or(existing.num_format),
+            bold: schema.format.bold || existing.bold,
+            align: schema.format.align.or(existing.align),
+            ..existing
+        };
+        worksheet.set_column_format(col + shift, &format);
+    }
+
+    for (col, align) in &options.align {
+        let cols = headers
+            .map_or(usize::MAX, |x| x.len() - shift)
+            .min(options.max_columns.unwrap_or(usize::MAX));
+        if *col >= cols {
+            bail!("--align column {col} is out of range, the csv has {cols} columns");
+        }
+        // A column's format replaces the default number format, so it has to carry it
+        let existing =
+            worksheet
+                .column_format(*col + shift)
+                .unwrap_or_else(|| excel_rs_xlsx::Format {
+                    num_format: options.number_format.clone(),
+                    ..Default::default()
+                });
+        let format = excel_rs_xlsx::Format {
+            align: Some(*align),
+            ..existing
+        };
+        worksheet.set_column_format(*col + shift, &format);
+    }
+
+    let written = write_sheet(&mut worksheet, title, headers, records, options)?;
+    worksheet.close()?;
+
+    // Every row has been read by now, so the whole input has been hashed
+    if let Some(hasher) = &options.source_hash {
+        let digest = hasher.borrow().clone().finalize();
+        let hex: String = digest.iter().map(|x| format!("{x:02x}")).collect();
+        workbook.set_custom_property(SOURCE_HASH_PROPERTY, &hex);
+    }
+
+    Ok((workbook.finish()?, written))
+}
+
+fn to_xls(
+    sheet_name: &str,
+    title: Option<&str>,
+    headers: Option<&ByteRecord>,
+    records: impl Iterator<Item = Result<ByteRecord>>,
+    options: &Options,
+) -> Result<(Vec<u8>, Written)> {
+    let mut worksheet = XlsWriter::new(String::from(sheet_name));
+
+    let written = write_sheet(&mut worksheet, title, headers, records, options)?;
+    let buf = worksheet.finish(Cursor::new(vec![]))?;
+    Ok((buf.into_inner(), written))
+}
+
+fn to_output(
+    sheet_name: &str,
+    title: Option<&str>,
+    headers: Option<&ByteRecord>,
+    records: impl Iterator<Item = Result<ByteRecord>>,
+    options: &Options,
+) -> Result<(Vec<u8>, Written)> {
+    match options.format {
+        Format::Xlsx => {
+            let (buf, written) = to_xlsx(
+                Cursor::new(vec![]),
+                sheet_name,
+                title,
+                headers,
+                records,
+                options,
+            )?;
+            Ok((buf.into_inner(), written))
+        }
+        Format::Xls => to_xls(sheet_name, title, headers, records, options),
+    }
+}
+
+fn count(n: usize, noun: &str) -> String {
+    match n {
+        1 => format!("1 {noun}"),
+        _ => format!("{n} {noun}s"),
+    }
+}
+
+fn report_file(out: &str, written: &Written, options: &Options) {
+    if options.verbosity < Verbosity::Verbose {
+        return;
+    }
+    eprintln!("{out}: {}", count(written.rows, "row"));
+    if options.locale.is_some() {
+        eprintln!(
+            "{out}: inferred {} and {}, other cells are text",
+            count(written.numbers, "number"),
+            count(written.dates, "date")
+        );
+    }
+}
+
+fn write_output(out: &str, buf: &[u8], durable: bool) -> Result<()> {
+    if out == "-" {
+        // Rust never translates line endings, so the zip bytes reach stdout as-is
+        let mut stdout = io::stdout().lock();
+        stdout
+            .write_all(buf)
+            .and_then(|_| stdout.flush())
+            .context("Failed to write to stdout")?;
+    } else {
+        let mut f = create_output(out)?;
+        f.write_all(buf)
+            .with_context(|| format!("Failed to write to file {out}"))?;
+        if durable {
+            sync_output(&f, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn create_output(out: &str) -> Result<File> {
+    File::create(out).with_context(|| format!("unable to write to {out}"))
+}
+
+fn sync_output(f: &File, out: &str) -> Result<()> {
+    f.sync_all()
+        .with_context(|| format!("Failed to sync file {out}"))?;
+    sync_parent_dir(Path::new(out))
+}
+
+// A new file's directory entry only survives a crash once the directory is synced too
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)
+        .and_then(|x| x.sync_all())
+        .with_context(|| format!("Failed to sync directory {}", dir.display()))
+}
+
+// Directories can't be opened as files on Windows, where syncing the file is enough
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+// Group values can hold anything, so keep only what is safe in a file name on every platform
+fn group_file_name(group: &[u8], used: &mut HashSet<String>) -> String {
+    let name: String = String::from_utf8_lossy(group)
+        .chars()
+        .map(|x| match x {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            x if x.is_control() => '_',
+            x => x,
+        })
+        .collect();
+
+    let name = match name.trim() {
+        "" | "." | ".." => String::from("blank"),
+        x => String::from(x),
+    };
+
+    // Distinct values can still end up with the same name, e.g. "a/b" and "a_b"
+    let mut unique = name.clone();
+    let mut n = 1;
+    while !used.insert(unique.to_lowercase()) {
+        n += 1;
+        unique = format!("{name}_{n}");
+    }
+
+    unique
+}
+
+// Sheet names are at most 31 characters, can't hold []:*?/\ and can't start or end with
+// an apostrophe. Excel compares them ignoring case
+fn group_sheet_name(template: &str, group: &[u8], used: &mut HashSet<String>) -> String {
+    let name: String = template
+        .replace("{value}", &String::from_utf8_lossy(group))
+        .chars()
+        .map(|x| match x {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            x if x.is_control() => '_',
+            x => x,
+        })
+        .collect();
+
+    let truncate = |len: usize| {
+        let name: String = name.chars().take(len).collect();
+        match name.trim().trim_matches('\'').trim() {
+            "" => String::from("blank"),
+            x => String::from(x),
+        }
+    };
+
+    // Values that only differ past the 31st character end up with the same name
+    let mut unique = truncate(MAX_SHEET_NAME_LEN);
+    let mut n = 1;
+    while !used.insert(unique.to_lowercase()) {
+        n += 1;
+        let suffix = format!(" ({n})");
+        unique = truncate(MAX_SHEET_NAME_LEN - suffix.len()) + &suffix;
+    }
+
+    unique
+}
+
+type Records = Box<dyn Iterator<Item = Result<ByteRecord>>>;
+
+// Hashes the input exactly as it is read, BOM and all, so it is only read once
+struct HashingReader<R> {
+    inner: R,
+    hasher: Rc<RefCell<Sha256>>,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.borrow_mut().update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// Records are streamed, so only the xlsx being built is held in memory
+fn open_csv(
+    input: &str,
+    keep_bom: bool,
+    skip_blank_lines: bool,
+    hasher: Option<&Rc<RefCell<Sha256>>>,
+    delimiter: u8,
+) -> Result<(Option<ByteRecord>, Records)> {
+    let mut data: Box<dyn Read> = if input == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(input).with_context(|| format!("can't read input csv file {input}"))?)
+    };
+    if let Some(hasher) = hasher {
+        data = Box::new(HashingReader {
+            inner: data,
+            hasher: Rc::clone(hasher),
+        });
+    }
+
+    // Read errors end the records, and the conversion with them
+    let input = String::from(input);
+    let context = move || format!("can't read input csv file {input}");
+    if keep_bom {
+        let mut reader = CsvReader::from_reader_keep_bom(data).with_delimiter(delimiter);
+        if !skip_blank_lines {
+            reader.set_keep_blank_lines();
+        }
+        let headers = reader.headers().with_context(&context)?.clone();
+        Ok((
+            Some(headers),
+            Box::new(reader.map(move |x| x.with_context(&context))),
+        ))
+    } else {
+        let mut reader = CsvReader::from_reader(data).with_delimiter(delimiter);
+        if !skip_blank_lines {
+            reader.set_keep_blank_lines();
+        }
+        let headers = reader.headers().with_context(&context)?.clone();
+        Ok((
+            Some(headers),
+            Box::new(reader.map(move |x| x.with_context(&context))),
+        ))
+    }
+}
+
+// The header comes from the first file, the others' headers are checked against it and
+// dropped. A source hash covers the files one after another, in order
+fn open_concat(
+    inputs: &[&str],
+    keep_bom: bool,
+    skip_blank_lines: bool,
+    options: &Options,
+) -> Result<(Option<ByteRecord>, Records)> {
+    let hasher = options.source_hash.as_ref();
+    let delimiter = options.delimiter;
+    let (headers, mut records) =
+        open_csv(inputs[0], keep_bom, skip_blank_lines, hasher, delimiter)?;
+    let cols = headers.as_ref().map_or(0, |x| x.len());
+
+    for input in &inputs[1..] {
+        let (next_headers, next_records) =
+            open_csv(input, keep_bom, skip_blank_lines, hasher, delimiter)?;
+        let next_cols = next_headers.as_ref().map_or(0, |x| x.len());
+        if next_cols != cols {
+            bail!(
+                "{input} has {next_cols} columns, but {} has {cols}",
+                inputs[0]
+            );
+        }
+
+        records = if options.no_header {
+            Box::new(records.chain(next_headers.map(Ok)).chain(next_records))
+        } else {
+            Box::new(records.chain(next_records))
+        };
+    }
+
+    Ok((headers, records))
+}
+
+// Rows are numbered in the order they're read, so the numbers still lead back to the csv
+// once rows are split into groups
+fn number_rows(
+    headers: Option<ByteRecord>,
+    records: impl Iterator<Item = Result<ByteRecord>>,
+    header: Option<&str>,
+) -> (Option<ByteRecord>, impl Iterator<Item = Result<ByteRecord>>) {
+    let prepend = |first: &[u8], record: &ByteRecord| {
+        let mut numbered =
+            ByteRecord::with_capacity(record.as_slice().len() + first.len(), record.len() + 1);
+        numbered.push_field(first);
+        numbered.extend(record);
+        numbered
+    };
+
+    let headers = match header {
+        Some(header) => headers.map(|x| prepend(header.as_bytes(), &x)),
+        None => headers,
+    };
+    let numbered = header.is_some();
+    let records = records.enumerate().map(move |(i, record)| match numbered {
+        true => record.map(|x| prepend((i + 1).to_string().as_bytes(), &x)),
+        false => record,
+    });
+
+    (headers, records)
+}
+
+fn convert(
+    headers: Option<ByteRecord>,
+    reader: impl Iterator<Item = Result<ByteRecord>>,
+    sub_matches: &ArgMatches,
+    options: &Options,
+) -> Result<()> {
+    let started = Instant::now();
+    let summary = |rows: usize, to: &str| match options.verbosity {
+        Verbosity::Quiet => (),
+        Verbosity::Summary => eprintln!("Wrote {} to {to}", count(rows, "row")),
+        Verbosity::Verbose => eprintln!(
+            "Wrote {} to {to} in {:.2?}",
+            count(rows, "row"),
+            started.elapsed()
+        ),
+    };
+
+    let Some(split_by) = sub_matches.get_one::<String>("split-by") else {
+        let out = sub_matches.get_one::<String>("out").expect("required");
+        // What was read as the header is the first data row
+        let (headers, first_row) = match options.no_header {
+            true => (None, headers),
+            false => (headers, None),
+        };
+        let records = first_row.into_iter().map(Ok).chain(reader);
+        let (headers, records) = number_rows(headers, records, options.row_numbers.as_deref());
+
+        // An xlsx file is written as rows are read, so memory use doesn't grow with the
+        // input. Other outputs are built in memory first
+        let written = if options.format == Format::Xlsx && out != "-" {
+            let writer = BufWriter::new(create_output(out)?);
+            let (writer, written) =
+                to_xlsx(writer, SHEET_NAME, None, headers.as_ref(), records, options)?;
+            let f = writer
+                .into_inner()
+                .map_err(|e| e.into_error())
+                .with_context(|| format!("Failed to write to file {out}"))?;
+            if options.durable {
+                sync_output(&f, out)?;
+            }
+            written
+        } else {
+            let (buf, written) = to_output(SHEET_NAME, None, headers.as_ref(), records, options)?;
+            write_output(out, &buf, options.durable)?;
+            written
+        };
+
+        let to = if out == "-" { "stdout" } else { out };
+        report_file(to, &written, options);
+        summary(written.rows, to);
+        return Ok(());
+    };
+
+    let out_dir = sub_matches.get_one::<String>("out-dir").expect("required");
+
+    let split_col = headers
+        .as_ref()
+        .and_then(|x| x.iter().position(|x| x == split_by.as_bytes()))
+        .ok_or_else(|| anyhow!("column {split_by} not found in the csv header"))?
+        + options.row_numbers.is_some() as usize;
+    let (headers, reader) = number_rows(headers, reader, options.row_numbers.as_deref());
+
+    // Groups keep the order their first row appears in
+    let mut groups: Vec<(Vec<u8>, Vec<ByteRecord>)> = Vec::new();
+    let mut group_ids: HashMap<Vec<u8>, usize> = HashMap::new();
+
+    for record in reader {
+        let record = record?;
+        let group = record.get(split_col).unwrap_or(&[]);
+        let id = match group_ids.get(group) {
+            Some(id) => *id,
+            None => {
+                groups.push((group.to_vec(), Vec::new()));
+                group_ids.insert(group.to_vec(), groups.len() - 1);
+                groups.len() - 1
+            }
+        };
+        groups[id].1.push(record);
+    }
+
+    fs::create_dir_all(out_dir).with_context(|| format!("unable to create {out_dir}"))?;
+
+    let template = sub_matches.get_one::<String>("sheet-name-template");
+    let mut used = HashSet::new();
+    let mut used_sheet_names = HashSet::new();
+    let files = groups.len();
+    let mut rows = 0;
+    for (group, records) in groups {
+        let name = group_file_name(&group, &mut used);
+        let path = Path::new(out_dir).join(format!("{name}.{}", options.format.extension()));
+        let sheet_name = match template {
+            Some(template) => group_sheet_name(template, &group, &mut used_sheet_names),
+            None => String::from(SHEET_NAME),
+        };
+        // The banner takes the template unchanged, as titles can hold any character
+        let title = options.group_banner.then(|| {
+            let value = String::from_utf8_lossy(&group);
+            template.map_or(value.to_string(), |x| x.replace("{value}", &value))
+        });
+        let (buf, written) = to_output(
+            &sheet_name,
+            title.as_deref(),
+            headers.as_ref(),
+            records.into_iter().map(Ok),
+            options,
+        )?;
+        let path = path.to_string_lossy();
+        write_output(&path, &buf, options.durable)?;
+        report_file(&path, &written, options);
+        rows += written.rows;
+    }
+
+    summary(rows, &format!("{} in {out_dir}", count(files, "file")));
+    Ok(())
 }
 
+// Cells are written as they'd be read back by the csv subcommand with --infer-types
+fn cell_to_csv(cell: &ParsedCell) -> Cow<'_, str> {
+    match cell {
+        ParsedCell::Empty => Cow::Borrowed(""),
+        ParsedCell::String(x) | ParsedCell::IsoDate(x) | ParsedCell::Error(x) => Cow::Borrowed(x),
+        ParsedCell::Number(x) => Cow::Owned(x.to_string()),
+        ParsedCell::Bool(x) => Cow::Borrowed(if *x { "true" } else { "false" }),
+        ParsedCell::Date(x) => match from_excel_serial(*x) {
+            Some((year, month, day, 0)) => Cow::Owned(format!("{year:04}-{month:02}-{day:02}")),
+            Some((year, month, day, seconds)) => Cow::Owned(format!(
+                "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            )),
+            None => Cow::Owned(x.to_string()),
+        },
+    }
+}
+
+fn xlsx_to_csv(sub_matches: &ArgMatches, verbosity: Verbosity) -> Result<()> {
+    let input = sub_matches.get_one::<String>("in").expect("required");
+    let out = sub_matches
+        .get_one::<String>("out")
+        .map_or("-", |x| x.as_str());
+    let delimiter = delimiter(sub_matches)?;
+
+    // Encrypted workbooks are decrypted into memory, others are read from the file
+    match sub_matches.get_one::<String>("password") {
+        Some(password) => {
+            let reader = read_encrypted_workbook(input, password)?;
+            sheets_to_csv(sub_matches, reader, input, out, delimiter, verbosity)
+        }
+        None => {
+            let reader = read_workbook(input)?;
+            sheets_to_csv(sub_matches, reader, input, out, delimiter, verbosity)
+        }
+    }
+}
+
+fn sheets_to_csv<R: Read + Seek>(
+    sub_matches: &ArgMatches,
+    mut reader: CalamineBackedReader<R>,
+    input: &str,
+    out: &str,
+    delimiter: u8,
+    verbosity: Verbosity,
+) -> Result<()> {
+    let names = reader.sheet_names();
+
+    if let Some(list) = sub_matches.get_one::<String>("sheets") {
+        if out == "-" {
+            bail!("--sheets writes a file per sheet, so --out can't be stdout");
+        }
+        for pos in select_sheets(list, &names, input)? {
+            let rows = reader.read_sheet(&names[pos])?;
+            let sheet_out = sheet_file_name(out, pos + 1);
+            write_csv(&rows, &sheet_out, delimiter)?;
+            if verbosity > Verbosity::Quiet {
+                eprintln!("Wrote {} to {sheet_out}", count(rows.len(), "row"));
+            }
+        }
+        return Ok(());
+    }
+
+    let sheet = match sub_matches.get_one::<String>("sheet") {
+        Some(sheet) => sheet.clone(),
+        None => names
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("{input} has no sheets"))?,
+    };
+    let rows = reader.read_sheet(&sheet)?;
+    write_csv(&rows, out, delimiter)?;
+
+    if verbosity > Verbosity::Quiet && out != "-" {
+        eprintln!("Wrote {} to {out}", count(rows.len(), "row"));
+    }
+    Ok(())
+}
+
+fn write_csv(rows: &[Vec<ParsedCell>], out: &str, delimiter: u8) -> Result<()> {
+    let output: Box<dyn Write> = match out {
+        "-" => Box::new(io::stdout().lock()),
+        out => Box::new(BufWriter::new(create_output(out)?)),
+    };
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_writer(output);
+    for row in rows {
+        writer
+            .write_record(row.iter().map(cell_to_csv).map(|x| x.into_owned()))
+            .with_context(|| format!("unable to write to {out}"))?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("unable to write to {out}"))
+}
+
+// The positions, from 0, of the sheets a --sheets list picks, in workbook order. Items
+// are positions from 1, ranges of them like 1-3, or sheet names. A number is always a
+// position, so a sheet named "2024-25" is picked by name but one named "3" isn't
+fn select_sheets(list: &str, names: &[String], input: &str) -> Result<Vec<usize>> {
+    let position = |x: &str| -> Result<Option<usize>> {
+        let Ok(pos) = x.trim().parse::<usize>() else {
+            return Ok(None);
+        };
+        if pos == 0 || pos > names.len() {
+            bail!(
+                "--sheets {pos} is out of range, {input} has {}",
+                count(names.len(), "sheet")
+            );
+        }
+        Ok(Some(pos - 1))
+    };
+
+    let mut selected = vec![false; names.len()];
+    for item in list.split(',') {
+        if let Some(pos) = position(item)? {
+            selected[pos] = true;
+        } else if let Some(pos) = names.iter().position(|x| x == item) {
+            selected[pos] = true;
+        } else if let Some((first, last)) = item.split_once('-') {
+            let (Some(first), Some(last)) = (position(first)?, position(last)?) else {
+                bail!("--sheets names {item:?}, which isn't a sheet of {input}");
+            };
+            if first > last {
+                bail!("--sheets range {item:?} runs backwards");
+            }
+            selected[first..=last].fill(true);
+        } else {
+            bail!("--sheets names {item:?}, which isn't a sheet of {input}");
+        }
+    }
+    Ok((0..names.len()).filter(|x| selected[*x]).collect())
+}
+
+// out.csv becomes out.sheet2.csv for the second sheet
+fn sheet_file_name(out: &str, sheet: usize) -> String {
+    let path = Path::new(out);
+    let name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => format!(
+            "{}.sheet{sheet}.{}",
+            stem.to_string_lossy(),
+            ext.to_string_lossy()
+        ),
+        _ => format!(
+            "{}.sheet{sheet}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// Errors are reported as a single line rather than a panic and its backtrace
 fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {e:#}");
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
     let matches = cli().get_matches();
 
     match matches.subcommand() {
         Some(("csv", sub_matches)) => {
-            let input = sub_matches.get_one::<String>("in").expect("required");
-            let out = sub_matches.get_one::<String>("out").expect("required");
+            let inputs: Vec<&str> = match sub_matches.get_many::<String>("in") {
+                Some(inputs) => inputs.map(|x| x.as_str()).collect(),
+                None => vec!["-"],
+            };
+            if inputs.len() > 1 && !sub_matches.get_flag("concat") {
+                bail!("pass --concat to append several --in files to one sheet");
+            }
+            if inputs.iter().filter(|x| **x == "-").count() > 1 {
+                bail!("stdin can only be read once");
+            }
+            let fit_headers = sub_matches.get_flag("fit-headers");
+            let keep_bom = sub_matches.get_flag("keep-bom");
 
-            let mut f = File::open(input).expect("input csv file not found");
-            let mut data: Vec<u8> = Vec::new();
+            let locale = if sub_matches.get_flag("infer-types") {
+                let locale = NumberLocale {
+                    decimal_sep: separator(sub_matches, "decimal-sep")?.unwrap_or(b'.'),
+                    thousands_sep: separator(sub_matches, "thousands-sep")?,
+                    day_first: sub_matches.get_flag("day-first"),
+                };
+                if Some(locale.decimal_sep) == locale.thousands_sep {
+                    bail!("--decimal-sep and --thousands-sep must be different");
+                }
+                Some(locale)
+            } else {
+                None
+            };
 
-            f.read_to_end(&mut data).expect(&format!("Unable to read file {input}"));
+            let out = sub_matches.get_one::<String>("out");
+            let format = match sub_matches.get_one::<String>("format").map(|x| x.as_str()) {
+                Some("xls") => Format::Xls,
+                Some(_) => Format::Xlsx,
+                None if out.is_some_and(|x| x.to_lowercase().ends_with(".xls")) => Format::Xls,
+                None => Format::Xlsx,
+            };
 
-            let output_buffer = vec![];
-            let mut workbook = WorkBook::new(Cursor::new(output_buffer));
-            let mut worksheet = workbook.get_worksheet(String::from("Sheet 1"));
+            let number_format = sub_matches.get_one::<String>("number-format").cloned();
+            if format == Format::Xls && number_format.is_some() {
+                bail!("--number-format isn't supported for xls output");
+            }
 
-            let mut reader = bytes_to_csv(data.as_slice());
-            let headers = get_headers(&mut reader);
+            let align = sub_matches
+                .get_one::<String>("align")
+                .map(|x| parse_align(x))
+                .transpose()?
+                .unwrap_or_default();
+            if format == Format::Xls && !align.is_empty() {
+                bail!("--align isn't supported for xls output");
+            }
 
-            if headers.is_some() {
-                let headers_to_bytes = headers.unwrap().iter().to_owned().collect();
-                if let Err(e) = worksheet.write_row(headers_to_bytes) {
-                    panic!("{e}");
-                }
+            let schema = sub_matches
+                .get_one::<String>("schema")
+                .map(|x| parse_schema(x))
+                .transpose()?
+                .unwrap_or_default();
+            if format == Format::Xls && !schema.is_empty() {
+                bail!("--schema isn't supported for xls output");
             }
 
-            while let Some(record) = get_next_record(&mut reader) {
-                let row_data = record.iter().to_owned().collect();
-                if let Err(e) = worksheet.write_row(row_data) {
-                    panic!("{e}");
-                }
+            let bool_columns = sub_matches
+                .get_one::<String>("bool-columns")
+                .map(|x| parse_columns(x, "--bool-columns"))
+                .transpose()?
+                .unwrap_or_default();
+            let literals = |id: &str, default: &str| -> Vec<String> {
+                let list = sub_matches.get_one::<String>(id);
+                let list = list.map_or(default, |x| x.as_str());
+                list.split(',').map(|x| x.trim().to_string()).collect()
+            };
+            let bool_literals = BoolLiterals {
+                truthy: literals("true-values", "true"),
+                falsy: literals("false-values", "false"),
+            };
+            // True values are matched first, so a false value read as true is in both
+            let in_both = |x: &&String| bool_literals.parse(x.as_bytes()) == Some(b"1");
+            if let Some(x) = bool_literals.falsy.iter().find(in_both) {
+                bail!("{x:?} can't be in both --true-values and --false-values");
             }
 
-            if let Err(e) = worksheet.close() {
-                panic!("{e}");
+            let currency_columns = sub_matches
+                .get_one::<String>("currency-columns")
+                .map(|x| parse_columns(x, "--currency-columns"))
+                .transpose()?
+                .unwrap_or_default();
+            if format == Format::Xls && !currency_columns.is_empty() {
+                bail!("--currency-columns isn't supported for xls output");
+            }
+            if let Some(x) = currency_columns.iter().find(|x| bool_columns.contains(x)) {
+                bail!("column {x} can't be in both --bool-columns and --currency-columns");
+            }
+            let currency_format = sub_matches
+                .get_one::<String>("currency-format")
+                .map_or("$#,##0.00", |x| x.as_str());
+
+            let freeze_first_col = sub_matches.get_flag("freeze-first-col");
+            if format == Format::Xls && freeze_first_col {
+                bail!("--freeze-first-col isn't supported for xls output");
+            }
+
+            let autofit = sub_matches.get_flag("autofit");
+            if format == Format::Xls && autofit {
+                bail!("--autofit isn't supported for xls output");
+            }
+
+            let bold_header = sub_matches.get_flag("bold-header");
+            if format == Format::Xls && bold_header {
+                bail!("--bold-header isn't supported for xls output");
+            }
+
+            let skip_empty_cells = sub_matches.get_flag("skip-empty-cells");
+            if format == Format::Xls && skip_empty_cells {
+                bail!("--skip-empty-cells isn't supported for xls output");
             }
 
-            let final_buffer = workbook.finish().ok().unwrap().into_inner();
+            let autofilter = sub_matches.get_flag("autofilter");
+            if format == Format::Xls && autofilter {
+                bail!("--autofilter isn't supported for xls output");
+            }
+
+            let group_banner = sub_matches.get_flag("group-banner");
+            if format == Format::Xls && group_banner {
+                bail!("--group-banner isn't supported for xls output");
+            }
+
+            let embed_source_hash = sub_matches.get_flag("embed-source-hash");
+            if format == Format::Xls && embed_source_hash {
+                bail!("--embed-source-hash isn't supported for xls output");
+            }
 
-            f = File::create(out).expect(&format!("unable to write to {out}"));
-            f.write(&final_buffer).expect(&format!("Failed to write to file {out}"));
+            let options = Options {
+                format,
+                delimiter: delimiter(sub_matches)?,
+                no_header: sub_matches.get_flag("no-header"),
+                fit_headers,
+                number_format,
+                align,
+                schema,
+                bool_columns,
+                bool_literals,
+                currency_columns,
+                currency_format: String::from(currency_format),
+                freeze_first_col,
+                autofit,
+                autofilter,
+                filter_header_style: sub_matches.get_flag("filter-header-style"),
+                bold_header,
+                dedup_headers: sub_matches.get_flag("dedup-headers"),
+                sanitize_formulas: sub_matches.get_flag("sanitize-formulas"),
+                locale,
+                scan_all: sub_matches.get_flag("scan-all"),
+                pad_rows: match (
+                    sub_matches.get_flag("pad-rows"),
+                    sub_matches.get_flag("pad-blank"),
+                ) {
+                    (true, true) => Some(TYPE_BLANK),
+                    (true, false) => Some(TYPE_STRING),
+                    _ => None,
+                },
+                trim_trailing_empty: sub_matches.get_flag("trim-trailing-empty"),
+                skip_empty_cells,
+                max_columns: sub_matches
+                    .get_one::<u64>("max-columns")
+                    .map(|x| *x as usize),
+                durable: sub_matches.get_flag("durable"),
+                group_banner,
+                source_hash: embed_source_hash.then(|| Rc::new(RefCell::new(Sha256::new()))),
+                row_numbers: sub_matches.get_flag("add-row-numbers").then(|| {
+                    sub_matches
+                        .get_one::<String>("row-number-header")
+                        .map_or(String::from("Row"), |x| x.clone())
+                }),
+                verbosity: match (matches.get_flag("quiet"), matches.get_flag("verbose")) {
+                    (true, _) => Verbosity::Quiet,
+                    (_, true) => Verbosity::Verbose,
+                    _ => Verbosity::Summary,
+                },
+            };
+
+            let skip_blank_lines = sub_matches.get_flag("skip-blank-lines");
+            let (mut headers, records) =
+                open_concat(&inputs, keep_bom, skip_blank_lines, &options)?;
+            // Trimmed before anything matches on header names, e.g. --split-by
+            if sub_matches.get_flag("trim-headers") {
+                if let Some(headers) = &mut headers {
+                    headers.trim();
+                }
+            }
+            convert(headers, records, sub_matches, &options)
+        }
+        Some(("xlsx2csv", sub_matches)) => {
+            let verbosity = if matches.get_flag("quiet") {
+                Verbosity::Quiet
+            } else {
+                Verbosity::Summary
+            };
+            xlsx_to_csv(sub_matches, verbosity)
         }
         _ => unreachable!("Unsupported subcommand"),
     }
@@ -0,0 +1,1250 @@
+//! Runs the built binary the way a shell would and reads back the files it writes.
+
+use std::{
+    fs::{self, File},
+    io::{Cursor, Write},
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+};
+
+use calamine::{open_workbook_from_rs, Data, Reader, Xls};
+use excel_rs_xlsx::{
+    testing::{read_cells, read_part, ParsedCell},
+    WorkBook,
+};
+use sha2::{Digest, Sha256};
+
+/// An empty directory for the files of the test called `name`.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("excel-rs-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs the binary with `args`, feeding it `stdin`.
+fn excel_rs(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cli-excel-rs"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // It may exit without reading stdin, which is for the test to check
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+/// Like `excel_rs`, but fails the test if the binary does.
+fn excel_rs_ok(args: &[&str], stdin: &[u8]) -> Output {
+    let output = excel_rs(args, stdin);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+fn text(x: &str) -> ParsedCell {
+    ParsedCell::String(String::from(x))
+}
+
+#[test]
+fn csv_is_read_from_stdin() {
+    let dir = temp_dir("stdin");
+    let csv = b"Name,Age\nAda,36\n";
+
+    for args in [vec![], vec!["--in", "-"]] {
+        let out = dir.join("out.xlsx");
+        let out = out.to_str().unwrap();
+        excel_rs_ok(&[&["csv", "--out", out], args.as_slice()].concat(), csv);
+
+        // Without --infer-types every field is text, numbers included
+        let xlsx = fs::read(out).unwrap();
+        let xml = read_part(&xlsx, "xl/worksheets/sheet1.xml");
+        assert!(xml.contains(r#"<c r="B2" t="str"><v>36</v></c>"#), "{xml}");
+        assert_eq!(read_cells(&xlsx, "Sheet 1").len(), 2);
+    }
+}
+
+#[test]
+fn xlsx_is_written_to_stdout() {
+    let csv = b"Name,Age\nAda,36\n";
+
+    // Verbose output goes to stderr, so it can't end up in the file
+    let output = excel_rs_ok(&["csv", "--out", "-", "--verbose"], csv);
+    assert!(output.stdout.starts_with(b"PK\x03\x04"));
+    assert!(!output.stderr.is_empty());
+
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(
+        xml.contains(r#"<c r="A2" t="str"><v>Ada</v></c><c r="B2" t="str"><v>36</v></c>"#),
+        "{xml}"
+    );
+}
+
+#[test]
+fn fit_headers_widens_columns_with_long_headers() {
+    let output = excel_rs_ok(
+        &["csv", "--out", "-", "--fit-headers"],
+        b"Id,A much longer header\n1,2\n",
+    );
+
+    let sheet = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    let widths: Vec<f64> = sheet
+        .split("<col ")
+        .skip(1)
+        .map(|col| {
+            let width = col.split("width=\"").nth(1).unwrap();
+            width[..width.find('"').unwrap()].parse().unwrap()
+        })
+        .collect();
+    assert_eq!(widths.len(), 2, "{sheet}");
+    assert!(widths[1] > widths[0], "{widths:?}");
+    // <cols> has to come before the data
+    assert!(sheet.find("<cols>").unwrap() < sheet.find("<sheetData>").unwrap());
+}
+
+#[test]
+fn european_numbers_are_stored_with_a_decimal_point() {
+    let output = excel_rs_ok(
+        &[
+            "csv",
+            "--out",
+            "-",
+            "--delimiter",
+            ";",
+            "--infer-types",
+            "--decimal-sep",
+            ",",
+            "--thousands-sep",
+            ".",
+        ],
+        b"Item;Price\nChair;1.234,56\n",
+    );
+
+    let sheet = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(sheet.contains("<v>1234.56</v>"), "{sheet}");
+    assert_eq!(
+        read_cells(&output.stdout, "Sheet 1")[1],
+        vec![text("Chair"), ParsedCell::Number(1234.56)]
+    );
+}
+
+#[test]
+fn split_by_writes_one_file_per_group_with_the_header() {
+    let dir = temp_dir("split-by");
+    let out_dir = dir.join("out");
+    excel_rs_ok(
+        &[
+            "csv",
+            "--split-by",
+            "Region",
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+        ],
+        b"Name,Region\na,North\nb,South\nc,North\nd,West\n",
+    );
+
+    let mut files: Vec<String> = fs::read_dir(&out_dir)
+        .unwrap()
+        .map(|x| x.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["North.xlsx", "South.xlsx", "West.xlsx"]);
+
+    for (file, names) in [
+        ("North.xlsx", vec!["a", "c"]),
+        ("South.xlsx", vec!["b"]),
+        ("West.xlsx", vec!["d"]),
+    ] {
+        let cells = read_cells(&fs::read(out_dir.join(file)).unwrap(), "Sheet 1");
+        let region = file.trim_end_matches(".xlsx");
+        let mut expected = vec![vec![text("Name"), text("Region")]];
+        expected.extend(names.into_iter().map(|x| vec![text(x), text(region)]));
+        assert_eq!(cells, expected, "{file}");
+    }
+}
+
+#[test]
+fn dedup_headers_renames_repeated_names() {
+    let csv = b"Amount,Amount,Amount\n1,2,3\n";
+
+    let output = excel_rs_ok(&["csv", "--out", "-", "--dedup-headers"], csv);
+    assert_eq!(
+        read_cells(&output.stdout, "Sheet 1")[0],
+        vec![text("Amount"), text("Amount (2)"), text("Amount (3)")]
+    );
+
+    // Without the flag they're written as they are
+    let output = excel_rs_ok(&["csv", "--out", "-"], csv);
+    assert_eq!(
+        read_cells(&output.stdout, "Sheet 1")[0],
+        vec![text("Amount"); 3]
+    );
+}
+
+#[test]
+fn sanitize_formulas_neutralizes_formula_text() {
+    let csv = b"Name,Note\n=cmd|'/c calc'!A1,@SUM(A1)\n";
+
+    let output = excel_rs_ok(&["csv", "--out", "-", "--sanitize-formulas"], csv);
+    assert_eq!(
+        read_cells(&output.stdout, "Sheet 1")[1],
+        vec![text("'=cmd|'/c calc'!A1"), text("'@SUM(A1)")]
+    );
+
+    // Off by default
+    let output = excel_rs_ok(&["csv", "--out", "-"], csv);
+    assert_eq!(
+        read_cells(&output.stdout, "Sheet 1")[1],
+        vec![text("=cmd|'/c calc'!A1"), text("@SUM(A1)")]
+    );
+}
+
+#[test]
+fn read_errors_fail_the_conversion() {
+    // Opening a directory works, but reading it doesn't
+    let dir = temp_dir("read-error");
+    let out = dir.join("out.xlsx");
+    let output = excel_rs(
+        &[
+            "csv",
+            "--in",
+            dir.to_str().unwrap(),
+            "--out",
+            out.to_str().unwrap(),
+        ],
+        b"",
+    );
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("error: can't read input csv file"),
+        "{stderr}"
+    );
+    assert!(!out.exists());
+}
+
+/// The number of cells written in each row of the first sheet.
+fn cells_per_row(xlsx: &[u8]) -> Vec<usize> {
+    let xml = read_part(xlsx, "xl/worksheets/sheet1.xml");
+    xml.split("<row ")
+        .skip(1)
+        .map(|row| row.split("</row>").next().unwrap().matches("<c ").count())
+        .collect()
+}
+
+#[test]
+fn pad_rows_makes_every_row_as_wide_as_the_header() {
+    let csv = b"Name,Age,City\nAda\nAlan,41\n";
+
+    let output = excel_rs_ok(&["csv", "--out", "-"], csv);
+    assert_eq!(cells_per_row(&output.stdout), vec![3, 1, 2]);
+
+    let output = excel_rs_ok(&["csv", "--out", "-", "--pad-rows"], csv);
+    assert_eq!(cells_per_row(&output.stdout), vec![3, 3, 3]);
+    assert_eq!(
+        read_cells(&output.stdout, "Sheet 1")[1],
+        vec![text("Ada"), text(""), text("")]
+    );
+
+    // Blank padding has no value, so reads back as nothing at all
+    let output = excel_rs_ok(&["csv", "--out", "-", "--pad-rows", "--pad-blank"], csv);
+    assert_eq!(cells_per_row(&output.stdout), vec![3, 3, 3]);
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(xml.contains(r#"<c r="C3"/>"#), "{xml}");
+    assert_eq!(
+        read_cells(&output.stdout, "Sheet 1")[1],
+        vec![text("Ada"), ParsedCell::Empty, ParsedCell::Empty]
+    );
+}
+
+#[test]
+fn no_header_types_the_first_row_like_data() {
+    let csv = b"1,2.5,Ada\n3,4,Alan\n";
+
+    let args = ["csv", "--out", "-", "--infer-types", "--no-header"];
+    let output = excel_rs_ok(&args, csv);
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(
+        xml.contains(concat!(
+            r#"<row r="1"><c r="A1" t="n"><v>1</v></c><c r="B1" t="n"><v>2.5</v></c>"#,
+            r#"<c r="C1" t="str"><v>Ada</v></c></row>"#
+        )),
+        "{xml}"
+    );
+    assert!(xml.contains(r#"<c r="A2" t="n"><v>3</v></c>"#), "{xml}");
+
+    // With a header the same row is text
+    let output = excel_rs_ok(&args[..4], csv);
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(xml.contains(r#"<c r="A1" t="str"><v>1</v></c>"#), "{xml}");
+    assert!(xml.contains(r#"<c r="A2" t="n"><v>3</v></c>"#), "{xml}");
+}
+
+#[test]
+fn concat_appends_every_file_under_one_header() {
+    let dir = temp_dir("concat");
+    let day1 = dir.join("day1.csv");
+    let day2 = dir.join("day2.csv");
+    fs::write(&day1, "Name,Age\nAda,36\nAlan,41\n").unwrap();
+    fs::write(&day2, "Name,Age\nGrace,85\n").unwrap();
+
+    let output = excel_rs_ok(
+        &[
+            "csv",
+            "--concat",
+            "--in",
+            day1.to_str().unwrap(),
+            "--in",
+            day2.to_str().unwrap(),
+            "--out",
+            "-",
+        ],
+        b"",
+    );
+    let cells = read_cells(&output.stdout, "Sheet 1");
+    // 3 + 2 rows, less the second file's header
+    assert_eq!(cells.len(), 4);
+    assert_eq!(cells[0], vec![text("Name"), text("Age")]);
+    assert_eq!(cells[3][0], text("Grace"));
+
+    let day3 = dir.join("day3.csv");
+    fs::write(&day3, "Name\nEdsger\n").unwrap();
+    let output = excel_rs(
+        &[
+            "csv",
+            "--concat",
+            "--in",
+            day1.to_str().unwrap(),
+            "--in",
+            day3.to_str().unwrap(),
+            "--out",
+            "-",
+        ],
+        b"",
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("day3.csv has 1 columns"), "{stderr}");
+}
+
+/// The `<xf>` of the style the cell `cell` references, as written in `xl/styles.xml`.
+fn cell_xf(xlsx: &[u8], cell: &str) -> String {
+    let sheet = read_part(xlsx, "xl/worksheets/sheet1.xml");
+    let start = sheet.find(&format!("<c r=\"{cell}\"")).unwrap();
+    let tag = &sheet[start..start + sheet[start..].find('>').unwrap()];
+    let style: usize = match tag.split(" s=\"").nth(1) {
+        Some(x) => x[..x.find('"').unwrap()].parse().unwrap(),
+        None => 0,
+    };
+
+    let styles = read_part(xlsx, "xl/styles.xml");
+    let xfs = styles.split("<cellXfs").nth(1).unwrap();
+    let xf = xfs.split("<xf ").nth(style + 1).unwrap();
+    let end = xf.find("</xf>").or_else(|| xf.find("/>")).unwrap();
+    String::from(&xf[..end])
+}
+
+#[test]
+fn align_styles_the_columns_it_names() {
+    let csv = b"Name,Total,Note\nAda,1234,x\n";
+
+    let args = [
+        "csv",
+        "--out",
+        "-",
+        "--infer-types",
+        "--align",
+        "1:right,2:center",
+    ];
+    let output = excel_rs_ok(&args, csv);
+    assert!(cell_xf(&output.stdout, "B2").contains(r#"<alignment horizontal="right"/>"#));
+    assert!(cell_xf(&output.stdout, "C2").contains(r#"<alignment horizontal="center"/>"#));
+    assert!(!cell_xf(&output.stdout, "A2").contains("alignment"));
+
+    // The aligned column keeps the default number format
+    let output = excel_rs_ok(&[&args[..], &["--number-format", "#,##0"]].concat(), csv);
+    let xf = cell_xf(&output.stdout, "B2");
+    assert!(xf.contains(r#"numFmtId="164""#), "{xf}");
+    assert!(xf.contains(r#"<alignment horizontal="right"/>"#), "{xf}");
+
+    let output = excel_rs(&["csv", "--out", "-", "--align", "3:left"], csv);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--align column 3 is out of range"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn aligned_dates_keep_their_date_format() {
+    let csv = b"Joined,Name\n2024-01-02,Ada\n";
+
+    let args = ["csv", "--out", "-", "--infer-types", "--align", "0:left"];
+    let output = excel_rs_ok(&args, csv);
+    let xf = cell_xf(&output.stdout, "A2");
+    assert!(xf.contains(r#"numFmtId="164""#), "{xf}");
+    assert!(xf.contains(r#"<alignment horizontal="left"/>"#), "{xf}");
+    let styles = read_part(&output.stdout, "xl/styles.xml");
+    assert!(styles.contains(r#"<numFmt numFmtId="164" formatCode="yyyy-mm-dd"/>"#));
+    assert_eq!(
+        read_cells(&output.stdout, "Sheet 1")[1][0],
+        ParsedCell::Date(45293.0)
+    );
+}
+
+#[test]
+fn blank_lines_keep_the_rows_in_place_unless_skipped() {
+    let csv = b"Name\nAda\n\nAlan\n";
+
+    let output = excel_rs_ok(&["csv", "--out", "-"], csv);
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(xml.contains(r#"<row r="3"></row>"#), "{xml}");
+    assert!(
+        xml.contains(r#"<c r="A4" t="str"><v>Alan</v></c>"#),
+        "{xml}"
+    );
+
+    let output = excel_rs_ok(&["csv", "--out", "-", "--skip-blank-lines"], csv);
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(
+        xml.contains(r#"<c r="A3" t="str"><v>Alan</v></c>"#),
+        "{xml}"
+    );
+    assert_eq!(
+        read_cells(&output.stdout, "Sheet 1"),
+        vec![vec![text("Name")], vec![text("Ada")], vec![text("Alan")]]
+    );
+}
+
+#[test]
+fn trim_headers_leaves_data_spacing_alone() {
+    let csv = b" Name ,\tAge\n Ada , 36\n";
+
+    let output = excel_rs_ok(&["csv", "--out", "-", "--trim-headers"], csv);
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(
+        xml.contains(r#"<c r="A1" t="str"><v>Name</v></c>"#),
+        "{xml}"
+    );
+    assert!(xml.contains(r#"<c r="B1" t="str"><v>Age</v></c>"#), "{xml}");
+    assert!(
+        xml.contains(r#"<c r="A2" t="str"><v> Ada </v></c>"#),
+        "{xml}"
+    );
+
+    let output = excel_rs_ok(&["csv", "--out", "-"], csv);
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(
+        xml.contains(r#"<c r="A1" t="str"><v> Name </v></c>"#),
+        "{xml}"
+    );
+}
+
+#[test]
+fn inferred_dates_keep_the_csv_layout() {
+    let csv = b"Day\n25/12/2023\n12/25/2023\n2023-12-25\n01/02/2023\n";
+
+    let output = excel_rs_ok(&["csv", "--out", "-", "--infer-types"], csv);
+    let formats: Vec<String> = (2..=5)
+        .map(|row| {
+            let xf = cell_xf(&output.stdout, &format!("A{row}"));
+            let id = xf.split("numFmtId=\"").nth(1).unwrap();
+            String::from(&id[..id.find('"').unwrap()])
+        })
+        .collect();
+    let styles = read_part(&output.stdout, "xl/styles.xml");
+    let code = |id: &str| {
+        let start = styles
+            .find(&format!("numFmtId=\"{id}\" formatCode=\""))
+            .unwrap();
+        let code = &styles[start..].split("formatCode=\"").nth(1).unwrap();
+        String::from(&code[..code.find('"').unwrap()])
+    };
+    assert_eq!(code(&formats[0]), "dd/mm/yyyy");
+    assert_eq!(code(&formats[1]), "mm/dd/yyyy");
+    assert_eq!(code(&formats[2]), "yyyy-mm-dd");
+    assert_eq!(code(&formats[3]), "mm/dd/yyyy");
+
+    // Every one is the same day, apart from the ambiguous one
+    let cells = read_cells(&output.stdout, "Sheet 1");
+    for row in &cells[1..4] {
+        assert_eq!(row[0], ParsedCell::Date(45285.0));
+    }
+    assert_eq!(cells[4][0], ParsedCell::Date(44928.0));
+
+    let output = excel_rs_ok(&["csv", "--out", "-", "--infer-types", "--day-first"], csv);
+    assert_eq!(
+        read_cells(&output.stdout, "Sheet 1")[4][0],
+        ParsedCell::Date(44958.0)
+    );
+}
+
+#[test]
+fn sheet_name_template_names_are_valid_and_unique() {
+    let dir = temp_dir("sheet-name-template");
+    let out_dir = dir.join("out");
+    let csv = "Name,Region\n\
+               a,Northern European Sales Territory A\n\
+               b,Northern European Sales Territory B\n\
+               c,North/West\n\
+               d,north/west\n";
+    excel_rs_ok(
+        &[
+            "csv",
+            "--split-by",
+            "Region",
+            "--sheet-name-template",
+            "Region - {value}",
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+        ],
+        csv.as_bytes(),
+    );
+
+    let mut names = Vec::new();
+    for file in fs::read_dir(&out_dir).unwrap() {
+        let bytes = fs::read(file.unwrap().path()).unwrap();
+        let workbook = read_part(&bytes, "xl/workbook.xml");
+        let name = workbook.split("<sheet name=\"").nth(1).unwrap();
+        let name = String::from(&name[..name.find('"').unwrap()]);
+
+        let cells = read_cells(&bytes, &name);
+        names.push((cells[1][0].clone(), name));
+    }
+    names.sort_by_key(|x| format!("{:?}", x.0));
+
+    assert_eq!(
+        names,
+        vec![
+            (text("a"), String::from("Region - Northern European Sale")),
+            (text("b"), String::from("Region - Northern European (2)")),
+            (text("c"), String::from("Region - North_West")),
+            (text("d"), String::from("Region - north_west (2)")),
+        ]
+    );
+    for (_, name) in names {
+        assert!(name.chars().count() <= 31, "{name}");
+    }
+}
+
+// Whether the data reached the disk can't be seen from here, so this checks that every
+// output --durable syncs is complete by the time the process returns
+#[test]
+fn durable_outputs_are_complete_when_the_process_returns() {
+    let dir = temp_dir("durable");
+    let csv = b"Name,Region\nAda,North\nAlan,South\n";
+    let expected = vec![text("Ada"), text("North")];
+
+    let out = dir.join("out.xlsx");
+    excel_rs_ok(&["csv", "--durable", "--out", out.to_str().unwrap()], csv);
+    assert_eq!(read_cells(&fs::read(&out).unwrap(), "Sheet 1")[1], expected);
+
+    let out_dir = dir.join("split");
+    let args = ["csv", "--durable", "--split-by", "Region", "--out-dir"];
+    excel_rs_ok(&[&args[..], &[out_dir.to_str().unwrap()]].concat(), csv);
+    let bytes = fs::read(out_dir.join("North.xlsx")).unwrap();
+    assert_eq!(read_cells(&bytes, "Sheet 1")[1], expected);
+
+    let out = dir.join("out.xls");
+    excel_rs_ok(&["csv", "--durable", "--out", out.to_str().unwrap()], csv);
+    assert!(fs::read(&out).unwrap().starts_with(b"\xD0\xCF\x11\xE0"));
+
+    // A bare file name syncs the working directory
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cli-excel-rs"))
+        .args(["csv", "--durable", "--out", "relative.xlsx"])
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(csv).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bytes = fs::read(dir.join("relative.xlsx")).unwrap();
+    assert_eq!(read_cells(&bytes, "Sheet 1")[1], expected);
+
+    // Nothing to sync on stdout
+    let output = excel_rs_ok(&["csv", "--durable", "--out", "-"], csv);
+    assert_eq!(read_cells(&output.stdout, "Sheet 1")[1], expected);
+}
+
+#[test]
+fn row_numbers_are_a_leading_number_column() {
+    let output = excel_rs_ok(
+        &[
+            "csv",
+            "--add-row-numbers",
+            "--row-number-header",
+            "#",
+            "--freeze-first-col",
+            "--autofilter",
+            "--infer-types",
+            "--out",
+            "-",
+        ],
+        b"Name,Age\nAda,36\nAlan,41\n",
+    );
+    let xlsx = &output.stdout;
+
+    let cells = read_cells(xlsx, "Sheet 1");
+    assert_eq!(cells[0], vec![text("#"), text("Name"), text("Age")]);
+    assert_eq!(
+        cells[2],
+        vec![
+            ParsedCell::Number(2.0),
+            text("Alan"),
+            ParsedCell::Number(41.0)
+        ]
+    );
+
+    let xml = read_part(xlsx, "xl/worksheets/sheet1.xml");
+    assert!(xml.contains(r#"<c r="A2" t="n"><v>1</v></c>"#), "{xml}");
+    assert!(xml.contains(r#"<c r="A3" t="n"><v>2</v></c>"#), "{xml}");
+    // The frozen csv column and the filter both move over with the csv's columns
+    assert!(xml.contains(r#"<pane xSplit="2" "#), "{xml}");
+    assert!(xml.contains(r#"<autoFilter ref="A1:C3"/>"#), "{xml}");
+}
+
+#[test]
+fn quiet_is_silent_and_verbose_counts_the_rows() {
+    let dir = temp_dir("verbosity");
+    let out = dir.join("out.xlsx");
+    let out = out.to_str().unwrap();
+    let csv = b"Name,Age\nAda,36\nAlan,41\n";
+
+    let output = excel_rs_ok(&["csv", "--out", out, "--quiet"], csv);
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+    assert_eq!(read_cells(&fs::read(out).unwrap(), "Sheet 1").len(), 3);
+
+    // Rows are counted without the header
+    let output = excel_rs_ok(&["csv", "--out", out], csv);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!("Wrote 2 rows to {out}\n")
+    );
+
+    let output = excel_rs_ok(&["csv", "--out", out, "--verbose", "--infer-types"], csv);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("{out}: 2 rows\n")), "{stderr}");
+    assert!(
+        stderr.contains(&format!("{out}: inferred 2 numbers and 0 dates")),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains(&format!("Wrote 2 rows to {out} in ")),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn filter_header_style_marks_the_filtered_header() {
+    let csv = b"Name,Age\nAda,36\n";
+
+    let plain = excel_rs_ok(&["csv", "--autofilter", "--out", "-"], csv).stdout;
+    let xml = read_part(&plain, "xl/worksheets/sheet1.xml");
+    assert!(xml.contains(r#"<autoFilter ref="A1:B2"/>"#), "{xml}");
+    assert!(
+        xml.contains(r#"<c r="A1" t="str"><v>Name</v></c>"#),
+        "{xml}"
+    );
+
+    let styled = excel_rs_ok(
+        &["csv", "--autofilter", "--filter-header-style", "--out", "-"],
+        csv,
+    )
+    .stdout;
+    let xml = read_part(&styled, "xl/worksheets/sheet1.xml");
+    assert!(xml.contains(r#"<autoFilter ref="A1:B2"/>"#), "{xml}");
+    for cell in ["A1", "B1"] {
+        let xf = cell_xf(&styled, cell);
+        assert!(
+            xf.contains(r#"applyFont="1" applyFill="1""#),
+            "{cell}: {xf}"
+        );
+    }
+    // Data cells keep the default style
+    assert_eq!(cell_xf(&styled, "A2"), cell_xf(&plain, "A2"));
+
+    let styles = read_part(&styled, "xl/styles.xml");
+    assert!(
+        styles.contains(r#"<patternFill patternType="solid"><fgColor rgb="FFDDEBF7"/>"#),
+        "{styles}"
+    );
+    assert!(styles.contains("<b/>"), "{styles}");
+}
+
+#[test]
+fn short_rows_only_take_the_types_of_their_cells() {
+    let output = excel_rs_ok(
+        &["csv", "--infer-types", "--out", "-"],
+        b"Name,Age\nAda\nAlan,41\n",
+    );
+    let cells = read_cells(&output.stdout, "Sheet 1");
+    assert_eq!(cells[1], vec![text("Ada"), ParsedCell::Empty]);
+    assert_eq!(cells[2], vec![text("Alan"), ParsedCell::Number(41.0)]);
+}
+
+#[test]
+fn schema_styles_columns_by_name_and_index() {
+    let dir = temp_dir("schema");
+    let schema = dir.join("report.json");
+    fs::write(
+        &schema,
+        r##"{"columns": [
+            {"name": "Total", "width": 14, "bold": true, "numFmt": "#,##0.00", "fill": "#FFF2CC"},
+            {"index": 0, "width": 24}
+        ]}"##,
+    )
+    .unwrap();
+    let schema = schema.to_str().unwrap();
+    let csv = b"Name,Total,Note\nAda,1234.5,x\n";
+
+    let output = excel_rs_ok(
+        &[
+            "csv",
+            "--infer-types",
+            "--fit-headers",
+            "--schema",
+            schema,
+            "--align",
+            "1:right",
+            "--out",
+            "-",
+        ],
+        csv,
+    );
+    let xlsx = &output.stdout;
+
+    // Explicit widths win over --fit-headers, which sizes the rest
+    let xml = read_part(xlsx, "xl/worksheets/sheet1.xml");
+    assert!(
+        xml.contains(concat!(
+            r#"<cols><col min="1" max="1" width="24.00" customWidth="1"/>"#,
+            r#"<col min="2" max="2" width="14.00" customWidth="1"/>"#,
+            r#"<col min="3" max="3" width="6.00" customWidth="1"/></cols>"#
+        )),
+        "{xml}"
+    );
+
+    let styles = read_part(xlsx, "xl/styles.xml");
+    assert!(
+        styles.contains(r##"<numFmt numFmtId="164" formatCode="#,##0.00"/>"##),
+        "{styles}"
+    );
+    assert_eq!(
+        cell_xf(xlsx, "B2"),
+        concat!(
+            r#"numFmtId="164" fontId="1" fillId="2" borderId="0" xfId="0" applyNumberFormat="1" "#,
+            r#"applyFont="1" applyFill="1" applyAlignment="1"><alignment horizontal="right"/>"#
+        )
+    );
+    assert!(styles.contains("<font><b/>"), "{styles}");
+    assert!(styles.contains(r#"<fgColor rgb="FFFFF2CC"/>"#), "{styles}");
+    // A width alone adds no style
+    assert!(xml.contains(r#"<c r="A2" t="str"><v>Ada</v></c>"#), "{xml}");
+
+    let cells = read_cells(xlsx, "Sheet 1");
+    assert_eq!(cells[1][1], ParsedCell::Number(1234.5));
+}
+
+#[test]
+fn schema_errors_name_the_entry() {
+    let dir = temp_dir("schema-errors");
+    let schema = dir.join("report.json");
+    let schema_path = schema.to_str().unwrap();
+    for (json, error) in [
+        (
+            r#"{"columns": [{"name": "Totl", "bold": true}]}"#,
+            String::from(r#"error: --schema column "Totl" isn't in the csv's header"#),
+        ),
+        (
+            r#"{"columns": [{"index": 0}, {"name": "Total", "colour": "red"}]}"#,
+            format!(r#"error: --schema {schema_path} column 2: has an unknown key "colour""#),
+        ),
+        (
+            r#"{"columns": [{"index": 5, "width": 3}]}"#,
+            String::from("error: --schema column 5 is out of range, the csv has 2 columns"),
+        ),
+        (
+            r#"{"columns": [{"name": "Total", "fill": "yellow"}]}"#,
+            format!(
+                r#"error: --schema {schema_path} column 1: "fill" must be an RGB color like "FFF2CC", got "yellow""#
+            ),
+        ),
+    ] {
+        fs::write(&schema, json).unwrap();
+        let output = excel_rs(
+            &["csv", "--schema", schema_path, "--out", "-"],
+            b"Name,Total\nAda,1\n",
+        );
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stderr).trim_end(), error);
+    }
+}
+
+#[test]
+fn max_columns_writes_only_the_first_columns() {
+    let header: Vec<String> = (1..=10).map(|x| format!("c{x}")).collect();
+    let row: Vec<String> = (1..=10).map(|x| x.to_string()).collect();
+    let csv = format!("{}\n{}\n1,2\n", header.join(","), row.join(","));
+
+    let output = excel_rs_ok(
+        &[
+            "csv",
+            "--max-columns",
+            "3",
+            "--infer-types",
+            "--autofilter",
+            "--out",
+            "-",
+        ],
+        csv.as_bytes(),
+    );
+    let xlsx = &output.stdout;
+    // The short row isn't padded
+    assert_eq!(cells_per_row(xlsx), [3, 3, 2]);
+    let cells = read_cells(xlsx, "Sheet 1");
+    assert_eq!(cells[0], [text("c1"), text("c2"), text("c3")]);
+    assert_eq!(
+        cells[1],
+        [
+            ParsedCell::Number(1.0),
+            ParsedCell::Number(2.0),
+            ParsedCell::Number(3.0)
+        ]
+    );
+    let xml = read_part(xlsx, "xl/worksheets/sheet1.xml");
+    assert!(xml.contains(r#"<autoFilter ref="A1:C3"/>"#), "{xml}");
+
+    let output = excel_rs_ok(
+        &["csv", "--max-columns", "3", "--pad-rows", "--out", "-"],
+        csv.as_bytes(),
+    );
+    assert_eq!(cells_per_row(&output.stdout), [3, 3, 3]);
+}
+
+#[test]
+fn sheets_are_each_written_to_their_own_csv() {
+    let dir = temp_dir("sheets");
+    let xlsx = dir.join("book.xlsx");
+    let mut workbook = WorkBook::new(File::create(&xlsx).unwrap());
+    for name in ["One", "Two", "Three", "Four", "Five"] {
+        let mut worksheet = workbook.get_typed_worksheet(String::from(name));
+        worksheet
+            .write_row(vec![b"Sheet", name.as_bytes()], &vec![])
+            .unwrap();
+        worksheet.close().unwrap();
+    }
+    workbook.finish().unwrap();
+    let xlsx = xlsx.to_str().unwrap();
+    let out = dir.join("out.csv");
+    let out = out.to_str().unwrap();
+
+    excel_rs_ok(
+        &[
+            "xlsx2csv", "--in", xlsx, "--sheets", "1-2,Four", "--out", out,
+        ],
+        b"",
+    );
+    let mut written: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|x| x.ends_with(".csv"))
+        .collect();
+    written.sort();
+    assert_eq!(
+        written,
+        ["out.sheet1.csv", "out.sheet2.csv", "out.sheet4.csv"]
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("out.sheet4.csv")).unwrap(),
+        "Sheet,Four\n"
+    );
+
+    for (sheets, error) in [
+        (
+            "2,7",
+            "error: --sheets 7 is out of range, {xlsx} has 5 sheets",
+        ),
+        (
+            "Six",
+            "error: --sheets names \"Six\", which isn't a sheet of {xlsx}",
+        ),
+        ("3-1", "error: --sheets range \"3-1\" runs backwards"),
+    ] {
+        let output = excel_rs(
+            &["xlsx2csv", "--in", xlsx, "--sheets", sheets, "--out", out],
+            b"",
+        );
+        assert!(!output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr).trim_end(),
+            error.replace("{xlsx}", xlsx)
+        );
+    }
+}
+
+#[test]
+fn source_hash_is_the_sha256_of_the_input() {
+    // The BOM and blank line are hashed as they are in the file
+    let csv = b"\xEF\xBB\xBFName,Age\n\nAda,36\r\n";
+    let digest: String = Sha256::digest(csv)
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect();
+    let property = format!(r#"name="SourceSHA256"><vt:lpwstr>{digest}</vt:lpwstr>"#);
+
+    let dir = temp_dir("source-hash");
+    let input = dir.join("in.csv");
+    fs::write(&input, csv).unwrap();
+    for args in [vec!["--in", input.to_str().unwrap()], vec![]] {
+        let output = excel_rs_ok(
+            &[
+                &["csv", "--embed-source-hash", "--out", "-"],
+                args.as_slice(),
+            ]
+            .concat(),
+            csv,
+        );
+        let custom = read_part(&output.stdout, "docProps/custom.xml");
+        assert!(custom.contains(&property), "{custom}");
+    }
+}
+
+#[test]
+fn bool_columns_match_their_literals_in_any_case() {
+    let csv = b"Active,Name\nTrue,Ada\nfalse,Alan\nmaybe,Grace\nTRUE,Edsger\n";
+
+    let output = excel_rs_ok(&["csv", "--bool-columns", "0", "--out", "-"], csv);
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(xml.contains(r#"<c r="A2" t="b"><v>1</v></c>"#), "{xml}");
+    assert!(xml.contains(r#"<c r="A3" t="b"><v>0</v></c>"#), "{xml}");
+    assert!(xml.contains(r#"<c r="A5" t="b"><v>1</v></c>"#), "{xml}");
+    // Anything else keeps its type, and other columns are left alone
+    assert!(
+        xml.contains(r#"<c r="A4" t="str"><v>maybe</v></c>"#),
+        "{xml}"
+    );
+    assert!(
+        xml.contains(r#"<c r="A1" t="str"><v>Active</v></c>"#),
+        "{xml}"
+    );
+    assert!(xml.contains(r#"<c r="B2" t="str"><v>Ada</v></c>"#), "{xml}");
+
+    let output = excel_rs_ok(
+        &[
+            "csv",
+            "--bool-columns",
+            "0",
+            "--true-values",
+            "yes, y",
+            "--false-values",
+            "no",
+            "--out",
+            "-",
+        ],
+        b"Active\nY\nNo\ntrue\n",
+    );
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(xml.contains(r#"<c r="A2" t="b"><v>1</v></c>"#), "{xml}");
+    assert!(xml.contains(r#"<c r="A3" t="b"><v>0</v></c>"#), "{xml}");
+    assert!(
+        xml.contains(r#"<c r="A4" t="str"><v>true</v></c>"#),
+        "{xml}"
+    );
+
+    let output = excel_rs(&["csv", "--bool-columns", "2", "--out", "-"], csv);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--bool-columns column 2 is out of range, the csv has 2 columns"));
+
+    let output = excel_rs(
+        &[
+            "csv",
+            "--bool-columns",
+            "0",
+            "--true-values",
+            "x",
+            "--false-values",
+            "X",
+            "--out",
+            "-",
+        ],
+        csv,
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains(r#""X" can't be in both --true-values and --false-values"#));
+}
+
+#[test]
+fn trim_trailing_empty_drops_empty_fields_ending_a_row() {
+    let csv = b"A,B,C\n1,2,,\n1,,\n,,,,\n1,2,3,\n";
+
+    let output = excel_rs_ok(&["csv", "--out", "-"], csv);
+    assert_eq!(cells_per_row(&output.stdout), vec![3, 4, 3, 5, 4]);
+
+    let output = excel_rs_ok(&["csv", "--out", "-", "--trim-trailing-empty"], csv);
+    assert_eq!(cells_per_row(&output.stdout), vec![3, 2, 1, 0, 3]);
+
+    // Padding runs after trimming, so only the fields past the header are gone
+    let output = excel_rs_ok(
+        &["csv", "--out", "-", "--trim-trailing-empty", "--pad-rows"],
+        csv,
+    );
+    assert_eq!(cells_per_row(&output.stdout), vec![3, 3, 3, 3, 3]);
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(
+        xml.contains(
+            r#"<c r="A3" t="str"><v>1</v></c><c r="B3" t="str"><v></v></c><c r="C3" t="str"><v></v></c>"#
+        ),
+        "{xml}"
+    );
+
+    let output = excel_rs_ok(
+        &[
+            "csv",
+            "--out",
+            "-",
+            "--trim-trailing-empty",
+            "--pad-rows",
+            "--pad-blank",
+        ],
+        csv,
+    );
+    assert_eq!(cells_per_row(&output.stdout), vec![3, 3, 3, 3, 3]);
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(xml.contains(r#"<c r="B3"/><c r="C3"/>"#), "{xml}");
+    assert!(
+        xml.contains(r#"<c r="A4"/><c r="B4"/><c r="C4"/>"#),
+        "{xml}"
+    );
+}
+
+#[test]
+fn group_banner_titles_freezes_and_filters_each_group() {
+    let dir = temp_dir("group-banner");
+    let out_dir = dir.join("out");
+    excel_rs_ok(
+        &[
+            "csv",
+            "--split-by",
+            "Region",
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+            "--group-banner",
+            "--sheet-name-template",
+            "Region: {value}",
+        ],
+        b"Name,Region,Sales\na,North,1\nb,South,2\nc,North,3\n",
+    );
+
+    for (file, rows) in [("North.xlsx", 4), ("South.xlsx", 3)] {
+        let xlsx = fs::read(out_dir.join(file)).unwrap();
+        let region = file.trim_end_matches(".xlsx");
+        let xml = read_part(&xlsx, "xl/worksheets/sheet1.xml");
+        // The title isn't held to the sheet name's limits, so keeps the colon the name loses
+        assert!(
+            xml.contains(&format!(r#"<c r="A1" t="str"><v>Region: {region}</v></c>"#)),
+            "{file}: {xml}"
+        );
+        assert!(xml.contains(r#"<mergeCell ref="A1:C1"/>"#), "{file}: {xml}");
+        assert!(
+            xml.contains(r#"<pane ySplit="2" topLeftCell="A3" "#),
+            "{file}: {xml}"
+        );
+        assert!(
+            xml.contains(&format!(r#"<autoFilter ref="A2:C{rows}"/>"#)),
+            "{file}: {xml}"
+        );
+        assert!(
+            xml.contains(r#"<c r="A2" t="str"><v>Name</v></c>"#),
+            "{file}: {xml}"
+        );
+        let cells = read_cells(&xlsx, &format!("Region_ {region}"));
+        assert_eq!(cells.len(), rows, "{file}");
+        assert_eq!(cells[1], vec![text("Name"), text("Region"), text("Sales")]);
+        assert_eq!(cells[2][1], text(region));
+    }
+
+    let output = excel_rs(&["csv", "--group-banner", "--out", "-"], b"Name\na\n");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn currency_columns_are_numbers_in_the_currency_format() {
+    let csv = b"Item,Price\nTea,5\nCake,5.1\nJam,\n";
+
+    // Without --infer-types every field would be text, which the format doesn't apply to
+    let output = excel_rs_ok(&["csv", "--currency-columns", "1", "--out", "-"], csv);
+    let xlsx = &output.stdout;
+    let xml = read_part(xlsx, "xl/worksheets/sheet1.xml");
+    assert!(
+        xml.contains(r#"<c r="B2" t="n" s="2"><v>5</v></c>"#),
+        "{xml}"
+    );
+    assert!(
+        xml.contains(r#"<c r="B3" t="n" s="2"><v>5.1</v></c>"#),
+        "{xml}"
+    );
+    // Empty fields stay empty, and the header and other columns are left alone
+    assert!(!xml.contains(r#"<c r="B4" t="n""#), "{xml}");
+    assert!(
+        xml.contains(r#"<c r="B1" t="str"><v>Price</v></c>"#),
+        "{xml}"
+    );
+    assert!(xml.contains(r#"<c r="A2" t="str"><v>Tea</v></c>"#), "{xml}");
+    let xf = cell_xf(xlsx, "B2");
+    let styles = read_part(xlsx, "xl/styles.xml");
+    assert!(
+        styles.contains(r#"<numFmt numFmtId="164" formatCode="$#,##0.00"/>"#),
+        "{styles}"
+    );
+    assert!(xf.contains(r#"numFmtId="164""#), "{xf}");
+
+    // Numbers are read in the --infer-types locale, and the format can be changed
+    let output = excel_rs_ok(
+        &[
+            "csv",
+            "--infer-types",
+            "--decimal-sep",
+            ",",
+            "--thousands-sep",
+            ".",
+            "--currency-columns",
+            "1",
+            "--currency-format",
+            "#,##0.00 [$€-407]",
+            "--out",
+            "-",
+        ],
+        b"Item,Price\nTea,\"1.234,50\"\n",
+    );
+    let xml = read_part(&output.stdout, "xl/worksheets/sheet1.xml");
+    assert!(
+        xml.contains(r#"<c r="B2" t="n" s="2"><v>1234.50</v></c>"#),
+        "{xml}"
+    );
+    let styles = read_part(&output.stdout, "xl/styles.xml");
+    assert!(
+        styles.contains(r##"formatCode="#,##0.00 [$€-407]""##),
+        "{styles}"
+    );
+
+    let output = excel_rs(
+        &["csv", "--currency-columns", "1", "--out", "-"],
+        b"Item,Price\nTea,5\nScone,n/a\n",
+    );
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains(
+            r#"--currency-columns column 1 holds "n/a" in data row 2, which isn't a number"#
+        ),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = excel_rs(
+        &[
+            "csv",
+            "--currency-columns",
+            "1",
+            "--bool-columns",
+            "1",
+            "--out",
+            "-",
+        ],
+        csv,
+    );
+    assert!(!output.status.success());
+    let output = excel_rs(
+        &[
+            "csv",
+            "--currency-columns",
+            "1",
+            "--format",
+            "xls",
+            "--out",
+            "-",
+        ],
+        csv,
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn format_xls_writes_a_legacy_workbook() {
+    let dir = temp_dir("format-xls");
+    let csv = b"Name,Age,Joined,Active\nAda,36,2024-01-31,TRUE\nGrace,,2023-01-01,false\n";
+
+    // The format is taken from --format on stdout, and from the extension of a file
+    let stdout = excel_rs_ok(
+        &["csv", "--out", "-", "--format", "xls", "--infer-types"],
+        csv,
+    );
+    let out = dir.join("out.xls");
+    let out = out.to_str().unwrap();
+    excel_rs_ok(&["csv", "--out", out, "--infer-types"], csv);
+
+    for bytes in [stdout.stdout, fs::read(out).unwrap()] {
+        let mut workbook: Xls<_> = open_workbook_from_rs(Cursor::new(bytes)).unwrap();
+        let range = workbook.worksheet_range("Sheet 1").unwrap();
+        let rows: Vec<Vec<Data>> = range.rows().map(|x| x.to_vec()).collect();
+
+        let names = ["Name", "Age", "Joined", "Active"];
+        assert_eq!(rows[0], names.map(|x| Data::String(x.to_string())));
+        assert_eq!(
+            rows[1][..2],
+            [Data::String("Ada".to_string()), Data::Float(36.0)]
+        );
+        assert!(matches!(&rows[1][2], Data::DateTime(x) if x.as_f64() == 45322.0));
+        assert_eq!(rows[1][3], Data::Bool(true));
+        assert_eq!(
+            rows[2][..2],
+            [Data::String("Grace".to_string()), Data::Empty]
+        );
+        assert!(matches!(&rows[2][2], Data::DateTime(x) if x.as_f64() == 44927.0));
+        assert_eq!(rows[2][3], Data::Bool(false));
+    }
+}
+
+#[test]
+fn password_decrypts_an_encrypted_workbook() {
+    let xlsx = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../crates/excel-rs-xlsx/tests/fixtures/encrypted.xlsx"
+    );
+
+    let output = excel_rs_ok(&["xlsx2csv", "--in", xlsx, "--password", "excel-rs"], b"");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Name,Age\nAda,36\nGrace,85.5\n"
+    );
+
+    for (args, error) in [
+        (vec![], "the workbook is encrypted, so it needs a password"),
+        (vec!["--password", "wrong"], "the password is wrong"),
+    ] {
+        let output = excel_rs(
+            &[&["xlsx2csv", "--in", xlsx], args.as_slice()].concat(),
+            b"",
+        );
+        assert!(!output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr).trim_end(),
+            format!("error: can't read {xlsx} as xlsx: {error}")
+        );
+    }
+}
@@ -1,39 +1,463 @@
-use std::io::Read;
+use std::{
+    collections::VecDeque,
+    io::{Read, Result},
+};
 
-use csv::{ByteRecord, Reader};
+pub use csv::{ByteRecord, Reader, WriterBuilder};
 
+pub const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// A leading UTF-8 BOM is stripped, so it never ends up in the first header. Records
+/// may have a different number of fields than the header.
 pub fn bytes_to_csv<V: Read>(bytes: V) -> Reader<V> {
-    csv::ReaderBuilder::new().from_reader(bytes)
+    bytes_to_csv_with_delimiter(bytes, b',')
 }
 
-pub fn get_headers<V: Read>(reader: &mut Reader<V>) -> Option<&ByteRecord> {
-    match reader.byte_headers() {
-        Ok(record) => Some(record),
-        Err(_) => None,
+/// Like `bytes_to_csv`, but fields are separated by `delimiter`, e.g. `b'\t'` for TSV or
+/// `b';'` for exports from locales that use a decimal comma.
+pub fn bytes_to_csv_with_delimiter<V: Read>(bytes: V, delimiter: u8) -> Reader<V> {
+    csv::ReaderBuilder::new()
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(bytes)
+}
+
+/// Like `bytes_to_csv`, but a leading UTF-8 BOM is kept as part of the first header.
+pub fn bytes_to_csv_keep_bom<V: Read>(bytes: V) -> Reader<KeepBom<V>> {
+    bytes_to_csv(KeepBom {
+        inner: bytes,
+        started: false,
+    })
+}
+
+/// Prepends a BOM for the csv parser to strip, leaving the input's own BOM in place.
+pub struct KeepBom<V: Read> {
+    inner: V,
+    started: bool,
+}
+
+impl<V: Read> Read for KeepBom<V> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.started {
+            return self.inner.read(buf);
+        }
+        // Only the first read can carry the BOM, or it would land mid-stream
+        self.started = true;
+        // The extra BOM has to arrive in the same read as the data behind it, as the
+        // parser takes a read that is empty after stripping to be the end of input
+        if buf.len() <= UTF8_BOM.len() {
+            return self.inner.read(buf);
+        }
+
+        let n = self.inner.read(&mut buf[UTF8_BOM.len()..])?;
+        buf[..UTF8_BOM.len()].copy_from_slice(UTF8_BOM);
+        Ok(n + UTF8_BOM.len())
     }
 }
 
-pub fn get_next_record<V: Read>(reader: &mut Reader<V>) -> Option<ByteRecord> {
-    let mut record = csv::ByteRecord::new();
-    match reader.read_byte_record(&mut record) {
-        Ok(status) => {
-            if status {
-                Some(record)
-            } else {
-                None
+/// Streams records from any reader, e.g. a `File` or stdin, without loading the whole
+/// input into memory. Iterating yields the records after the header row until the end of
+/// the input. A read or parse error is yielded as an `Err` and ends the iteration. Blank
+/// lines are skipped unless `set_keep_blank_lines` is called.
+pub struct CsvReader<V: Read> {
+    reader: Reader<BlankLines<V>>,
+    blank_lines: u64,
+    held: Option<ByteRecord>,
+    failed: bool,
+}
+
+impl<V: Read> CsvReader<V> {
+    pub fn from_reader(reader: V) -> Self {
+        CsvReader::new(reader)
+    }
+
+    fn new(reader: V) -> Self {
+        CsvReader {
+            reader: bytes_to_csv(BlankLines::new(reader)),
+            blank_lines: 0,
+            held: None,
+            failed: false,
+        }
+    }
+
+    pub fn headers(&mut self) -> Result<&ByteRecord> {
+        read_headers(&mut self.reader)
+    }
+
+    /// Yields an empty record for each blank line between records, so records keep
+    /// their place in the file. Blank lines before the header or at the end of the input
+    /// are still skipped. Must be called before the headers are read.
+    pub fn set_keep_blank_lines(&mut self) {
+        self.reader.get_mut().enabled = true;
+    }
+
+    /// Separates fields by `delimiter` instead of a comma. Must be called before the
+    /// headers are read.
+    pub fn with_delimiter(self, delimiter: u8) -> Self {
+        let mut inner = self.reader.into_inner();
+        inner.delimiter = delimiter;
+        CsvReader {
+            reader: bytes_to_csv_with_delimiter(inner, delimiter),
+            ..self
+        }
+    }
+}
+
+impl<V: Read> CsvReader<KeepBom<V>> {
+    /// Like `from_reader`, but a leading UTF-8 BOM is kept as part of the first header.
+    pub fn from_reader_keep_bom(reader: V) -> Self {
+        CsvReader::new(KeepBom {
+            inner: reader,
+            started: false,
+        })
+    }
+}
+
+impl<V: Read> Iterator for CsvReader<V> {
+    type Item = Result<ByteRecord>;
+
+    fn next(&mut self) -> Option<Result<ByteRecord>> {
+        if self.blank_lines > 0 {
+            self.blank_lines -= 1;
+            return Some(Ok(ByteRecord::new()));
+        }
+        if let Some(record) = self.held.take() {
+            return Some(Ok(record));
+        }
+        // The parser may not be able to pick up after an error, so nothing follows it
+        if self.failed {
+            return None;
+        }
+
+        let record = match read_next_record(&mut self.reader) {
+            Ok(record) => record?,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
             }
+        };
+        let index = record.position().map_or(0, |x| x.record());
+        let blank_lines = self.reader.get_mut().blank_lines_before(index);
+        if blank_lines == 0 {
+            return Some(Ok(record));
         }
-        Err(_) => None,
+
+        self.blank_lines = blank_lines - 1;
+        self.held = Some(record);
+        Some(Ok(ByteRecord::new()))
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
+#[derive(Clone, Copy, PartialEq)]
+enum LineState {
+    StartRecord,
+    StartField,
+    InField,
+    InQuotes,
+    // A quote inside quotes, either closing them or the first of an escaped pair
+    QuoteInQuotes,
+}
 
-//     #[test]
-//     fn it_works() {
-//         let result = add(2, 2);
-//         assert_eq!(result, 4);
-//     }
-// }
+/// Counts the blank lines the csv parser skips between records, by reading the input with
+/// the same quoting rules as the parser. Passes the input through untouched unless
+/// enabled.
+pub struct BlankLines<V: Read> {
+    inner: V,
+    enabled: bool,
+    delimiter: u8,
+    state: LineState,
+    last_cr: bool,
+    count: u64,
+    records: u64,
+    // Records that follow blank lines, as (record index, blank lines) with the header at 0
+    found: VecDeque<(u64, u64)>,
+}
+
+impl<V: Read> BlankLines<V> {
+    fn new(inner: V) -> Self {
+        BlankLines {
+            inner,
+            enabled: false,
+            delimiter: b',',
+            state: LineState::StartRecord,
+            last_cr: false,
+            count: 0,
+            records: 0,
+            found: VecDeque::new(),
+        }
+    }
+
+    // The input is read ahead of the parser, so counts for later records may be queued
+    fn blank_lines_before(&mut self, record: u64) -> u64 {
+        while let Some((index, count)) = self.found.front().copied() {
+            if index > record {
+                break;
+            }
+            self.found.pop_front();
+            if index == record {
+                return count;
+            }
+        }
+        0
+    }
+
+    fn scan(&mut self, buf: &[u8]) {
+        let delimiter = self.delimiter;
+        let mut i = 0;
+        while i < buf.len() {
+            // Most bytes are inside fields, so skip ahead to the next one that matters
+            let skip = match self.state {
+                LineState::InField => memchr::memchr3(delimiter, b'\r', b'\n', &buf[i..]),
+                LineState::InQuotes => memchr::memchr(b'"', &buf[i..]),
+                _ => Some(0),
+            };
+            let Some(skip) = skip else {
+                break;
+            };
+            let byte = &buf[i + skip];
+            i += skip + 1;
+
+            self.state = match (self.state, *byte) {
+                (LineState::StartRecord, b'\r') => {
+                    self.count += 1;
+                    self.last_cr = true;
+                    continue;
+                }
+                (LineState::StartRecord, b'\n') => {
+                    // The second half of a \r\n was counted with the \r
+                    if !self.last_cr {
+                        self.count += 1;
+                    }
+                    self.last_cr = false;
+                    continue;
+                }
+                (LineState::StartRecord, _) => {
+                    if self.count > 0 {
+                        self.found.push_back((self.records, self.count));
+                    }
+                    self.records += 1;
+                    match *byte {
+                        b'"' => LineState::InQuotes,
+                        x if x == delimiter => LineState::StartField,
+                        _ => LineState::InField,
+                    }
+                }
+                (LineState::InQuotes, b'"') => LineState::QuoteInQuotes,
+                (LineState::InQuotes, _) => LineState::InQuotes,
+                (LineState::QuoteInQuotes, b'"') => LineState::InQuotes,
+                (LineState::StartField, b'"') => LineState::InQuotes,
+                (_, x) if x == delimiter => LineState::StartField,
+                (_, b'\r' | b'\n') => {
+                    // The record's own line ending isn't a blank line
+                    self.count = 0;
+                    self.last_cr = *byte == b'\r';
+                    LineState::StartRecord
+                }
+                _ => LineState::InField,
+            };
+        }
+    }
+}
+
+impl<V: Read> Read for BlankLines<V> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        if self.enabled {
+            self.scan(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// Reads the header row, which is empty if the input is.
+pub fn read_headers<V: Read>(reader: &mut Reader<V>) -> Result<&ByteRecord> {
+    Ok(reader.byte_headers()?)
+}
+
+/// Reads the next record, or returns None at the end of the input.
+pub fn read_next_record<V: Read>(reader: &mut Reader<V>) -> Result<Option<ByteRecord>> {
+    let mut record = csv::ByteRecord::new();
+    match reader.read_byte_record(&mut record)? {
+        true => Ok(Some(record)),
+        false => Ok(None),
+    }
+}
+
+/// Reads the header row, or returns None if it can't be read.
+#[deprecated(note = "read errors are lost, use `read_headers`, which returns them")]
+pub fn get_headers<V: Read>(reader: &mut Reader<V>) -> Option<&ByteRecord> {
+    read_headers(reader).ok()
+}
+
+/// Reads the next record, or returns None at the end of the input or if it can't be
+/// read.
+#[deprecated(note = "read errors end the records silently, use `read_next_record`")]
+pub fn get_next_record<V: Read>(reader: &mut Reader<V>) -> Option<ByteRecord> {
+    read_next_record(reader).ok().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &[u8] = b"\xef\xbb\xbfName,Age\nAda,36\n";
+
+    #[test]
+    fn bom_is_stripped_from_the_first_header() {
+        let mut reader = bytes_to_csv(CSV);
+        assert_eq!(read_headers(&mut reader).unwrap(), vec!["Name", "Age"]);
+
+        let mut reader = CsvReader::from_reader(CSV);
+        assert_eq!(reader.headers().unwrap(), vec!["Name", "Age"]);
+        assert_eq!(reader.next().unwrap().unwrap(), vec!["Ada", "36"]);
+    }
+
+    #[test]
+    fn bom_can_be_kept() {
+        let mut reader = bytes_to_csv_keep_bom(CSV);
+        assert_eq!(
+            read_headers(&mut reader).unwrap(),
+            vec!["\u{feff}Name", "Age"]
+        );
+
+        let mut reader = CsvReader::from_reader_keep_bom(CSV);
+        assert_eq!(reader.headers().unwrap(), vec!["\u{feff}Name", "Age"]);
+        assert_eq!(reader.next().unwrap().unwrap(), vec!["Ada", "36"]);
+    }
+
+    #[test]
+    fn records_are_streamed_from_a_reader() {
+        let mut csv = b"id,name,amount\n".to_vec();
+        for i in 0..100_000 {
+            csv.extend_from_slice(format!("{i},\"name, {i}\",{i}.5\n").as_bytes());
+        }
+        assert!(csv.len() > 2_000_000);
+
+        let mut reader = CsvReader::from_reader(csv.as_slice());
+        assert_eq!(reader.headers().unwrap(), vec!["id", "name", "amount"]);
+
+        let mut count = 0;
+        for record in reader {
+            let record = record.unwrap();
+            assert_eq!(record[0], *count.to_string().as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 100_000);
+    }
+
+    // Fails once `ok` bytes have been read
+    struct FailingReader {
+        data: &'static [u8],
+        ok: usize,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            if self.ok == 0 {
+                return Err(std::io::Error::other("disk on fire"));
+            }
+            let n = buf.len().min(self.ok).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            self.ok -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn read_errors_are_yielded_and_end_the_records() {
+        let mut reader = CsvReader::from_reader(FailingReader {
+            data: b"a,b\n1,2\n3,4\n",
+            ok: 8,
+        });
+        assert_eq!(reader.headers().unwrap(), vec!["a", "b"]);
+
+        let records: Vec<Result<ByteRecord>> = reader.collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].as_ref().unwrap(), &vec!["1", "2"]);
+        assert_eq!(records[1].as_ref().unwrap_err().to_string(), "disk on fire");
+    }
+
+    #[test]
+    fn header_read_errors_are_returned() {
+        let mut reader = CsvReader::from_reader(FailingReader { data: b"", ok: 0 });
+        assert!(reader.headers().is_err());
+
+        let mut reader = bytes_to_csv(FailingReader { data: b"", ok: 0 });
+        assert!(read_headers(&mut reader).is_err());
+
+        let mut reader = bytes_to_csv(FailingReader { data: b"", ok: 0 });
+        assert!(read_next_record(&mut reader).is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_readers_return_none_on_errors() {
+        let mut reader = bytes_to_csv(CSV);
+        assert_eq!(get_headers(&mut reader).unwrap(), vec!["Name", "Age"]);
+        assert_eq!(get_next_record(&mut reader).unwrap(), vec!["Ada", "36"]);
+        assert_eq!(get_next_record(&mut reader), None);
+
+        let mut reader = bytes_to_csv(FailingReader { data: b"", ok: 0 });
+        assert_eq!(get_headers(&mut reader), None);
+        assert_eq!(get_next_record(&mut reader), None);
+    }
+
+    #[test]
+    fn kept_bom_is_only_added_by_the_first_read() {
+        let mut input = KeepBom {
+            inner: &b"Name,Age\n"[..],
+            started: false,
+        };
+        // Too small to carry the BOM with data, so it is passed through as it is
+        let mut buf = [0; 3];
+        assert_eq!(input.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"Nam");
+
+        let mut buf = [0; 64];
+        let n = input.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"e,Age\n");
+    }
+
+    fn records_keeping_blank_lines(csv: &[u8]) -> Vec<ByteRecord> {
+        let mut reader = CsvReader::from_reader(csv);
+        reader.set_keep_blank_lines();
+        reader.headers().unwrap();
+        reader.map(|x| x.unwrap()).collect()
+    }
+
+    #[test]
+    fn blank_lines_between_records_are_kept() {
+        let empty = ByteRecord::new();
+        for csv in [
+            &b"Name\n\nAda\n\n\nAlan\n\n"[..],
+            b"\nName\r\n\r\nAda\r\n\r\n\r\nAlan",
+        ] {
+            assert_eq!(
+                records_keeping_blank_lines(csv),
+                vec![
+                    empty.clone(),
+                    ByteRecord::from(vec!["Ada"]),
+                    empty.clone(),
+                    empty.clone(),
+                    ByteRecord::from(vec!["Alan"])
+                ]
+            );
+        }
+
+        // Blank lines inside a quoted field are part of it
+        assert_eq!(
+            records_keeping_blank_lines(b"Note\n\"a\n\nb\"\n\nc\n"),
+            vec![
+                ByteRecord::from(vec!["a\n\nb"]),
+                empty.clone(),
+                ByteRecord::from(vec!["c"])
+            ]
+        );
+
+        // Skipped by default
+        let reader = CsvReader::from_reader(&b"Name\n\nAda\n"[..]);
+        let records: Vec<ByteRecord> = reader.map(|x| x.unwrap()).collect();
+        assert_eq!(records, vec![ByteRecord::from(vec!["Ada"])]);
+    }
+}
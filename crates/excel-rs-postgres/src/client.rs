@@ -1,16 +1,28 @@
-use anyhow::Result;
-use postgres::{Client, NoTls, RowIter};
+use std::io::Cursor;
+
+use anyhow::{bail, Result};
+use excel_rs_xlsx::WorkBook;
+use postgres::{
+    fallible_iterator::FallibleIterator, Client, NoTls, Portal, Row, RowIter, Transaction,
+};
 use rustls::ClientConfig;
 use tokio_postgres_rustls::MakeRustlsConnect;
 
 use crate::ssl::SkipServerVerification;
+use crate::{cell_type, write_typed_row};
+
+/// Rows fetched per round trip when exporting a query.
+///
+/// Small values spend more time waiting on the network, large values hold more rows in
+/// memory at once. Tune it to the row width and the latency to the server.
+pub const DEFAULT_FETCH_SIZE: i32 = 10_000;
 
 pub struct PostgresClient {
     client: Client,
 }
 
 impl PostgresClient {
-    pub fn new(conn_string: &str) -> PostgresClient {
+    pub fn new(conn_string: &str) -> Result<PostgresClient> {
         let config = ClientConfig::builder()
             .with_root_certificates(rustls::RootCertStore::empty())
             .with_no_client_auth();
@@ -31,20 +43,20 @@ impl PostgresClient {
 
                 let tls = MakeRustlsConnect::new(config);
 
-                match Client::connect(&conn_string, tls) {
+                match Client::connect(conn_string, tls) {
                     Ok(c) => c,
                     Err(_) => {
                         // Attempt no SSL
-                        match Client::connect(&conn_string, NoTls) {
+                        match Client::connect(conn_string, NoTls) {
                             Ok(c) => c,
-                            Err(e) => panic!("Couldn't connec to server: {e}"),
+                            Err(e) => bail!("Couldn't connect to server: {e}"),
                         }
                     }
                 }
             }
         };
 
-        PostgresClient { client }
+        Ok(PostgresClient { client })
     }
 
     pub fn make_query(&mut self, query: &str, params: Vec<String>) -> Result<RowIter<'_>> {
@@ -52,8 +64,131 @@ impl PostgresClient {
         Ok(iter)
     }
 
+    /// Runs `query` through a server-side portal and writes the result to an xlsx,
+    /// fetching `fetch_size` rows per round trip (see [`DEFAULT_FETCH_SIZE`]). Columns are
+    /// typed by their Postgres type (see [`cell_type`]), and the column names are written
+    /// as the header, kept in view while scrolling if `freeze_top_row` is set.
+    pub fn get_xlsx_from_query(
+        &mut self,
+        query: &str,
+        fetch_size: i32,
+        numeric_as_number: bool,
+        freeze_top_row: bool,
+    ) -> Result<Vec<u8>> {
+        if fetch_size <= 0 {
+            bail!("fetch_size must be positive, got {fetch_size}");
+        }
+
+        let mut transaction = self.client.transaction()?;
+        let statement = transaction.prepare(query)?;
+        let portal = transaction.bind(&statement, &[])?;
+
+        let output_buffer = vec![];
+        let mut workbook = WorkBook::new(Cursor::new(output_buffer));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        if freeze_top_row {
+            worksheet.freeze_top_row();
+        }
+
+        let headers: Vec<&[u8]> = statement
+            .columns()
+            .iter()
+            .map(|x| x.name().as_bytes())
+            .collect();
+        let col_types: Vec<&str> = statement
+            .columns()
+            .iter()
+            .map(|x| cell_type(x.type_(), numeric_as_number))
+            .collect();
+
+        worksheet.write_row(headers, &col_types)?;
+
+        fetch_portal(&mut transaction, &portal, fetch_size, |row| {
+            write_typed_row(&mut worksheet, row, &col_types)
+        })?;
+
+        worksheet.close()?;
+        transaction.commit()?;
+
+        let final_buffer = workbook.finish()?;
+
+        Ok(final_buffer.into_inner())
+    }
+
     pub fn close(self) -> Result<()> {
         self.client.close()?;
         Ok(())
     }
 }
+
+/// Passes every row of `portal` to `write`, fetching `fetch_size` rows per round trip,
+/// and returns the number of round trips made.
+fn fetch_portal(
+    transaction: &mut Transaction<'_>,
+    portal: &Portal,
+    fetch_size: i32,
+    mut write: impl FnMut(&Row) -> Result<()>,
+) -> Result<u32> {
+    let mut round_trips = 0;
+
+    loop {
+        let mut iter = transaction.query_portal_raw(portal, fetch_size)?;
+        let mut fetched = 0;
+        round_trips += 1;
+
+        while let Some(row) = iter.next()? {
+            write(&row)?;
+            fetched += 1;
+        }
+
+        if fetched < fetch_size {
+            return Ok(round_trips);
+        }
+    }
+}
+
+// These need a server, so they're ignored unless run with `--ignored` and a connection
+// string in EXCEL_RS_TEST_POSTGRES, e.g. "host=localhost user=postgres"
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect() -> PostgresClient {
+        let conn_string = std::env::var("EXCEL_RS_TEST_POSTGRES")
+            .expect("EXCEL_RS_TEST_POSTGRES should hold a connection string");
+        PostgresClient::new(&conn_string).unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn cursor_fetches_fetch_size_rows_per_round_trip() {
+        let mut client = connect();
+        let mut transaction = client.client.transaction().unwrap();
+        let statement = transaction
+            .prepare("SELECT generate_series(1, 25)")
+            .unwrap();
+
+        for (fetch_size, expected) in [(10, 3), (25, 2), (100, 1)] {
+            let portal = transaction.bind(&statement, &[]).unwrap();
+            let mut rows = Vec::new();
+            let round_trips = fetch_portal(&mut transaction, &portal, fetch_size, |row| {
+                rows.push(row.get::<_, i32>(0));
+                Ok(())
+            })
+            .unwrap();
+
+            assert_eq!(round_trips, expected, "fetch_size {fetch_size}");
+            assert_eq!(rows, (1..=25).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn fetch_size_must_be_positive() {
+        let mut client = connect();
+        let err = client
+            .get_xlsx_from_query("SELECT 1", 0, false, false)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "fetch_size must be positive, got 0");
+    }
+}
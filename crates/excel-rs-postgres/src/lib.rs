@@ -2,55 +2,261 @@ mod client;
 mod sql_impl;
 mod ssl;
 
-use std::io::Cursor;
+use std::io::{Cursor, Seek, Write};
 
 use anyhow::Result;
-pub use client::PostgresClient;
-use excel_rs_xlsx::WorkBook;
+pub use client::{PostgresClient, DEFAULT_FETCH_SIZE};
+use excel_rs_xlsx::{
+    typed_sheet::{TypedSheet, TYPE_BLANK, TYPE_DATE, TYPE_NUMBER, TYPE_STRING},
+    WorkBook,
+};
 pub use postgres::fallible_iterator::FallibleIterator;
-use postgres::RowIter;
+use postgres::{types::Type, Column, Row, RowIter};
 pub use sql_impl::{ExcelBytes, ExcelBytesBorrowed};
 
-pub fn postgres_to_xlsx<'a>(mut iter: RowIter<'a>) -> Result<Vec<u8>> {
+const TYPE_BOOL: &str = "b";
+
+/// Writes the rows of `iter` to an xlsx under a header of `columns`, the query's columns
+/// (e.g. from `Statement::columns`), so a query returning no rows still gets its header.
+/// Each column is typed by its Postgres type (see [`cell_type`]), with `numeric`
+/// columns written as text.
+pub fn postgres_to_xlsx(columns: &[Column], mut iter: RowIter<'_>) -> Result<Vec<u8>> {
     let output_buffer = vec![];
     let mut workbook = WorkBook::new(Cursor::new(output_buffer));
-    let mut worksheet = workbook.get_worksheet(String::from("Sheet 1"));
+    let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+
+    let headers: Vec<&[u8]> = columns.iter().map(|x| x.name().as_bytes()).collect();
+    let col_types: Vec<&str> = columns
+        .iter()
+        .map(|x| cell_type(x.type_(), false))
+        .collect();
 
-    let headers = iter.next().ok().unwrap().unwrap();
-    let len = headers.len();
+    worksheet.write_row(headers, &col_types)?;
 
-    // TODO: Add if len == 0 check
+    while let Some(row) = iter.next()? {
+        write_typed_row(&mut worksheet, &row, &col_types)?;
+    }
 
-    // Write headers
-    let mut row_vec: Vec<&[u8]> = vec![&[]; len];
+    worksheet.close()?;
 
-    for col in 0..len {
-        let column = headers.columns().get(col).unwrap();
-        row_vec[col] = column.name().as_bytes();
+    let final_buffer = workbook.finish()?;
+
+    Ok(final_buffer.into_inner())
+}
+
+/// The xlsx cell type a column of `pg_type` is written as: ints and floats as numbers,
+/// dates and timestamps as dates, bools as booleans and anything else as text. `numeric`
+/// columns are numbers if `numeric_as_number` is set, otherwise text, which keeps any
+/// digits past the 15 Excel can hold.
+pub fn cell_type(pg_type: &Type, numeric_as_number: bool) -> &'static str {
+    match *pg_type {
+        Type::INT2 | Type::INT4 | Type::INT8 | Type::FLOAT4 | Type::FLOAT8 => TYPE_NUMBER,
+        Type::NUMERIC if numeric_as_number => TYPE_NUMBER,
+        Type::DATE | Type::TIMESTAMP | Type::TIMESTAMPTZ => TYPE_DATE,
+        Type::BOOL => TYPE_BOOL,
+        _ => TYPE_STRING,
     }
+}
 
-    worksheet.write_row(row_vec)?;
+pub(crate) fn write_typed_row<W: Write + Seek>(
+    worksheet: &mut TypedSheet<'_, W>,
+    row: &Row,
+    col_types: &[&str],
+) -> Result<()> {
+    let cells = row_to_cells(row, col_types.len());
 
-    while let Some(row) = iter.next()? {
-        let mut row_vec: Vec<Box<[u8]>> = vec![Box::from([]); len];
-
-        for col in 0..len {
-            if let Ok(bytes) = row.try_get::<usize, ExcelBytesBorrowed>(col) {
-                row_vec[col] = Box::from(bytes.0);
-            } else if let Ok(bytes) = row.try_get::<usize, ExcelBytes>(col) {
-                let asdasd = bytes.0;
-                row_vec[col] = asdasd
-            }
+    // NULLs are left blank, and NaN and infinity, which xlsx has no number for, are text
+    let types: Vec<&str> = cells
+        .iter()
+        .zip(col_types)
+        .map(|(cell, col_type)| match *col_type {
+            _ if cell.is_empty() => TYPE_BLANK,
+            TYPE_NUMBER if !is_finite(cell) => TYPE_STRING,
+            col_type => col_type,
+        })
+        .collect();
+
+    worksheet.write_row(cells.iter().map(|x| x.as_ref()).collect(), &types)
+}
+
+fn is_finite(cell: &[u8]) -> bool {
+    std::str::from_utf8(cell)
+        .ok()
+        .and_then(|x| x.parse::<f64>().ok())
+        .is_some_and(f64::is_finite)
+}
+
+// A NULL of any type is read as None and left as an empty cell, which write_typed_row
+// writes blank rather than as text
+pub(crate) fn row_to_cells(row: &Row, len: usize) -> Vec<Box<[u8]>> {
+    let mut row_vec: Vec<Box<[u8]>> = vec![Box::from([]); len];
+
+    for (col, cell) in row_vec.iter_mut().enumerate() {
+        if let Ok(bytes) = row.try_get::<usize, Option<ExcelBytesBorrowed>>(col) {
+            *cell = bytes.map_or(Box::from([]), |x| Box::from(x.0));
+        } else if let Ok(bytes) = row.try_get::<usize, Option<ExcelBytes>>(col) {
+            *cell = bytes.map_or(Box::from([]), |x| x.0);
+        }
+    }
+
+    row_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use excel_rs_xlsx::testing::{read_cells, read_part, ParsedCell};
+    use postgres::{Client, NoTls};
+
+    use super::*;
+
+    // Needs a server, e.g. EXCEL_RS_TEST_POSTGRES="host=localhost user=postgres"
+    fn conn_string() -> String {
+        std::env::var("EXCEL_RS_TEST_POSTGRES")
+            .expect("EXCEL_RS_TEST_POSTGRES should hold a connection string")
+    }
+
+    fn query_to_xlsx(client: &mut Client, query: &str) -> Result<Vec<u8>> {
+        let statement = client.prepare(query)?;
+        let iter = client.query_raw(&statement, Vec::<String>::new())?;
+        postgres_to_xlsx(statement.columns(), iter)
+    }
+
+    #[test]
+    fn cell_types_follow_postgres_types() {
+        for pg_type in [
+            Type::INT2,
+            Type::INT4,
+            Type::INT8,
+            Type::FLOAT4,
+            Type::FLOAT8,
+        ] {
+            assert_eq!(cell_type(&pg_type, false), TYPE_NUMBER);
+        }
+        for pg_type in [Type::DATE, Type::TIMESTAMP, Type::TIMESTAMPTZ] {
+            assert_eq!(cell_type(&pg_type, false), TYPE_DATE);
+        }
+        assert_eq!(cell_type(&Type::BOOL, false), TYPE_BOOL);
+        assert_eq!(cell_type(&Type::NUMERIC, false), TYPE_STRING);
+        assert_eq!(cell_type(&Type::NUMERIC, true), TYPE_NUMBER);
+        assert_eq!(cell_type(&Type::TEXT, false), TYPE_STRING);
+        assert_eq!(cell_type(&Type::JSON, false), TYPE_STRING);
+    }
+
+    #[test]
+    #[ignore]
+    fn every_column_type_is_written_as_its_cell_type() {
+        let query = "SELECT 1::int2 AS i2, 2::int4 AS i4, 3::int8 AS i8, 1.5::float4 AS f4, \
+                     2.5::float8 AS f8, 1.25::numeric AS num, '2024-01-02'::date AS d, \
+                     '2024-01-02 06:00'::timestamp AS ts, '2024-01-02 06:00+00'::timestamptz AS tz, \
+                     true AS b, 'x'::text AS t \
+                     UNION ALL SELECT NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL";
+        let mut client = PostgresClient::new(&conn_string()).unwrap();
+
+        let bytes = client
+            .get_xlsx_from_query(query, 100, false, false)
+            .unwrap();
+        let xml = read_part(&bytes, "xl/worksheets/sheet1.xml");
+        for cell in [
+            r#"<c r="A2" t="n"><v>1</v></c>"#,
+            r#"<c r="C2" t="n"><v>3</v></c>"#,
+            r#"<c r="D2" t="n"><v>1.5</v></c>"#,
+            r#"<c r="E2" t="n"><v>2.5</v></c>"#,
+            r#"<c r="F2" t="str"><v>1.25</v></c>"#,
+            r#"<c r="G2" t="n" s="1"><v>45293</v></c>"#,
+            r#"<c r="J2" t="b"><v>1</v></c>"#,
+            r#"<c r="K2" t="str"><v>x</v></c>"#,
+            // NULLs are left blank whatever the column's type
+            r#"<c r="A3"/>"#,
+            r#"<c r="G3"/>"#,
+            r#"<c r="K3"/>"#,
+        ] {
+            assert!(xml.contains(cell), "{cell} in {xml}");
         }
+        let cells = read_cells(&bytes, "Sheet 1");
+        assert_eq!(cells[1][7], ParsedCell::Date(45293.25));
+        assert_eq!(cells[1][8], ParsedCell::Date(45293.25));
+
+        let bytes = client.get_xlsx_from_query(query, 100, true, false).unwrap();
+        let xml = read_part(&bytes, "xl/worksheets/sheet1.xml");
+        assert!(xml.contains(r#"<c r="F2" t="n"><v>1.25</v></c>"#), "{xml}");
+    }
 
-        let new_vec: Vec<&[u8]> = row_vec.iter().map(|x| x.as_ref()).collect();
+    #[test]
+    #[ignore]
+    fn every_row_is_written_under_the_header() {
+        let mut client = Client::connect(&conn_string(), NoTls).unwrap();
 
-        worksheet.write_row(new_vec)?;
+        let bytes = query_to_xlsx(&mut client, "SELECT generate_series(1, 3) AS n").unwrap();
+        let cells = read_cells(&bytes, "Sheet 1");
+        let number = |x| vec![ParsedCell::Number(x)];
+        assert_eq!(
+            cells,
+            vec![
+                vec![ParsedCell::String(String::from("n"))],
+                number(1.0),
+                number(2.0),
+                number(3.0)
+            ]
+        );
+
+        // No rows, just the header
+        let bytes = query_to_xlsx(&mut client, "SELECT 1 AS n WHERE false").unwrap();
+        assert_eq!(
+            read_cells(&bytes, "Sheet 1"),
+            vec![vec![ParsedCell::String(String::from("n"))]]
+        );
     }
 
-    worksheet.close()?;
+    #[test]
+    #[ignore]
+    fn query_errors_are_returned() {
+        let mut client = Client::connect(&conn_string(), NoTls).unwrap();
 
-    let final_buffer = workbook.finish()?;
+        // Fails on the third row, after the first were sent
+        let query = "SELECT 1 / (3 - n) AS x FROM generate_series(1, 5) AS n";
+        let err = query_to_xlsx(&mut client, query).unwrap_err();
+        assert!(err.to_string().contains("division by zero"), "{err}");
+    }
 
-    Ok(final_buffer.into_inner())
+    #[test]
+    #[ignore]
+    fn nulls_in_a_table_are_empty_cells() {
+        let mut client = Client::connect(&conn_string(), NoTls).unwrap();
+        client
+            .batch_execute(
+                "CREATE TEMPORARY TABLE nullable (i int4, f float8, t text, d date); \
+                 INSERT INTO nullable VALUES (1, 1.5, 'x', '2024-01-02'), \
+                 (NULL, NULL, NULL, NULL), (2, NULL, 'NULL', NULL)",
+            )
+            .unwrap();
+
+        let bytes = query_to_xlsx(&mut client, "SELECT * FROM nullable").unwrap();
+        let xml = read_part(&bytes, "xl/worksheets/sheet1.xml");
+        assert!(
+            xml.contains(r#"<row r="3"><c r="A3"/><c r="B3"/><c r="C3"/><c r="D3"/></row>"#),
+            "{xml}"
+        );
+        assert!(!xml.contains("None"), "{xml}");
+
+        let cells = read_cells(&bytes, "Sheet 1");
+        assert_eq!(
+            cells[1],
+            [
+                ParsedCell::Number(1.0),
+                ParsedCell::Number(1.5),
+                ParsedCell::String(String::from("x")),
+                ParsedCell::Date(45293.0)
+            ]
+        );
+        // Only text that really says NULL is written as text
+        assert_eq!(
+            cells[3],
+            [
+                ParsedCell::Number(2.0),
+                ParsedCell::Empty,
+                ParsedCell::String(String::from("NULL")),
+                ParsedCell::Empty
+            ]
+        );
+    }
 }
@@ -1,11 +1,14 @@
 use std::error::Error;
 
-use chrono::DateTime;
+use chrono::{DateTime, Days, NaiveDate};
 use postgres::types::{FromSql, Type};
 use postgres_money::Money;
 use postgres_protocol::types;
 use rust_decimal::Decimal;
 
+// Postgres counts dates and timestamps from 2000-01-01
+const POSTGRES_EPOCH_MICROS: i64 = 946_684_800_000_000;
+
 pub struct ExcelBytesBorrowed<'a>(pub &'a [u8]);
 pub struct ExcelBytes(pub Box<[u8]>);
 
@@ -38,47 +41,50 @@ impl<'a> FromSql<'a> for ExcelBytesBorrowed<'a> {
 impl<'a> FromSql<'a> for ExcelBytes {
     fn from_sql(pg_type: &Type, raw: &'a [u8]) -> Result<ExcelBytes, Box<dyn Error + Sync + Send>> {
         let out: ExcelBytes = match *pg_type {
-            Type::TIMESTAMP => match types::timestamp_from_sql(raw) {
-                Ok(parsed) => {
-                    // println!("{parsed}");
-                    let bytes = Box::from(
-                        format!(
-                            "{}",
-                            DateTime::from_timestamp((parsed / 1000000) + 946684800, 0)
-                                .unwrap()
-                                .format("%Y-%m-%d %r")
-                        )
-                        .as_bytes(),
-                    );
-
-                    ExcelBytes(bytes)
-                }
-                Err(_) => ExcelBytes(Box::from([])),
-            },
+            // Written in UTC for timestamptz. Infinite values are left empty
+            Type::TIMESTAMP | Type::TIMESTAMPTZ => ExcelBytes(
+                match types::timestamp_from_sql(raw)
+                    .ok()
+                    .and_then(|x| x.checked_add(POSTGRES_EPOCH_MICROS))
+                    .and_then(DateTime::from_timestamp_micros)
+                {
+                    Some(time) => {
+                        Box::from(time.format("%Y-%m-%d %H:%M:%S%.f").to_string().as_bytes())
+                    }
+                    None => Box::from([]),
+                },
+            ),
+            Type::DATE => ExcelBytes(
+                match types::date_from_sql(raw).ok().and_then(|days| {
+                    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+                    match u64::try_from(days) {
+                        Ok(days) => epoch.checked_add_days(Days::new(days)),
+                        Err(_) => epoch.checked_sub_days(Days::new(days.unsigned_abs() as u64)),
+                    }
+                }) {
+                    Some(date) => Box::from(date.format("%Y-%m-%d").to_string().as_bytes()),
+                    None => Box::from([]),
+                },
+            ),
+            Type::BOOL => ExcelBytes(match types::bool_from_sql(raw) {
+                Ok(true) => Box::from(*b"1"),
+                Ok(false) => Box::from(*b"0"),
+                Err(_) => Box::from([]),
+            }),
             Type::INT2 => ExcelBytes(Box::from(
-                i16::from_be_bytes(raw.try_into().ok().unwrap())
-                    .to_string()
-                    .as_bytes(),
+                i16::from_be_bytes(raw.try_into()?).to_string().as_bytes(),
             )),
             Type::INT4 => ExcelBytes(Box::from(
-                i32::from_be_bytes(raw.try_into().ok().unwrap())
-                    .to_string()
-                    .as_bytes(),
+                i32::from_be_bytes(raw.try_into()?).to_string().as_bytes(),
             )),
             Type::INT8 => ExcelBytes(Box::from(
-                i64::from_be_bytes(raw.try_into().ok().unwrap())
-                    .to_string()
-                    .as_bytes(),
+                i64::from_be_bytes(raw.try_into()?).to_string().as_bytes(),
             )),
             Type::FLOAT4 => ExcelBytes(Box::from(
-                f32::from_be_bytes(raw.try_into().ok().unwrap())
-                    .to_string()
-                    .as_bytes(),
+                f32::from_be_bytes(raw.try_into()?).to_string().as_bytes(),
             )),
             Type::FLOAT8 => ExcelBytes(Box::from(
-                f64::from_be_bytes(raw.try_into().ok().unwrap())
-                    .to_string()
-                    .as_bytes(),
+                f64::from_be_bytes(raw.try_into()?).to_string().as_bytes(),
             )),
             Type::NUMERIC => ExcelBytes(match <Decimal as FromSql>::from_sql(pg_type, raw) {
                 Ok(num) => Box::from(num.to_string().as_bytes()),
@@ -96,6 +102,9 @@ impl<'a> FromSql<'a> for ExcelBytes {
     fn accepts(ty: &Type) -> bool {
         match *ty {
             Type::TIMESTAMP
+            | Type::TIMESTAMPTZ
+            | Type::DATE
+            | Type::BOOL
             | Type::MONEY
             | Type::NUMERIC
             | Type::INT8
@@ -108,3 +117,39 @@ impl<'a> FromSql<'a> for ExcelBytes {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_text(pg_type: &Type, raw: &[u8]) -> String {
+        let bytes = ExcelBytes::from_sql(pg_type, raw).unwrap();
+        String::from_utf8(bytes.0.into_vec()).unwrap()
+    }
+
+    #[test]
+    fn numbers_are_read_from_network_order() {
+        assert_eq!(to_text(&Type::INT2, &(-7i16).to_be_bytes()), "-7");
+        assert_eq!(to_text(&Type::INT4, &42i32.to_be_bytes()), "42");
+        assert_eq!(
+            to_text(&Type::INT8, &i64::MAX.to_be_bytes()),
+            i64::MAX.to_string()
+        );
+        assert_eq!(to_text(&Type::FLOAT4, &1.5f32.to_be_bytes()), "1.5");
+        assert_eq!(to_text(&Type::FLOAT8, &0.1f64.to_be_bytes()), "0.1");
+    }
+
+    #[test]
+    fn numbers_of_the_wrong_width_are_an_error() {
+        for pg_type in [
+            Type::INT2,
+            Type::INT4,
+            Type::INT8,
+            Type::FLOAT4,
+            Type::FLOAT8,
+        ] {
+            let err = ExcelBytes::from_sql(&pg_type, &[0; 3]).err();
+            assert!(err.is_some(), "{pg_type}");
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// BIFF8 record encoding, see [MS-XLS] for the layouts
+
+pub const BOF: u16 = 0x0809;
+pub const EOF: u16 = 0x000A;
+pub const CODEPAGE: u16 = 0x0042;
+pub const WINDOW1: u16 = 0x003D;
+pub const FONT: u16 = 0x0031;
+pub const XF: u16 = 0x00E0;
+pub const STYLE: u16 = 0x0293;
+pub const BOUNDSHEET: u16 = 0x0085;
+pub const SST: u16 = 0x00FC;
+pub const CONTINUE: u16 = 0x003C;
+pub const EXTSST: u16 = 0x00FF;
+pub const COLINFO: u16 = 0x007D;
+pub const DIMENSIONS: u16 = 0x0200;
+pub const LABELSST: u16 = 0x00FD;
+pub const NUMBER: u16 = 0x0203;
+pub const BOOLERR: u16 = 0x0205;
+pub const WINDOW2: u16 = 0x023E;
+
+// Longest record body allowed, anything longer goes in CONTINUE records
+pub const MAX_RECORD_LEN: usize = 8224;
+
+pub const BOF_GLOBALS: u16 = 0x0005;
+pub const BOF_WORKSHEET: u16 = 0x0010;
+
+pub fn write_record(out: &mut Vec<u8>, record_type: u16, data: &[u8]) {
+    out.extend_from_slice(&record_type.to_le_bytes());
+    out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+pub fn write_bof(out: &mut Vec<u8>, dt: u16) {
+    let mut data = Vec::with_capacity(16);
+    data.extend_from_slice(&0x0600u16.to_le_bytes()); // BIFF8
+    data.extend_from_slice(&dt.to_le_bytes());
+    data.extend_from_slice(&0x0DBBu16.to_le_bytes()); // build
+    data.extend_from_slice(&0x07CCu16.to_le_bytes()); // year
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&6u32.to_le_bytes());
+    write_record(out, BOF, &data);
+}
+
+/// Encodes `text` the way BIFF8 strings store their characters: one byte per character
+/// when every character fits in Latin-1, UTF-16LE otherwise. Returns the option flags,
+/// the character count and the bytes.
+pub fn encode_str(text: &str) -> (u8, u16, Vec<u8>) {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    if units.iter().all(|x| *x <= 0xFF) {
+        (
+            0x00,
+            units.len() as u16,
+            units.iter().map(|x| *x as u8).collect(),
+        )
+    } else {
+        let bytes = units.iter().flat_map(|x| x.to_le_bytes()).collect();
+        (0x01, units.len() as u16, bytes)
+    }
+}
+
+// A string with a one byte length, used for sheet and font names
+pub fn short_str(text: &str) -> Vec<u8> {
+    let (flags, len, bytes) = encode_str(text);
+    let mut data = vec![len as u8, flags];
+    data.extend_from_slice(&bytes);
+    data
+}
+
+/// Writes the shared string table and its index. `out` has to hold the whole stream so
+/// far, since the index points at absolute positions in it.
+pub fn write_sst(out: &mut Vec<u8>, strings: &[String], total: u32) {
+    // Excel wants between 8 strings per bucket and 128 buckets
+    let per_bucket = strings.len().div_ceil(128).max(8);
+
+    let mut records: Vec<Vec<u8>> = Vec::new();
+    let mut record: Vec<u8> = Vec::with_capacity(MAX_RECORD_LEN);
+    record.extend_from_slice(&total.to_le_bytes());
+    record.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+
+    // Where each bucket's first string starts, as (record, offset in the record body)
+    let mut buckets: Vec<(usize, usize)> = Vec::new();
+
+    for (i, text) in strings.iter().enumerate() {
+        let (flags, len, bytes) = encode_str(text);
+        let char_len = if flags == 0x01 { 2 } else { 1 };
+
+        // The length and flags can't be split from the first character
+        if record.len() + 3 + char_len.min(bytes.len()) > MAX_RECORD_LEN {
+            records.push(std::mem::replace(
+                &mut record,
+                Vec::with_capacity(MAX_RECORD_LEN),
+            ));
+        }
+
+        if i % per_bucket == 0 {
+            buckets.push((records.len(), record.len()));
+        }
+
+        record.extend_from_slice(&len.to_le_bytes());
+        record.push(flags);
+
+        let mut rest = bytes.as_slice();
+        loop {
+            // Characters are never split across records
+            let room = (MAX_RECORD_LEN - record.len()) / char_len * char_len;
+            let take = room.min(rest.len());
+            record.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+
+            if rest.is_empty() {
+                break;
+            }
+
+            records.push(std::mem::replace(
+                &mut record,
+                Vec::with_capacity(MAX_RECORD_LEN),
+            ));
+            // A continued string repeats its flags at the start of the next record
+            record.push(flags);
+        }
+    }
+    records.push(record);
+
+    let mut record_starts = Vec::with_capacity(records.len());
+    let mut pos = out.len();
+    for (i, record) in records.iter().enumerate() {
+        record_starts.push(pos);
+        write_record(out, if i == 0 { SST } else { CONTINUE }, record);
+        pos += 4 + record.len();
+    }
+
+    let mut data = Vec::with_capacity(2 + 8 * buckets.len());
+    data.extend_from_slice(&(per_bucket as u16).to_le_bytes());
+    for (record, pos) in buckets {
+        // Both offsets count the 4 byte record header
+        data.extend_from_slice(&((record_starts[record] + 4 + pos) as u32).to_le_bytes());
+        data.extend_from_slice(&((4 + pos) as u16).to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+    }
+    write_record(out, EXTSST, &data);
+}
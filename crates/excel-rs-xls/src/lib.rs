@@ -0,0 +1,4 @@
+mod biff;
+pub mod writer;
+
+pub use writer::XlsWriter;
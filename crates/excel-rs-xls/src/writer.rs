@@ -0,0 +1,466 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, Write},
+};
+
+use anyhow::{bail, Result};
+use excel_rs_xlsx::typed_sheet::{
+    date_serial, TYPE_BLANK, TYPE_BOOLEAN, TYPE_DATE, TYPE_DATE_DMY, TYPE_DATE_MDY, TYPE_DATE_YMD,
+    TYPE_NUMBER, TYPE_STRING,
+};
+
+use crate::biff::{self, write_bof, write_record};
+
+const MAX_ROWS: u32 = 65536;
+const MAX_COLS: usize = 256;
+const MAX_STR_LEN: usize = 32767;
+const MAX_SHEET_NAME_LEN: usize = 31;
+
+// The 15 style XFs Excel expects come first, then the cell formats used
+const XF_CELL: u16 = 15;
+const XF_DATE: u16 = 16;
+const XF_DATE_TIME: u16 = 17;
+
+// Streams shorter than this go in the compound file's mini stream, which some readers
+// don't expect for the workbook
+const MIN_STREAM_LEN: usize = 4096;
+
+/// Writes a single sheet workbook in the legacy Excel 97-2003 (BIFF8) format. Only the
+/// cell values are kept, apart from dates which get Excel's default date or date and
+/// time format.
+///
+/// Cells are held in memory until [`XlsWriter::finish`], since the shared string table
+/// has to be written before the sheet.
+pub struct XlsWriter {
+    name: String,
+    row_num: u32,
+    max_col: usize,
+    col_widths: Vec<f64>,
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    total_strings: u32,
+    cells: Vec<u8>,
+    has_header: bool,
+}
+
+impl XlsWriter {
+    pub fn new(name: String) -> XlsWriter {
+        XlsWriter {
+            name,
+            row_num: 0,
+            max_col: 0,
+            col_widths: Vec::new(),
+            strings: Vec::new(),
+            string_ids: HashMap::new(),
+            total_strings: 0,
+            cells: Vec::new(),
+            has_header: true,
+        }
+    }
+
+    /// Writes the first row like any other, typed by `types`, for sheets that have no
+    /// header row.
+    pub fn set_no_header(&mut self) {
+        self.has_header = false;
+    }
+
+    /// Sizes each column to fit the matching header.
+    pub fn fit_headers(&mut self, headers: &[&[u8]]) {
+        self.col_widths = headers
+            .iter()
+            .map(|x| {
+                let chars = String::from_utf8_lossy(x).chars().count();
+                (chars as f64 + 2.0).min(255.0)
+            })
+            .collect();
+    }
+
+    /// Takes the same cell types as `TypedSheet::write_row`. The header row is always
+    /// written as text, and numbers that don't parse are written as text too.
+    pub fn write_row(&mut self, data: Vec<&[u8]>, types: &Vec<&str>) -> Result<()> {
+        if self.row_num >= MAX_ROWS {
+            bail!("xls sheets can't have more than {MAX_ROWS} rows");
+        }
+        if data.len() > MAX_COLS {
+            bail!("xls sheets can't have more than {MAX_COLS} columns");
+        }
+
+        let row = self.row_num as u16;
+
+        for (col, datum) in data.iter().enumerate() {
+            let cell_type = match types.get(col) {
+                Some(t) if self.row_num > 0 || !self.has_header => *t,
+                _ => TYPE_STRING,
+            };
+
+            // Cells are only written for values, so empty text is blank too
+            if datum.is_empty() || cell_type == TYPE_BLANK {
+                continue;
+            }
+
+            let mut cell = Vec::with_capacity(14);
+            cell.extend_from_slice(&row.to_le_bytes());
+            cell.extend_from_slice(&(col as u16).to_le_bytes());
+
+            // Dates are stored as serial numbers, and text if they aren't dates Excel has
+            let number = match cell_type {
+                TYPE_NUMBER => std::str::from_utf8(datum)
+                    .ok()
+                    .and_then(|x| x.trim().parse::<f64>().ok())
+                    .filter(|x| x.is_finite())
+                    .map(|x| (x, XF_CELL)),
+                TYPE_DATE | TYPE_DATE_YMD | TYPE_DATE_MDY | TYPE_DATE_DMY => {
+                    date_serial(datum, cell_type).map(|x| match x.fract() == 0.0 {
+                        true => (x, XF_DATE),
+                        false => (x, XF_DATE_TIME),
+                    })
+                }
+                _ => None,
+            };
+
+            if let Some((number, xf)) = number {
+                cell.extend_from_slice(&xf.to_le_bytes());
+                cell.extend_from_slice(&number.to_le_bytes());
+                write_record(&mut self.cells, biff::NUMBER, &cell);
+            } else if cell_type == TYPE_BOOLEAN && matches!(*datum, b"0" | b"1") {
+                cell.extend_from_slice(&XF_CELL.to_le_bytes());
+                cell.push(datum[0] - b'0');
+                cell.push(0);
+                write_record(&mut self.cells, biff::BOOLERR, &cell);
+            } else {
+                let id = self.add_string(datum)?;
+                cell.extend_from_slice(&XF_CELL.to_le_bytes());
+                cell.extend_from_slice(&id.to_le_bytes());
+                write_record(&mut self.cells, biff::LABELSST, &cell);
+            }
+
+            self.max_col = self.max_col.max(col + 1);
+        }
+
+        self.row_num += 1;
+        Ok(())
+    }
+
+    fn add_string(&mut self, datum: &[u8]) -> Result<u32> {
+        let text = String::from_utf8_lossy(datum);
+        if text.encode_utf16().count() > MAX_STR_LEN {
+            bail!("xls cells can't hold more than {MAX_STR_LEN} characters");
+        }
+
+        self.total_strings += 1;
+        if let Some(id) = self.string_ids.get(text.as_ref()) {
+            return Ok(*id);
+        }
+
+        let id = self.strings.len() as u32;
+        self.strings.push(text.to_string());
+        self.string_ids.insert(text.into(), id);
+        Ok(id)
+    }
+
+    /// Writes the workbook as a compound file into `writer`.
+    pub fn finish<W: Read + Write + Seek>(self, writer: W) -> Result<W> {
+        if self.name.is_empty() || self.name.chars().count() > MAX_SHEET_NAME_LEN {
+            bail!("xls sheet names must be 1 to {MAX_SHEET_NAME_LEN} characters long");
+        }
+
+        let mut stream = Vec::new();
+        let sheet_pos_field = self.write_globals(&mut stream);
+
+        // The sheet list points at the sheet's BOF, which is only known now
+        let sheet_pos = stream.len() as u32;
+        stream[sheet_pos_field..sheet_pos_field + 4].copy_from_slice(&sheet_pos.to_le_bytes());
+
+        self.write_sheet(&mut stream);
+
+        if stream.len() < MIN_STREAM_LEN {
+            stream.resize(MIN_STREAM_LEN, 0);
+        }
+
+        // Excel 97-2003 files use the older 512 byte sectors
+        let mut file = cfb::CompoundFile::create_with_version(cfb::Version::V3, writer)?;
+        let mut workbook = file.create_stream("/Workbook")?;
+        workbook.write_all(&stream)?;
+        workbook.flush()?;
+        drop(workbook);
+        file.flush()?;
+
+        Ok(file.into_inner())
+    }
+
+    // Returns where the sheet's position goes in the BOUNDSHEET record
+    fn write_globals(&self, out: &mut Vec<u8>) -> usize {
+        write_bof(out, biff::BOF_GLOBALS);
+        write_record(out, biff::CODEPAGE, &1200u16.to_le_bytes()); // UTF-16
+
+        let mut window = Vec::with_capacity(18);
+        for x in [0u16, 0, 0x4000, 0x2000, 0x38, 0, 0, 1, 0x258] {
+            window.extend_from_slice(&x.to_le_bytes());
+        }
+        write_record(out, biff::WINDOW1, &window);
+
+        // Excel skips font 4, so four fonts are written even though one is used
+        for _ in 0..4 {
+            let mut font = Vec::with_capacity(20);
+            for x in [200u16, 0, 0x7FFF, 400, 0] {
+                font.extend_from_slice(&x.to_le_bytes());
+            }
+            font.extend_from_slice(&[0, 0, 0, 0]);
+            font.extend_from_slice(&biff::short_str("Arial"));
+            write_record(out, biff::FONT, &font);
+        }
+
+        for _ in 0..XF_CELL {
+            write_record(out, biff::XF, &xf(0, 0xFFF5, 0));
+        }
+        write_record(out, biff::XF, &xf(0, 0x0001, 0));
+        // Built-in formats 14 and 22 are the locale's short date and date with time, the
+        // same ones xlsx dates use
+        write_record(out, biff::XF, &xf(14, 0x0001, 0x04));
+        write_record(out, biff::XF, &xf(22, 0x0001, 0x04));
+
+        write_record(out, biff::STYLE, &[0x00, 0x80, 0x00, 0xFF]);
+
+        let mut bound_sheet = vec![0; 6];
+        bound_sheet.extend_from_slice(&biff::short_str(&self.name));
+        write_record(out, biff::BOUNDSHEET, &bound_sheet);
+        let sheet_pos_field = out.len() - bound_sheet.len();
+
+        biff::write_sst(out, &self.strings, self.total_strings);
+
+        write_record(out, biff::EOF, &[]);
+        sheet_pos_field
+    }
+
+    fn write_sheet(&self, out: &mut Vec<u8>) {
+        write_bof(out, biff::BOF_WORKSHEET);
+
+        for (col, width) in self.col_widths.iter().enumerate() {
+            let mut col_info = Vec::with_capacity(12);
+            let width = (width * 256.0) as u16;
+            for x in [col as u16, col as u16, width, XF_CELL, 0, 0] {
+                col_info.extend_from_slice(&x.to_le_bytes());
+            }
+            write_record(out, biff::COLINFO, &col_info);
+        }
+
+        let mut dimensions = Vec::with_capacity(14);
+        dimensions.extend_from_slice(&0u32.to_le_bytes());
+        dimensions.extend_from_slice(&self.row_num.to_le_bytes());
+        for x in [0u16, self.max_col as u16, 0] {
+            dimensions.extend_from_slice(&x.to_le_bytes());
+        }
+        write_record(out, biff::DIMENSIONS, &dimensions);
+
+        out.extend_from_slice(&self.cells);
+
+        let mut window = Vec::with_capacity(18);
+        for x in [0x06B6u16, 0, 0, 0x40, 0, 0, 0, 0, 0] {
+            window.extend_from_slice(&x.to_le_bytes());
+        }
+        write_record(out, biff::WINDOW2, &window);
+
+        write_record(out, biff::EOF, &[]);
+    }
+}
+
+fn xf(num_fmt: u16, flags: u16, used_attrs: u8) -> Vec<u8> {
+    let mut data = Vec::with_capacity(20);
+    data.extend_from_slice(&0u16.to_le_bytes()); // font
+    data.extend_from_slice(&num_fmt.to_le_bytes());
+    data.extend_from_slice(&flags.to_le_bytes());
+    data.extend_from_slice(&[0x20, 0, 0, used_attrs]); // bottom aligned
+    data.extend_from_slice(&[0; 8]);
+    data.extend_from_slice(&0x20C0u16.to_le_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use calamine::{open_workbook_from_rs, Data, Reader, Xls};
+
+    use super::*;
+
+    fn write_xls(rows: &[Vec<&[u8]>], types: &Vec<&str>) -> Vec<u8> {
+        let mut writer = XlsWriter::new("Sheet 1".to_string());
+        for row in rows {
+            writer.write_row(row.clone(), types).unwrap();
+        }
+        writer.finish(Cursor::new(Vec::new())).unwrap().into_inner()
+    }
+
+    fn read_xls(bytes: Vec<u8>) -> Vec<Vec<Data>> {
+        let mut workbook: Xls<_> = open_workbook_from_rs(Cursor::new(bytes)).unwrap();
+        let range = workbook.worksheet_range("Sheet 1").unwrap();
+        range.rows().map(|x| x.to_vec()).collect()
+    }
+
+    #[test]
+    fn dates_are_serial_numbers_in_a_date_format() {
+        let types = vec![
+            TYPE_DATE,
+            TYPE_DATE,
+            TYPE_DATE_MDY,
+            TYPE_DATE_DMY,
+            TYPE_DATE_YMD,
+        ];
+        let bytes = write_xls(
+            &[
+                vec![b"a", b"b", b"c", b"d", b"e"],
+                vec![
+                    b"2024-01-31",
+                    b"2023-01-01T06:00",
+                    b"1/2/2024",
+                    b"2/1/2024",
+                    b"2024-01-02",
+                ],
+                vec![b"not a date", b"2023-02-30", b"13/1/2024", b"", b"2024-1-2"],
+            ],
+            &types,
+        );
+
+        let rows = read_xls(bytes);
+        for (col, serial) in [45322.0, 44927.25, 45293.0, 45293.0, 45293.0]
+            .into_iter()
+            .enumerate()
+        {
+            match &rows[1][col] {
+                Data::DateTime(date) => assert_eq!(date.as_f64(), serial),
+                other => panic!("column {col} isn't a date: {other:?}"),
+            }
+        }
+        assert_eq!(
+            rows[2],
+            [
+                Data::String("not a date".to_string()),
+                Data::String("2023-02-30".to_string()),
+                Data::String("13/1/2024".to_string()),
+                Data::Empty,
+                Data::String("2024-1-2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn cells_are_read_back_with_their_types() {
+        let types = vec![
+            TYPE_STRING,
+            TYPE_NUMBER,
+            TYPE_BOOLEAN,
+            TYPE_DATE,
+            TYPE_NUMBER,
+        ];
+        let bytes = write_xls(
+            &[
+                vec![b"text", b"number", b"bool", b"date", b"other"],
+                vec![
+                    b"caf\xc3\xa9 \xe2\x82\xac",
+                    b"-36.5",
+                    b"1",
+                    b"2024-01-31",
+                    b"12abc",
+                ],
+                vec![b"36", b"1e3", b"0", b"", b"text"],
+            ],
+            &types,
+        );
+
+        let rows = read_xls(bytes);
+        assert_eq!(
+            rows[0],
+            ["text", "number", "bool", "date", "other"].map(|x| Data::String(x.to_string()))
+        );
+        assert_eq!(rows[1][0], Data::String("café €".to_string()));
+        assert_eq!(rows[1][1], Data::Float(-36.5));
+        assert_eq!(rows[1][2], Data::Bool(true));
+        assert!(matches!(&rows[1][3], Data::DateTime(x) if x.as_f64() == 45322.0));
+        assert_eq!(rows[1][4], Data::String("12abc".to_string()));
+        assert_eq!(
+            rows[2],
+            [
+                Data::String("36".to_string()),
+                Data::Float(1000.0),
+                Data::Bool(false),
+                Data::Empty,
+                Data::String("text".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_strings_are_continued_across_records() {
+        // Both together fill more than one SST record, and the second is split part way
+        // through, once as single bytes and once as UTF-16
+        let latin = "a".repeat(6000) + &"b".repeat(6000);
+        let wide = "€".repeat(5000) + "end";
+        let shared: Vec<String> = (0..3000).map(|x| format!("string {x}")).collect();
+
+        let mut rows = vec![vec![b"long".as_slice(), b"wide", b"shared"]];
+        rows.push(vec![
+            latin.as_bytes(),
+            wide.as_bytes(),
+            shared[0].as_bytes(),
+        ]);
+        for text in &shared[1..] {
+            rows.push(vec![b"", b"", text.as_bytes()]);
+        }
+        let bytes = write_xls(&rows, &vec![TYPE_STRING; 3]);
+
+        let rows = read_xls(bytes);
+        assert_eq!(rows.len(), shared.len() + 1);
+        assert_eq!(rows[1][0], Data::String(latin));
+        assert_eq!(rows[1][1], Data::String(wide));
+        for (row, text) in rows[1..].iter().zip(shared) {
+            assert_eq!(row[2], Data::String(text));
+        }
+    }
+
+    #[test]
+    fn sheets_past_the_format_limits_are_errors() {
+        let mut writer = XlsWriter::new("Sheet 1".to_string());
+        for _ in 0..MAX_ROWS {
+            writer.write_row(vec![b"1"], &vec![TYPE_NUMBER]).unwrap();
+        }
+        let err = writer
+            .write_row(vec![b"1"], &vec![TYPE_NUMBER])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "xls sheets can't have more than 65536 rows"
+        );
+
+        // The last row and column the format has are still read back
+        let bytes = writer.finish(Cursor::new(Vec::new())).unwrap().into_inner();
+        assert_eq!(read_xls(bytes).len(), MAX_ROWS as usize);
+
+        let mut writer = XlsWriter::new("Sheet 1".to_string());
+        let row = vec![b"1".as_slice(); MAX_COLS];
+        writer
+            .write_row(row.clone(), &vec![TYPE_NUMBER; MAX_COLS])
+            .unwrap();
+        let mut wide = row;
+        wide.push(b"1");
+        let err = writer
+            .write_row(wide, &vec![TYPE_NUMBER; MAX_COLS + 1])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "xls sheets can't have more than 256 columns"
+        );
+
+        let bytes = writer.finish(Cursor::new(Vec::new())).unwrap().into_inner();
+        assert_eq!(read_xls(bytes)[0].len(), MAX_COLS);
+
+        let mut writer = XlsWriter::new("Sheet 1".to_string());
+        let text = "a".repeat(MAX_STR_LEN + 1);
+        let err = writer
+            .write_row(vec![text.as_bytes()], &vec![TYPE_STRING])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "xls cells can't hold more than 32767 characters"
+        );
+    }
+}
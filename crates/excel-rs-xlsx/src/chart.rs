@@ -0,0 +1,344 @@
+use std::io::Write;
+
+use anyhow::{bail, Result};
+
+use super::format::{col_name, escape_xml};
+
+// Each chart is drawn this many columns wide and rows tall
+const CHART_COLS: usize = 8;
+const CHART_ROWS: u32 = 15;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChartType {
+    Bar,
+    Line,
+    Pie,
+}
+
+/// A chart over data on the same sheet. Ranges are A1-style, e.g. `A2:A13`, and each
+/// must be a single column or row. A series under a header row is named after its
+/// header cell.
+#[derive(Clone, Debug)]
+pub struct ChartSpec {
+    pub chart_type: ChartType,
+    pub title: Option<String>,
+    pub categories: String,
+    pub series: Vec<String>,
+}
+
+impl ChartSpec {
+    pub fn new(chart_type: ChartType, categories: &str, series: &[&str]) -> Self {
+        ChartSpec {
+            chart_type,
+            title: None,
+            categories: String::from(categories),
+            series: series.iter().map(|x| String::from(*x)).collect(),
+        }
+    }
+}
+
+/// The drawings, charts and notes written so far, so the workbook can list them in
+/// `[Content_Types].xml`. Each sheet with charts has one drawing, and each sheet with
+/// notes one comments part, numbered after the sheet.
+#[derive(Default)]
+pub struct Drawings {
+    pub(crate) sheets: Vec<u16>,
+    pub(crate) charts: u32,
+    pub(crate) notes: Vec<u16>,
+    // Blocks of 1024 VML shape ids taken by the notes written so far
+    pub(crate) vml_blocks: u32,
+}
+
+// First and last cells as `(row, col)`, with rows counted from 1 and columns from 0
+type CellRange = ((u32, usize), (u32, usize));
+
+pub(crate) struct Chart {
+    chart_type: ChartType,
+    title: Option<String>,
+    categories: CellRange,
+    series: Vec<CellRange>,
+}
+
+impl Chart {
+    pub(crate) fn parse(spec: &ChartSpec) -> Result<Chart> {
+        if spec.series.is_empty() {
+            bail!("A chart needs at least one series");
+        }
+
+        Ok(Chart {
+            chart_type: spec.chart_type,
+            title: spec.title.clone(),
+            categories: parse_range(&spec.categories)?,
+            series: spec
+                .series
+                .iter()
+                .map(|x| parse_range(x))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Fails if any range reaches past `rows` rows or `cols` columns.
+    pub(crate) fn check_bounds(&self, rows: u32, cols: usize) -> Result<()> {
+        for ((_, _), (last_row, last_col)) in self.series.iter().chain([&self.categories]) {
+            if *last_row > rows || *last_col >= cols {
+                bail!(
+                    "The chart range ending at {}{} is outside the {} rows and {} columns written",
+                    col_name(*last_col),
+                    last_row,
+                    rows,
+                    cols
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write_to<X: Write>(
+        &self,
+        writer: &mut X,
+        sheet_name: &str,
+        has_header: bool,
+    ) -> Result<()> {
+        // Sheet names are quoted in formulas, with quotes doubled
+        let sheet = escape_xml(&format!("'{}'!", sheet_name.replace('\'', "''")));
+
+        writer.write_all(b"<c:chartSpace xmlns:c=\"http://schemas.openxmlformats.org/drawingml/2006/chart\" xmlns:a=\"http://schemas.openxmlformats.org/drawingml/2006/main\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\"><c:chart>")?;
+        if let Some(title) = &self.title {
+            write!(
+                writer,
+                "<c:title><c:tx><c:rich><a:bodyPr/><a:p><a:r><a:t>{}</a:t></a:r></a:p></c:rich></c:tx><c:overlay val=\"0\"/></c:title><c:autoTitleDeleted val=\"0\"/>",
+                escape_xml(title)
+            )?;
+        }
+        writer.write_all(b"<c:plotArea><c:layout/>")?;
+
+        match self.chart_type {
+            ChartType::Bar => writer.write_all(
+                b"<c:barChart><c:barDir val=\"col\"/><c:grouping val=\"clustered\"/><c:varyColors val=\"0\"/>",
+            )?,
+            ChartType::Line => writer
+                .write_all(b"<c:lineChart><c:grouping val=\"standard\"/><c:varyColors val=\"0\"/>")?,
+            ChartType::Pie => writer.write_all(b"<c:pieChart><c:varyColors val=\"1\"/>")?,
+        }
+
+        for (i, range) in self.series.iter().enumerate() {
+            write!(
+                writer,
+                "<c:ser><c:idx val=\"{0}\"/><c:order val=\"{0}\"/>",
+                i
+            )?;
+            let ((first_row, col), _) = *range;
+            if has_header && first_row == 2 {
+                write!(
+                    writer,
+                    "<c:tx><c:strRef><c:f>{}${}$1</c:f></c:strRef></c:tx>",
+                    sheet,
+                    col_name(col)
+                )?;
+            }
+            write!(
+                writer,
+                "<c:cat><c:strRef><c:f>{}{}</c:f></c:strRef></c:cat><c:val><c:numRef><c:f>{}{}</c:f></c:numRef></c:val>",
+                sheet,
+                range_ref(&self.categories),
+                sheet,
+                range_ref(range)
+            )?;
+            if self.chart_type == ChartType::Line {
+                writer.write_all(b"<c:smooth val=\"0\"/>")?;
+            }
+            writer.write_all(b"</c:ser>")?;
+        }
+
+        match self.chart_type {
+            ChartType::Bar => writer.write_all(
+                b"<c:gapWidth val=\"150\"/><c:axId val=\"1\"/><c:axId val=\"2\"/></c:barChart>",
+            )?,
+            ChartType::Line => writer.write_all(
+                b"<c:marker val=\"1\"/><c:axId val=\"1\"/><c:axId val=\"2\"/></c:lineChart>",
+            )?,
+            ChartType::Pie => writer.write_all(b"<c:firstSliceAng val=\"0\"/></c:pieChart>")?,
+        }
+        if self.chart_type != ChartType::Pie {
+            writer.write_all(b"<c:catAx><c:axId val=\"1\"/><c:scaling><c:orientation val=\"minMax\"/></c:scaling><c:delete val=\"0\"/><c:axPos val=\"b\"/><c:numFmt formatCode=\"General\" sourceLinked=\"1\"/><c:tickLblPos val=\"nextTo\"/><c:crossAx val=\"2\"/><c:crosses val=\"autoZero\"/><c:auto val=\"1\"/><c:lblAlgn val=\"ctr\"/><c:lblOffset val=\"100\"/></c:catAx><c:valAx><c:axId val=\"2\"/><c:scaling><c:orientation val=\"minMax\"/></c:scaling><c:delete val=\"0\"/><c:axPos val=\"l\"/><c:majorGridlines/><c:numFmt formatCode=\"General\" sourceLinked=\"1\"/><c:tickLblPos val=\"nextTo\"/><c:crossAx val=\"1\"/><c:crosses val=\"autoZero\"/><c:crossBetween val=\"between\"/></c:valAx>")?;
+        }
+
+        writer.write_all(b"</c:plotArea><c:legend><c:legendPos val=\"r\"/><c:overlay val=\"0\"/></c:legend><c:plotVisOnly val=\"1\"/></c:chart></c:chartSpace>")?;
+        Ok(())
+    }
+}
+
+/// Writes a drawing placing `count` charts to the right of column `after_col`, one under
+/// the other.
+pub(crate) fn write_drawing<X: Write>(
+    writer: &mut X,
+    count: usize,
+    after_col: usize,
+) -> Result<()> {
+    writer.write_all(b"<xdr:wsDr xmlns:xdr=\"http://schemas.openxmlformats.org/drawingml/2006/spreadsheetDrawing\" xmlns:a=\"http://schemas.openxmlformats.org/drawingml/2006/main\">")?;
+
+    let col = after_col + 1;
+    for i in 0..count {
+        let row = i as u32 * (CHART_ROWS + 1) + 1;
+        write!(
+            writer,
+            "<xdr:twoCellAnchor><xdr:from><xdr:col>{}</xdr:col><xdr:colOff>0</xdr:colOff><xdr:row>{}</xdr:row><xdr:rowOff>0</xdr:rowOff></xdr:from><xdr:to><xdr:col>{}</xdr:col><xdr:colOff>0</xdr:colOff><xdr:row>{}</xdr:row><xdr:rowOff>0</xdr:rowOff></xdr:to>",
+            col,
+            row,
+            col + CHART_COLS,
+            row + CHART_ROWS
+        )?;
+        write!(
+            writer,
+            "<xdr:graphicFrame macro=\"\"><xdr:nvGraphicFramePr><xdr:cNvPr id=\"{}\" name=\"Chart {}\"/><xdr:cNvGraphicFramePr/></xdr:nvGraphicFramePr><xdr:xfrm><a:off x=\"0\" y=\"0\"/><a:ext cx=\"0\" cy=\"0\"/></xdr:xfrm><a:graphic><a:graphicData uri=\"http://schemas.openxmlformats.org/drawingml/2006/chart\"><c:chart xmlns:c=\"http://schemas.openxmlformats.org/drawingml/2006/chart\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\" r:id=\"rId{}\"/></a:graphicData></a:graphic></xdr:graphicFrame><xdr:clientData/></xdr:twoCellAnchor>",
+            i + 2,
+            i + 1,
+            i + 1
+        )?;
+    }
+
+    writer.write_all(b"</xdr:wsDr>")?;
+    Ok(())
+}
+
+/// Writes the relationships from a drawing to its `count` charts, numbered from
+/// `first_chart`.
+pub(crate) fn write_drawing_rels<X: Write>(
+    writer: &mut X,
+    first_chart: u32,
+    count: usize,
+) -> Result<()> {
+    writer.write_all(
+        b"<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">",
+    )?;
+    for i in 0..count {
+        write!(
+            writer,
+            "<Relationship Id=\"rId{}\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/chart\" Target=\"../charts/chart{}.xml\"/>",
+            i + 1,
+            first_chart + i as u32
+        )?;
+    }
+    writer.write_all(b"</Relationships>")?;
+    Ok(())
+}
+
+fn parse_range(range: &str) -> Result<CellRange> {
+    let (first, last) = match range.split_once(':') {
+        Some((first, last)) => (parse_cell(first), parse_cell(last)),
+        None => (parse_cell(range), parse_cell(range)),
+    };
+    let (Some(first), Some(last)) = (first, last) else {
+        bail!("{} isn't an A1-style range like A2:A10", range);
+    };
+
+    if first.0 > last.0 || first.1 > last.1 {
+        bail!(
+            "The range {} must go from its first cell to its last",
+            range
+        );
+    }
+    if first.0 != last.0 && first.1 != last.1 {
+        bail!("The range {} must be a single row or column", range);
+    }
+
+    Ok((first, last))
+}
+
+// Reads a cell like `B2` or `$B$2`
+fn parse_cell(cell: &str) -> Option<(u32, usize)> {
+    let cell = cell.trim().replace('$', "");
+    let split = cell.find(|x: char| !x.is_ascii_alphabetic())?;
+    let (letters, digits) = cell.split_at(split);
+    if letters.is_empty() || letters.len() > 3 {
+        return None;
+    }
+
+    let col = letters.bytes().fold(0, |acc, x| {
+        acc * 26 + (x.to_ascii_uppercase() - b'A') as usize + 1
+    });
+    let row = digits.parse().ok().filter(|x| *x > 0)?;
+
+    Some((row, col - 1))
+}
+
+fn range_ref(range: &CellRange) -> String {
+    let ((first_row, first_col), (last_row, last_col)) = *range;
+    format!(
+        "${}${}:${}${}",
+        col_name(first_col),
+        first_row,
+        col_name(last_col),
+        last_row
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::testing::read_part;
+    use crate::WorkBook;
+
+    fn write_chart(spec: &ChartSpec) -> Result<Vec<u8>> {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        let types = vec!["str", "n"];
+        worksheet.write_row(vec![b"Month", b"Sales"], &types)?;
+        for (month, sales) in [("Jan", "10"), ("Feb", "20"), ("Mar", "30")] {
+            worksheet.write_row(vec![month.as_bytes(), sales.as_bytes()], &types)?;
+        }
+        worksheet.add_chart(spec)?;
+        worksheet.close()?;
+        Ok(workbook.finish()?.into_inner())
+    }
+
+    #[test]
+    fn bar_chart_is_drawn_over_its_ranges() {
+        let bytes = write_chart(&ChartSpec::new(ChartType::Bar, "A2:A4", &["B2:B4"])).unwrap();
+
+        let chart = read_part(&bytes, "xl/charts/chart1.xml");
+        assert!(chart.contains("<c:barChart><c:barDir val=\"col\"/>"));
+        assert!(chart.contains(concat!(
+            "<c:tx><c:strRef><c:f>&apos;Sheet 1&apos;!$B$1</c:f></c:strRef></c:tx>",
+            "<c:cat><c:strRef><c:f>&apos;Sheet 1&apos;!$A$2:$A$4</c:f></c:strRef></c:cat>",
+            "<c:val><c:numRef><c:f>&apos;Sheet 1&apos;!$B$2:$B$4</c:f></c:numRef></c:val>"
+        )));
+
+        // Sheet -> drawing -> chart, each listed in the content types
+        let sheet = read_part(&bytes, "xl/worksheets/sheet1.xml");
+        assert!(sheet.contains("<drawing r:id=\"rId1\"/>"));
+        let rels = read_part(&bytes, "xl/worksheets/_rels/sheet1.xml.rels");
+        assert!(rels.contains("Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/drawing\" Target=\"../drawings/drawing1.xml\""));
+        let drawing = read_part(&bytes, "xl/drawings/drawing1.xml");
+        assert!(drawing.contains("<xdr:from><xdr:col>3</xdr:col>"));
+        assert!(drawing.contains("r:id=\"rId1\""));
+        let rels = read_part(&bytes, "xl/drawings/_rels/drawing1.xml.rels");
+        assert!(rels.contains("Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/chart\" Target=\"../charts/chart1.xml\""));
+        let types = read_part(&bytes, "[Content_Types].xml");
+        assert!(types.contains("PartName=\"/xl/charts/chart1.xml\""));
+        assert!(types.contains("PartName=\"/xl/drawings/drawing1.xml\""));
+    }
+
+    #[test]
+    fn chart_ranges_must_be_within_the_data() {
+        let err = write_chart(&ChartSpec::new(ChartType::Line, "A2:A5", &["B2:B5"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The chart range ending at B5 is outside the 4 rows and 2 columns written"
+        );
+
+        for (range, message) in [
+            ("B2:C3", "The range B2:C3 must be a single row or column"),
+            (
+                "B4:B2",
+                "The range B4:B2 must go from its first cell to its last",
+            ),
+            ("2B", "2B isn't an A1-style range like A2:A10"),
+        ] {
+            let err = Chart::parse(&ChartSpec::new(ChartType::Bar, "A2:A4", &[range]));
+            assert_eq!(err.err().unwrap().to_string(), message);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::{Seek, Write};
+
+use anyhow::Result;
+
+use super::typed_sheet::TypedSheet;
+
+/// Experimental column-major writer for sources like Arrow, Polars or Postgres.
+///
+/// Worksheet XML is row-major, so every column is copied into memory until `close()`
+/// assembles and writes the rows. Peak memory is roughly the size of all cell values.
+/// The first value of each column is row 1, which is written as a header like
+/// `TypedSheet::write_row` unless `treat_first_row_as_header(false)` is called.
+pub struct ColumnarSheet<'a, W: Write + Seek> {
+    sheet: TypedSheet<'a, W>,
+    columns: Vec<Vec<Vec<u8>>>,
+    types: Vec<String>,
+}
+
+impl<'a, W: Write + Seek> ColumnarSheet<'a, W> {
+    pub fn new(sheet: TypedSheet<'a, W>) -> Self {
+        ColumnarSheet {
+            sheet,
+            columns: Vec::new(),
+            types: Vec::new(),
+        }
+    }
+
+    /// See `TypedSheet::treat_first_row_as_header`.
+    pub fn treat_first_row_as_header(&mut self, header: bool) {
+        self.sheet.treat_first_row_as_header(header);
+    }
+
+    /// Buffers `values` as column `col_idx`, replacing anything previously written there.
+    pub fn write_column(&mut self, col_idx: usize, values: Vec<&[u8]>, col_type: &str) {
+        if self.columns.len() <= col_idx {
+            self.columns.resize(col_idx + 1, Vec::new());
+            self.types.resize(col_idx + 1, String::from("str"));
+        }
+
+        self.columns[col_idx] = values.into_iter().map(|x| x.to_vec()).collect();
+        self.types[col_idx] = String::from(col_type);
+    }
+
+    /// Transposes the buffered columns into rows and closes the sheet. Columns shorter
+    /// than the longest one are padded with empty strings.
+    pub fn close(mut self) -> Result<()> {
+        let num_of_rows = self.columns.iter().map(|x| x.len()).max().unwrap_or(0);
+        let types: Vec<&str> = self.types.iter().map(|x| x.as_str()).collect();
+
+        for row_num in 0..num_of_rows {
+            let row: Vec<&[u8]> = self
+                .columns
+                .iter()
+                .map(|col| col.get(row_num).map(|x| x.as_slice()).unwrap_or(&[]))
+                .collect();
+
+            self.sheet.write_row(row, &types)?;
+        }
+
+        self.sheet.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::testing::{read_cells, read_part, ParsedCell};
+    use crate::WorkBook;
+
+    const TYPES: [&str; 2] = ["str", "n"];
+
+    #[test]
+    fn columns_are_written_as_the_same_rows() {
+        let names: Vec<&[u8]> = vec![b"Name", b"Ada", b"Grace", b""];
+        let ages: Vec<&[u8]> = vec![b"Age", b"36", b"85", b"7"];
+
+        let mut columnar = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = columnar.get_columnar_worksheet(String::from("Sheet 1"));
+        // Out of order, and the first write of column 0 is replaced
+        worksheet.write_column(1, ages.clone(), TYPES[1]);
+        worksheet.write_column(0, vec![b"x"], "n");
+        worksheet.write_column(0, names.clone(), TYPES[0]);
+        worksheet.close().unwrap();
+        let columnar = columnar.finish().unwrap().into_inner();
+
+        let mut row_wise = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = row_wise.get_typed_worksheet(String::from("Sheet 1"));
+        for (name, age) in names.into_iter().zip(ages) {
+            worksheet
+                .write_row(vec![name, age], &TYPES.to_vec())
+                .unwrap();
+        }
+        worksheet.close().unwrap();
+        let row_wise = row_wise.finish().unwrap().into_inner();
+
+        let sheet = "xl/worksheets/sheet1.xml";
+        assert_eq!(read_part(&columnar, sheet), read_part(&row_wise, sheet));
+    }
+
+    #[test]
+    fn short_columns_are_padded() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_columnar_worksheet(String::from("Sheet 1"));
+        worksheet.write_column(0, vec![b"a", b"b"], "str");
+        worksheet.write_column(1, vec![b"c"], "str");
+        worksheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let cells = read_cells(&bytes, "Sheet 1");
+        assert_eq!(cells.len(), 2);
+        assert_eq!(
+            cells[1],
+            vec![
+                ParsedCell::String(String::from("b")),
+                ParsedCell::String(String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn first_value_is_typed_when_it_is_not_a_header() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_columnar_worksheet(String::from("Sheet 1"));
+        worksheet.treat_first_row_as_header(false);
+        worksheet.write_column(0, vec![b"36", b"41"], "n");
+        worksheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let sheet = read_part(&bytes, "xl/worksheets/sheet1.xml");
+        assert!(
+            sheet.contains("<c r=\"A1\" t=\"n\"><v>36</v></c>"),
+            "{sheet}"
+        );
+        assert_eq!(
+            read_cells(&bytes, "Sheet 1")[0][0],
+            ParsedCell::Number(36.0)
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use super::format::{col_name, escape_xml};
+
+/// How `TypedSheet::add_text_rule` compares a cell's text with the rule's value. Like
+/// Excel, comparisons ignore case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextOp {
+    Contains,
+    NotContains,
+    BeginsWith,
+    EndsWith,
+    Equal,
+}
+
+pub(crate) struct TextRule {
+    pub(crate) col: usize,
+    pub(crate) first_row: u32,
+    pub(crate) last_row: u32,
+    pub(crate) op: TextOp,
+    pub(crate) value: String,
+    pub(crate) dxf_id: u32,
+}
+
+impl TextRule {
+    /// Writes the rule in its own `<conditionalFormatting>` element. Rules are checked in
+    /// `priority` order, lowest first.
+    pub(crate) fn write_to<X: Write>(&self, writer: &mut X, priority: usize) -> Result<()> {
+        let col = col_name(self.col);
+        let first = format!("{}{}", col, self.first_row + 1);
+        // A string in a formula has its quotes doubled
+        let value = format!("\"{}\"", self.value.replace('"', "\"\""));
+
+        // The formula is written for the range's first cell, and moves with each cell
+        let (rule_type, operator, formula) = match self.op {
+            TextOp::Contains => (
+                "containsText",
+                "containsText",
+                format!("NOT(ISERROR(SEARCH({},{})))", value, first),
+            ),
+            TextOp::NotContains => (
+                "notContainsText",
+                "notContains",
+                format!("ISERROR(SEARCH({},{}))", value, first),
+            ),
+            TextOp::BeginsWith => (
+                "beginsWith",
+                "beginsWith",
+                format!("LEFT({1},LEN({0}))={0}", value, first),
+            ),
+            TextOp::EndsWith => (
+                "endsWith",
+                "endsWith",
+                format!("RIGHT({1},LEN({0}))={0}", value, first),
+            ),
+            TextOp::Equal => ("cellIs", "equal", value),
+        };
+
+        write!(
+            writer,
+            "<conditionalFormatting sqref=\"{}:{}{}\"><cfRule type=\"{}\" dxfId=\"{}\" priority=\"{}\" operator=\"{}\"",
+            first,
+            col,
+            self.last_row + 1,
+            rule_type,
+            self.dxf_id,
+            priority,
+            operator
+        )?;
+        if self.op != TextOp::Equal {
+            write!(writer, " text=\"{}\"", escape_xml(&self.value))?;
+        }
+        write!(
+            writer,
+            "><formula>{}</formula></cfRule></conditionalFormatting>",
+            escape_xml(&formula)
+        )?;
+        Ok(())
+    }
+}
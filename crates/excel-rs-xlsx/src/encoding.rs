@@ -0,0 +1,81 @@
+use anyhow::{bail, Result};
+
+/// A single byte code page for `WorkBook::finish_with_encoding` to write XML parts in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XmlEncoding {
+    /// Latin-1 plus the Windows additions like `€`, `‘’` and `Š` in 0x80-0x9F
+    Windows1252,
+    /// Latin-1, U+0000 to U+00FF
+    Iso8859_1,
+}
+
+// What Windows-1252 puts in 0x80-0x9F, where Latin-1 has control characters. Its five
+// unassigned bytes hold \0, which never gets this far
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\0', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\0', '\u{017D}', '\0', '\0',
+    '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}', '\u{02DC}',
+    '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\0', '\u{017E}', '\u{0178}',
+];
+
+impl XmlEncoding {
+    /// The name used in the `<?xml ...?>` declaration
+    pub fn name(&self) -> &'static str {
+        match self {
+            XmlEncoding::Windows1252 => "windows-1252",
+            XmlEncoding::Iso8859_1 => "ISO-8859-1",
+        }
+    }
+
+    fn encode_char(&self, ch: char) -> Option<u8> {
+        match (self, ch as u32) {
+            (_, x @ 0..=0x7F) | (_, x @ 0xA0..=0xFF) => Some(x as u8),
+            (XmlEncoding::Iso8859_1, x @ 0x80..=0x9F) => Some(x as u8),
+            (XmlEncoding::Windows1252, _) => WINDOWS_1252_HIGH
+                .iter()
+                .position(|x| *x == ch)
+                .map(|x| 0x80 + x as u8),
+            _ => None,
+        }
+    }
+
+    /// Encodes the XML part `xml`, replacing its declaration with one naming this
+    /// encoding, or adding one if it has none. Characters the encoding can't hold are
+    /// written as character references with `escape`, otherwise they fail. `part` names
+    /// the part in errors.
+    pub(crate) fn encode_part(&self, part: &str, xml: &str, escape: bool) -> Result<Vec<u8>> {
+        let body = match xml.strip_prefix("<?xml") {
+            Some(rest) => match rest.find("?>") {
+                Some(end) => rest[end + 2..].trim_start_matches('\n'),
+                None => bail!("{part} has an unterminated XML declaration"),
+            },
+            None => xml,
+        };
+
+        let mut out = format!(
+            "<?xml version=\"1.0\" encoding=\"{}\" standalone=\"yes\"?>\n",
+            self.name()
+        )
+        .into_bytes();
+        out.reserve(body.len());
+
+        // Counted as written, so the declaration is line 1
+        let mut line = 2;
+        for ch in body.chars() {
+            match self.encode_char(ch) {
+                Some(byte) => out.push(byte),
+                None if escape => out.extend(format!("&#x{:X};", ch as u32).bytes()),
+                None => bail!(
+                    "{part} has {ch:?} (U+{:04X}) on line {line}, which can't be written in {}",
+                    ch as u32,
+                    self.name()
+                ),
+            }
+            if ch == '\n' {
+                line += 1;
+            }
+        }
+
+        Ok(out)
+    }
+}
@@ -0,0 +1,314 @@
+//! Decrypts password-protected xlsx files. Excel keeps these in an OLE compound file, with
+//! the zip encrypted by ECMA-376 agile encryption in its `EncryptedPackage` stream and the
+//! parameters in `EncryptionInfo`, see [MS-OFFCRYPTO] 2.3.4.10 to 2.3.4.15.
+
+use std::io::{Read, Seek};
+
+use aes::{Aes128, Aes192, Aes256};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use cbc::cipher::{block_padding::NoPadding, BlockCipher, BlockDecryptMut, KeyInit, KeyIvInit};
+use cfb::CompoundFile;
+use quick_xml::events::{BytesStart, Event};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+// The only key encryptor a password can open, the other is for certificates
+const PASSWORD_KEY_ENCRYPTOR: &str =
+    "http://schemas.microsoft.com/office/2006/keyEncryptor/password";
+
+// Hashed with the password's key to make the key for each value the password decrypts
+const VERIFIER_INPUT_BLOCK: [u8; 8] = [0xfe, 0xa7, 0xd2, 0x76, 0x3b, 0x4b, 0x9e, 0x79];
+const VERIFIER_HASH_BLOCK: [u8; 8] = [0xd7, 0xaa, 0x0f, 0x6d, 0x30, 0x61, 0x34, 0x4e];
+const KEY_VALUE_BLOCK: [u8; 8] = [0x14, 0x6e, 0x0b, 0xe7, 0xab, 0xac, 0xd0, 0xd6];
+
+// The package is encrypted in segments of this size, each with an IV of its own
+const SEGMENT_LEN: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    fn hash(self, parts: &[&[u8]]) -> Vec<u8> {
+        fn hash<D: Digest>(parts: &[&[u8]]) -> Vec<u8> {
+            let mut hasher = D::new();
+            for part in parts {
+                hasher.update(part);
+            }
+            hasher.finalize().to_vec()
+        }
+
+        match self {
+            HashAlgorithm::Sha1 => hash::<Sha1>(parts),
+            HashAlgorithm::Sha256 => hash::<Sha256>(parts),
+            HashAlgorithm::Sha384 => hash::<Sha384>(parts),
+            HashAlgorithm::Sha512 => hash::<Sha512>(parts),
+        }
+    }
+}
+
+// The attributes `keyData` and `encryptedKey` share
+#[derive(Debug)]
+struct CipherParams {
+    salt: Vec<u8>,
+    block_size: usize,
+    key_len: usize,
+    hash_size: usize,
+    hash: HashAlgorithm,
+}
+
+// The password's `encryptedKey`, which holds the key the package is encrypted with
+#[derive(Debug)]
+struct PasswordKey {
+    params: CipherParams,
+    spin_count: u32,
+    verifier_hash_input: Vec<u8>,
+    verifier_hash_value: Vec<u8>,
+    key_value: Vec<u8>,
+}
+
+/// Decrypts the workbook in the compound file `reader` with `password`, returning the
+/// xlsx zip it holds.
+pub(crate) fn decrypt_workbook<R: Read + Seek>(reader: R, password: &str) -> Result<Vec<u8>> {
+    let mut file = CompoundFile::open(reader)?;
+    let info = read_stream(&mut file, "/EncryptionInfo")?;
+    let package = read_stream(&mut file, "/EncryptedPackage")?;
+
+    let (key_data, password_key) = read_encryption_info(&info)?;
+    let key = unlock_key(&password_key, password, key_data.key_len)?;
+    decrypt_package(&package, &key, &key_data)
+}
+
+fn read_stream<R: Read + Seek>(file: &mut CompoundFile<R>, name: &str) -> Result<Vec<u8>> {
+    let mut stream = file
+        .open_stream(name)
+        .map_err(|e| anyhow!("can't read the {} stream: {}", &name[1..], e))?;
+    let mut bytes = Vec::new();
+    stream.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+// The package's `keyData` and the password's key, from the XML after the version
+fn read_encryption_info(info: &[u8]) -> Result<(CipherParams, PasswordKey)> {
+    let version = info.get(..4).map(|x| {
+        (
+            u16::from_le_bytes([x[0], x[1]]),
+            u16::from_le_bytes([x[2], x[3]]),
+        )
+    });
+    match version {
+        Some((4, 4)) => {}
+        // Version 2, 3 or 4 with a minor version of 2 is standard encryption, from Excel
+        // 2007, and 3 the extensible encryption nothing writes
+        Some((major, minor)) => bail!(
+            "unsupported encryption: version {}.{}, only the agile encryption of Excel 2010 \
+             and later can be read",
+            major,
+            minor
+        ),
+        None => bail!("the EncryptionInfo stream is too short"),
+    }
+
+    let mut reader = quick_xml::Reader::from_reader(&info[8..]);
+    let (mut key_data, mut password_key) = (None, None);
+    let mut in_password_encryptor = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                b"keyData" => key_data = Some(cipher_params(&e)?),
+                b"keyEncryptor" => {
+                    in_password_encryptor = attribute(&e, "uri")? == PASSWORD_KEY_ENCRYPTOR
+                }
+                b"encryptedKey" if in_password_encryptor => {
+                    password_key = Some(PasswordKey {
+                        params: cipher_params(&e)?,
+                        spin_count: number(&e, "spinCount")?,
+                        verifier_hash_input: base64(&e, "encryptedVerifierHashInput")?,
+                        verifier_hash_value: base64(&e, "encryptedVerifierHashValue")?,
+                        key_value: base64(&e, "encryptedKeyValue")?,
+                    })
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    match (key_data, password_key) {
+        (Some(key_data), Some(password_key)) => Ok((key_data, password_key)),
+        (None, _) => bail!("EncryptionInfo has no keyData"),
+        (_, None) => bail!("the workbook isn't encrypted with a password"),
+    }
+}
+
+fn cipher_params(e: &BytesStart) -> Result<CipherParams> {
+    let cipher = attribute(e, "cipherAlgorithm")?;
+    let chaining = attribute(e, "cipherChaining")?;
+    if cipher != "AES" || chaining != "ChainingModeCBC" {
+        bail!("unsupported encryption: {} in {}", cipher, chaining);
+    }
+
+    let hash = match attribute(e, "hashAlgorithm")?.as_str() {
+        "SHA1" => HashAlgorithm::Sha1,
+        "SHA256" => HashAlgorithm::Sha256,
+        "SHA384" => HashAlgorithm::Sha384,
+        "SHA512" => HashAlgorithm::Sha512,
+        other => bail!("unsupported encryption: {} hashes", other),
+    };
+    let key_bits: usize = number(e, "keyBits")?;
+    if !matches!(key_bits, 128 | 192 | 256) {
+        bail!("unsupported encryption: {}-bit AES keys", key_bits);
+    }
+
+    Ok(CipherParams {
+        salt: base64(e, "saltValue")?,
+        block_size: number(e, "blockSize")?,
+        key_len: key_bits / 8,
+        hash_size: number(e, "hashSize")?,
+        hash,
+    })
+}
+
+fn attribute(e: &BytesStart, name: &str) -> Result<String> {
+    let value = e.try_get_attribute(name)?.ok_or_else(|| {
+        anyhow!(
+            "EncryptionInfo has no {} in {}",
+            name,
+            String::from_utf8_lossy(e.local_name().as_ref())
+        )
+    })?;
+    let value = String::from_utf8_lossy(&value.value);
+    Ok(quick_xml::escape::unescape(&value)?.into_owned())
+}
+
+fn number<T: std::str::FromStr>(e: &BytesStart, name: &str) -> Result<T> {
+    let value = attribute(e, name)?;
+    value
+        .parse()
+        .map_err(|_| anyhow!("EncryptionInfo has {}={:?}, not a number", name, value))
+}
+
+fn base64(e: &BytesStart, name: &str) -> Result<Vec<u8>> {
+    STANDARD
+        .decode(attribute(e, name)?)
+        .map_err(|e| anyhow!("EncryptionInfo has {} that isn't base64: {}", name, e))
+}
+
+// Derives the password's key, checks it against the verifier and decrypts the package's
+// key with it
+fn unlock_key(password_key: &PasswordKey, password: &str, key_len: usize) -> Result<Vec<u8>> {
+    let params = &password_key.params;
+    let password: Vec<u8> = password.encode_utf16().flat_map(u16::to_le_bytes).collect();
+
+    // The spin count makes guessing passwords slow, 100,000 hashes by default
+    let mut hash = params.hash.hash(&[&params.salt, &password]);
+    for i in 0..password_key.spin_count {
+        hash = params.hash.hash(&[&i.to_le_bytes(), &hash]);
+    }
+
+    let iv = resized(params.salt.clone(), params.block_size);
+    let decrypt = |block: &[u8], data: &[u8]| {
+        let key = resized(params.hash.hash(&[&hash, block]), params.key_len);
+        aes_cbc_decrypt(&key, &iv, data)
+    };
+
+    let verifier = decrypt(&VERIFIER_INPUT_BLOCK, &password_key.verifier_hash_input)?;
+    let verifier_hash = decrypt(&VERIFIER_HASH_BLOCK, &password_key.verifier_hash_value)?;
+    let expected = params
+        .hash
+        .hash(&[&verifier[..params.salt.len().min(verifier.len())]]);
+    if verifier_hash.get(..params.hash_size) != expected.get(..params.hash_size) {
+        bail!("the password is wrong");
+    }
+
+    let key = decrypt(&KEY_VALUE_BLOCK, &password_key.key_value)?;
+    key.get(..key_len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow!("the encrypted key is too short"))
+}
+
+// The package is the zip's length as 8 bytes, then the zip in segments
+fn decrypt_package(package: &[u8], key: &[u8], key_data: &CipherParams) -> Result<Vec<u8>> {
+    let Some((len, segments)) = package.split_first_chunk::<8>() else {
+        bail!("the EncryptedPackage stream is too short");
+    };
+    let len = u64::from_le_bytes(*len) as usize;
+
+    let mut zip = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.chunks(SEGMENT_LEN).enumerate() {
+        let salt = key_data
+            .hash
+            .hash(&[&key_data.salt, &(i as u32).to_le_bytes()]);
+        let iv = resized(salt, key_data.block_size);
+        zip.extend(aes_cbc_decrypt(key, &iv, segment)?);
+    }
+
+    if zip.len() < len {
+        bail!("the EncryptedPackage stream is shorter than the workbook it holds");
+    }
+    zip.truncate(len);
+    Ok(zip)
+}
+
+// Keys and IVs are cut to length, or padded with 0x36
+fn resized(mut bytes: Vec<u8>, len: usize) -> Vec<u8> {
+    bytes.resize(len, 0x36);
+    bytes
+}
+
+fn aes_cbc_decrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    fn decrypt<C>(key: &[u8], iv: &[u8], data: &mut [u8]) -> Result<()>
+    where
+        C: BlockCipher + BlockDecryptMut + KeyInit,
+    {
+        cbc::Decryptor::<C>::new_from_slices(key, iv)
+            .map_err(|_| anyhow!("unsupported encryption: {}-byte IVs", iv.len()))?
+            .decrypt_padded_mut::<NoPadding>(data)
+            .map_err(|_| anyhow!("encrypted data isn't a whole number of blocks"))?;
+        Ok(())
+    }
+
+    let mut data = data.to_vec();
+    match key.len() {
+        16 => decrypt::<Aes128>(key, iv, &mut data)?,
+        24 => decrypt::<Aes192>(key, iv, &mut data)?,
+        _ => decrypt::<Aes256>(key, iv, &mut data)?,
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn keys_and_ivs_are_cut_or_padded() {
+        assert_eq!(resized(vec![1, 2, 3], 2), [1, 2]);
+        assert_eq!(resized(vec![1], 3), [1, 0x36, 0x36]);
+    }
+
+    #[test]
+    fn standard_encryption_is_an_unsupported_scheme() {
+        let mut info = vec![4, 0, 2, 0, 0x24, 0, 0, 0];
+        info.extend([0; 32]);
+        let e = read_encryption_info(&info).unwrap_err().to_string();
+        assert_eq!(
+            e,
+            "unsupported encryption: version 4.2, only the agile encryption of Excel 2010 and \
+             later can be read"
+        );
+    }
+
+    #[test]
+    fn files_that_arent_compound_files_are_an_error() {
+        assert!(decrypt_workbook(Cursor::new(b"PK\x03\x04".to_vec()), "x").is_err());
+    }
+}
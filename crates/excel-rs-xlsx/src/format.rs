@@ -1,14 +1,75 @@
-use std::io::{Result, Seek, Write};
+use std::{
+    borrow::Cow,
+    io::{Result, Seek, Write},
+};
 
 use zip::{write::SimpleFileOptions, ZipWriter};
 
+use super::{
+    chart::Drawings,
+    style::Styles,
+    typed_sheet::{SharedStrings, SheetBuffers},
+    workbook::CalcMode,
+};
+
+pub struct SheetEntry {
+    pub id: u16,
+    pub name: String,
+}
+
+/// The workbook window Excel opens with. `active_tab` is the index of the selected sheet
+/// in the workbook's sheet order.
+pub struct BookView {
+    pub active_tab: usize,
+    pub window_width: u32,
+    pub window_height: u32,
+}
+
 pub struct XlsxFormatter<W: Write + Seek> {
     pub zip_writer: ZipWriter<W>,
+    pub styles: Styles,
+    pub sheet_buffers: SheetBuffers,
+    pub drawings: Drawings,
+    // Set once shared strings are enabled, and filled as sheets are written
+    pub shared_strings: Option<SharedStrings>,
+    // The id of the sheet being written, until it's closed
+    pub open_sheet: Option<u16>,
+    // Written to docProps/app.xml as the program that made the file
+    pub application: String,
+    // Written to docProps/custom.xml as text properties, if there are any
+    pub custom_properties: Vec<(String, String)>,
+    // A JPEG written to docProps/thumbnail.jpeg, if set
+    pub thumbnail: Option<Vec<u8>>,
+    pub minimal_xml: bool,
+    // Written to xl/workbook.xml as calcPr, if set
+    pub calc_mode: Option<CalcMode>,
 }
 
 impl<W: Write + Seek> XlsxFormatter<W> {
     pub fn new(zip_writer: ZipWriter<W>) -> Self {
-        XlsxFormatter { zip_writer }
+        XlsxFormatter {
+            zip_writer,
+            styles: Styles::new(),
+            sheet_buffers: SheetBuffers::default(),
+            drawings: Drawings::default(),
+            shared_strings: None,
+            open_sheet: None,
+            application: format!("excel-rs v{}", env!("CARGO_PKG_VERSION")),
+            custom_properties: Vec::new(),
+            thumbnail: None,
+            minimal_xml: false,
+            calc_mode: None,
+        }
+    }
+
+    // The parts written in finish are small and indented for reading, so they're
+    // buffered whole and minified here when asked
+    fn write_xml(&mut self, xml: &[u8]) -> Result<()> {
+        if self.minimal_xml {
+            return self.zip_writer.write_all(&minify(xml));
+        }
+        self.zip_writer.write_all(xml_declaration(false))?;
+        self.zip_writer.write_all(xml)
     }
 
     // pub fn write_sheet(&mut self, sheet: Sheet) -> Result<()> {
@@ -26,16 +87,68 @@ impl<W: Write + Seek> XlsxFormatter<W> {
     //     Ok(())
     // }
 
-    pub fn finish(mut self, num_of_sheets: u16) -> Result<W> {
+    /// Writes a table of contents sheet with an internal hyperlink to each of `sheets`.
+    pub fn write_toc_sheet(&mut self, id: u16, sheets: &[SheetEntry]) -> Result<()> {
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(Some(1));
+
+        self.zip_writer
+            .start_file(format!("xl/worksheets/sheet{}.xml", id), options)?;
+
+        let nl = newline(self.minimal_xml);
+        self.zip_writer
+            .write_all(xml_declaration(self.minimal_xml))?;
+        self.zip_writer.write_all(b"<worksheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\">")?;
+        self.zip_writer.write_all(nl)?;
+        self.zip_writer.write_all(b"<sheetViews><sheetView workbookViewId=\"0\"><pane ySplit=\"1\" topLeftCell=\"A2\" activePane=\"bottomLeft\" state=\"frozen\"/><selection pane=\"bottomLeft\" activeCell=\"A2\" sqref=\"A2\"/></sheetView></sheetViews>")?;
+        self.zip_writer.write_all(nl)?;
+        self.zip_writer.write_all(b"<sheetData>")?;
+        self.zip_writer.write_all(nl)?;
+        self.zip_writer
+            .write_all(b"<row r=\"1\"><c r=\"A1\" t=\"str\"><v>Sheet</v></c></row>")?;
+
+        for (i, sheet) in sheets.iter().enumerate() {
+            write!(
+                self.zip_writer,
+                "<row r=\"{}\"><c r=\"A{}\" t=\"str\"><v>{}</v></c></row>",
+                i + 2,
+                i + 2,
+                escape_xml(&sheet.name)
+            )?;
+        }
+
+        self.zip_writer.write_all(nl)?;
+        self.zip_writer.write_all(b"</sheetData>")?;
+        self.zip_writer.write_all(nl)?;
+        self.zip_writer.write_all(b"<hyperlinks>")?;
+
+        for (i, sheet) in sheets.iter().enumerate() {
+            write!(
+                self.zip_writer,
+                "<hyperlink ref=\"A{}\" location=\"{}\" display=\"{}\"/>",
+                i + 2,
+                escape_xml(&format!("'{}'!A1", sheet.name.replace('\'', "''"))),
+                escape_xml(&sheet.name)
+            )?;
+        }
+
+        self.zip_writer.write_all(b"</hyperlinks>")?;
+        self.zip_writer.write_all(nl)?;
+        self.zip_writer.write_all(b"</worksheet>")?;
+        self.zip_writer.write_all(nl)
+    }
+
+    pub fn finish(mut self, sheets: &[SheetEntry], book_view: Option<&BookView>) -> Result<W> {
         let options = SimpleFileOptions::default();
-        self.write_content_type(&options, num_of_sheets)?;
+        self.write_content_type(&options, sheets)?;
         self.write_rels(&options)?;
         self.write_doc_props(&options)?;
         self.write_styles(&options)?;
         self.write_shared_strings(&options)?;
-        self.write_work_book(&options, num_of_sheets)?;
+        self.write_work_book(&options, sheets, book_view)?;
         self.write_calc_chain(&options)?;
-        self.write_xl_rels(&options, num_of_sheets)?;
+        self.write_xl_rels(&options, sheets)?;
         self.write_theme(&options)?;
         let result = self.zip_writer.finish()?;
         Ok(result)
@@ -44,39 +157,69 @@ impl<W: Write + Seek> XlsxFormatter<W> {
     fn write_content_type(
         &mut self,
         options: &SimpleFileOptions,
-        num_of_sheets: u16,
+        sheets: &[SheetEntry],
     ) -> Result<()> {
         self.zip_writer
             .start_file("[Content_Types].xml", *options)?;
+        let mut xml = Vec::new();
         write!(
-            self.zip_writer,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types" xmlns:xsd="http://www.w3.org/2001/XMLSchema" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"><Default Extension="xml" ContentType="application/xml"/><Default Extension="bin" ContentType="application/vnd.ms-excel.sheet.binary.macroEnabled.main"/><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>"#
+            xml,
+            r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types" xmlns:xsd="http://www.w3.org/2001/XMLSchema" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"><Default Extension="xml" ContentType="application/xml"/><Default Extension="bin" ContentType="application/vnd.ms-excel.sheet.binary.macroEnabled.main"/><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="vml" ContentType="application/vnd.openxmlformats-officedocument.vmlDrawing"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>"#
         )?;
-        for i in 0..num_of_sheets {
-            writeln!(self.zip_writer, "<Override PartName=\"/xl/worksheets/sheet{}.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>", i + 1)?;
+        if self.thumbnail.is_some() {
+            write!(
+                xml,
+                r#"<Default Extension="jpeg" ContentType="image/jpeg"/>"#
+            )?;
+        }
+        for sheet in sheets {
+            writeln!(xml, "<Override PartName=\"/xl/worksheets/sheet{}.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>", sheet.id)?;
+        }
+        for id in self.drawings.sheets.iter() {
+            write!(xml, "<Override PartName=\"/xl/drawings/drawing{}.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.drawing+xml\"/>", id)?;
+        }
+        for id in self.drawings.notes.iter() {
+            write!(xml, "<Override PartName=\"/xl/comments{}.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.comments+xml\"/>", id)?;
+        }
+        for id in 1..=self.drawings.charts {
+            write!(xml, "<Override PartName=\"/xl/charts/chart{}.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.drawingml.chart+xml\"/>", id)?;
         }
         write!(
-            self.zip_writer,
-            r#"<Override PartName="/xl/theme/theme1.xml" ContentType="application/vnd.openxmlformats-officedocument.theme+xml"/><Override PartName="/xl/sharedStrings.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sharedStrings+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/><Override PartName="/docProps/core.xml" ContentType="application/vnd.openxmlformats-package.core-properties+xml"/><Override PartName="/docProps/app.xml" ContentType="application/vnd.openxmlformats-officedocument.extended-properties+xml"/></Types>"#
-        )
+            xml,
+            r#"<Override PartName="/xl/theme/theme1.xml" ContentType="application/vnd.openxmlformats-officedocument.theme+xml"/><Override PartName="/xl/sharedStrings.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sharedStrings+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/><Override PartName="/docProps/core.xml" ContentType="application/vnd.openxmlformats-package.core-properties+xml"/><Override PartName="/docProps/app.xml" ContentType="application/vnd.openxmlformats-officedocument.extended-properties+xml"/>"#
+        )?;
+        if !self.custom_properties.is_empty() {
+            write!(xml, "<Override PartName=\"/docProps/custom.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.custom-properties+xml\"/>")?;
+        }
+        write!(xml, "</Types>")?;
+        self.write_xml(&xml)
     }
 
     fn write_rels(&mut self, options: &SimpleFileOptions) -> Result<()> {
         self.zip_writer.start_file("_rels/.rels", *options)?;
+        let mut xml = Vec::new();
         write!(
-            self.zip_writer,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties" Target="docProps/core.xml"/><Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/extended-properties" Target="docProps/app.xml"/><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#
-        )
+            xml,
+            r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties" Target="docProps/core.xml"/><Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/extended-properties" Target="docProps/app.xml"/><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/>"#
+        )?;
+        if !self.custom_properties.is_empty() {
+            write!(xml, "<Relationship Id=\"rId4\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/custom-properties\" Target=\"docProps/custom.xml\"/>")?;
+        }
+        if self.thumbnail.is_some() {
+            write!(xml, "<Relationship Id=\"rId5\" Type=\"http://schemas.openxmlformats.org/package/2006/relationships/metadata/thumbnail\" Target=\"docProps/thumbnail.jpeg\"/>")?;
+        }
+        write!(xml, "</Relationships>")?;
+        self.write_xml(&xml)
     }
 
     fn write_doc_props(&mut self, options: &SimpleFileOptions) -> Result<()> {
         self.zip_writer.start_file("docProps/app.xml", *options)?;
+        let mut xml = Vec::new();
         write!(
-            self.zip_writer,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-        <Properties xmlns="http://schemas.openxmlformats.org/officeDocument/2006/extended-properties"
+            xml,
+            r#"        <Properties xmlns="http://schemas.openxmlformats.org/officeDocument/2006/extended-properties"
                     xmlns:vt="http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes">
-            <Application>SheetJS</Application>
+            <Application>{}</Application>
             <HeadingPairs>
                 <vt:vector size="2" baseType="variant">
                     <vt:variant>
@@ -92,149 +235,189 @@ impl<W: Write + Seek> XlsxFormatter<W> {
                 <vt:lpstr>SheetJS</vt:lpstr>
                 </vt:vector>
             </TitlesOfParts>
-        </Properties>"#
+        </Properties>"#,
+            escape_xml(&self.application)
         )?;
+        self.write_xml(&xml)?;
+
         self.zip_writer.start_file("docProps/core.xml", *options)?;
+        let mut xml = Vec::new();
         write!(
-            self.zip_writer,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-        <cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties"
+            xml,
+            r#"        <cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties"
                            xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:dcterms="http://purl.org/dc/terms/"
                            xmlns:dcmitype="http://purl.org/dc/dcmitype/" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"/>"#
-        )
+        )?;
+        self.write_xml(&xml)?;
+
+        if let Some(thumbnail) = &self.thumbnail {
+            self.zip_writer
+                .start_file("docProps/thumbnail.jpeg", *options)?;
+            self.zip_writer.write_all(thumbnail)?;
+        }
+
+        if self.custom_properties.is_empty() {
+            return Ok(());
+        }
+        self.zip_writer
+            .start_file("docProps/custom.xml", *options)?;
+        let mut xml = Vec::new();
+        write!(
+            xml,
+            r#"<Properties xmlns="http://schemas.openxmlformats.org/officeDocument/2006/custom-properties" xmlns:vt="http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes">"#
+        )?;
+        // Every custom property has this format id, and ids from 2 up
+        for (i, (name, value)) in self.custom_properties.iter().enumerate() {
+            write!(
+                xml,
+                "<property fmtid=\"{{D5CDD505-2E9C-101B-9397-08002B2CF9AE}}\" pid=\"{}\" name=\"{}\"><vt:lpwstr>{}</vt:lpwstr></property>",
+                i + 2,
+                escape_xml(name),
+                escape_xml(value)
+            )?;
+        }
+        write!(xml, "</Properties>")?;
+        self.write_xml(&xml)
     }
 
     fn write_styles(&mut self, options: &SimpleFileOptions) -> Result<()> {
         self.zip_writer.start_file("xl/styles.xml", *options)?;
-        write!(
-            self.zip_writer,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-        <styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"
-                    xmlns:vt="http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes">
-            <fonts count="1">
-                <font>
-                    <sz val="12"/>
-                    <color theme="1"/>
-                    <name val="Calibri"/>
-                    <family val="2"/>
-                    <scheme val="minor"/>
-                </font>
-            </fonts>
-            <fills count="2">
-                <fill>
-                    <patternFill patternType="none"/>
-                </fill>
-                <fill>
-                    <patternFill patternType="gray125"/>
-                </fill>
-            </fills>
-            <borders count="1">
-                <border>
-                    <left/>
-                    <right/>
-                    <top/>
-                    <bottom/>
-                    <diagonal/>
-                </border>
-            </borders>
-        <cellStyleXfs count="1">
-            <xf numFmtId="0" fontId="0" fillId="0" borderId="0"/>
-        </cellStyleXfs>
-        <cellXfs count="1">
-            <xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/>
-            <xf numFmtId="14" borderId="0" fillId="0" fontId="0" xfId="0"/>
-        </cellXfs>
-        <cellStyles count="1">
-            <cellStyle name="Normal" xfId="0" builtinId="0"/>
-        </cellStyles>
-        <dxfs count="0"/>
-        <tableStyles count="0" defaultTableStyle="TableStyleMedium9" defaultPivotStyle="PivotStyleMedium4"/>
-    </styleSheet>"#
-        )
+        self.zip_writer
+            .write_all(xml_declaration(self.minimal_xml))?;
+        self.styles.write_to(&mut self.zip_writer)
     }
 
     fn write_shared_strings(&mut self, options: &SimpleFileOptions) -> Result<()> {
         self.zip_writer
             .start_file("xl/sharedStrings.xml", *options)?;
+        let Some(shared_strings) = &self.shared_strings else {
+            let mut xml = Vec::new();
+            write!(
+                xml,
+                r#"            <sst xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" count="0" uniqueCount="0"></sst>"#
+            )?;
+            return self.write_xml(&xml);
+        };
+
+        // Written straight out rather than minified, which would trim the strings
+        let strings = shared_strings.strings();
+        let out = &mut self.zip_writer;
+        out.write_all(xml_declaration(self.minimal_xml))?;
         write!(
-            self.zip_writer,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-            <sst xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" count="0" uniqueCount="0"></sst>"#
-        )
+            out,
+            r#"<sst xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" count="{}" uniqueCount="{}">"#,
+            shared_strings.count,
+            strings.len()
+        )?;
+        for text in strings {
+            // Sheets only intern valid UTF-8
+            let text = String::from_utf8_lossy(text);
+            // Without this, readers drop spaces at either end
+            let padded =
+                text.starts_with(char::is_whitespace) || text.ends_with(char::is_whitespace);
+            let space = match padded {
+                true => " xml:space=\"preserve\"",
+                false => "",
+            };
+            write!(out, "<si><t{}>{}</t></si>", space, escape_xml(&text))?;
+        }
+        out.write_all(b"</sst>")
     }
 
-    fn write_work_book(&mut self, options: &SimpleFileOptions, num_of_sheets: u16) -> Result<()> {
+    fn write_work_book(
+        &mut self,
+        options: &SimpleFileOptions,
+        sheets: &[SheetEntry],
+        book_view: Option<&BookView>,
+    ) -> Result<()> {
         self.zip_writer.start_file("xl/workbook.xml", *options)?;
+        let mut xml = Vec::new();
         write!(
-            self.zip_writer,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-        <workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">
+            xml,
+            r#"        <workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">
             <workbookPr date1904="false"/>
-            <sheets>
     "#
         )?;
-        for i in 0..num_of_sheets {
+        if let Some(view) = book_view {
             writeln!(
-                self.zip_writer,
-                "<sheet name=\"Sheet {}\" sheetId=\"{}\" r:id=\"rId{}\"/>",
-                i + 1,
-                i + 1,
-                i + 3
+                xml,
+                "<bookViews><workbookView windowWidth=\"{}\" windowHeight=\"{}\" activeTab=\"{}\"/></bookViews>",
+                view.window_width, view.window_height, view.active_tab
+            )?;
+        }
+        writeln!(xml, "<sheets>")?;
+        for sheet in sheets {
+            writeln!(
+                xml,
+                "<sheet name=\"{}\" sheetId=\"{}\" r:id=\"rId{}\"/>",
+                escape_xml(&sheet.name),
+                sheet.id,
+                sheet.id + 2
             )?;
         }
         write!(
-            self.zip_writer,
+            xml,
             r#"
         </sheets>
-    </workbook>
+"#
+        )?;
+        if let Some(mode) = self.calc_mode {
+            writeln!(xml, "<calcPr calcMode=\"{}\"/>", mode.as_str())?;
+        }
+        write!(
+            xml,
+            r#"    </workbook>
     "#
-        )
+        )?;
+        self.write_xml(&xml)
     }
 
     fn write_calc_chain(&mut self, options: &SimpleFileOptions) -> Result<()> {
         self.zip_writer.start_file("xl/calcChain.xml", *options)?;
+        let mut xml = Vec::new();
         write!(
-            self.zip_writer,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-        <calcChain xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"></calcChain>"#
-        )
+            xml,
+            r#"        <calcChain xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"></calcChain>"#
+        )?;
+        self.write_xml(&xml)
     }
 
-    fn write_xl_rels(&mut self, options: &SimpleFileOptions, num_of_sheets: u16) -> Result<()> {
+    fn write_xl_rels(&mut self, options: &SimpleFileOptions, sheets: &[SheetEntry]) -> Result<()> {
         self.zip_writer
             .start_file("xl/_rels/workbook.xml.rels", *options)?;
+        let mut xml = Vec::new();
         write!(
-            self.zip_writer,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-        <Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+            xml,
+            r#"        <Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
         <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/theme" Target="theme/theme1.xml"/>
         <Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>
                 "#
         )?;
         let mut last_rid = 2;
-        for i in 0..num_of_sheets {
+        for sheet in sheets {
             writeln!(
-                self.zip_writer,
-                "<Relationship Id=\"rId{}\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet\" Target=\"worksheets/sheet{}.xml\"/>", i + 3, i + 1
+                xml,
+                "<Relationship Id=\"rId{}\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet\" Target=\"worksheets/sheet{}.xml\"/>", sheet.id + 2, sheet.id
             )?;
-            last_rid = i + 3;
+            last_rid = last_rid.max(sheet.id + 2);
         }
         write!(
-            self.zip_writer,
+            xml,
             r#"
             <Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/sharedStrings" Target="sharedStrings.xml"/>
         </Relationships>"#,
             last_rid + 1
-        )
+        )?;
+        self.write_xml(&xml)
     }
 
     fn write_theme(&mut self, options: &SimpleFileOptions) -> Result<()> {
         self.zip_writer
             .start_file("xl/theme/theme1.xml", *options)?;
+        let mut xml = Vec::new();
         write!(
-            self.zip_writer,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-        <a:theme xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main" name="Office Theme">
+            xml,
+            r#"        <a:theme xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main" name="Office Theme">
             <a:themeElements>
                 <a:clrScheme name="Office">
                     <a:dk1>
@@ -551,6 +734,74 @@ impl<W: Write + Seek> XlsxFormatter<W> {
             </a:objectDefaults>
             <a:extraClrSchemeLst/>
         </a:theme>"#
-        )
+        )?;
+        self.write_xml(&xml)
+    }
+}
+
+// Every part starts with this, unless WorkBook::minimal_xml leaves it out
+pub(crate) fn xml_declaration(minimal: bool) -> &'static [u8] {
+    if minimal {
+        b""
+    } else {
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n"
+    }
+}
+
+// The line break between elements in sheets, left out by WorkBook::minimal_xml
+pub(crate) fn newline(minimal: bool) -> &'static [u8] {
+    if minimal {
+        b""
+    } else {
+        b"\n"
+    }
+}
+
+// Joins the lines of a part written with indentation. Lines are only split between
+// elements and between attributes, so a line break next to a tag is dropped and one
+// inside a tag becomes a space.
+fn minify(xml: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(xml.len());
+    for line in xml.split(|x| *x == b'\n').map(|x| x.trim_ascii()) {
+        if line.is_empty() {
+            continue;
+        }
+        if out.last().is_some_and(|x| *x != b'>') && line[0] != b'<' {
+            out.push(b' ');
+        }
+        out.extend_from_slice(line);
+    }
+    out
+}
+
+pub(crate) fn col_name(col: usize) -> String {
+    let mut name = Vec::new();
+    let mut col = col as i64;
+    while col >= 0 {
+        name.push(b'A' + (col % 26) as u8);
+        col = col / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Raw bytes are written straight into the XML, where invalid UTF-8 makes the whole sheet
+// unreadable. They are replaced with U+FFFD, as from_utf8_lossy does when inferring types
+pub(crate) fn to_valid_utf8(bytes: &[u8]) -> Cow<'_, [u8]> {
+    // Most fields are ASCII, which is cheaper to check for
+    if bytes.is_ascii() {
+        return Cow::Borrowed(bytes);
+    }
+    match String::from_utf8_lossy(bytes) {
+        Cow::Borrowed(_) => Cow::Borrowed(bytes),
+        Cow::Owned(text) => Cow::Owned(text.into_bytes()),
     }
 }
@@ -1,18 +1,29 @@
 
+#[cfg(any(test, feature = "calamine"))]
+mod encryption;
 mod format;
+mod note;
+pub mod chart;
+pub mod columnar_sheet;
+pub mod conditional;
+pub mod encoding;
 pub mod workbook;
+#[cfg(any(test, feature = "calamine"))]
+pub mod reader;
+pub mod rows;
 pub mod sheet;
+pub mod style;
 pub mod typed_sheet;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
-pub use workbook::WorkBook;
-
-// TODO: Implement Tests
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn it_works() {
-//         assert_eq!(1, 1);
-//     }
-// }
+pub use chart::{ChartSpec, ChartType};
+pub use conditional::TextOp;
+pub use encoding::XmlEncoding;
+#[cfg(feature = "calamine")]
+pub use reader::{read_encrypted_workbook, read_workbook, CalamineBackedReader, ParsedCell};
+pub use rows::{write_xlsx, CellValue, WriteOptions};
+pub use sheet::{validate_position, MAX_COLS, MAX_ROWS};
+pub use style::{Border, BorderStyle, Fill, Format, HeaderStyle, HorizontalAlign, PatternType};
+pub use typed_sheet::DateMode;
+pub use workbook::{CalcMode, WorkBook};
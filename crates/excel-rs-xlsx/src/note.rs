@@ -0,0 +1,68 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use super::format::{col_name, escape_xml};
+
+pub(crate) struct Note {
+    pub(crate) row: u32,
+    pub(crate) col: usize,
+    pub(crate) text: String,
+}
+
+impl Note {
+    fn cell(&self) -> String {
+        format!("{}{}", col_name(self.col), self.row + 1)
+    }
+}
+
+/// Writes the notes' text, all under a single blank author.
+pub(crate) fn write_comments<X: Write>(writer: &mut X, notes: &[Note]) -> Result<()> {
+    writer.write_all(b"<comments xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\"><authors><author></author></authors><commentList>")?;
+    for note in notes {
+        write!(
+            writer,
+            "<comment ref=\"{}\" authorId=\"0\"><text><t xml:space=\"preserve\">{}</t></text></comment>",
+            note.cell(),
+            escape_xml(&note.text)
+        )?;
+    }
+    writer.write_all(b"</commentList></comments>")?;
+    Ok(())
+}
+
+/// The blocks of 1024 VML shape ids `notes` take, one more than the full blocks they fill.
+pub(crate) fn vml_blocks(notes: &[Note]) -> u32 {
+    (notes.len() / 1024 + 1) as u32
+}
+
+/// Writes the boxes the notes are shown in. Excel only shows notes that have one, in this
+/// legacy VML format. Shape ids must be unique across the workbook, so each sheet takes
+/// `vml_blocks` blocks of 1024 from `first_block`, after those of the sheets before it.
+pub(crate) fn write_vml<X: Write>(writer: &mut X, notes: &[Note], first_block: u32) -> Result<()> {
+    let blocks: Vec<String> = (first_block..first_block + vml_blocks(notes))
+        .map(|x| x.to_string())
+        .collect();
+    write!(
+        writer,
+        "<xml xmlns:v=\"urn:schemas-microsoft-com:vml\" xmlns:o=\"urn:schemas-microsoft-com:office:office\" xmlns:x=\"urn:schemas-microsoft-com:office:excel\"><o:shapelayout v:ext=\"edit\"><o:idmap v:ext=\"edit\" data=\"{}\"/></o:shapelayout><v:shapetype id=\"_x0000_t202\" coordsize=\"21600,21600\" o:spt=\"202\" path=\"m,l,21600r21600,l21600,xe\"><v:stroke joinstyle=\"miter\"/><v:path gradientshapeok=\"t\" o:connecttype=\"rect\"/></v:shapetype>",
+        blocks.join(",")
+    )?;
+    for (i, note) in notes.iter().enumerate() {
+        // Hidden until hovered, in a box to the right of the cell
+        write!(
+            writer,
+            "<v:shape id=\"_x0000_s{}\" type=\"#_x0000_t202\" style=\"position:absolute;margin-left:59.25pt;margin-top:1.5pt;width:108pt;height:59.25pt;z-index:{};visibility:hidden\" fillcolor=\"#ffffe1\" o:insetmode=\"auto\"><v:fill color2=\"#ffffe1\"/><v:shadow on=\"t\" color=\"black\" obscured=\"t\"/><v:path o:connecttype=\"none\"/><v:textbox style=\"mso-direction-alt:auto\"><div style=\"text-align:left\"></div></v:textbox><x:ClientData ObjectType=\"Note\"><x:MoveWithCells/><x:SizeWithCells/><x:Anchor>{}, 15, {}, 10, {}, 15, {}, 4</x:Anchor><x:AutoFill>False</x:AutoFill><x:Row>{}</x:Row><x:Column>{}</x:Column></x:ClientData></v:shape>",
+            first_block as usize * 1024 + i + 1,
+            i + 1,
+            note.col + 1,
+            note.row,
+            note.col + 3,
+            note.row + 4,
+            note.row,
+            note.col
+        )?;
+    }
+    writer.write_all(b"</xml>")?;
+    Ok(())
+}
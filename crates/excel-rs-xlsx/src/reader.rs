@@ -0,0 +1,388 @@
+//! Reads xlsx files with calamine, until the crate has a reader of its own. Needs the
+//! `calamine` feature.
+//!
+//! ```ignore
+//! let mut reader = read_workbook("report.xlsx")?;
+//! for name in reader.sheet_names() {
+//!     let rows = reader.read_sheet(&name)?;
+//! }
+//! // Password-protected workbooks are decrypted in memory first
+//! let mut reader = read_encrypted_workbook("protected.xlsx", "password")?;
+//! ```
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+use calamine::{Data, Range, Reader, Xlsx};
+
+use crate::encryption::decrypt_workbook;
+
+/// A cell as a reader sees it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParsedCell {
+    /// No cell, or a cell with no value
+    Empty,
+    String(String),
+    Number(f64),
+    Bool(bool),
+    /// A number with a date format, as the serial Excel stores
+    Date(f64),
+    /// A `t="d"` cell, as its ISO-8601 text
+    IsoDate(String),
+    /// An error value like `#N/A`
+    Error(String),
+}
+
+impl From<&Data> for ParsedCell {
+    fn from(data: &Data) -> Self {
+        match data {
+            Data::Empty => ParsedCell::Empty,
+            Data::String(x) => ParsedCell::String(x.clone()),
+            Data::Float(x) => ParsedCell::Number(*x),
+            Data::Int(x) => ParsedCell::Number(*x as f64),
+            Data::Bool(x) => ParsedCell::Bool(*x),
+            Data::DateTime(x) => ParsedCell::Date(x.as_f64()),
+            Data::DateTimeIso(x) | Data::DurationIso(x) => ParsedCell::IsoDate(x.clone()),
+            Data::Error(x) => ParsedCell::Error(x.to_string()),
+        }
+    }
+}
+
+/// An xlsx workbook opened for reading.
+pub struct CalamineBackedReader<R: Read + Seek> {
+    workbook: Xlsx<R>,
+}
+
+// Encrypted workbooks are stored in an OLE compound file rather than a zip, as are legacy
+// .xls files, and start with its signature
+const COMPOUND_FILE_MAGIC: [u8; 8] = [0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+// What a compound file holds, neither of which is a zip calamine can read
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CompoundFile {
+    Encrypted,
+    LegacyXls,
+}
+
+impl CompoundFile {
+    fn error(self) -> &'static str {
+        match self {
+            CompoundFile::Encrypted => "the workbook is encrypted, so it needs a password",
+            CompoundFile::LegacyXls => "it's a legacy .xls workbook, only xlsx is supported",
+        }
+    }
+}
+
+/// Opens the xlsx file at `path` for reading. Fails with "the workbook is encrypted, so it
+/// needs a password" for a password-protected workbook, which
+/// [`read_encrypted_workbook`] reads.
+pub fn read_workbook<P: AsRef<Path>>(path: P) -> Result<CalamineBackedReader<BufReader<File>>> {
+    let path = path.as_ref();
+    let mut file = BufReader::new(
+        File::open(path).map_err(|e| anyhow!("can't read {}: {}", path.display(), e))?,
+    );
+    if let Some(kind) = compound_file(&mut file)? {
+        bail!("can't read {} as xlsx: {}", path.display(), kind.error());
+    }
+    let workbook =
+        Xlsx::new(file).map_err(|e| anyhow!("can't read {} as xlsx: {}", path.display(), e))?;
+    Ok(CalamineBackedReader { workbook })
+}
+
+/// Opens the password-protected xlsx file at `path` for reading, decrypting the whole
+/// workbook into memory with `password`. Only the agile encryption Excel has used since
+/// 2010 can be read. Workbooks that aren't encrypted are read as they are.
+pub fn read_encrypted_workbook<P: AsRef<Path>>(
+    path: P,
+    password: &str,
+) -> Result<CalamineBackedReader<Cursor<Vec<u8>>>> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| anyhow!("can't read {}: {}", path.display(), e))?;
+    let zip = decrypted(BufReader::new(file), password)
+        .map_err(|e| anyhow!("can't read {} as xlsx: {}", path.display(), e))?;
+    let workbook =
+        Xlsx::new(zip).map_err(|e| anyhow!("can't read {} as xlsx: {}", path.display(), e))?;
+    Ok(CalamineBackedReader { workbook })
+}
+
+// The zip in `reader`, decrypted with `password` if it's encrypted
+fn decrypted<R: Read + Seek>(mut reader: R, password: &str) -> Result<Cursor<Vec<u8>>> {
+    let zip = match compound_file(&mut reader)? {
+        None => {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+            bytes
+        }
+        Some(CompoundFile::Encrypted) => decrypt_workbook(reader, password)?,
+        Some(kind) => bail!(kind.error()),
+    };
+    Ok(Cursor::new(zip))
+}
+
+// What `reader` holds if it's a compound file. The position is left where it was
+fn compound_file<R: Read + Seek>(reader: &mut R) -> io::Result<Option<CompoundFile>> {
+    let start = reader.stream_position()?;
+    let mut magic = Vec::with_capacity(COMPOUND_FILE_MAGIC.len());
+    reader
+        .by_ref()
+        .take(COMPOUND_FILE_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    if magic != COMPOUND_FILE_MAGIC {
+        reader.seek(SeekFrom::Start(start))?;
+        return Ok(None);
+    }
+
+    // The encrypted zip is kept in a stream of this name, in UTF-16 like every stream name
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    reader.seek(SeekFrom::Start(start))?;
+    let name: Vec<u8> = "EncryptedPackage"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    Ok(Some(if bytes.windows(name.len()).any(|x| x == name) {
+        CompoundFile::Encrypted
+    } else {
+        CompoundFile::LegacyXls
+    }))
+}
+
+impl<R: Read + Seek> CalamineBackedReader<R> {
+    /// Reads an xlsx file from `reader`, e.g. a `Cursor` over a workbook written in memory.
+    pub fn from_reader(mut reader: R) -> Result<Self> {
+        if let Some(kind) = compound_file(&mut reader)? {
+            bail!("not a readable xlsx file: {}", kind.error());
+        }
+        let workbook = Xlsx::new(reader).map_err(|e| anyhow!("not a readable xlsx file: {}", e))?;
+        Ok(CalamineBackedReader { workbook })
+    }
+
+    /// The names of the sheets, in workbook order.
+    pub fn sheet_names(&self) -> Vec<String> {
+        self.workbook.sheet_names()
+    }
+
+    /// Reads every cell of `sheet`, as rows starting from A1. Rows are as long as the
+    /// longest row written, padded with `ParsedCell::Empty`.
+    pub fn read_sheet(&mut self, sheet: &str) -> Result<Vec<Vec<ParsedCell>>> {
+        let range = self
+            .workbook
+            .worksheet_range(sheet)
+            .map_err(|e| anyhow!("can't read sheet {:?}: {}", sheet, e))?;
+        Ok(range_to_rows(&range))
+    }
+}
+
+impl CalamineBackedReader<Cursor<Vec<u8>>> {
+    /// Reads a password-protected xlsx file from `reader`, decrypting the whole workbook
+    /// into memory with `password`. Workbooks that aren't encrypted are read as they are.
+    pub fn from_encrypted_reader<R: Read + Seek>(reader: R, password: &str) -> Result<Self> {
+        let zip =
+            decrypted(reader, password).map_err(|e| anyhow!("not a readable xlsx file: {}", e))?;
+        let workbook = Xlsx::new(zip).map_err(|e| anyhow!("not a readable xlsx file: {}", e))?;
+        Ok(CalamineBackedReader { workbook })
+    }
+}
+
+// The range starts at the first cell with a value, rather than at A1
+fn range_to_rows(range: &Range<Data>) -> Vec<Vec<ParsedCell>> {
+    let Some((first_row, first_col)) = range.start() else {
+        return Vec::new();
+    };
+    let width = first_col as usize + range.width();
+
+    let mut rows = vec![vec![ParsedCell::Empty; width]; first_row as usize];
+    for row in range.rows() {
+        let mut cells = vec![ParsedCell::Empty; first_col as usize];
+        cells.extend(row.iter().map(ParsedCell::from));
+        rows.push(cells);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::typed_sheet::{TYPE_BOOLEAN, TYPE_DATE, TYPE_NUMBER, TYPE_STRING};
+    use crate::WorkBook;
+
+    // The start of a compound file holding `streams`, with each name as it is stored in
+    // the file's directory. Enough to be told apart, though not a file Excel would open
+    fn compound_file(streams: &[&str]) -> Vec<u8> {
+        let mut bytes = COMPOUND_FILE_MAGIC.to_vec();
+        bytes.resize(512, 0);
+        for name in streams {
+            let mut entry: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            entry.resize(128, 0);
+            bytes.extend(entry);
+        }
+        bytes
+    }
+
+    fn error(bytes: Vec<u8>) -> String {
+        match CalamineBackedReader::from_reader(Cursor::new(bytes)) {
+            Ok(_) => panic!("read as xlsx"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn encrypted_workbooks_are_an_error() {
+        let encrypted = compound_file(&["Root Entry", "EncryptionInfo", "EncryptedPackage"]);
+        assert_eq!(
+            error(encrypted.clone()),
+            "not a readable xlsx file: the workbook is encrypted, so it needs a password"
+        );
+
+        let path = std::env::temp_dir().join(format!("encrypted-{}.xlsx", std::process::id()));
+        std::fs::write(&path, encrypted).unwrap();
+        let e = read_workbook(&path).err().unwrap().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            e,
+            format!(
+                "can't read {} as xlsx: the workbook is encrypted, so it needs a password",
+                path.display()
+            )
+        );
+    }
+
+    // Written by umya-spreadsheet's write_with_password, with agile encryption using
+    // AES-256, SHA-512 and a spin count of 100,000
+    const ENCRYPTED: &[u8] = include_bytes!("../tests/fixtures/encrypted.xlsx");
+    const PASSWORD: &str = "excel-rs";
+
+    #[test]
+    fn encrypted_workbooks_are_read_with_their_password() {
+        let mut reader =
+            CalamineBackedReader::from_encrypted_reader(Cursor::new(ENCRYPTED), PASSWORD).unwrap();
+        assert_eq!(reader.sheet_names(), ["Sheet1"]);
+        assert_eq!(
+            reader.read_sheet("Sheet1").unwrap(),
+            [
+                [
+                    ParsedCell::String(String::from("Name")),
+                    ParsedCell::String(String::from("Age")),
+                ],
+                [
+                    ParsedCell::String(String::from("Ada")),
+                    ParsedCell::Number(36.0),
+                ],
+                [
+                    ParsedCell::String(String::from("Grace")),
+                    ParsedCell::Number(85.5),
+                ],
+            ]
+        );
+
+        let path = std::env::temp_dir().join(format!("protected-{}.xlsx", std::process::id()));
+        std::fs::write(&path, ENCRYPTED).unwrap();
+        let read = read_encrypted_workbook(&path, PASSWORD).map(|mut x| x.read_sheet("Sheet1"));
+        let wrong = read_encrypted_workbook(&path, "excel-RS").err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap().unwrap().len(), 3);
+        assert_eq!(
+            wrong.to_string(),
+            format!(
+                "can't read {} as xlsx: the password is wrong",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn workbooks_without_encryption_are_read_with_any_password() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        worksheet.write_row(vec![b"Name"], &vec![]).unwrap();
+        worksheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let mut reader =
+            CalamineBackedReader::from_encrypted_reader(Cursor::new(bytes), "unused").unwrap();
+        assert_eq!(
+            reader.read_sheet("Sheet 1").unwrap(),
+            vec![vec![ParsedCell::String(String::from("Name"))]]
+        );
+
+        let legacy = compound_file(&["Root Entry", "Workbook"]);
+        let e = CalamineBackedReader::from_encrypted_reader(Cursor::new(legacy), "unused");
+        assert_eq!(
+            e.err().unwrap().to_string(),
+            "not a readable xlsx file: it's a legacy .xls workbook, only xlsx is supported"
+        );
+    }
+
+    #[test]
+    fn legacy_xls_workbooks_are_an_error() {
+        assert_eq!(
+            error(compound_file(&["Root Entry", "Workbook"])),
+            "not a readable xlsx file: it's a legacy .xls workbook, only xlsx is supported"
+        );
+    }
+
+    #[test]
+    fn xlsx_is_read_after_the_check() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        worksheet.write_row(vec![b"Name"], &vec![]).unwrap();
+        worksheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let mut reader = CalamineBackedReader::from_reader(Cursor::new(bytes)).unwrap();
+        assert_eq!(
+            reader.read_sheet("Sheet 1").unwrap(),
+            vec![vec![ParsedCell::String(String::from("Name"))]]
+        );
+
+        // Too short to hold the signature
+        assert!(error(vec![0xD0, 0xCF]).starts_with("not a readable xlsx file: "));
+    }
+
+    #[test]
+    fn workbook_written_by_the_crate_reads_back() {
+        let path = std::env::temp_dir().join(format!("read-back-{}.xlsx", std::process::id()));
+        let mut workbook = WorkBook::new(File::create(&path).unwrap());
+        let mut worksheet = workbook.get_typed_worksheet(String::from("People"));
+        let types = vec![
+            TYPE_STRING,
+            TYPE_NUMBER,
+            TYPE_BOOLEAN,
+            TYPE_DATE,
+            TYPE_STRING,
+        ];
+        worksheet
+            .write_row(vec![b"Name", b"Age", b"Active", b"Joined", b"Note"], &types)
+            .unwrap();
+        worksheet
+            .write_row(vec![b"Ada", b"36.5", b"true", b"2024-01-02", b""], &types)
+            .unwrap();
+        worksheet.close().unwrap();
+        workbook
+            .get_typed_worksheet(String::from("Empty"))
+            .close()
+            .unwrap();
+        workbook.finish().unwrap();
+
+        let mut reader = read_workbook(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reader.sheet_names(), ["People", "Empty"]);
+        assert_eq!(
+            reader.read_sheet("People").unwrap()[1],
+            [
+                ParsedCell::String(String::from("Ada")),
+                ParsedCell::Number(36.5),
+                ParsedCell::Bool(true),
+                ParsedCell::Date(45293.0),
+                ParsedCell::String(String::new()),
+            ]
+        );
+        assert_eq!(reader.read_sheet("Empty").unwrap(), Vec::<Vec<_>>::new());
+    }
+}
@@ -0,0 +1,155 @@
+//! Writes a workbook from rows produced by any iterator, e.g. a database cursor or a
+//! filtered stream, without collecting them first.
+//!
+//! ```ignore
+//! let rows = (0..1000).map(|i| vec![CellValue::Number(i as f64)]);
+//! let xlsx = write_xlsx(rows, &WriteOptions { header: false, ..Default::default() })?;
+//! ```
+
+use std::io::Cursor;
+
+use anyhow::Result;
+
+use super::{
+    typed_sheet::{TYPE_BLANK, TYPE_BOOLEAN, TYPE_DATE, TYPE_NUMBER, TYPE_STRING},
+    workbook::WorkBook,
+};
+
+/// A value for one cell, typed by its variant.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CellValue {
+    /// A cell with no value
+    Empty,
+    String(String),
+    /// Written as text if NaN or infinite, which xlsx has no number for
+    Number(f64),
+    Bool(bool),
+    /// An ISO-8601 date or date and time, written like `TYPE_DATE`
+    Date(String),
+}
+
+impl CellValue {
+    fn into_cell(self) -> (Vec<u8>, &'static str) {
+        match self {
+            CellValue::Empty => (Vec::new(), TYPE_BLANK),
+            CellValue::String(x) => (x.into_bytes(), TYPE_STRING),
+            CellValue::Number(x) if x.is_finite() => (x.to_string().into_bytes(), TYPE_NUMBER),
+            CellValue::Number(x) => (x.to_string().into_bytes(), TYPE_STRING),
+            CellValue::Bool(x) => (vec![b'0' + x as u8], TYPE_BOOLEAN),
+            CellValue::Date(x) => (x.into_bytes(), TYPE_DATE),
+        }
+    }
+}
+
+/// Options for `write_xlsx`.
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    /// Defaults to `Sheet 1`
+    pub sheet_name: String,
+    /// Whether the first row is a header, written as text whatever its values. Defaults
+    /// to true.
+    pub header: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            sheet_name: String::from("Sheet 1"),
+            header: true,
+        }
+    }
+}
+
+/// Writes `rows` to a single sheet workbook and returns the xlsx file. Rows are written
+/// as they are produced, so only the compressed output is held in memory.
+pub fn write_xlsx<R, C>(rows: R, options: &WriteOptions) -> Result<Vec<u8>>
+where
+    R: IntoIterator<Item = C>,
+    C: IntoIterator<Item = CellValue>,
+{
+    let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+    let mut worksheet = workbook.get_typed_worksheet(options.sheet_name.clone());
+    worksheet.treat_first_row_as_header(options.header);
+
+    for row in rows {
+        let (cells, types): (Vec<Vec<u8>>, Vec<&str>) =
+            row.into_iter().map(CellValue::into_cell).unzip();
+        worksheet.write_row(cells.iter().map(|x| x.as_slice()).collect(), &types)?;
+    }
+
+    worksheet.close()?;
+    Ok(workbook.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{read_cells, read_part, ParsedCell};
+
+    #[test]
+    fn rows_are_written_from_an_iterator() {
+        let rows = (0..1000).map(|i| {
+            [
+                CellValue::Number(i as f64),
+                CellValue::String(format!("row {i}")),
+                CellValue::Bool(i % 2 == 0),
+            ]
+            .into_iter()
+        });
+        let xlsx = write_xlsx(
+            rows,
+            &WriteOptions {
+                header: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let cells = read_cells(&xlsx, "Sheet 1");
+        assert_eq!(cells.len(), 1000);
+        assert_eq!(
+            cells[0],
+            [
+                ParsedCell::Number(0.0),
+                ParsedCell::String(String::from("row 0")),
+                ParsedCell::Bool(true)
+            ]
+        );
+        assert_eq!(
+            cells[999],
+            [
+                ParsedCell::Number(999.0),
+                ParsedCell::String(String::from("row 999")),
+                ParsedCell::Bool(false)
+            ]
+        );
+    }
+
+    #[test]
+    fn every_value_is_written_as_its_type() {
+        let rows = vec![
+            vec![CellValue::String(String::from("Value")); 5],
+            vec![
+                CellValue::Empty,
+                CellValue::Number(1.5),
+                CellValue::Number(f64::NAN),
+                CellValue::Bool(false),
+                CellValue::Date(String::from("2024-01-02")),
+            ],
+        ];
+        let xlsx = write_xlsx(rows, &WriteOptions::default()).unwrap();
+
+        let sheet = read_part(&xlsx, "xl/worksheets/sheet1.xml");
+        assert!(sheet.contains(r#"<c r="A2"/>"#), "{sheet}");
+        assert_eq!(
+            read_cells(&xlsx, "Sheet 1")[1],
+            [
+                ParsedCell::Empty,
+                ParsedCell::Number(1.5),
+                ParsedCell::String(String::from("NaN")),
+                ParsedCell::Bool(false),
+                ParsedCell::Date(45293.0)
+            ]
+        );
+    }
+}
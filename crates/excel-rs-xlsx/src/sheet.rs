@@ -3,21 +3,61 @@ use std::{
     io::{Seek, Write},
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use zip::{write::SimpleFileOptions, ZipWriter};
 
+use super::{
+    format::{newline, to_valid_utf8, xml_declaration},
+    typed_sheet::SharedStrings,
+};
+
+/// The most rows an xlsx sheet can have.
+pub const MAX_ROWS: u32 = 1_048_576;
+/// The most columns an xlsx sheet can have, up to column XFD.
+pub const MAX_COLS: usize = 16_384;
+
+/// Fails if the cell at `col` and `row`, both counted from 0, is past the last column
+/// or row of a sheet, which Excel would refuse to open.
+pub fn validate_position(col: usize, row: u32) -> Result<()> {
+    if row >= MAX_ROWS {
+        bail!(
+            "row {} is past the last row of a sheet ({})",
+            row + 1,
+            MAX_ROWS
+        );
+    }
+    if col >= MAX_COLS {
+        bail!(
+            "column {} is past the last column of a sheet ({}, XFD)",
+            col + 1,
+            MAX_COLS
+        );
+    }
+    Ok(())
+}
+
 pub struct Sheet<'a, W: Write + Seek> {
     pub sheet_buf: &'a mut ZipWriter<W>,
     pub _name: String,
     // pub id: u16,
-    // pub is_closed: bool,
+    is_closed: bool,
+    open_sheet: &'a mut Option<u16>,
     col_num_to_letter: Vec<Vec<u8>>,
-    current_row_num: u32
+    current_row_num: u32,
+    minimal_xml: bool,
+    pub(crate) skip_empty_cells: bool,
+    pub(crate) shared_strings: Option<&'a mut SharedStrings>,
 }
 
 
 impl<'a, W: Write + Seek> Sheet<'a, W> {
-    pub fn new(name: String, id: u16, writer: &'a mut ZipWriter<W>) -> Self {
+    pub fn new(
+        name: String,
+        id: u16,
+        writer: &'a mut ZipWriter<W>,
+        minimal_xml: bool,
+        open_sheet: &'a mut Option<u16>,
+    ) -> Self {
         let options = SimpleFileOptions::default()
             .compression_method(zip::CompressionMethod::Deflated)
             .compression_level(Some(1))
@@ -28,55 +68,78 @@ impl<'a, W: Write + Seek> Sheet<'a, W> {
             .ok();
 
         // Writes Sheet Header
-        writer.write(b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<worksheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\">\n<sheetData>\n").ok();
-
+        let nl = newline(minimal_xml);
+        writer.write_all(xml_declaration(minimal_xml)).ok();
+        writer.write_all(b"<worksheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\">").ok();
+        writer.write_all(nl).ok();
+        writer.write_all(b"<sheetData>").ok();
+        writer.write_all(nl).ok();
+        *open_sheet = Some(id);
 
         Sheet {
             sheet_buf: writer,
             // id,
             _name: name,
-            // is_closed: false,
+            is_closed: false,
+            open_sheet,
             col_num_to_letter: Vec::with_capacity(64),
-            current_row_num: 0
+            current_row_num: 0,
+            minimal_xml,
+            skip_empty_cells: false,
+            shared_strings: None,
         }
     }
 
     // TOOD: Use ShortVec over Vec for cell ID
     pub fn write_row(&mut self, data: Vec<&[u8]>) -> Result<()> {
+        if self.is_closed {
+            bail!(
+                "sheet {:?} is closed, rows can't be written to it",
+                self._name
+            );
+        }
+        validate_position(data.len().saturating_sub(1), self.current_row_num)?;
+        self.current_row_num += 1;
         let mut final_vec = Vec::with_capacity(512 * data.len());
 
         // TODO: Proper Error Handling
         let (row_in_chars_arr, digits) = self.num_to_bytes(self.current_row_num);
 
-        final_vec.write(b"<row r=\"")?;
-        final_vec.write(&row_in_chars_arr[9 - digits..])?;
-        final_vec.write(b"\">")?;
+        final_vec.write_all(b"<row r=\"")?;
+        final_vec.write_all(&row_in_chars_arr[9 - digits..])?;
+        final_vec.write_all(b"\">")?;
 
-        let mut col = 0;
-        for datum in data {
+        for (col, datum) in data.into_iter().enumerate() {
+            // Every cell has a reference, so leaving one out shifts nothing
+            if self.skip_empty_cells && datum.is_empty() {
+                continue;
+            }
             let (ref_id, pos) = self.ref_id(col, (row_in_chars_arr, digits))?;
+            let datum = &to_valid_utf8(datum)[..];
 
-            final_vec.write(b"<c r=\"")?;
-            final_vec.write(&ref_id.as_slice()[0..pos])?;
-            final_vec.write(b"\" t=\"str\"><v>")?;
+            final_vec.write_all(b"<c r=\"")?;
+            final_vec.write_all(&ref_id.as_slice()[0..pos])?;
+            if let Some(strings) = &mut self.shared_strings {
+                write!(final_vec, "\" t=\"s\"><v>{}</v></c>", strings.intern(datum))?;
+                continue;
+            }
+            final_vec.write_all(b"\" t=\"str\"><v>")?;
 
             let (mut chars, chars_pos) = self.escape_in_place(datum);
             let mut current_pos = 0;
             for char_pos in chars_pos {
-                final_vec.write(&datum[current_pos..char_pos])?;
-                final_vec.write(chars.pop_front().unwrap())?;
+                final_vec.write_all(&datum[current_pos..char_pos])?;
+                final_vec.write_all(chars.pop_front().unwrap())?;
                 current_pos = char_pos + 1;
             }
 
-            final_vec.write(&datum[current_pos..])?;
-            final_vec.write(b"</v></c>")?;
-
-            col += 1;
+            final_vec.write_all(&datum[current_pos..])?;
+            final_vec.write_all(b"</v></c>")?;
         }
 
-        final_vec.write(b"</row>")?;
+        final_vec.write_all(b"</row>")?;
 
-        self.sheet_buf.write(&final_vec)?;
+        self.sheet_buf.write_all(&final_vec)?;
 
         Ok(())
     }
@@ -84,9 +147,8 @@ impl<'a, W: Write + Seek> Sheet<'a, W> {
     fn escape_in_place(&self, bytes: &[u8]) -> (VecDeque<&[u8]>, VecDeque<usize>) {
         let mut special_chars: VecDeque<&[u8]> = VecDeque::new();
         let mut special_char_pos: VecDeque<usize> = VecDeque::new();
-        let len = bytes.len();
-        for x in 0..len {
-            let _ = match bytes[x] {
+        for (x, byte) in bytes.iter().enumerate() {
+            match byte {
                 b'<' => {
                     special_chars.push_back(b"&lt;".as_slice());
                     special_char_pos.push_back(x);
@@ -108,14 +170,26 @@ impl<'a, W: Write + Seek> Sheet<'a, W> {
                     special_char_pos.push_back(x);
                 }
                 _ => (),
-            };
+            }
         }
 
         (special_chars, special_char_pos)
     }
 
+    /// Ends the sheet. Rows can't be written after it, and the workbook can't be
+    /// finished until it is called.
     pub fn close(&mut self) -> Result<()> {
-        self.sheet_buf.write(b"\n</sheetData>\n</worksheet>\n")?;
+        if self.is_closed {
+            bail!("sheet {:?} is already closed", self._name);
+        }
+        let nl = newline(self.minimal_xml);
+        self.sheet_buf.write_all(nl)?;
+        self.sheet_buf.write_all(b"</sheetData>")?;
+        self.sheet_buf.write_all(nl)?;
+        self.sheet_buf.write_all(b"</worksheet>")?;
+        self.sheet_buf.write_all(nl)?;
+        self.is_closed = true;
+        *self.open_sheet = None;
         Ok(())
     }
 
@@ -127,7 +201,7 @@ impl<'a, W: Write + Seek> Sheet<'a, W> {
         let mut digits = 0;
         while row > 0 {
             row_in_chars_arr[char_pos] = b'0' + (row % 10) as u8;
-            row = row / 10;
+            row /= 10;
             char_pos -= 1;
             digits += 1;
         }
@@ -157,16 +231,18 @@ impl<'a, W: Write + Seek> Sheet<'a, W> {
 
     fn col_to_letter(& mut self, col: usize) -> &[u8] {
 
-        if self.col_num_to_letter.len() < col + 1 as usize {
+        // Fills in every column up to `col`, as skipped cells can leave gaps
+        while self.col_num_to_letter.len() < col + 1 {
             let mut result = Vec::with_capacity(2);
-            let mut col = col as i16;
+            let mut col = self.col_num_to_letter.len();
 
             loop {
                 result.push(b'A' + (col % 26) as u8);
-                col = col / 26 - 1;
-                if col < 0 {
+                col /= 26;
+                if col == 0 {
                     break;
                 }
+                col -= 1;
             }
 
             result.reverse();
@@ -0,0 +1,742 @@
+use std::{
+    collections::HashMap,
+    io::{Result, Write},
+};
+
+use super::format::escape_xml;
+
+// Excel's built-in number formats, for `Format::builtin_num_format`. Their codes are not
+// written to the file, readers know them by id.
+pub const NUM_FMT_GENERAL: u32 = 0;
+/// `0`
+pub const NUM_FMT_INTEGER: u32 = 1;
+/// `0.00`
+pub const NUM_FMT_DECIMAL: u32 = 2;
+/// `#,##0`
+pub const NUM_FMT_THOUSANDS: u32 = 3;
+/// `#,##0.00`
+pub const NUM_FMT_THOUSANDS_DECIMAL: u32 = 4;
+/// `0%`
+pub const NUM_FMT_PERCENT: u32 = 9;
+/// `0.00%`
+pub const NUM_FMT_PERCENT_DECIMAL: u32 = 10;
+/// `0.00E+00`
+pub const NUM_FMT_SCIENTIFIC: u32 = 11;
+/// `m/d/yyyy`, shown in the reader's locale
+pub const NUM_FMT_DATE: u32 = 14;
+/// `d-mmm-yy`
+pub const NUM_FMT_DAY_MONTH_YEAR: u32 = 15;
+/// `h:mm`
+pub const NUM_FMT_TIME: u32 = 20;
+/// `h:mm:ss`
+pub const NUM_FMT_TIME_SECONDS: u32 = 21;
+/// `m/d/yyyy h:mm`
+pub const NUM_FMT_DATE_TIME: u32 = 22;
+/// `@`
+pub const NUM_FMT_TEXT: u32 = 49;
+
+/// Fill patterns understood by `<patternFill patternType="...">`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PatternType {
+    None,
+    Solid,
+    MediumGray,
+    DarkGray,
+    LightGray,
+    DarkHorizontal,
+    DarkVertical,
+    DarkDown,
+    DarkUp,
+    DarkGrid,
+    DarkTrellis,
+    LightHorizontal,
+    LightVertical,
+    LightDown,
+    LightUp,
+    LightGrid,
+    LightTrellis,
+    Gray125,
+    Gray0625,
+}
+
+impl PatternType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PatternType::None => "none",
+            PatternType::Solid => "solid",
+            PatternType::MediumGray => "mediumGray",
+            PatternType::DarkGray => "darkGray",
+            PatternType::LightGray => "lightGray",
+            PatternType::DarkHorizontal => "darkHorizontal",
+            PatternType::DarkVertical => "darkVertical",
+            PatternType::DarkDown => "darkDown",
+            PatternType::DarkUp => "darkUp",
+            PatternType::DarkGrid => "darkGrid",
+            PatternType::DarkTrellis => "darkTrellis",
+            PatternType::LightHorizontal => "lightHorizontal",
+            PatternType::LightVertical => "lightVertical",
+            PatternType::LightDown => "lightDown",
+            PatternType::LightUp => "lightUp",
+            PatternType::LightGrid => "lightGrid",
+            PatternType::LightTrellis => "lightTrellis",
+            PatternType::Gray125 => "gray125",
+            PatternType::Gray0625 => "gray0625",
+        }
+    }
+}
+
+/// A cell fill. Colors are RGB; `fg_color` is the pattern color (the cell color for
+/// `Solid`) and `bg_color` shows through the gaps of the pattern.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Fill {
+    pub pattern_type: PatternType,
+    pub fg_color: Option<[u8; 3]>,
+    pub bg_color: Option<[u8; 3]>,
+}
+
+impl Fill {
+    pub fn new(pattern_type: PatternType) -> Self {
+        Fill {
+            pattern_type,
+            fg_color: None,
+            bg_color: None,
+        }
+    }
+
+    pub fn solid(color: [u8; 3]) -> Self {
+        Fill {
+            pattern_type: PatternType::Solid,
+            fg_color: Some(color),
+            bg_color: None,
+        }
+    }
+
+    pub fn gray125() -> Self {
+        Fill::new(PatternType::Gray125)
+    }
+
+    fn write_to<X: Write>(&self, writer: &mut X) -> Result<()> {
+        write!(
+            writer,
+            "<fill><patternFill patternType=\"{}\"",
+            self.pattern_type.as_str()
+        )?;
+
+        if self.fg_color.is_none() && self.bg_color.is_none() {
+            return writer.write_all(b"/></fill>");
+        }
+
+        writer.write_all(b">")?;
+        if let Some([r, g, b]) = self.fg_color {
+            write!(writer, "<fgColor rgb=\"FF{:02X}{:02X}{:02X}\"/>", r, g, b)?;
+        }
+        if let Some([r, g, b]) = self.bg_color {
+            write!(writer, "<bgColor rgb=\"FF{:02X}{:02X}{:02X}\"/>", r, g, b)?;
+        }
+        writer.write_all(b"</patternFill></fill>")
+    }
+
+    // Conditional formats take a solid fill's color from bgColor, so it's written as both
+    fn write_dxf_to<X: Write>(&self, writer: &mut X) -> Result<()> {
+        match self.pattern_type {
+            PatternType::Solid => Fill {
+                pattern_type: PatternType::Solid,
+                fg_color: self.fg_color.or(self.bg_color),
+                bg_color: self.fg_color.or(self.bg_color),
+            }
+            .write_to(writer),
+            _ => self.write_to(writer),
+        }
+    }
+}
+
+/// Line styles for a cell border.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BorderStyle {
+    Thin,
+    Medium,
+    Thick,
+}
+
+impl BorderStyle {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BorderStyle::Thin => "thin",
+            BorderStyle::Medium => "medium",
+            BorderStyle::Thick => "thick",
+        }
+    }
+}
+
+/// A cell border. Sides left as `None` have no line. `color` is RGB and applies to every
+/// side drawn, the default is black.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Border {
+    pub left: Option<BorderStyle>,
+    pub right: Option<BorderStyle>,
+    pub top: Option<BorderStyle>,
+    pub bottom: Option<BorderStyle>,
+    pub color: Option<[u8; 3]>,
+}
+
+impl Border {
+    fn write_to<X: Write>(&self, writer: &mut X) -> Result<()> {
+        writer.write_all(b"<border>")?;
+        // The schema requires the sides in this order
+        for (name, side) in [
+            ("left", self.left),
+            ("right", self.right),
+            ("top", self.top),
+            ("bottom", self.bottom),
+        ] {
+            match (side, self.color) {
+                (Some(style), Some([r, g, b])) => write!(
+                    writer,
+                    "<{0} style=\"{1}\"><color rgb=\"FF{2:02X}{3:02X}{4:02X}\"/></{0}>",
+                    name,
+                    style.as_str(),
+                    r,
+                    g,
+                    b
+                )?,
+                (Some(style), None) => write!(writer, "<{} style=\"{}\"/>", name, style.as_str())?,
+                (None, _) => write!(writer, "<{}/>", name)?,
+            }
+        }
+        writer.write_all(b"<diagonal/></border>")
+    }
+}
+
+/// Horizontal alignment of cell content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HorizontalAlign {
+    Left,
+    Center,
+    Right,
+}
+
+impl HorizontalAlign {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HorizontalAlign::Left => "left",
+            HorizontalAlign::Center => "center",
+            HorizontalAlign::Right => "right",
+        }
+    }
+}
+
+/// The look of the header row, applied with `TypedSheet::style_header`. The default is
+/// bold text on a light gray fill with a thin bottom border, and the row frozen.
+#[derive(Clone, Debug)]
+pub struct HeaderStyle {
+    pub bold: bool,
+    pub fill: Option<Fill>,
+    pub bottom_border: Option<BorderStyle>,
+    pub freeze: bool,
+}
+
+impl Default for HeaderStyle {
+    fn default() -> Self {
+        HeaderStyle {
+            bold: true,
+            fill: Some(Fill::solid([0xD9, 0xD9, 0xD9])),
+            bottom_border: Some(BorderStyle::Thin),
+            freeze: true,
+        }
+    }
+}
+
+impl HeaderStyle {
+    /// A subtler look that marks a header as filterable: bold text on a pale blue fill,
+    /// with no border and nothing frozen.
+    pub fn filter() -> Self {
+        HeaderStyle {
+            bold: true,
+            fill: Some(Fill::solid([0xDD, 0xEB, 0xF7])),
+            bottom_border: None,
+            freeze: false,
+        }
+    }
+
+    pub(crate) fn format(&self) -> Format {
+        Format {
+            fill: self.fill.clone(),
+            bold: self.bold,
+            border: self.bottom_border.map(|x| Border {
+                bottom: Some(x),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// A cell format. Register it with a sheet (e.g. `TypedSheet::set_column_format`) to get
+/// it written to `xl/styles.xml` and referenced from cells.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Format {
+    pub fill: Option<Fill>,
+    /// A custom number format code, e.g. `#,##0.00`
+    pub num_format: Option<String>,
+    /// The id of one of Excel's built-in number formats, e.g. `NUM_FMT_DATE`. Ignored when
+    /// `num_format` is set.
+    pub builtin_num_format: Option<u32>,
+    pub bold: bool,
+    pub border: Option<Border>,
+    pub align: Option<HorizontalAlign>,
+}
+
+struct Xf {
+    num_fmt_id: u32,
+    font_id: u32,
+    fill_id: u32,
+    border_id: u32,
+    align: Option<HorizontalAlign>,
+}
+
+// Excel's default indexed colour palette. Sheet views can only name gridline colours by
+// index, so custom ones replace entries from the end, which Excel itself rarely uses
+#[rustfmt::skip]
+const DEFAULT_PALETTE: [u32; 64] = [
+    0x000000, 0xFFFFFF, 0xFF0000, 0x00FF00, 0x0000FF, 0xFFFF00, 0xFF00FF, 0x00FFFF,
+    0x000000, 0xFFFFFF, 0xFF0000, 0x00FF00, 0x0000FF, 0xFFFF00, 0xFF00FF, 0x00FFFF,
+    0x800000, 0x008000, 0x000080, 0x808000, 0x800080, 0x008080, 0xC0C0C0, 0x808080,
+    0x9999FF, 0x993366, 0xFFFFCC, 0xCCFFFF, 0x660066, 0xFF8080, 0x0066CC, 0xCCCCFF,
+    0x000080, 0xFF00FF, 0xFFFF00, 0x00FFFF, 0x800080, 0x800000, 0x008080, 0x0000FF,
+    0x00CCFF, 0xCCFFFF, 0xCCFFCC, 0xFFFF99, 0x99CCFF, 0xFF99CC, 0xCC99FF, 0xFFCC99,
+    0x3366FF, 0x33CCCC, 0x99CC00, 0xFFCC00, 0xFF9900, 0xFF6600, 0x666699, 0x969696,
+    0x003366, 0x339966, 0x003300, 0x333300, 0x993300, 0x993366, 0x333399, 0x333333,
+];
+
+// Indexes 0-7 repeat 8-15 and can't be redefined
+pub(crate) const MAX_CUSTOM_COLORS: usize = 56;
+
+/// The workbook's style registry. Formats are deduplicated, so registering the same
+/// `Format` twice returns the same cell style index.
+pub struct Styles {
+    num_formats: Vec<String>,
+    fills: Vec<Fill>,
+    borders: Vec<Border>,
+    xfs: Vec<Xf>,
+    lookup: HashMap<Format, u32>,
+    formats: HashMap<u32, Format>,
+    dxfs: Vec<Format>,
+    // Palette entries replaced from index 63 down, in order
+    custom_colors: Vec<u32>,
+}
+
+impl Styles {
+    pub fn new() -> Self {
+        let mut styles = Styles {
+            num_formats: Vec::new(),
+            // Excel reserves the first two fills
+            fills: vec![Fill::new(PatternType::None), Fill::gray125()],
+            borders: vec![Border::default()],
+            // Style 1 is the date style used by the typed writers
+            xfs: vec![
+                Xf {
+                    num_fmt_id: 0,
+                    font_id: 0,
+                    fill_id: 0,
+                    border_id: 0,
+                    align: None,
+                },
+                Xf {
+                    num_fmt_id: 14,
+                    font_id: 0,
+                    fill_id: 0,
+                    border_id: 0,
+                    align: None,
+                },
+            ],
+            lookup: HashMap::new(),
+            formats: HashMap::new(),
+            dxfs: Vec::new(),
+            custom_colors: Vec::new(),
+        };
+
+        // So a built-in date format maps onto the existing date style
+        let date = Format {
+            builtin_num_format: Some(NUM_FMT_DATE),
+            ..Default::default()
+        };
+        styles.lookup.insert(date.clone(), 1);
+        styles.formats.insert(1, date);
+
+        styles
+    }
+
+    pub fn register(&mut self, format: &Format) -> u32 {
+        if let Some(id) = self.lookup.get(format) {
+            return *id;
+        }
+
+        let num_fmt_id = match &format.num_format {
+            Some(code) => self.num_fmt_id(code),
+            None => format.builtin_num_format.unwrap_or(NUM_FMT_GENERAL),
+        };
+
+        let fill_id = match &format.fill {
+            Some(fill) => self.fill_id(fill),
+            None => 0,
+        };
+
+        let border_id = match &format.border {
+            Some(border) => self.border_id(border),
+            None => 0,
+        };
+
+        // Font 0 is the regular font and font 1 its bold version
+        self.xfs.push(Xf {
+            num_fmt_id,
+            font_id: format.bold as u32,
+            fill_id,
+            border_id,
+            align: format.align,
+        });
+
+        let id = (self.xfs.len() - 1) as u32;
+        self.lookup.insert(format.clone(), id);
+        self.formats.insert(id, format.clone());
+        id
+    }
+
+    /// Returns the format registered as style `id`, if any.
+    pub(crate) fn format(&self, id: u32) -> Option<&Format> {
+        self.formats.get(&id)
+    }
+
+    /// Returns the style that is `style` (or the default style) with `border` added.
+    pub(crate) fn with_border(&mut self, style: Option<u32>, border: &Border) -> u32 {
+        let base = style.and_then(|x| self.formats.get(&x));
+        let format = Format {
+            border: Some(border.clone()),
+            ..base.cloned().unwrap_or_default()
+        };
+        self.register(&format)
+    }
+
+    /// Returns `style` with the number format of `number_style` added, e.g. a date's,
+    /// unless `style` has a number format of its own.
+    pub(crate) fn with_number_format(&mut self, style: u32, number_style: u32) -> u32 {
+        let Some(base) = self.formats.get(&style) else {
+            return style;
+        };
+        if base.num_format.is_some() || base.builtin_num_format.is_some() {
+            return style;
+        }
+        let number = self.formats.get(&number_style).cloned().unwrap_or_default();
+        let format = Format {
+            num_format: number.num_format,
+            builtin_num_format: number.builtin_num_format,
+            ..base.clone()
+        };
+        self.register(&format)
+    }
+
+    /// Registers `format` as a differential format, applied over a cell's own style by
+    /// conditional formatting, and returns its index. Built-in number formats are left
+    /// out, as a differential format has to spell out its format code.
+    pub(crate) fn register_dxf(&mut self, format: &Format) -> u32 {
+        if let Some(code) = &format.num_format {
+            self.num_fmt_id(code);
+        }
+        match self.dxfs.iter().position(|x| x == format) {
+            Some(pos) => pos as u32,
+            None => {
+                self.dxfs.push(format.clone());
+                (self.dxfs.len() - 1) as u32
+            }
+        }
+    }
+
+    /// Puts `color` in the indexed colour palette and returns its index, for the parts
+    /// that can only refer to colours by index. Returns `None` for a new colour once the
+    /// palette's 56 custom entries are taken.
+    pub(crate) fn register_indexed_color(&mut self, color: [u8; 3]) -> Option<u32> {
+        let [r, g, b] = color.map(u32::from);
+        let color = r << 16 | g << 8 | b;
+        let pos = match self.custom_colors.iter().position(|x| *x == color) {
+            Some(pos) => pos,
+            None => {
+                if self.custom_colors.len() >= MAX_CUSTOM_COLORS {
+                    return None;
+                }
+                self.custom_colors.push(color);
+                self.custom_colors.len() - 1
+            }
+        };
+        Some((DEFAULT_PALETTE.len() - 1 - pos) as u32)
+    }
+
+    fn num_fmt_id(&mut self, code: &str) -> u32 {
+        // Ids below 164 are reserved for Excel's built-in formats
+        match self.num_formats.iter().position(|x| x == code) {
+            Some(pos) => 164 + pos as u32,
+            None => {
+                self.num_formats.push(String::from(code));
+                164 + (self.num_formats.len() - 1) as u32
+            }
+        }
+    }
+
+    fn fill_id(&mut self, fill: &Fill) -> u32 {
+        match self.fills.iter().position(|x| x == fill) {
+            Some(pos) => pos as u32,
+            None => {
+                self.fills.push(fill.clone());
+                (self.fills.len() - 1) as u32
+            }
+        }
+    }
+
+    fn border_id(&mut self, border: &Border) -> u32 {
+        match self.borders.iter().position(|x| x == border) {
+            Some(pos) => pos as u32,
+            None => {
+                self.borders.push(border.clone());
+                (self.borders.len() - 1) as u32
+            }
+        }
+    }
+
+    pub(crate) fn write_to<X: Write>(&self, writer: &mut X) -> Result<()> {
+        writer.write_all(
+            b"<styleSheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\">",
+        )?;
+
+        if !self.num_formats.is_empty() {
+            write!(writer, "<numFmts count=\"{}\">", self.num_formats.len())?;
+            for (i, code) in self.num_formats.iter().enumerate() {
+                write!(
+                    writer,
+                    "<numFmt numFmtId=\"{}\" formatCode=\"{}\"/>",
+                    164 + i,
+                    escape_xml(code)
+                )?;
+            }
+            writer.write_all(b"</numFmts>")?;
+        }
+
+        writer.write_all(b"<fonts count=\"2\"><font><sz val=\"12\"/><color theme=\"1\"/><name val=\"Calibri\"/><family val=\"2\"/><scheme val=\"minor\"/></font><font><b/><sz val=\"12\"/><color theme=\"1\"/><name val=\"Calibri\"/><family val=\"2\"/><scheme val=\"minor\"/></font></fonts>")?;
+
+        write!(writer, "<fills count=\"{}\">", self.fills.len())?;
+        for fill in &self.fills {
+            fill.write_to(writer)?;
+        }
+        writer.write_all(b"</fills>")?;
+
+        write!(writer, "<borders count=\"{}\">", self.borders.len())?;
+        for border in &self.borders {
+            border.write_to(writer)?;
+        }
+        writer.write_all(b"</borders>")?;
+
+        writer.write_all(b"<cellStyleXfs count=\"1\"><xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\"/></cellStyleXfs>")?;
+
+        write!(writer, "<cellXfs count=\"{}\">", self.xfs.len())?;
+        for xf in &self.xfs {
+            write!(
+                writer,
+                "<xf numFmtId=\"{}\" fontId=\"{}\" fillId=\"{}\" borderId=\"{}\" xfId=\"0\"",
+                xf.num_fmt_id, xf.font_id, xf.fill_id, xf.border_id
+            )?;
+            if xf.num_fmt_id != 0 {
+                writer.write_all(b" applyNumberFormat=\"1\"")?;
+            }
+            if xf.font_id != 0 {
+                writer.write_all(b" applyFont=\"1\"")?;
+            }
+            if xf.fill_id != 0 {
+                writer.write_all(b" applyFill=\"1\"")?;
+            }
+            if xf.border_id != 0 {
+                writer.write_all(b" applyBorder=\"1\"")?;
+            }
+            match xf.align {
+                Some(align) => write!(
+                    writer,
+                    " applyAlignment=\"1\"><alignment horizontal=\"{}\"/></xf>",
+                    align.as_str()
+                )?,
+                None => writer.write_all(b"/>")?,
+            }
+        }
+        writer.write_all(b"</cellXfs>")?;
+
+        writer.write_all(b"<cellStyles count=\"1\"><cellStyle name=\"Normal\" xfId=\"0\" builtinId=\"0\"/></cellStyles>")?;
+
+        write!(writer, "<dxfs count=\"{}\">", self.dxfs.len())?;
+        for dxf in &self.dxfs {
+            self.write_dxf(writer, dxf)?;
+        }
+        writer.write_all(b"</dxfs>")?;
+
+        writer.write_all(b"<tableStyles count=\"0\" defaultTableStyle=\"TableStyleMedium9\" defaultPivotStyle=\"PivotStyleMedium4\"/>")?;
+
+        // The palette replaces Excel's whole, so every entry is written
+        if !self.custom_colors.is_empty() {
+            let mut palette = DEFAULT_PALETTE;
+            for (pos, color) in self.custom_colors.iter().enumerate() {
+                palette[palette.len() - 1 - pos] = *color;
+            }
+            writer.write_all(b"<colors><indexedColors>")?;
+            for color in palette {
+                write!(writer, "<rgbColor rgb=\"FF{:06X}\"/>", color)?;
+            }
+            writer.write_all(b"</indexedColors></colors>")?;
+        }
+
+        writer.write_all(b"</styleSheet>")
+    }
+}
+
+impl Styles {
+    // The schema requires the parts in this order
+    fn write_dxf<X: Write>(&self, writer: &mut X, format: &Format) -> Result<()> {
+        writer.write_all(b"<dxf>")?;
+        if format.bold {
+            writer.write_all(b"<font><b/></font>")?;
+        }
+        if let Some(code) = &format.num_format {
+            let pos = self.num_formats.iter().position(|x| x == code).unwrap_or(0);
+            write!(
+                writer,
+                "<numFmt numFmtId=\"{}\" formatCode=\"{}\"/>",
+                164 + pos,
+                escape_xml(code)
+            )?;
+        }
+        if let Some(fill) = &format.fill {
+            fill.write_dxf_to(writer)?;
+        }
+        if let Some(align) = format.align {
+            write!(writer, "<alignment horizontal=\"{}\"/>", align.as_str())?;
+        }
+        if let Some(border) = &format.border {
+            border.write_to(writer)?;
+        }
+        writer.write_all(b"</dxf>")
+    }
+}
+
+impl Default for Styles {
+    fn default() -> Self {
+        Styles::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::testing::read_part;
+    use crate::WorkBook;
+
+    #[test]
+    fn pattern_fill_is_written_and_referenced() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        worksheet.set_column_format(
+            0,
+            &Format {
+                fill: Some(Fill {
+                    pattern_type: PatternType::DarkGrid,
+                    fg_color: Some([0xFF, 0x00, 0x00]),
+                    bg_color: Some([0x00, 0x00, 0xFF]),
+                }),
+                ..Default::default()
+            },
+        );
+        worksheet.write_row(vec![b"Name"], &vec!["str"]).unwrap();
+        worksheet.write_row(vec![b"Ada"], &vec!["str"]).unwrap();
+        worksheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let styles = read_part(&bytes, "xl/styles.xml");
+        assert!(styles.contains(
+            "<fills count=\"3\"><fill><patternFill patternType=\"none\"/></fill>\
+             <fill><patternFill patternType=\"gray125\"/></fill>\
+             <fill><patternFill patternType=\"darkGrid\"><fgColor rgb=\"FFFF0000\"/>\
+             <bgColor rgb=\"FF0000FF\"/></patternFill></fill></fills>"
+        ));
+        assert!(styles.contains(
+            "<xf numFmtId=\"0\" fontId=\"0\" fillId=\"2\" borderId=\"0\" xfId=\"0\" applyFill=\"1\"/>"
+        ));
+
+        // The header isn't a data cell, so only the second row takes the fill's style
+        let sheet = read_part(&bytes, "xl/worksheets/sheet1.xml");
+        assert!(
+            sheet.contains("<c r=\"A1\" t=\"str\"><v>Name</v></c>"),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains("<c r=\"A2\" t=\"str\" s=\"2\"><v>Ada</v></c>"),
+            "{sheet}"
+        );
+    }
+
+    #[test]
+    fn styles_part_is_registered_and_referenced_by_cells() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        worksheet.write_row(vec![b"Name"], &vec!["str"]).unwrap();
+        worksheet.write_row(vec![b"Ada"], &vec!["str"]).unwrap();
+        worksheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let types = read_part(&bytes, "[Content_Types].xml");
+        assert!(types.contains(
+            r#"<Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/>"#
+        ));
+        let rels = read_part(&bytes, "xl/_rels/workbook.xml.rels");
+        assert!(rels.contains(
+            r#"Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>"#
+        ));
+
+        // With nothing registered there is the default style and the date style, and
+        // cells in the default style carry no index
+        let styles = read_part(&bytes, "xl/styles.xml");
+        assert!(
+            styles.contains(
+                "<cellXfs count=\"2\"><xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\"/>\
+                 <xf numFmtId=\"14\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\" applyNumberFormat=\"1\"/></cellXfs>"
+            ),
+            "{styles}"
+        );
+        let sheet = read_part(&bytes, "xl/worksheets/sheet1.xml");
+        assert!(!sheet.contains(" s=\""), "{sheet}");
+    }
+
+    #[test]
+    fn registering_a_format_again_returns_its_index() {
+        let mut styles = Styles::new();
+        let bold = Format {
+            bold: true,
+            ..Default::default()
+        };
+        let percent = Format {
+            num_format: Some(String::from("0.0%")),
+            ..Default::default()
+        };
+        assert_eq!(styles.register(&bold), 2);
+        assert_eq!(styles.register(&percent), 3);
+        assert_eq!(styles.register(&bold), 2);
+        // A built-in date format is the date style every workbook starts with
+        let date = Format {
+            builtin_num_format: Some(NUM_FMT_DATE),
+            ..Default::default()
+        };
+        assert_eq!(styles.register(&date), 1);
+
+        let mut xml = Vec::new();
+        styles.write_to(&mut xml).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(
+            xml.contains(r#"<numFmt numFmtId="164" formatCode="0.0%"/>"#),
+            "{xml}"
+        );
+        assert!(xml.contains(r#"<cellXfs count="4">"#), "{xml}");
+    }
+}
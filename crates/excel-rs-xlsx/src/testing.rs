@@ -0,0 +1,271 @@
+//! Reads a written workbook back into typed cells, so tests can check both what was
+//! written and how. Needs the `testing` feature.
+//!
+//! ```ignore
+//! let cells = read_cells(&bytes, "Sheet 1");
+//! assert_eq!(cells[1][0], ParsedCell::Number(42.0));
+//! ```
+
+use std::io::{Cursor, Read};
+
+use quick_xml::events::{BytesStart, Event};
+use zip::ZipArchive;
+
+use super::reader::CalamineBackedReader;
+pub use super::reader::ParsedCell;
+
+/// Reads every cell of `sheet` from the xlsx file in `bytes`, as rows starting from A1.
+/// Rows are as long as the longest row written, padded with `ParsedCell::Empty`. Text
+/// cells, `t="str"` and `t="inlineStr"`, are always `ParsedCell::String`, even when the
+/// text is a number.
+///
+/// Panics if the file can't be read or has no sheet called `sheet`, as a failed
+/// assertion would.
+pub fn read_cells(bytes: &[u8], sheet: &str) -> Vec<Vec<ParsedCell>> {
+    let mut reader =
+        CalamineBackedReader::from_reader(Cursor::new(bytes)).unwrap_or_else(|e| panic!("{}", e));
+    let mut rows = reader.read_sheet(sheet).unwrap_or_else(|e| panic!("{}", e));
+
+    // calamine reads text that looks like a number as a number, so text cells are taken
+    // from the sheet's XML instead
+    let xml = read_part(bytes, &sheet_part(bytes, sheet));
+    for (row, col, text) in text_cells(&xml) {
+        if rows.len() <= row {
+            rows.resize(row + 1, Vec::new());
+        }
+        if rows[row].len() <= col {
+            rows[row].resize(col + 1, ParsedCell::Empty);
+        }
+        rows[row][col] = ParsedCell::String(text);
+    }
+
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    for row in &mut rows {
+        row.resize(width, ParsedCell::Empty);
+    }
+    rows
+}
+
+/// Reads the part called `name` (e.g. `xl/styles.xml`) from the xlsx file in `bytes`, for
+/// checking markup the cell values don't show.
+///
+/// Panics if the file can't be read or has no such part.
+pub fn read_part(bytes: &[u8], name: &str) -> String {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap_or_else(|e| panic!("{}", e));
+    let mut part = archive
+        .by_name(name)
+        .unwrap_or_else(|e| panic!("{}: {}", name, e));
+    let mut xml = String::new();
+    part.read_to_string(&mut xml).unwrap();
+    xml
+}
+
+// The name of the part holding `sheet`, from the workbook's relationships
+fn sheet_part(bytes: &[u8], sheet: &str) -> String {
+    let mut id = None;
+    for_each_element(&read_part(bytes, "xl/workbook.xml"), |e| {
+        if e.local_name().as_ref() == b"sheet" && attribute(e, "name").as_deref() == Some(sheet) {
+            id = attribute(e, "r:id");
+        }
+    });
+    let id = id.unwrap_or_else(|| panic!("no sheet called {:?}", sheet));
+
+    let mut target = None;
+    for_each_element(&read_part(bytes, "xl/_rels/workbook.xml.rels"), |e| {
+        if attribute(e, "Id").as_deref() == Some(id.as_str()) {
+            target = attribute(e, "Target");
+        }
+    });
+    let target = target.unwrap_or_else(|| panic!("no relationship {:?}", id));
+    match target.strip_prefix('/') {
+        Some(absolute) => absolute.to_string(),
+        None => format!("xl/{}", target),
+    }
+}
+
+// The position and text of every text cell in a sheet's XML. Shared strings aren't
+// included, since calamine reads them as text already
+fn text_cells(xml: &str) -> Vec<(usize, usize, String)> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut cells = Vec::new();
+    // The cell being read if it's a text cell, and whether its text is being read
+    let (mut cell, mut in_text) = (None, false);
+    loop {
+        match reader.read_event().unwrap_or_else(|e| panic!("{}", e)) {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"c" => {
+                    let is_text =
+                        matches!(attribute(&e, "t").as_deref(), Some("str" | "inlineStr"));
+                    cell = attribute(&e, "r")
+                        .filter(|_| is_text)
+                        .map(|x| (cell_position(&x), String::new()));
+                }
+                b"v" | b"t" => in_text = true,
+                _ => {}
+            },
+            Event::Text(e) if in_text => {
+                if let Some((_, text)) = &mut cell {
+                    text.push_str(&e.unescape().unwrap_or_else(|e| panic!("{}", e)));
+                }
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"c" => {
+                    if let Some(((row, col), text)) = cell.take() {
+                        cells.push((row, col, text));
+                    }
+                }
+                b"v" | b"t" => in_text = false,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    cells
+}
+
+fn for_each_element(xml: &str, mut f: impl FnMut(&BytesStart)) {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    loop {
+        match reader.read_event().unwrap_or_else(|e| panic!("{}", e)) {
+            Event::Start(e) | Event::Empty(e) => f(&e),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+}
+
+fn attribute(e: &BytesStart, name: &str) -> Option<String> {
+    let value = e.try_get_attribute(name).ok()??;
+    let value = String::from_utf8_lossy(&value.value);
+    Some(quick_xml::escape::unescape(&value).ok()?.into_owned())
+}
+
+// The row and column of a reference like `B12`, counted from 0
+fn cell_position(reference: &str) -> (usize, usize) {
+    let digits = reference
+        .find(|x: char| x.is_ascii_digit())
+        .unwrap_or(reference.len());
+    let (letters, row) = reference.split_at(digits);
+    let col = letters.bytes().fold(0, |col, x| {
+        col * 26 + (x.to_ascii_uppercase() - b'A') as usize + 1
+    });
+    let row: usize = row
+        .parse()
+        .unwrap_or_else(|_| panic!("bad cell reference {:?}", reference));
+    (row - 1, col - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typed_sheet::{TYPE_BLANK, TYPE_BOOLEAN, TYPE_DATE, TYPE_NUMBER, TYPE_STRING};
+    use crate::WorkBook;
+
+    fn workbook() -> Vec<u8> {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Data"));
+        let types = vec![
+            TYPE_STRING,
+            TYPE_NUMBER,
+            TYPE_BOOLEAN,
+            TYPE_DATE,
+            TYPE_BLANK,
+        ];
+        worksheet
+            .write_row(vec![b"Name", b"Age", b"Active", b"Joined", b"Note"], &types)
+            .unwrap();
+        worksheet
+            .write_row(vec![b"Ada", b"36.5", b"true", b"2023-01-01", b""], &types)
+            .unwrap();
+        worksheet.write_row(vec![b"Alan"], &vec![]).unwrap();
+        worksheet.close().unwrap();
+        workbook.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn cells_are_read_back_with_their_types() {
+        let text = |x: &str| ParsedCell::String(String::from(x));
+        assert_eq!(
+            read_cells(&workbook(), "Data"),
+            vec![
+                vec![
+                    text("Name"),
+                    text("Age"),
+                    text("Active"),
+                    text("Joined"),
+                    text("Note")
+                ],
+                vec![
+                    text("Ada"),
+                    ParsedCell::Number(36.5),
+                    ParsedCell::Bool(true),
+                    ParsedCell::Date(44927.0),
+                    ParsedCell::Empty
+                ],
+                // Short rows are padded to the widest
+                vec![
+                    text("Alan"),
+                    ParsedCell::Empty,
+                    ParsedCell::Empty,
+                    ParsedCell::Empty,
+                    ParsedCell::Empty
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn text_cells_stay_text_when_they_look_like_numbers() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook
+            .get_typed_worksheet(String::from("First"))
+            .close()
+            .unwrap();
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Data"));
+        let types = vec![TYPE_STRING, TYPE_NUMBER, TYPE_STRING];
+        // The header row is always text
+        worksheet.write_row(vec![b"1", b"2", b"3"], &types).unwrap();
+        worksheet
+            .write_row(vec![b"007", b"36", b"1e3"], &types)
+            .unwrap();
+        worksheet
+            .write_serialized_row(
+                b"<row r=\"3\"><c r=\"A3\" t=\"inlineStr\"><is><t>4.5</t></is></c>\
+                  <c r=\"C3\" t=\"inlineStr\"><is><r><t>1</t></r><r><t>2</t></r></is></c></row>",
+            )
+            .unwrap();
+        worksheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let text = |x: &str| ParsedCell::String(String::from(x));
+        assert_eq!(
+            read_cells(&bytes, "Data"),
+            vec![
+                vec![text("1"), text("2"), text("3")],
+                vec![text("007"), ParsedCell::Number(36.0), text("1e3")],
+                vec![text("4.5"), ParsedCell::Empty, text("12")],
+            ]
+        );
+    }
+
+    #[test]
+    fn cell_references_are_positions_from_zero() {
+        assert_eq!(cell_position("A1"), (0, 0));
+        assert_eq!(cell_position("Z10"), (9, 25));
+        assert_eq!(cell_position("AA2"), (1, 26));
+        assert_eq!(cell_position("XFD1048576"), (1_048_575, 16_383));
+    }
+
+    #[test]
+    fn parts_are_read_as_text() {
+        let xml = read_part(&workbook(), "xl/workbook.xml");
+        assert!(xml.contains("<sheet name=\"Data\""));
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing")]
+    fn reading_a_missing_sheet_panics() {
+        read_cells(&workbook(), "Missing");
+    }
+}
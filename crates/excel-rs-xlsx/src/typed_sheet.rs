@@ -1,119 +1,947 @@
 use std::{
-    collections::VecDeque,
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
     io::{Seek, Write},
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use zip::{write::SimpleFileOptions, ZipWriter};
 
+use super::{
+    chart::{write_drawing, write_drawing_rels, Chart, ChartSpec, Drawings},
+    conditional::{TextOp, TextRule},
+    format::{escape_xml, newline, to_valid_utf8, xml_declaration},
+    note::{vml_blocks, write_comments, write_vml, Note},
+    sheet::validate_position,
+    style::{Border, Format, HeaderStyle, Styles, MAX_CUSTOM_COLORS, NUM_FMT_DATE_TIME},
+};
+
+pub const TYPE_STRING: &str = "str";
+/// Writes a number like `-12`, `0.5` or `1.5E-7`. An empty field is written as a blank
+/// cell, and anything else, e.g. `N/A`, as text.
+pub const TYPE_NUMBER: &str = "n";
+/// Writes a cell with no value at all, rather than an empty string. The data is ignored.
+pub const TYPE_BLANK: &str = "";
+/// Writes `1`, `0`, `true` or `false` (in any case) as a TRUE or FALSE cell. An empty
+/// field is written as a blank cell, and anything else as text. Chosen by
+/// `infer_row_types` for `true` and `false` only, as `1` and `0` are numbers there.
+pub const TYPE_BOOLEAN: &str = "b";
+/// Writes an ISO-8601 date (`2024-01-31`) or date and time (`2024-01-31T13:45:00`, a space
+/// in place of the `T` works too) as a date, the way the workbook's `DateMode` says. An
+/// empty field is written as a blank cell, and anything else as text.
+pub const TYPE_DATE: &str = "date";
+/// Writes a date like `2024-01-31` as a date shown `yyyy-mm-dd`. Like `TYPE_DATE` it is
+/// stored the way the workbook's `DateMode` says, and anything that isn't a valid date in
+/// the layout is written as text, or as a blank cell if empty. Chosen by
+/// `infer_row_types`, as are the two below.
+pub const TYPE_DATE_YMD: &str = "date_ymd";
+/// Writes a month-first date like `01/31/2024` or `1/31/2024` shown `mm/dd/yyyy`.
+pub const TYPE_DATE_MDY: &str = "date_mdy";
+/// Writes a day-first date like `31/01/2024` or `31/1/2024` shown `dd/mm/yyyy`.
+pub const TYPE_DATE_DMY: &str = "date_dmy";
+
+/// How `TYPE_DATE` cells are stored. Both attach a date number format, so the cell shows
+/// as a date either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DateMode {
+    /// A number counting days since 1900, the way Excel itself stores dates. Every
+    /// reader understands it, including Excel 2007 and older streaming readers, but
+    /// dates before 1900 can't be stored and are written as text.
+    #[default]
+    Serial,
+    /// The ISO-8601 text in a `t="d"` cell. Readers get the date without converting
+    /// serials, which suits calamine, openpyxl and LibreOffice, but Excel 2007 can't
+    /// open the file.
+    IsoTyped,
+}
+
+struct BorderRange {
+    first: (u32, usize),
+    last: (u32, usize),
+    border: Border,
+    outline: bool,
+}
+
+impl BorderRange {
+    fn border_at(&self, row: u32, col: usize) -> Option<Border> {
+        let (first_row, first_col) = self.first;
+        let (last_row, last_col) = self.last;
+        if row < first_row || row > last_row || col < first_col || col > last_col {
+            return None;
+        }
+
+        if !self.outline {
+            return Some(self.border.clone());
+        }
+
+        let border = Border {
+            left: self.border.left.filter(|_| col == first_col),
+            right: self.border.right.filter(|_| col == last_col),
+            top: self.border.top.filter(|_| row == first_row),
+            bottom: self.border.bottom.filter(|_| row == last_row),
+            color: self.border.color,
+        };
+
+        // Interior cells of an outline get nothing
+        if border.left.is_none()
+            && border.right.is_none()
+            && border.top.is_none()
+            && border.bottom.is_none()
+        {
+            return None;
+        }
+
+        Some(border)
+    }
+}
+
+/// Scratch space owned by the workbook and lent to each sheet in turn, so sheets don't
+/// each allocate their own.
+#[derive(Default)]
+pub struct SheetBuffers {
+    row: Vec<u8>,
+    col_num_to_letter: Vec<Vec<u8>>,
+}
+
+/// The workbook's shared strings table, lent to each sheet in turn when
+/// `WorkBook::enable_shared_strings` is on. Text cells refer to their string by index.
+#[derive(Default)]
+pub struct SharedStrings {
+    ids: HashMap<Vec<u8>, u32>,
+    // Cells referring to the table, which can be more than u32 holds
+    pub(crate) count: u64,
+}
+
+impl SharedStrings {
+    // The index of `text` in the table, adding it if it isn't there yet
+    pub(crate) fn intern(&mut self, text: &[u8]) -> u32 {
+        self.count += 1;
+        if let Some(id) = self.ids.get(text) {
+            return *id;
+        }
+        let id = self.ids.len() as u32;
+        self.ids.insert(text.to_vec(), id);
+        id
+    }
+
+    // The strings in index order
+    pub(crate) fn strings(&self) -> Vec<&[u8]> {
+        let mut strings = vec![&[][..]; self.ids.len()];
+        for (text, id) in self.ids.iter() {
+            strings[*id as usize] = text;
+        }
+        strings
+    }
+}
+
 pub struct TypedSheet<'a, W: Write + Seek> {
     pub sheet_buf: &'a mut ZipWriter<W>,
     pub _name: String,
-    // pub id: u16,
-    // pub is_closed: bool,
-    col_num_to_letter: Vec<Vec<u8>>,
+    id: u16,
+    is_closed: bool,
+    is_initialized: bool,
+    dimension: Option<(u32, usize)>,
+    styles: &'a mut Styles,
+    column_styles: Vec<Option<u32>>,
+    header_style: Option<u32>,
+    border_ranges: Vec<BorderRange>,
+    // Rows and columns kept in view
+    frozen: (u32, usize),
+    // Index of the gridline colour in the palette
+    gridline_color: Option<u32>,
+    right_to_left: bool,
+    default_number_style: Option<u32>,
+    col_widths: Vec<Option<f64>>,
+    // The longest text in each column, in characters, while auto-fit is on
+    fit_widths: Option<Vec<usize>>,
+    // Rows written while auto-fit is on, held until the widths are known
+    held_rows: Vec<u8>,
+    has_autofilter: bool,
+    filter_criteria: Vec<Option<Vec<String>>>,
+    max_cols: usize,
+    has_header: bool,
+    // Rows written as header when has_header is set
+    header_rows: u32,
+    // Ranges of merged cells, like A1:C1
+    merged: Vec<String>,
+    pub(crate) date_mode: DateMode,
+    pub(crate) minimal_xml: bool,
+    pub(crate) omit_cell_refs: bool,
+    pub(crate) skip_empty_cells: bool,
+    pub(crate) shared_strings: Option<&'a mut SharedStrings>,
+    date_time_style: Option<u32>,
+    // Keyed by number format code
+    date_layout_styles: Vec<(&'static str, u32)>,
+    charts: Vec<Chart>,
+    notes: Vec<Note>,
+    text_rules: Vec<TextRule>,
+    drawings: &'a mut Drawings,
+    buffers: &'a mut SheetBuffers,
+    open_sheet: &'a mut Option<u16>,
     current_row_num: u32,
 }
 
 impl<'a, W: Write + Seek> TypedSheet<'a, W> {
-    pub fn new(name: String, id: u16, writer: &'a mut ZipWriter<W>) -> Self {
+    pub fn new(
+        name: String,
+        id: u16,
+        writer: &'a mut ZipWriter<W>,
+        styles: &'a mut Styles,
+        buffers: &'a mut SheetBuffers,
+        drawings: &'a mut Drawings,
+        open_sheet: &'a mut Option<u16>,
+    ) -> Self {
+        *open_sheet = Some(id);
+        TypedSheet {
+            sheet_buf: writer,
+            id,
+            _name: name,
+            is_closed: false,
+            is_initialized: false,
+            dimension: None,
+            styles,
+            column_styles: Vec::new(),
+            header_style: None,
+            border_ranges: Vec::new(),
+            frozen: (0, 0),
+            gridline_color: None,
+            right_to_left: false,
+            default_number_style: None,
+            col_widths: Vec::new(),
+            fit_widths: None,
+            held_rows: Vec::new(),
+            has_autofilter: false,
+            filter_criteria: Vec::new(),
+            max_cols: 0,
+            has_header: true,
+            header_rows: 1,
+            merged: Vec::new(),
+            date_mode: DateMode::Serial,
+            minimal_xml: false,
+            omit_cell_refs: false,
+            skip_empty_cells: false,
+            shared_strings: None,
+            date_time_style: None,
+            date_layout_styles: Vec::new(),
+            charts: Vec::new(),
+            notes: Vec::new(),
+            text_rules: Vec::new(),
+            drawings,
+            buffers,
+            open_sheet,
+            current_row_num: 0,
+        }
+    }
+
+    // Everything before <sheetData> has to be known here, so sheet level settings must be
+    // made before the first row is written
+    fn init_sheet(&mut self) -> Result<()> {
+        if self.is_closed {
+            bail!(
+                "sheet {:?} is closed, rows can't be written to it",
+                self._name
+            );
+        }
+        // Auto-fit widths go before <sheetData>, so with it on nothing is written until close
+        if self.is_initialized || self.fit_widths.is_some() {
+            return Ok(());
+        }
+        self.is_initialized = true;
+
         let options = SimpleFileOptions::default()
             .compression_method(zip::CompressionMethod::Deflated)
             .compression_level(Some(1))
             .large_file(true);
 
-        writer
-            .start_file(format!("xl/worksheets/sheet{}.xml", id), options)
-            .ok();
+        self.sheet_buf
+            .start_file(format!("xl/worksheets/sheet{}.xml", self.id), options)?;
 
         // Writes Sheet Header
-        writer.write(b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<worksheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\">\n<sheetData>\n").ok();
+        let nl = newline(self.minimal_xml);
+        self.sheet_buf
+            .write_all(xml_declaration(self.minimal_xml))?;
+        self.sheet_buf.write_all(b"<worksheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\">")?;
+        self.sheet_buf.write_all(nl)?;
 
-        TypedSheet {
-            sheet_buf: writer,
-            // id,
-            _name: name,
-            // is_closed: false,
-            col_num_to_letter: Vec::with_capacity(64),
-            current_row_num: 0,
+        // Excel expects a sheet with rows hidden by a filter to say so. Conditional
+        // formats are evaluated by default, so text rules need nothing here
+        if self.filter_criteria.iter().any(|x| x.is_some()) {
+            self.sheet_buf.write_all(b"<sheetPr filterMode=\"1\"/>")?;
+            self.sheet_buf.write_all(nl)?;
+        }
+
+        if let Some((rows, cols)) = self.dimension {
+            let last_col = self.col_to_letter(cols.max(1) - 1).to_vec();
+            write!(
+                self.sheet_buf,
+                "<dimension ref=\"A1:{}{}\"/>",
+                String::from_utf8_lossy(&last_col),
+                rows.max(1)
+            )?;
+            self.sheet_buf.write_all(nl)?;
+        }
+
+        self.write_sheet_views()?;
+
+        if self.col_widths.iter().any(|x| x.is_some()) {
+            self.sheet_buf.write_all(b"<cols>")?;
+            for (col, width) in self.col_widths.iter().enumerate() {
+                if let Some(width) = width {
+                    write!(
+                        self.sheet_buf,
+                        "<col min=\"{}\" max=\"{}\" width=\"{:.2}\" customWidth=\"1\"/>",
+                        col + 1,
+                        col + 1,
+                        width
+                    )?;
+                }
+            }
+            self.sheet_buf.write_all(b"</cols>")?;
+            self.sheet_buf.write_all(nl)?;
+        }
+
+        self.sheet_buf.write_all(b"<sheetData>")?;
+        self.sheet_buf.write_all(nl)?;
+
+        Ok(())
+    }
+
+    fn write_sheet_views(&mut self) -> Result<()> {
+        let (rows, cols) = self.frozen;
+        if rows == 0 && cols == 0 && self.gridline_color.is_none() && !self.right_to_left {
+            return Ok(());
+        }
+
+        self.sheet_buf.write_all(b"<sheetViews><sheetView")?;
+        if self.right_to_left {
+            self.sheet_buf.write_all(b" rightToLeft=\"1\"")?;
+        }
+        if let Some(color) = self.gridline_color {
+            write!(
+                self.sheet_buf,
+                " defaultGridColor=\"0\" colorId=\"{}\"",
+                color
+            )?;
+        }
+        self.sheet_buf.write_all(b" workbookViewId=\"0\">")?;
+        if rows > 0 || cols > 0 {
+            self.write_pane()?;
+        }
+        self.sheet_buf.write_all(b"</sheetView></sheetViews>")?;
+        self.sheet_buf.write_all(newline(self.minimal_xml))?;
+
+        Ok(())
+    }
+
+    fn write_pane(&mut self) -> Result<()> {
+        let (rows, cols) = self.frozen;
+        let col = String::from_utf8_lossy(self.col_to_letter(cols)).into_owned();
+        let top_left = format!("{}{}", col, rows + 1);
+
+        self.sheet_buf.write_all(b"<pane")?;
+        if cols > 0 {
+            write!(self.sheet_buf, " xSplit=\"{}\"", cols)?;
+        }
+        if rows > 0 {
+            write!(self.sheet_buf, " ySplit=\"{}\"", rows)?;
+        }
+
+        let active_pane = match (rows, cols) {
+            (_, 0) => "bottomLeft",
+            (0, _) => "topRight",
+            _ => "bottomRight",
+        };
+        write!(
+            self.sheet_buf,
+            " topLeftCell=\"{}\" activePane=\"{}\" state=\"frozen\"/>",
+            top_left, active_pane
+        )?;
+
+        // The panes a split creates, each selecting its own top left cell. The active
+        // pane, where scrolling starts, comes last
+        let mut panes = vec![(None, String::from("A1"))];
+        if cols > 0 {
+            panes.push((Some("topRight"), format!("{}1", col)));
+        }
+        if rows > 0 {
+            panes.push((Some("bottomLeft"), format!("A{}", rows + 1)));
+        }
+        if rows > 0 && cols > 0 {
+            panes.push((Some("bottomRight"), top_left.clone()));
+        }
+
+        for (pane, cell) in panes {
+            self.sheet_buf.write_all(b"<selection")?;
+            if let Some(pane) = pane {
+                write!(self.sheet_buf, " pane=\"{}\"", pane)?;
+            }
+            write!(self.sheet_buf, " activeCell=\"{0}\" sqref=\"{0}\"/>", cell)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sizes the sheet's buffers up front for a sheet of `rows` rows (header included) and
+    /// `cols` columns, and writes them as the sheet's `<dimension>` so readers know its
+    /// size before reading the rows. The counts should match what is then written. Must
+    /// be called before the first row is written.
+    pub fn reserve_rows(&mut self, rows: u32, cols: usize) {
+        self.dimension = Some((rows, cols));
+        if cols > 0 {
+            self.col_to_letter(cols - 1);
+        }
+        self.buffers.row.reserve(512 * cols);
+    }
+
+    /// Keeps the header row in view while scrolling. Must be called before the first row
+    /// is written.
+    pub fn freeze_top_row(&mut self) {
+        self.freeze_panes(1, 0);
+    }
+
+    /// Keeps the first `rows` rows and `cols` columns in view while scrolling. Must be
+    /// called before the first row is written.
+    pub fn freeze_panes(&mut self, rows: u32, cols: usize) {
+        self.frozen = (rows, cols);
+    }
+
+    /// Draws the sheet's gridlines in `color` instead of Excel's automatic grey. The
+    /// colour goes in the workbook's indexed colour palette, which has room for 56
+    /// custom colours, so fails for a 57th. Must be called before the first row is
+    /// written.
+    pub fn set_gridline_color(&mut self, color: [u8; 3]) -> Result<()> {
+        let Some(id) = self.styles.register_indexed_color(color) else {
+            let [r, g, b] = color;
+            bail!(
+                "can't use {r:02X}{g:02X}{b:02X} for gridlines, the palette only has room \
+                 for {MAX_CUSTOM_COLORS} custom colours"
+            );
+        };
+        self.gridline_color = Some(id);
+        Ok(())
+    }
+
+    /// Lays the sheet out right to left, with column A on the right, e.g. for Arabic or
+    /// Hebrew reports. Defaults to false. Must be called before the first row is written.
+    pub fn set_rtl(&mut self, rtl: bool) {
+        self.right_to_left = rtl;
+    }
+
+    /// Sets whether row 1 is a header, written as text whatever its types. Defaults to
+    /// true. Pass false for sheets with no header row, so the first row is typed like the
+    /// others. Must be called before the first row is written.
+    pub fn treat_first_row_as_header(&mut self, header: bool) {
+        self.has_header = header;
+    }
+
+    /// Same as `treat_first_row_as_header(false)`.
+    pub fn set_no_header(&mut self) {
+        self.treat_first_row_as_header(false);
+    }
+
+    /// Styles the header row in one call, see `HeaderStyle` for the defaults. Must be
+    /// called before the first row is written.
+    pub fn style_header(&mut self, style: HeaderStyle) {
+        self.header_style = Some(self.styles.register(&style.format()));
+        if style.freeze {
+            self.freeze_top_row();
+        }
+    }
+
+    /// Sizes each column to fit its header text, except columns already given a width
+    /// with `set_column_width`. Must be called before the first row is written.
+    pub fn fit_headers(&mut self, headers: &[&[u8]]) {
+        if self.col_widths.len() < headers.len() {
+            self.col_widths.resize(headers.len(), None);
+        }
+        for (width, header) in self.col_widths.iter_mut().zip(headers) {
+            let chars = String::from_utf8_lossy(header).chars().count();
+            // Leave a little room for the cell padding and the filter button
+            width.get_or_insert((chars as f64 + 2.0).min(255.0));
+        }
+    }
+
+    /// Sets the width of column `col`, counted from 0, in characters of the default font.
+    /// Must be called before the first row is written.
+    pub fn set_column_width(&mut self, col: usize, width: f64) {
+        if self.col_widths.len() <= col {
+            self.col_widths.resize(col + 1, None);
+        }
+        self.col_widths[col] = Some(width.clamp(0.0, 255.0));
+    }
+
+    /// Sizes each column to fit the longest text written to it, header included. Widths
+    /// set with `set_column_width` or `fit_headers` are kept. The widths are only known
+    /// once every row is written, so rows are held in memory until `close`. Must be
+    /// called before the first row is written.
+    pub fn auto_fit_columns(&mut self) {
+        self.fit_widths = Some(Vec::new());
+    }
+
+    /// Writes a two-row header: each group label on row 1 merged across `span` columns,
+    /// and `subheaders` below it on row 2, then keeps both rows in view. Both rows are
+    /// styled and written as text like a single header row, and the autofilter goes on
+    /// row 2. Fails unless the spans add up to the number of sub-headers, or if a row
+    /// has already been written.
+    ///
+    /// ```ignore
+    /// let months = ["Jan", "Feb", "Mar", "Apr", "May", "Jun"];
+    /// sheet.write_grouped_header(&[("Q1", 3), ("Q2", 3)], &months)?;
+    /// ```
+    pub fn write_grouped_header(
+        &mut self,
+        groups: &[(&str, usize)],
+        subheaders: &[&str],
+    ) -> Result<()> {
+        if self.current_row_num > 0 {
+            bail!("the grouped header must be written before any other row");
+        }
+        if groups.iter().any(|(_, span)| *span == 0) {
+            bail!("every header group must span at least one column");
+        }
+        let width: usize = groups.iter().map(|(_, span)| span).sum();
+        if width != subheaders.len() {
+            bail!(
+                "the header groups span {} columns but there are {} sub-headers",
+                width,
+                subheaders.len()
+            );
+        }
+
+        let mut labels: Vec<&[u8]> = vec![b""; width];
+        let mut col = 0;
+        for (label, span) in groups {
+            labels[col] = label.as_bytes();
+            if *span > 1 {
+                let first = String::from_utf8_lossy(self.col_to_letter(col)).into_owned();
+                let last = String::from_utf8_lossy(self.col_to_letter(col + span - 1));
+                let range = format!("{}1:{}1", first, last);
+                self.merged.push(range);
+            }
+            col += span;
+        }
+
+        self.has_header = true;
+        self.header_rows = 2;
+        self.freeze_panes(2, self.frozen.1);
+
+        let subheaders = subheaders.iter().map(|x| x.as_bytes()).collect();
+        self.write_row(labels, &Vec::new())?;
+        self.write_row(subheaders, &Vec::new())
+    }
+
+    /// Applies `format` to every data cell (rows after the header) in column `col`.
+    pub fn set_column_format(&mut self, col: usize, format: &Format) {
+        let style = self.styles.register(format);
+        if self.column_styles.len() <= col {
+            self.column_styles.resize(col + 1, None);
+        }
+        self.column_styles[col] = Some(style);
+    }
+
+    /// Returns the format set on column `col` by `set_column_format`, if any, e.g. to
+    /// add to it rather than replace it.
+    pub fn column_format(&self, col: usize) -> Option<Format> {
+        let style = (*self.column_styles.get(col)?)?;
+        self.styles.format(style).cloned()
+    }
+
+    /// Applies one of Excel's built-in number formats (see the `NUM_FMT_` constants in
+    /// `style`) to every data cell in column `col`. Unlike a custom format code, nothing is
+    /// added to `xl/styles.xml` for the format itself. Fails if `id` isn't a built-in id.
+    pub fn set_column_builtin_format(&mut self, col: usize, id: u32) -> Result<()> {
+        // Ids from 164 up belong to custom formats defined in the file
+        if id >= 164 {
+            bail!("{id} is not a built-in number format id, custom formats start at 164");
+        }
+        let format = Format {
+            builtin_num_format: Some(id),
+            ..Default::default()
+        };
+        self.set_column_format(col, &format);
+        Ok(())
+    }
+
+    /// Draws `border` on the cells from `first` to `last`, both inclusive `(row, col)`
+    /// pairs counted from 0 with the header as row 0. With `outline` set only the outer
+    /// edges of the range get their side of the border and interior cells get nothing,
+    /// otherwise every cell gets the whole border. Only cells that are written get a
+    /// border. Must be called before the range's first row is written.
+    pub fn apply_border_range(
+        &mut self,
+        first: (u32, usize),
+        last: (u32, usize),
+        border: Border,
+        outline: bool,
+    ) {
+        self.border_ranges.push(BorderRange {
+            first,
+            last,
+            border,
+            outline,
+        });
+    }
+
+    // Later ranges win for the sides they draw
+    fn border_at(&self, row: u32, col: usize) -> Option<Border> {
+        self.border_ranges
+            .iter()
+            .filter_map(|x| x.border_at(row, col))
+            .reduce(|acc, x| Border {
+                left: x.left.or(acc.left),
+                right: x.right.or(acc.right),
+                top: x.top.or(acc.top),
+                bottom: x.bottom.or(acc.bottom),
+                color: x.color.or(acc.color),
+            })
+    }
+
+    fn cell_style(&mut self, col: usize, style: Option<u32>) -> Option<u32> {
+        if self.border_ranges.is_empty() {
+            return style;
+        }
+
+        match self.border_at(self.current_row_num - 1, col) {
+            Some(border) => Some(self.styles.with_border(style, &border)),
+            None => style,
+        }
+    }
+
+    /// Applies the number format `code` (e.g. `#,##0`) to every `TYPE_NUMBER` cell in
+    /// a column without its own format.
+    pub fn set_default_number_format(&mut self, code: &str) {
+        let format = Format {
+            num_format: Some(String::from(code)),
+            ..Default::default()
+        };
+        self.default_number_style = Some(self.styles.register(&format));
+    }
+
+    /// Adds filter dropdowns to the header row, covering every row and column written.
+    pub fn set_autofilter(&mut self) {
+        self.has_autofilter = true;
+    }
+
+    /// Pre-applies a filter on column `col` that only shows rows whose value is one of
+    /// `values` (compared case-insensitively, like Excel). An empty string matches blank
+    /// cells. Also enables the autofilter. The sheet is marked as filtered before its
+    /// rows and rows are hidden as they are written, so this must be called before the
+    /// first row is written.
+    pub fn set_filter_criteria(&mut self, col: usize, values: &[&str]) {
+        self.has_autofilter = true;
+        if self.filter_criteria.len() <= col {
+            self.filter_criteria.resize(col + 1, None);
         }
+        self.filter_criteria[col] = Some(values.iter().map(|x| String::from(*x)).collect());
+    }
+
+    fn is_filtered_out(&self, data: &[&[u8]]) -> bool {
+        self.filter_criteria
+            .iter()
+            .enumerate()
+            .any(|(col, values)| match values {
+                Some(values) => {
+                    let datum = data.get(col).copied().unwrap_or(&[]);
+                    !values
+                        .iter()
+                        .any(|x| x.as_bytes().eq_ignore_ascii_case(datum))
+                }
+                None => false,
+            })
+    }
+
+    /// Adds a chart over data on this sheet, placed to the right of the data. Its ranges
+    /// are checked against the rows and columns written when the sheet is closed.
+    pub fn add_chart(&mut self, spec: &ChartSpec) -> Result<()> {
+        self.charts.push(Chart::parse(spec)?);
+        Ok(())
+    }
+
+    /// Attaches a note with `text` to the cell at `(row, col)`, counted from 0 with the
+    /// header as row 0. Excel shows it when the cell is hovered. Notes can be added at any
+    /// time before the sheet is closed.
+    pub fn add_note(&mut self, row: u32, col: usize, text: &str) {
+        self.notes.push(Note {
+            row,
+            col,
+            text: String::from(text),
+        });
+    }
+
+    /// Highlights cells in column `col` from `first_row` to `last_row`, inclusive and
+    /// counted from 0 with the header as row 0, with `format` when their text matches
+    /// `value` by `op`. Excel evaluates the rule as the sheet changes, so it can be added
+    /// at any time before the sheet is closed. The first rule added wins where rules
+    /// overlap.
+    pub fn add_text_rule(
+        &mut self,
+        col: usize,
+        first_row: u32,
+        last_row: u32,
+        op: TextOp,
+        value: &str,
+        format: &Format,
+    ) {
+        let dxf_id = self.styles.register_dxf(format);
+        self.text_rules.push(TextRule {
+            col,
+            first_row,
+            last_row,
+            op,
+            value: String::from(value),
+            dxf_id,
+        });
     }
 
+    /// The number of the last row written, counted from 1 and including the header, or 0
+    /// before any row is written.
+    pub fn current_row(&self) -> u32 {
+        self.current_row_num
+    }
+
+    /// The number of cells in the widest row written with `write_row`, or 0 before any
+    /// row is written. Rows from `write_serialized_row` aren't counted.
+    pub fn max_column(&self) -> usize {
+        self.max_cols
+    }
+
+    /// Writes `data` as the next row, each cell typed by the same position in `types`.
+    /// Cells past the end of `types` are text. Fails if `types` is longer than `data`,
+    /// which usually means cells went missing, except for the header, which is always
+    /// text.
     // TOOD: Use ShortVec over Vec for cell ID
     pub fn write_row(&mut self, data: Vec<&[u8]>, types: &Vec<&str>) -> Result<()> {
+        let is_header = self.has_header && self.current_row_num < self.header_rows;
+        if !is_header && types.len() > data.len() {
+            bail!(
+                "row {} has more types ({}) than cells ({})",
+                self.current_row_num + 1,
+                types.len(),
+                data.len()
+            );
+        }
+
+        validate_position(data.len().saturating_sub(1), self.current_row_num)?;
+        self.init_sheet()?;
         self.current_row_num += 1;
 
-        let mut final_vec = Vec::with_capacity(512 * data.len());
+        // Taken so the helpers below can still borrow self
+        let mut final_vec = std::mem::take(&mut self.buffers.row);
+        final_vec.clear();
+        final_vec.reserve(512 * data.len());
 
         // TODO: Proper Error Handling
         let (row_in_chars_arr, digits) = self.num_to_bytes(self.current_row_num);
 
-        final_vec.write(b"<row r=\"")?;
-        final_vec.write(&row_in_chars_arr[9 - digits..])?;
-        final_vec.write(b"\">")?;
+        final_vec.write_all(b"<row r=\"")?;
+        final_vec.write_all(&row_in_chars_arr[9 - digits..])?;
 
-        let mut col = 0;
-        if self.current_row_num == 1 {
-            for datum in data {
-                let (ref_id, pos) = self.ref_id(col, (row_in_chars_arr, digits))?;
+        if !is_header && self.is_filtered_out(&data) {
+            final_vec.write_all(b"\" hidden=\"1")?;
+        }
+        final_vec.write_all(b"\">")?;
+        self.max_cols = self.max_cols.max(data.len());
 
-                final_vec.write(b"<c r=\"")?;
-                final_vec.write(&ref_id.as_slice()[0..pos])?;
-                final_vec.write(b"\" t=\"str\"><v>")?;
+        if let Some(widths) = &mut self.fit_widths {
+            if widths.len() < data.len() {
+                widths.resize(data.len(), 0);
+            }
+            for (width, datum) in widths.iter_mut().zip(&data) {
+                *width = (*width).max(String::from_utf8_lossy(datum).chars().count());
+            }
+        }
+
+        // The column after the last cell written, where a cell needs no reference
+        let mut next_col = 0;
+        if is_header {
+            for (col, datum) in data.into_iter().enumerate() {
+                let datum = &to_valid_utf8(datum)[..];
+                let style = self.cell_style(col, self.header_style);
+                if self.skip_empty_cells && datum.is_empty() && style.is_none() {
+                    continue;
+                }
+                let shared_id = self.shared_strings.as_mut().map(|x| x.intern(datum));
+
+                final_vec.write_all(b"<c")?;
+                if !self.omit_cell_refs || col != next_col {
+                    self.write_cell_ref(&mut final_vec, col, (row_in_chars_arr, digits))?;
+                }
+                next_col = col + 1;
+                match shared_id {
+                    Some(_) => final_vec.write_all(b" t=\"s\"")?,
+                    None => final_vec.write_all(b" t=\"str\"")?,
+                }
+                if let Some(style) = style {
+                    write!(final_vec, " s=\"{}\"", style)?;
+                }
+                final_vec.write_all(b"><v>")?;
+                if let Some(id) = shared_id {
+                    write!(final_vec, "{}</v></c>", id)?;
+                    continue;
+                }
 
                 let (mut chars, chars_pos) = self.escape_in_place(datum);
                 let mut current_pos = 0;
                 for char_pos in chars_pos {
-                    final_vec.write(&datum[current_pos..char_pos])?;
-                    final_vec.write(chars.pop_front().unwrap())?;
+                    final_vec.write_all(&datum[current_pos..char_pos])?;
+                    final_vec.write_all(chars.pop_front().unwrap())?;
                     current_pos = char_pos + 1;
                 }
 
-                final_vec.write(&datum[current_pos..])?;
-                final_vec.write(b"</v></c>")?;
-
-                col += 1;
+                final_vec.write_all(&datum[current_pos..])?;
+                final_vec.write_all(b"</v></c>")?;
             }
         } else {
-            for datum in data {
-                let (ref_id, pos) = self.ref_id(col, (row_in_chars_arr, digits))?;
+            for (col, datum) in data.into_iter().enumerate() {
+                let valid_datum = to_valid_utf8(datum);
+                let mut datum = &valid_datum[..];
+
+                let mut col_type = *types.get(col).unwrap_or(&TYPE_STRING);
+                let mut date_style = None;
+                let date_value;
+                if matches!(
+                    col_type,
+                    TYPE_DATE | TYPE_DATE_YMD | TYPE_DATE_MDY | TYPE_DATE_DMY
+                ) {
+                    match self.date_cell(datum, col_type) {
+                        // A missing date is left blank rather than written as empty text
+                        _ if datum.is_empty() => col_type = TYPE_BLANK,
+                        Some((cell_type, value, style)) => {
+                            col_type = cell_type;
+                            date_style = Some(style);
+                            date_value = value;
+                            datum = &date_value;
+                        }
+                        None => col_type = TYPE_STRING,
+                    }
+                }
+                if col_type == TYPE_BOOLEAN {
+                    match parse_bool(datum) {
+                        _ if datum.is_empty() => col_type = TYPE_BLANK,
+                        Some(value) => datum = value,
+                        None => col_type = TYPE_STRING,
+                    }
+                }
+                // Excel reports a file with anything but a number in a number cell as
+                // corrupt, so the one cell is written another way instead
+                if col_type == TYPE_NUMBER && !is_xlsx_number(datum) {
+                    col_type = if datum.is_empty() {
+                        TYPE_BLANK
+                    } else {
+                        TYPE_STRING
+                    };
+                }
+
+                // A date keeps its number format in a column styled without one
+                let style = match (self.column_styles.get(col), date_style) {
+                    (Some(Some(style)), Some(date_style)) => {
+                        Some(self.styles.with_number_format(*style, date_style))
+                    }
+                    (Some(Some(style)), None) => Some(*style),
+                    (_, Some(date_style)) => Some(date_style),
+                    _ if col_type == TYPE_NUMBER => self.default_number_style,
+                    _ => None,
+                };
+                let style = self.cell_style(col, style);
 
-                let col_type = *types.get(col).unwrap_or(&"s");
+                // Without references an unstyled blank cell says nothing, so it's left
+                // out and the next cell written keeps its reference
+                let is_empty = col_type == TYPE_BLANK || datum.is_empty();
+                if (self.omit_cell_refs && col_type == TYPE_BLANK
+                    || self.skip_empty_cells && is_empty)
+                    && style.is_none()
+                {
+                    continue;
+                }
+
+                final_vec.write_all(b"<c")?;
+                if !self.omit_cell_refs || col != next_col {
+                    self.write_cell_ref(&mut final_vec, col, (row_in_chars_arr, digits))?;
+                }
+                next_col = col + 1;
+                let shared_id = match &mut self.shared_strings {
+                    Some(strings) if col_type == TYPE_STRING => Some(strings.intern(datum)),
+                    _ => None,
+                };
+                if shared_id.is_some() {
+                    final_vec.write_all(b" t=\"s\"")?;
+                } else if col_type != TYPE_BLANK {
+                    final_vec.write_all(b" t=\"")?;
+                    final_vec.write_all(col_type.as_bytes())?;
+                    final_vec.write_all(b"\"")?;
+                }
+                if let Some(style) = style {
+                    write!(final_vec, " s=\"{}\"", style)?;
+                }
 
-                final_vec.write(b"<c r=\"")?;
-                final_vec.write(&ref_id.as_slice()[0..pos])?;
-                final_vec.write(b"\" t=\"")?;
-                final_vec.write(col_type.as_bytes())?;
-                final_vec.write(b"\"><v>")?;
+                if col_type == TYPE_BLANK {
+                    final_vec.write_all(b"/>")?;
+                    continue;
+                }
+                final_vec.write_all(b"><v>")?;
+                if let Some(id) = shared_id {
+                    write!(final_vec, "{}</v></c>", id)?;
+                    continue;
+                }
 
                 let (mut chars, chars_pos) = self.escape_in_place(datum);
                 let mut current_pos = 0;
                 for char_pos in chars_pos {
-                    final_vec.write(&datum[current_pos..char_pos])?;
-                    final_vec.write(chars.pop_front().unwrap())?;
+                    final_vec.write_all(&datum[current_pos..char_pos])?;
+                    final_vec.write_all(chars.pop_front().unwrap())?;
                     current_pos = char_pos + 1;
                 }
 
-                final_vec.write(&datum[current_pos..])?;
-                final_vec.write(b"</v></c>")?;
-
-                col += 1;
+                final_vec.write_all(&datum[current_pos..])?;
+                final_vec.write_all(b"</v></c>")?;
             }
         }
 
-        final_vec.write(b"</row>")?;
+        final_vec.write_all(b"</row>")?;
+
+        self.write_row_xml(&final_vec)?;
+        self.buffers.row = final_vec;
+
+        Ok(())
+    }
+
+    /// Writes each of `rows` with `write_row`, all with the same `types`. Stops at the first
+    /// row that fails, leaving the rows before it written.
+    pub fn write_rows(&mut self, rows: &[Vec<&[u8]>], types: &[&str]) -> Result<()> {
+        let types = types.to_vec();
+        for row in rows {
+            self.write_row(row.clone(), &types)?;
+        }
+        Ok(())
+    }
 
-        self.sheet_buf.write(&final_vec)?;
+    /// Appends an already serialized `<row>...</row>` as the next row, for replaying rows
+    /// serialized once and written many times.
+    ///
+    /// The bytes are written as-is. The caller must supply a single valid row element
+    /// whose `r` attribute and cell references match the next row number (see
+    /// `write_row`), with any escaping and styles already applied. The row is included in
+    /// the autofilter's rows, but its cells are not checked against filter criteria.
+    pub fn write_serialized_row(&mut self, row_xml: &[u8]) -> Result<()> {
+        validate_position(0, self.current_row_num)?;
+        self.init_sheet()?;
+        self.current_row_num += 1;
+        self.write_row_xml(row_xml)
+    }
 
+    fn write_row_xml(&mut self, row_xml: &[u8]) -> Result<()> {
+        match self.fit_widths {
+            Some(_) => self.held_rows.extend_from_slice(row_xml),
+            None => self.sheet_buf.write_all(row_xml)?,
+        }
         Ok(())
     }
 
     fn escape_in_place(&self, bytes: &[u8]) -> (VecDeque<&[u8]>, VecDeque<usize>) {
         let mut special_chars: VecDeque<&[u8]> = VecDeque::new();
         let mut special_char_pos: VecDeque<usize> = VecDeque::new();
-        let len = bytes.len();
-        for x in 0..len {
-            let _ = match bytes[x] {
+        for (x, byte) in bytes.iter().enumerate() {
+            match byte {
                 b'<' => {
                     special_chars.push_back(b"&lt;".as_slice());
                     special_char_pos.push_back(x);
@@ -135,70 +963,1776 @@ impl<'a, W: Write + Seek> TypedSheet<'a, W> {
                     special_char_pos.push_back(x);
                 }
                 _ => (),
-            };
+            }
         }
 
         (special_chars, special_char_pos)
     }
 
+    /// Ends the sheet and writes its charts and notes. Rows can't be written after it, and
+    /// the workbook can't be finished until it is called.
     pub fn close(&mut self) -> Result<()> {
-        self.sheet_buf.write(b"\n</sheetData>\n</worksheet>\n")?;
+        if self.is_closed {
+            bail!("sheet {:?} is already closed", self._name);
+        }
+        for chart in self.charts.iter() {
+            chart.check_bounds(self.current_row_num, self.max_cols)?;
+        }
+
+        if let Some(fit_widths) = self.fit_widths.take() {
+            if self.col_widths.len() < fit_widths.len() {
+                self.col_widths.resize(fit_widths.len(), None);
+            }
+            for (width, chars) in self.col_widths.iter_mut().zip(fit_widths) {
+                if width.is_none() && chars > 0 {
+                    // The same room for padding and the filter button as fit_headers
+                    *width = Some((chars as f64 + 2.0).min(255.0));
+                }
+            }
+        }
+
+        self.init_sheet()?;
+        let held_rows = std::mem::take(&mut self.held_rows);
+        self.sheet_buf.write_all(&held_rows)?;
+        let nl = newline(self.minimal_xml);
+        self.sheet_buf.write_all(nl)?;
+        self.sheet_buf.write_all(b"</sheetData>")?;
+        self.sheet_buf.write_all(nl)?;
+
+        if self.has_autofilter && self.current_row_num > 0 && self.max_cols > 0 {
+            self.write_autofilter()?;
+        }
+
+        if !self.merged.is_empty() {
+            let count = self.merged.len();
+            write!(self.sheet_buf, "<mergeCells count=\"{}\">", count)?;
+            for range in self.merged.iter() {
+                write!(self.sheet_buf, "<mergeCell ref=\"{}\"/>", range)?;
+            }
+            self.sheet_buf.write_all(b"</mergeCells>")?;
+            self.sheet_buf.write_all(nl)?;
+        }
+
+        for (i, rule) in self.text_rules.iter().enumerate() {
+            rule.write_to(self.sheet_buf, i + 1)?;
+            self.sheet_buf.write_all(nl)?;
+        }
+
+        if !self.charts.is_empty() {
+            self.sheet_buf.write_all(b"<drawing r:id=\"rId1\"/>")?;
+            self.sheet_buf.write_all(nl)?;
+        }
+        if !self.notes.is_empty() {
+            self.sheet_buf
+                .write_all(b"<legacyDrawing r:id=\"rId2\"/>")?;
+            self.sheet_buf.write_all(nl)?;
+        }
+
+        self.sheet_buf.write_all(b"</worksheet>")?;
+        self.sheet_buf.write_all(nl)?;
+        self.is_closed = true;
+        *self.open_sheet = None;
+
+        if self.charts.is_empty() && self.notes.is_empty() {
+            return Ok(());
+        }
+        self.write_sheet_rels()?;
+        if !self.charts.is_empty() {
+            self.write_charts()?;
+        }
+        if !self.notes.is_empty() {
+            self.write_notes()?;
+        }
         Ok(())
     }
 
-    fn num_to_bytes(&self, n: u32) -> ([u8; 9], usize) {
-        // Convert from number to string manually
-        let mut row_in_chars_arr: [u8; 9] = [0; 9];
-        let mut row = n;
-        let mut char_pos = 8;
-        let mut digits = 0;
-        while row > 0 {
-            row_in_chars_arr[char_pos] = b'0' + (row % 10) as u8;
-            row = row / 10;
-            char_pos -= 1;
-            digits += 1;
+    // The ids match the ones close gives the drawing and legacyDrawing elements
+    fn write_sheet_rels(&mut self) -> Result<()> {
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(Some(1));
+
+        self.sheet_buf.start_file(
+            format!("xl/worksheets/_rels/sheet{}.xml.rels", self.id),
+            options,
+        )?;
+        self.sheet_buf
+            .write_all(xml_declaration(self.minimal_xml))?;
+        self.sheet_buf.write_all(b"<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">")?;
+        if !self.charts.is_empty() {
+            write!(
+                self.sheet_buf,
+                "<Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/drawing\" Target=\"../drawings/drawing{}.xml\"/>",
+                self.id
+            )?;
+        }
+        if !self.notes.is_empty() {
+            write!(
+                self.sheet_buf,
+                "<Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/vmlDrawing\" Target=\"../drawings/vmlDrawing{0}.vml\"/><Relationship Id=\"rId3\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/comments\" Target=\"../comments{0}.xml\"/>",
+                self.id
+            )?;
         }
+        self.sheet_buf.write_all(b"</Relationships>")?;
 
-        (row_in_chars_arr, digits)
+        Ok(())
     }
 
-    fn ref_id(&mut self, col: usize, row: ([u8; 9], usize)) -> Result<([u8; 12], usize)> {
-        let mut final_arr: [u8; 12] = [0; 12];
-        let letter = self.col_to_letter(col);
+    // The sheet has a single drawing, numbered after it, holding every chart
+    fn write_charts(&mut self) -> Result<()> {
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(Some(1));
+        let first_chart = self.drawings.charts + 1;
 
-        let mut pos: usize = 0;
-        for c in letter {
-            final_arr[pos] = *c;
-            pos += 1;
-        }
+        self.sheet_buf
+            .start_file(format!("xl/drawings/drawing{}.xml", self.id), options)?;
+        self.sheet_buf
+            .write_all(xml_declaration(self.minimal_xml))?;
+        write_drawing(self.sheet_buf, self.charts.len(), self.max_cols)?;
 
-        let (row_in_chars_arr, digits) = row;
+        self.sheet_buf.start_file(
+            format!("xl/drawings/_rels/drawing{}.xml.rels", self.id),
+            options,
+        )?;
+        self.sheet_buf
+            .write_all(xml_declaration(self.minimal_xml))?;
+        write_drawing_rels(self.sheet_buf, first_chart, self.charts.len())?;
 
-        for i in 0..digits {
-            final_arr[pos] = row_in_chars_arr[(8 - digits) + i + 1];
-            pos += 1;
+        for chart in self.charts.iter() {
+            self.drawings.charts += 1;
+            self.sheet_buf.start_file(
+                format!("xl/charts/chart{}.xml", self.drawings.charts),
+                options,
+            )?;
+            self.sheet_buf
+                .write_all(xml_declaration(self.minimal_xml))?;
+            chart.write_to(self.sheet_buf, &self._name, self.has_header)?;
         }
+        self.drawings.sheets.push(self.id);
 
-        Ok((final_arr, pos))
+        Ok(())
     }
 
-    fn col_to_letter(&mut self, col: usize) -> &[u8] {
-        if self.col_num_to_letter.len() < col + 1 as usize {
-            let mut result = Vec::with_capacity(2);
-            let mut col = col as i16;
+    fn write_notes(&mut self) -> Result<()> {
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(Some(1));
+
+        self.sheet_buf
+            .start_file(format!("xl/comments{}.xml", self.id), options)?;
+        self.sheet_buf
+            .write_all(xml_declaration(self.minimal_xml))?;
+        write_comments(self.sheet_buf, &self.notes)?;
+
+        self.sheet_buf
+            .start_file(format!("xl/drawings/vmlDrawing{}.vml", self.id), options)?;
+        // Block 0 is never used
+        let first_block = self.drawings.vml_blocks + 1;
+        self.drawings.vml_blocks += vml_blocks(&self.notes);
+        write_vml(self.sheet_buf, &self.notes, first_block)?;
+        self.drawings.notes.push(self.id);
+
+        Ok(())
+    }
+
+    fn write_autofilter(&mut self) -> Result<()> {
+        let last_col = self.col_to_letter(self.max_cols - 1).to_vec();
+        write!(
+            self.sheet_buf,
+            "<autoFilter ref=\"A{}:{}{}\"",
+            self.header_rows.min(self.current_row_num),
+            String::from_utf8_lossy(&last_col),
+            self.current_row_num
+        )?;
+
+        if self.filter_criteria.iter().all(|x| x.is_none()) {
+            self.sheet_buf.write_all(b"/>")?;
+            self.sheet_buf.write_all(newline(self.minimal_xml))?;
+            return Ok(());
+        }
+
+        self.sheet_buf.write_all(b">")?;
+        for (col, values) in self.filter_criteria.iter().enumerate() {
+            if let Some(values) = values {
+                write!(self.sheet_buf, "<filterColumn colId=\"{}\"><filters", col)?;
+                // Blank cells are matched with an attribute rather than an empty value
+                if values.iter().any(|x| x.is_empty()) {
+                    self.sheet_buf.write_all(b" blank=\"1\"")?;
+                }
+                self.sheet_buf.write_all(b">")?;
+                for value in values.iter().filter(|x| !x.is_empty()) {
+                    write!(self.sheet_buf, "<filter val=\"{}\"/>", escape_xml(value))?;
+                }
+                self.sheet_buf.write_all(b"</filters></filterColumn>")?;
+            }
+        }
+        self.sheet_buf.write_all(b"</autoFilter>")?;
+        self.sheet_buf.write_all(newline(self.minimal_xml))?;
+
+        Ok(())
+    }
+
+    // The cell type, value and style a `TYPE_DATE` cell is written with, or None if it
+    // can't be written as a date
+    fn date_cell<'d>(
+        &mut self,
+        datum: &'d [u8],
+        date_type: &str,
+    ) -> Option<(&'static str, Cow<'d, [u8]>, u32)> {
+        // Other layouts are put in ISO order, and keep their look with a number format
+        let layout_format = date_layout_format(date_type);
+        let iso = match layout_format {
+            Some(_) => Cow::Owned(layout_to_iso(datum, date_type)?),
+            None => Cow::Borrowed(datum),
+        };
+        let (days, seconds) = parse_iso_date(&iso)?;
+
+        let style = match (layout_format, seconds) {
+            (Some(code), _) => self.date_layout_style(code),
+            (None, None) => 1,
+            (None, Some(_)) => *self.date_time_style.get_or_insert_with(|| {
+                self.styles.register(&Format {
+                    builtin_num_format: Some(NUM_FMT_DATE_TIME),
+                    ..Default::default()
+                })
+            }),
+        };
+
+        match self.date_mode {
+            DateMode::Serial => {
+                let days = excel_serial_day(days)?;
+                let serial = match seconds {
+                    Some(seconds) if seconds > 0.0 => (days as f64 + seconds / 86400.0).to_string(),
+                    _ => days.to_string(),
+                };
+                Some((TYPE_NUMBER, Cow::Owned(serial.into_bytes()), style))
+            }
+            DateMode::IsoTyped if iso.get(10) == Some(&b' ') => {
+                let mut iso = iso.into_owned();
+                iso[10] = b'T';
+                Some(("d", Cow::Owned(iso), style))
+            }
+            DateMode::IsoTyped => Some(("d", iso, style)),
+        }
+    }
+
+    fn date_layout_style(&mut self, code: &'static str) -> u32 {
+        if let Some((_, style)) = self.date_layout_styles.iter().find(|x| x.0 == code) {
+            return *style;
+        }
+        let style = self.styles.register(&Format {
+            num_format: Some(String::from(code)),
+            ..Default::default()
+        });
+        self.date_layout_styles.push((code, style));
+        style
+    }
+
+    fn num_to_bytes(&self, n: u32) -> ([u8; 9], usize) {
+        // Convert from number to string manually
+        let mut row_in_chars_arr: [u8; 9] = [0; 9];
+        let mut row = n;
+        let mut char_pos = 8;
+        let mut digits = 0;
+        while row > 0 {
+            row_in_chars_arr[char_pos] = b'0' + (row % 10) as u8;
+            row /= 10;
+            char_pos -= 1;
+            digits += 1;
+        }
+
+        (row_in_chars_arr, digits)
+    }
+
+    fn write_cell_ref(
+        &mut self,
+        out: &mut Vec<u8>,
+        col: usize,
+        row: ([u8; 9], usize),
+    ) -> Result<()> {
+        let (ref_id, pos) = self.ref_id(col, row)?;
+        out.write_all(b" r=\"")?;
+        out.write_all(&ref_id[..pos])?;
+        out.write_all(b"\"")?;
+        Ok(())
+    }
+
+    fn ref_id(&mut self, col: usize, row: ([u8; 9], usize)) -> Result<([u8; 12], usize)> {
+        let mut final_arr: [u8; 12] = [0; 12];
+        let letter = self.col_to_letter(col);
+
+        let mut pos: usize = 0;
+        for c in letter {
+            final_arr[pos] = *c;
+            pos += 1;
+        }
+
+        let (row_in_chars_arr, digits) = row;
+
+        for i in 0..digits {
+            final_arr[pos] = row_in_chars_arr[(8 - digits) + i + 1];
+            pos += 1;
+        }
+
+        Ok((final_arr, pos))
+    }
+
+    fn col_to_letter(&mut self, col: usize) -> &[u8] {
+        // Fills in every column up to `col`, so the cache stays indexed by column
+        while self.buffers.col_num_to_letter.len() < col + 1 {
+            let mut result = Vec::with_capacity(2);
+            let mut col = self.buffers.col_num_to_letter.len();
 
             loop {
                 result.push(b'A' + (col % 26) as u8);
-                col = col / 26 - 1;
-                if col < 0 {
+                col /= 26;
+                if col == 0 {
                     break;
                 }
+                col -= 1;
             }
 
             result.reverse();
-            self.col_num_to_letter.push(result);
+            self.buffers.col_num_to_letter.push(result);
+        }
+
+        &self.buffers.col_num_to_letter[col]
+    }
+}
+
+// 1899-12-31 and 1900-03-01 as days since 0000-03-01, so Excel's serial 1 is 1900-01-01
+const EXCEL_EPOCH: i64 = 693_900;
+const EXCEL_LEAP_BUG: i64 = 693_960;
+
+/// Excel's serial number for a date, with `seconds` into the day added as a fraction of
+/// a day: 1 for 1900-01-01, 61 for 1900-03-01 and 44927 for 2023-01-01. Excel counts a
+/// 29th of February 1900 that never happened, so from March 1900 serials are one more
+/// than the days since 1899-12-31. Returns `None` for dates that don't exist or are
+/// before 1900, which Excel can't store.
+pub fn to_excel_serial(year: i32, month: u32, day: u32, seconds: f64) -> Option<f64> {
+    let days = days_from_civil(year as i64, month as i64, day as i64)?;
+    Some(excel_serial_day(days)? as f64 + seconds / 86400.0)
+}
+
+/// Excel's serial number for a datum of one of the `TYPE_DATE` types, read the way
+/// `TypedSheet` reads it: ISO dates with an optional time for `TYPE_DATE`, and the
+/// layouts of `TYPE_DATE_YMD`, `TYPE_DATE_MDY` and `TYPE_DATE_DMY`. Returns `None` for
+/// other types and for data that isn't a date Excel can store.
+pub fn date_serial(datum: &[u8], date_type: &str) -> Option<f64> {
+    let iso = match date_layout_format(date_type) {
+        Some(_) => Cow::Owned(layout_to_iso(datum, date_type)?),
+        None if date_type == TYPE_DATE => Cow::Borrowed(datum),
+        None => return None,
+    };
+    let (days, seconds) = parse_iso_date(&iso)?;
+    Some(excel_serial_day(days)? as f64 + seconds.unwrap_or(0.0) / 86400.0)
+}
+
+/// The date and whole seconds into the day of an Excel serial number, the inverse of
+/// `to_excel_serial`. Serial 60 is the 29th of February 1900 Excel counts. Returns
+/// `None` for serials below 1, which aren't dates.
+pub fn from_excel_serial(serial: f64) -> Option<(i32, u32, u32, u32)> {
+    let total_seconds = (serial * 86400.0).round();
+    if !(86400.0..=1e15).contains(&total_seconds) {
+        return None;
+    }
+    let (serial, seconds) = (
+        (total_seconds / 86400.0) as i64,
+        total_seconds as i64 % 86400,
+    );
+    if serial == 60 {
+        return Some((1900, 2, 29, seconds as u32));
+    }
+
+    let days = serial + EXCEL_EPOCH - if serial < 60 { 0 } else { 1 };
+    let (year, month, day) = civil_from_days(days);
+    Some((year as i32, month as u32, day as u32, seconds as u32))
+}
+
+// The year, month and day of a count of days since 0000-03-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Counted from March, as in days_from_civil
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let year = era * 400 + year_of_era;
+    if month < 10 {
+        (year, month + 3, day)
+    } else {
+        (year + 1, month - 9, day)
+    }
+}
+
+// The serial of a day counted from 0000-03-01, or None before 1900-01-01
+fn excel_serial_day(days: i64) -> Option<i64> {
+    // Excel counts the 29th of February 1900, which never happened
+    let serial = days - EXCEL_EPOCH + if days < EXCEL_LEAP_BUG { 0 } else { 1 };
+    (serial >= 1).then_some(serial)
+}
+
+// Days since 0000-03-01, or None if the date doesn't exist
+fn days_from_civil(year: i64, month: i64, day: i64) -> Option<i64> {
+    let is_leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let month_days = match month {
+        2 if is_leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if day < 1 || day > month_days {
+        return None;
+    }
+
+    // Counted from March, so the leap day falls at the end of the year
+    let (year, month) = if month > 2 {
+        (year, month - 3)
+    } else {
+        (year - 1, month + 9)
+    };
+    Some(year * 365 + year / 4 - year / 100 + year / 400 + (153 * month + 2) / 5 + day - 1)
+}
+
+// The number format a `TYPE_DATE_` type is shown with
+fn date_layout_format(date_type: &str) -> Option<&'static str> {
+    match date_type {
+        TYPE_DATE_YMD => Some("yyyy-mm-dd"),
+        TYPE_DATE_MDY => Some("mm/dd/yyyy"),
+        TYPE_DATE_DMY => Some("dd/mm/yyyy"),
+        _ => None,
+    }
+}
+
+// Reorders a date in the layout of `date_type` to `YYYY-MM-DD`, without checking that
+// it is a real date
+fn layout_to_iso(datum: &[u8], date_type: &str) -> Option<Vec<u8>> {
+    if date_type == TYPE_DATE_YMD {
+        return (datum.len() == 10).then(|| datum.to_vec());
+    }
+
+    let mut parts = datum.split(|x| *x == b'/');
+    let (Some(first), Some(second), Some(year), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    let is_digits =
+        |x: &[u8], lens: &[usize]| lens.contains(&x.len()) && x.iter().all(|c| c.is_ascii_digit());
+    if !is_digits(first, &[1, 2]) || !is_digits(second, &[1, 2]) || !is_digits(year, &[4]) {
+        return None;
+    }
+
+    let (month, day) = match date_type {
+        TYPE_DATE_MDY => (first, second),
+        TYPE_DATE_DMY => (second, first),
+        _ => return None,
+    };
+    let mut iso = year.to_vec();
+    for part in [month, day] {
+        iso.push(b'-');
+        if part.len() == 1 {
+            iso.push(b'0');
+        }
+        iso.extend_from_slice(part);
+    }
+    Some(iso)
+}
+
+// Parses `YYYY-MM-DD`, optionally followed by `THH:MM`, `THH:MM:SS` or `THH:MM:SS.fff`,
+// into days since 0000-03-01 and seconds into the day when a time is given
+fn parse_iso_date(datum: &[u8]) -> Option<(i64, Option<f64>)> {
+    let number = |x: &[u8]| -> Option<i64> {
+        if x.is_empty() || !x.iter().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        std::str::from_utf8(x).ok()?.parse().ok()
+    };
+
+    if datum.len() < 10 || datum[4] != b'-' || datum[7] != b'-' {
+        return None;
+    }
+    let days = days_from_civil(
+        number(&datum[..4])?,
+        number(&datum[5..7])?,
+        number(&datum[8..10])?,
+    )?;
+
+    if datum.len() == 10 {
+        return Some((days, None));
+    }
+    if !matches!(datum[10], b'T' | b' ') || datum.len() < 16 || datum[13] != b':' {
+        return None;
+    }
+    let (hour, minute) = (number(&datum[11..13])?, number(&datum[14..16])?);
+
+    let second = match &datum[16..] {
+        [] => 0.0,
+        [b':', rest @ ..] if rest.len() >= 2 && rest[..2].iter().all(|c| c.is_ascii_digit()) => {
+            match &rest[2..] {
+                [] => number(rest)? as f64,
+                [b'.', frac @ ..] if number(frac).is_some() => {
+                    std::str::from_utf8(rest).ok()?.parse().ok()?
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    if hour > 23 || minute > 59 || second >= 60.0 {
+        return None;
+    }
+
+    Some((days, Some((hour * 3600 + minute * 60) as f64 + second)))
+}
+
+/// The separators numbers are written with in the source data. The xlsx file always
+/// stores numbers with a `.` decimal and no grouping.
+#[derive(Clone, Copy, Debug)]
+pub struct NumberLocale {
+    pub decimal_sep: u8,
+    pub thousands_sep: Option<u8>,
+    /// Reads a date like `01/02/2023` as the 1st of February rather than January 2nd.
+    /// Dates that can only be read one way, like `13/02/2023`, are read that way.
+    pub day_first: bool,
+}
+
+impl Default for NumberLocale {
+    fn default() -> Self {
+        NumberLocale {
+            decimal_sep: b'.',
+            thousands_sep: None,
+            day_first: false,
         }
+    }
+}
+
+impl NumberLocale {
+    /// Returns the `TYPE_DATE_` type whose layout `datum` is a valid date in, if any.
+    pub fn date_type(&self, datum: &[u8]) -> Option<&'static str> {
+        let slash_dates = match self.day_first {
+            true => [TYPE_DATE_DMY, TYPE_DATE_MDY],
+            false => [TYPE_DATE_MDY, TYPE_DATE_DMY],
+        };
+        [TYPE_DATE_YMD]
+            .into_iter()
+            .chain(slash_dates)
+            .find(|x| layout_to_iso(datum, x).is_some_and(|iso| parse_iso_date(&iso).is_some()))
+    }
+
+    /// Rewrites `datum` the way xlsx stores numbers, or returns None if it isn't a number
+    /// in this locale. Thousands separators must split the integer part into groups of 3.
+    pub fn parse<'b>(&self, datum: &'b [u8]) -> Option<Cow<'b, [u8]>> {
+        let (sign, unsigned) = match datum.first() {
+            Some(b'-') => (&datum[..1], &datum[1..]),
+            _ => (&datum[..0], datum),
+        };
+
+        let (mantissa, exponent) = match unsigned.iter().position(|x| matches!(x, b'e' | b'E')) {
+            Some(pos) => (&unsigned[..pos], Some(&unsigned[pos + 1..])),
+            None => (unsigned, None),
+        };
+
+        let (int_part, frac_part) = match mantissa.iter().position(|x| *x == self.decimal_sep) {
+            Some(pos) => (&mantissa[..pos], Some(&mantissa[pos + 1..])),
+            None => (mantissa, None),
+        };
+
+        let is_digits = |x: &[u8]| x.iter().all(|c| c.is_ascii_digit());
+
+        let mut groups = match self.thousands_sep {
+            Some(sep) => int_part.split(|x| *x == sep).collect(),
+            None => vec![int_part],
+        };
+        if groups.len() > 1
+            && (groups[0].is_empty()
+                || groups[0].len() > 3
+                || groups[1..].iter().any(|x| x.len() != 3))
+        {
+            return None;
+        }
+        if !groups.iter().all(|x| is_digits(x)) {
+            return None;
+        }
+
+        let frac_part = frac_part.unwrap_or(&[]);
+        if !is_digits(frac_part) || (int_part.is_empty() && frac_part.is_empty()) {
+            return None;
+        }
+
+        if let Some(exponent) = exponent {
+            let digits = match exponent.first() {
+                Some(b'-' | b'+') => &exponent[1..],
+                _ => exponent,
+            };
+            if digits.is_empty() || !is_digits(digits) {
+                return None;
+            }
+        }
+
+        if groups.len() == 1 && (self.decimal_sep == b'.' || frac_part.is_empty()) {
+            return Some(Cow::Borrowed(datum));
+        }
+
+        let mut number = sign.to_vec();
+        groups.drain(..).for_each(|x| number.extend_from_slice(x));
+        if !frac_part.is_empty() {
+            number.push(b'.');
+            number.extend_from_slice(frac_part);
+        }
+        if let Some(exponent) = exponent {
+            number.push(b'E');
+            number.extend_from_slice(exponent);
+        }
+
+        Some(Cow::Owned(number))
+    }
+}
+
+/// A custom inference function for `infer_row_types_with`, taking a field and returning
+/// its cell type.
+pub type InferFn<'a> = dyn Fn(&[u8]) -> &'static str + 'a;
+
+/// Infers the type of each field in `row`: `TYPE_NUMBER` if it is a number in `locale`,
+/// `TYPE_BOOLEAN` for `true` or `false` in any case, the `TYPE_DATE_` type of the layout a
+/// date is written in, so it's shown the same way, otherwise `TYPE_STRING`.
+pub fn infer_row_types(row: &[&[u8]], locale: &NumberLocale) -> Vec<&'static str> {
+    row.iter().map(|x| infer_type(x, locale)).collect()
+}
+
+/// Infers one type for each column of `rows`, for columns that should be written the
+/// same way all the way down: the type `infer_type` gives every non-empty field of the
+/// column if they all agree, otherwise `TYPE_STRING`, as it is for columns with no
+/// non-empty field. Empty fields aren't numbers or dates, so they are best written as
+/// text whatever their column's type.
+pub fn infer_column_types(rows: &[Vec<&[u8]>], locale: &NumberLocale) -> Vec<&'static str> {
+    let cols = rows.iter().map(|x| x.len()).max().unwrap_or(0);
+    let mut types: Vec<Option<&'static str>> = vec![None; cols];
+
+    for row in rows {
+        for (col, datum) in row.iter().enumerate() {
+            // Once a column holds text it stays text, so there's nothing left to infer
+            if datum.is_empty() || types[col] == Some(TYPE_STRING) {
+                continue;
+            }
+            let inferred = infer_type(datum, locale);
+            types[col] = match types[col] {
+                Some(x) if x != inferred => Some(TYPE_STRING),
+                _ => Some(inferred),
+            };
+        }
+    }
+
+    types
+        .into_iter()
+        .map(|x| x.unwrap_or(TYPE_STRING))
+        .collect()
+}
+
+/// Infers the type of a single field, the way `infer_row_types` does.
+pub fn infer_type(datum: &[u8], locale: &NumberLocale) -> &'static str {
+    match locale.parse(datum) {
+        Some(_) => TYPE_NUMBER,
+        None if datum.eq_ignore_ascii_case(b"true") || datum.eq_ignore_ascii_case(b"false") => {
+            TYPE_BOOLEAN
+        }
+        None => locale.date_type(datum).unwrap_or(TYPE_STRING),
+    }
+}
+
+// Whether `datum` is a number as xlsx stores it, like `-12`, `0.5` or `1.5E-7`. Rust's
+// own parsing also takes `inf` and `NaN`, which Excel doesn't
+fn is_xlsx_number(datum: &[u8]) -> bool {
+    let digits = |x: &[u8]| x.iter().take_while(|x| x.is_ascii_digit()).count();
+
+    let mut i = usize::from(matches!(datum.first(), Some(b'+' | b'-')));
+    let int_digits = digits(&datum[i..]);
+    i += int_digits;
+    let mut frac_digits = 0;
+    if datum.get(i) == Some(&b'.') {
+        frac_digits = digits(&datum[i + 1..]);
+        i += 1 + frac_digits;
+    }
+    if int_digits + frac_digits == 0 {
+        return false;
+    }
+    if matches!(datum.get(i), Some(b'e' | b'E')) {
+        i += 1;
+        i += usize::from(matches!(datum.get(i), Some(b'+' | b'-')));
+        let exp_digits = digits(&datum[i..]);
+        if exp_digits == 0 {
+            return false;
+        }
+        i += exp_digits;
+    }
+    i == datum.len()
+}
+
+// The value of a TYPE_BOOLEAN cell
+fn parse_bool(datum: &[u8]) -> Option<&'static [u8]> {
+    match datum {
+        b"1" => Some(b"1"),
+        b"0" => Some(b"0"),
+        x if x.eq_ignore_ascii_case(b"true") => Some(b"1"),
+        x if x.eq_ignore_ascii_case(b"false") => Some(b"0"),
+        _ => None,
+    }
+}
+
+/// Like `infer_row_types`, but with `infer` given it is called once for each field of
+/// `row`, in order, and its type is used in place of the built-in inference. It can
+/// call `infer_type` for the fields it has no opinion on.
+///
+/// ```ignore
+/// let locale = NumberLocale::default();
+/// let ids: Box<InferFn> = Box::new(move |x| match x.starts_with(b"ID-") {
+///     true => TYPE_STRING,
+///     false => infer_type(x, &locale),
+/// });
+/// let types = infer_row_types_with(&row, &locale, Some(ids.as_ref()));
+/// ```
+pub fn infer_row_types_with(
+    row: &[&[u8]],
+    locale: &NumberLocale,
+    infer: Option<&InferFn<'_>>,
+) -> Vec<&'static str> {
+    match infer {
+        Some(infer) => row.iter().map(|x| infer(x)).collect(),
+        None => infer_row_types(row, locale),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::style::{
+        BorderStyle, Fill, HorizontalAlign, NUM_FMT_DATE, NUM_FMT_DATE_TIME, NUM_FMT_PERCENT,
+    };
+    use crate::testing::{read_cells, read_part, ParsedCell};
+    use crate::WorkBook;
+
+    type Sheet<'a> = TypedSheet<'a, Cursor<Vec<u8>>>;
+
+    /// Writes a workbook with one sheet, which `write` fills, and returns its bytes.
+    fn write_sheet(write: impl FnOnce(&mut Sheet<'_>) -> Result<()>) -> Vec<u8> {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        write(&mut worksheet).unwrap();
+        worksheet.close().unwrap();
+        workbook.finish().unwrap().into_inner()
+    }
+
+    fn sheet_xml(bytes: &[u8]) -> String {
+        read_part(bytes, "xl/worksheets/sheet1.xml")
+    }
+
+    #[test]
+    fn default_number_format_applies_to_number_cells_only() {
+        let bytes = write_sheet(|worksheet| {
+            worksheet.set_default_number_format("#,##0");
+            worksheet.write_row(vec![b"Name", b"Total"], &vec![TYPE_STRING, TYPE_NUMBER])?;
+            worksheet.write_row(vec![b"Ada", b"1234"], &vec![TYPE_STRING, TYPE_NUMBER])
+        });
+
+        let styles = read_part(&bytes, "xl/styles.xml");
+        assert!(styles.contains("<numFmt numFmtId=\"164\" formatCode=\"#,##0\"/>"));
+        assert!(styles.contains(
+            "<xf numFmtId=\"164\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\" applyNumberFormat=\"1\"/>"
+        ));
+
+        let sheet = sheet_xml(&bytes);
+        assert!(
+            sheet.contains("<c r=\"A2\" t=\"str\"><v>Ada</v></c>"),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains("<c r=\"B2\" t=\"n\" s=\"2\"><v>1234</v></c>"),
+            "{sheet}"
+        );
+        assert_eq!(
+            read_cells(&bytes, "Sheet 1")[1][1],
+            ParsedCell::Number(1234.0)
+        );
+    }
+
+    #[test]
+    fn filter_criteria_hide_rows_that_dont_match() {
+        let bytes = write_sheet(|worksheet| {
+            worksheet.set_filter_criteria(1, &["Active", ""]);
+            let types = vec![TYPE_STRING; 2];
+            worksheet.write_row(vec![b"Name", b"Status"], &types)?;
+            worksheet.write_row(vec![b"a", b"Active"], &types)?;
+            worksheet.write_row(vec![b"b", b"Closed"], &types)?;
+            worksheet.write_row(vec![b"c", b"ACTIVE"], &types)?;
+            worksheet.write_row(vec![b"d", b""], &types)
+        });
+
+        let sheet = sheet_xml(&bytes);
+        assert!(sheet.contains("<sheetPr filterMode=\"1\"/>"), "{sheet}");
+        assert!(
+            sheet.contains(
+                "<autoFilter ref=\"A1:B5\"><filterColumn colId=\"1\"><filters blank=\"1\">\
+                 <filter val=\"Active\"/></filters></filterColumn></autoFilter>"
+            ),
+            "{sheet}"
+        );
+
+        let hidden: Vec<&str> = sheet
+            .split("<row ")
+            .skip(1)
+            .map(|x| &x[..x.find('>').unwrap()])
+            .filter(|x| x.contains("hidden=\"1\""))
+            .collect();
+        assert_eq!(hidden, vec!["r=\"3\" hidden=\"1\""]);
+    }
+
+    #[test]
+    fn european_numbers_parse_with_a_decimal_point() {
+        let locale = NumberLocale {
+            decimal_sep: b',',
+            thousands_sep: Some(b'.'),
+            day_first: true,
+        };
+        let parse = |x: &[u8]| locale.parse(x).map(|x| x.into_owned());
+
+        assert_eq!(parse(b"1.234,56"), Some(b"1234.56".to_vec()));
+        assert_eq!(parse(b"-1.234.567"), Some(b"-1234567".to_vec()));
+        assert_eq!(parse(b"0,5"), Some(b"0.5".to_vec()));
+        assert_eq!(parse(b"12"), Some(b"12".to_vec()));
+        // Groups after the first must have 3 digits
+        assert_eq!(parse(b"1.23,4"), None);
+        assert_eq!(parse(b"1.234.5"), None);
+        assert_eq!(parse(b"1,2,3"), None);
+    }
+
+    #[test]
+    fn styled_header_references_the_composite_style() {
+        let bytes = write_sheet(|worksheet| {
+            worksheet.style_header(HeaderStyle::default());
+            let types = vec![TYPE_STRING; 2];
+            worksheet.write_row(vec![b"Name", b"Status"], &types)?;
+            worksheet.write_row(vec![b"a", b"Active"], &types)
+        });
+
+        // Bold, the gray fill after Excel's two and a thin bottom border
+        let styles = read_part(&bytes, "xl/styles.xml");
+        assert!(styles.contains(
+            "<fill><patternFill patternType=\"solid\"><fgColor rgb=\"FFD9D9D9\"/></patternFill></fill>"
+        ));
+        assert!(styles
+            .contains("<border><left/><right/><top/><bottom style=\"thin\"/><diagonal/></border>"));
+        assert!(styles.contains(
+            "<xf numFmtId=\"0\" fontId=\"1\" fillId=\"2\" borderId=\"1\" xfId=\"0\" \
+             applyFont=\"1\" applyFill=\"1\" applyBorder=\"1\"/>"
+        ));
+
+        let sheet = sheet_xml(&bytes);
+        assert!(sheet.contains("state=\"frozen\""), "{sheet}");
+        assert!(
+            sheet.contains(
+                "<row r=\"1\"><c r=\"A1\" t=\"str\" s=\"2\"><v>Name</v></c>\
+                 <c r=\"B1\" t=\"str\" s=\"2\"><v>Status</v></c></row>"
+            ),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains("<c r=\"A2\" t=\"str\"><v>a</v></c>"),
+            "{sheet}"
+        );
+    }
+
+    /// The sides each cell's border draws, e.g. "left top", by cell reference. Cells
+    /// without a style aren't included.
+    fn border_sides(bytes: &[u8]) -> HashMap<String, String> {
+        let styles = read_part(bytes, "xl/styles.xml");
+        let section = |name: &str| {
+            let start = styles.find(&format!("<{name} ")).unwrap();
+            let end = styles.find(&format!("</{name}>")).unwrap();
+            styles[start..end].to_string()
+        };
+        let attr = |xml: &str, name: &str| {
+            let value = xml.split(&format!(" {name}=\"")).nth(1)?;
+            Some(value[..value.find('"').unwrap()].to_string())
+        };
+
+        let borders: Vec<String> = section("borders")
+            .split("<border>")
+            .skip(1)
+            .map(|border| {
+                ["left", "right", "top", "bottom"]
+                    .into_iter()
+                    .filter(|side| border.contains(&format!("<{side} style=")))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        let xf_borders: Vec<usize> = section("cellXfs")
+            .split("<xf ")
+            .skip(1)
+            .map(|xf| {
+                attr(&format!(" {xf}"), "borderId")
+                    .unwrap()
+                    .parse()
+                    .unwrap()
+            })
+            .collect();
+
+        sheet_xml(bytes)
+            .split("<c ")
+            .skip(1)
+            .filter_map(|cell| {
+                let cell = format!(" {}", &cell[..cell.find('>').unwrap()]);
+                let style: usize = attr(&cell, "s")?.parse().unwrap();
+                let sides = borders[xf_borders[style]].clone();
+                Some((attr(&cell, "r").unwrap(), sides))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn border_range_outlines_a_box() {
+        let thin = Some(BorderStyle::Thin);
+        let border = Border {
+            left: thin,
+            right: thin,
+            top: thin,
+            bottom: thin,
+            color: None,
+        };
+
+        let bytes = write_sheet(|worksheet| {
+            worksheet.apply_border_range((1, 0), (3, 2), border, true);
+            let types = vec![TYPE_STRING; 4];
+            for _ in 0..5 {
+                worksheet.write_row(vec![b"x"; 4], &types)?;
+            }
+            Ok(())
+        });
+
+        let expected: HashMap<String, String> = [
+            ("A2", "left top"),
+            ("B2", "top"),
+            ("C2", "right top"),
+            ("A3", "left"),
+            ("C3", "right"),
+            ("A4", "left bottom"),
+            ("B4", "bottom"),
+            ("C4", "right bottom"),
+        ]
+        .into_iter()
+        .map(|(cell, sides)| (String::from(cell), String::from(sides)))
+        .collect();
+        // B3 in the middle, the header, column D and the row below get nothing
+        assert_eq!(border_sides(&bytes), expected);
+    }
+
+    #[test]
+    fn border_range_can_draw_every_cell() {
+        let border = Border {
+            bottom: Some(BorderStyle::Medium),
+            ..Default::default()
+        };
+
+        let bytes = write_sheet(|worksheet| {
+            worksheet.apply_border_range((1, 0), (2, 1), border, false);
+            for _ in 0..3 {
+                worksheet.write_row(vec![b"x"; 2], &vec![TYPE_STRING; 2])?;
+            }
+            Ok(())
+        });
+
+        let sides = border_sides(&bytes);
+        assert_eq!(sides.len(), 4);
+        assert!(sides.values().all(|x| x == "bottom"));
+    }
+
+    #[test]
+    fn date_column_uses_builtin_format_14() {
+        let bytes = write_sheet(|worksheet| {
+            worksheet.set_column_builtin_format(1, NUM_FMT_DATE)?;
+            worksheet.set_column_builtin_format(2, NUM_FMT_PERCENT)?;
+            let types = vec![TYPE_STRING, TYPE_NUMBER, TYPE_NUMBER];
+            worksheet.write_row(vec![b"Name", b"Joined", b"Share"], &types)?;
+            worksheet.write_row(vec![b"Ada", b"45000", b"0.25"], &types)
+        });
+
+        // Neither format is defined in the file, as readers know them by id. The date
+        // format maps onto the built-in date style
+        let styles = read_part(&bytes, "xl/styles.xml");
+        assert!(!styles.contains("<numFmts"), "{styles}");
+        assert!(styles.contains(
+            "<xf numFmtId=\"9\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\" applyNumberFormat=\"1\"/>"
+        ));
+
+        let sheet = sheet_xml(&bytes);
+        assert!(
+            sheet.contains("<c r=\"B2\" t=\"n\" s=\"1\"><v>45000</v></c>"),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains("<c r=\"C2\" t=\"n\" s=\"2\"><v>0.25</v></c>"),
+            "{sheet}"
+        );
+        assert_eq!(
+            read_cells(&bytes, "Sheet 1")[1][1],
+            ParsedCell::Date(45000.0)
+        );
+    }
+
+    #[test]
+    fn custom_format_ids_are_not_builtin() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        let err = worksheet.set_column_builtin_format(0, 164).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "164 is not a built-in number format id, custom formats start at 164"
+        );
+    }
+
+    #[test]
+    fn serialized_row_is_written_as_the_next_row() {
+        let bytes = write_sheet(|worksheet| {
+            worksheet.write_row(vec![b"Name", b"Age"], &vec![TYPE_STRING, TYPE_NUMBER])?;
+            worksheet.write_serialized_row(
+                b"<row r=\"2\"><c r=\"A2\" t=\"str\"><v>Ada</v></c><c r=\"B2\"><v>36</v></c></row>",
+            )?;
+            assert_eq!(worksheet.current_row(), 2);
+            worksheet.write_row(vec![b"Grace", b"85"], &vec![TYPE_STRING, TYPE_NUMBER])
+        });
+
+        assert_eq!(
+            read_cells(&bytes, "Sheet 1"),
+            vec![
+                vec![
+                    ParsedCell::String(String::from("Name")),
+                    ParsedCell::String(String::from("Age"))
+                ],
+                vec![
+                    ParsedCell::String(String::from("Ada")),
+                    ParsedCell::Number(36.0)
+                ],
+                vec![
+                    ParsedCell::String(String::from("Grace")),
+                    ParsedCell::Number(85.0)
+                ],
+            ]
+        );
+        assert!(sheet_xml(&bytes).contains("<row r=\"3\">"));
+    }
+
+    #[test]
+    fn four_section_number_format_round_trips() {
+        let code = "#,##0.00;[Red](#,##0.00);\"-\";@";
+        let bytes = write_sheet(|worksheet| {
+            worksheet.set_default_number_format(code);
+            worksheet.write_row(vec![b"Balance"], &vec![TYPE_NUMBER])?;
+            worksheet.write_row(vec![b"-1234.5"], &vec![TYPE_NUMBER])
+        });
+
+        let styles = read_part(&bytes, "xl/styles.xml");
+        let written = styles
+            .split("<numFmt numFmtId=\"164\" formatCode=\"")
+            .nth(1)
+            .unwrap();
+        let written = &written[..written.find('"').unwrap()];
+        assert_eq!(written.replace("&quot;", "\""), code);
+
+        assert!(sheet_xml(&bytes).contains("<c r=\"A2\" t=\"n\" s=\"2\"><v>-1234.5</v></c>"));
+        assert_eq!(
+            read_cells(&bytes, "Sheet 1")[1][0],
+            ParsedCell::Number(-1234.5)
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced_in_every_cell() {
+        let write = |worksheet: &mut Sheet<'_>| {
+            worksheet.write_row(vec![b"Na\xFFme"], &vec![TYPE_STRING])?;
+            worksheet.write_row(vec![b"\xFF"], &vec![TYPE_STRING])?;
+            worksheet.write_row(vec![b"caf\xC3"], &vec![TYPE_NUMBER])
+        };
+        let expected = vec![
+            vec![ParsedCell::String(String::from("Na\u{FFFD}me"))],
+            vec![ParsedCell::String(String::from("\u{FFFD}"))],
+            vec![ParsedCell::String(String::from("caf\u{FFFD}"))],
+        ];
+
+        // Reading the part as a string fails on invalid UTF-8
+        let bytes = write_sheet(write);
+        assert!(sheet_xml(&bytes).contains("<v>\u{FFFD}</v>"));
+        assert_eq!(read_cells(&bytes, "Sheet 1"), expected);
+
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook.enable_shared_strings();
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        write(&mut worksheet).unwrap();
+        worksheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+        assert!(read_part(&bytes, "xl/sharedStrings.xml").contains("Na\u{FFFD}me"));
+        assert_eq!(read_cells(&bytes, "Sheet 1"), expected);
+    }
+
+    #[test]
+    fn column_format_returns_the_format_set() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        let format = Format {
+            num_format: Some(String::from("#,##0")),
+            bold: true,
+            ..Default::default()
+        };
+        worksheet.set_column_format(1, &format);
+
+        assert_eq!(worksheet.column_format(0), None);
+        assert_eq!(worksheet.column_format(1), Some(format));
+        assert_eq!(worksheet.column_format(2), None);
+    }
+
+    fn pane_xml(rows: u32, cols: usize) -> String {
+        let xml = sheet_xml(&write_sheet(|worksheet| {
+            worksheet.freeze_panes(rows, cols);
+            worksheet.write_row(vec![b"Name", b"Age"], &vec![TYPE_STRING, TYPE_STRING])
+        }));
+        let start = xml.find("<pane ").unwrap();
+        String::from(&xml[start..xml.find("</sheetView>").unwrap()])
+    }
+
+    #[test]
+    fn frozen_first_column_splits_to_the_top_right() {
+        assert_eq!(
+            pane_xml(0, 1),
+            concat!(
+                r#"<pane xSplit="1" topLeftCell="B1" activePane="topRight" state="frozen"/>"#,
+                r#"<selection activeCell="A1" sqref="A1"/>"#,
+                r#"<selection pane="topRight" activeCell="B1" sqref="B1"/>"#
+            )
+        );
+        assert_eq!(
+            pane_xml(1, 0),
+            concat!(
+                r#"<pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/>"#,
+                r#"<selection activeCell="A1" sqref="A1"/>"#,
+                r#"<selection pane="bottomLeft" activeCell="A2" sqref="A2"/>"#
+            )
+        );
+        assert!(pane_xml(1, 1).starts_with(
+            r#"<pane xSplit="1" ySplit="1" topLeftCell="B2" activePane="bottomRight" state="frozen"/>"#
+        ));
+    }
+
+    fn write_dates(mode: DateMode, format: Option<&Format>) -> Vec<u8> {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook.set_date_mode(mode);
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        if let Some(format) = format {
+            worksheet.set_column_format(0, format);
+        }
+        let types = vec![TYPE_DATE];
+        worksheet.write_row(vec![b"Joined"], &types).unwrap();
+        worksheet.write_row(vec![b"2024-01-02"], &types).unwrap();
+        worksheet
+            .write_row(vec![b"2024-01-02 06:00:00"], &types)
+            .unwrap();
+        worksheet.close().unwrap();
+        workbook.finish().unwrap().into_inner()
+    }
+
+    // The number format of the style cell `cell` references
+    fn num_fmt_id(bytes: &[u8], cell: &str) -> String {
+        let sheet = sheet_xml(bytes);
+        let tag = sheet.split(&format!("<c r=\"{cell}\"")).nth(1).unwrap();
+        let tag = &tag[..tag.find('>').unwrap()];
+        let style: usize = tag
+            .split(" s=\"")
+            .nth(1)
+            .unwrap()
+            .split('"')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let styles = read_part(bytes, "xl/styles.xml");
+        let xf = styles
+            .split("<cellXfs")
+            .nth(1)
+            .unwrap()
+            .split("<xf ")
+            .nth(style + 1)
+            .unwrap();
+        let id = xf.split("numFmtId=\"").nth(1).unwrap();
+        String::from(&id[..id.find('"').unwrap()])
+    }
+
+    #[test]
+    fn dates_read_back_as_dates_in_both_modes() {
+        let bytes = write_dates(DateMode::Serial, None);
+        assert!(sheet_xml(&bytes).contains(r#"<c r="A2" t="n" s="1"><v>45293</v></c>"#));
+        assert_eq!(
+            read_cells(&bytes, "Sheet 1")[1..],
+            [
+                vec![ParsedCell::Date(45293.0)],
+                vec![ParsedCell::Date(45293.25)]
+            ]
+        );
+
+        let bytes = write_dates(DateMode::IsoTyped, None);
+        assert!(sheet_xml(&bytes).contains(r#"<c r="A2" t="d" s="1"><v>2024-01-02</v></c>"#));
+        assert_eq!(num_fmt_id(&bytes, "A3"), NUM_FMT_DATE_TIME.to_string());
+        assert_eq!(
+            read_cells(&bytes, "Sheet 1")[1..],
+            [
+                vec![ParsedCell::IsoDate(String::from("2024-01-02"))],
+                vec![ParsedCell::IsoDate(String::from("2024-01-02T06:00:00"))]
+            ]
+        );
+    }
+
+    #[test]
+    fn dates_keep_their_format_in_a_styled_column() {
+        let aligned = Format {
+            align: Some(HorizontalAlign::Left),
+            ..Default::default()
+        };
+        for mode in [DateMode::Serial, DateMode::IsoTyped] {
+            let bytes = write_dates(mode, Some(&aligned));
+            assert_eq!(num_fmt_id(&bytes, "A2"), NUM_FMT_DATE.to_string());
+            assert_eq!(num_fmt_id(&bytes, "A3"), NUM_FMT_DATE_TIME.to_string());
+            assert!(
+                read_part(&bytes, "xl/styles.xml").contains(r#"<alignment horizontal="left"/>"#)
+            );
+        }
+        let cells = read_cells(&write_dates(DateMode::Serial, Some(&aligned)), "Sheet 1");
+        assert_eq!(cells[1], vec![ParsedCell::Date(45293.0)]);
+
+        // A column's own number format wins
+        let formatted = Format {
+            num_format: Some(String::from("yyyy")),
+            ..Default::default()
+        };
+        let bytes = write_dates(DateMode::Serial, Some(&formatted));
+        assert_eq!(num_fmt_id(&bytes, "A2"), "164");
+        assert_eq!(num_fmt_id(&bytes, "A3"), "164");
+    }
+
+    #[test]
+    fn position_tracks_the_rows_written() {
+        write_sheet(|worksheet| {
+            assert_eq!((worksheet.current_row(), worksheet.max_column()), (0, 0));
+
+            worksheet.write_row(vec![b"Name", b"Age"], &vec![])?;
+            assert_eq!((worksheet.current_row(), worksheet.max_column()), (1, 2));
+
+            worksheet.write_row(vec![b"Ada", b"36", b"London"], &vec![])?;
+            worksheet.write_row(vec![b"Alan"], &vec![])?;
+            assert_eq!((worksheet.current_row(), worksheet.max_column()), (3, 3));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn first_row_is_typed_when_it_is_not_a_header() {
+        let types = vec![TYPE_STRING, TYPE_NUMBER];
+        let write = |header: bool| {
+            write_sheet(|worksheet| {
+                worksheet.treat_first_row_as_header(header);
+                worksheet.write_row(vec![b"Ada", b"36"], &types)?;
+                worksheet.write_row(vec![b"Alan", b"41"], &types)
+            })
+        };
+
+        let sheet = sheet_xml(&write(false));
+        assert!(
+            sheet.contains("<c r=\"B1\" t=\"n\"><v>36</v></c>"),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains("<c r=\"B2\" t=\"n\"><v>41</v></c>"),
+            "{sheet}"
+        );
+
+        let sheet = sheet_xml(&write(true));
+        assert!(
+            sheet.contains("<c r=\"B1\" t=\"str\"><v>36</v></c>"),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains("<c r=\"B2\" t=\"n\"><v>41</v></c>"),
+            "{sheet}"
+        );
+    }
+
+    #[test]
+    fn text_rule_highlights_its_range_with_a_dxf() {
+        let red = Format {
+            bold: true,
+            fill: Some(Fill::solid([0xFF, 0xC7, 0xCE])),
+            ..Default::default()
+        };
+        let bytes = write_sheet(|worksheet| {
+            worksheet.add_text_rule(1, 1, 3, TextOp::Contains, "FAILED", &red);
+            // The same format is one dxf
+            worksheet.add_text_rule(0, 1, 3, TextOp::Equal, "a \"b\"", &red);
+            let types = vec![TYPE_STRING; 2];
+            worksheet.write_row(vec![b"Name", b"Status"], &types)?;
+            worksheet.write_row(vec![b"a", b"FAILED"], &types)?;
+            worksheet.write_row(vec![b"b", b"passed"], &types)
+        });
+
+        let sheet = sheet_xml(&bytes);
+        assert!(
+            sheet.contains(
+                "<conditionalFormatting sqref=\"B2:B4\"><cfRule type=\"containsText\" dxfId=\"0\" \
+                 priority=\"1\" operator=\"containsText\" text=\"FAILED\">\
+                 <formula>NOT(ISERROR(SEARCH(&quot;FAILED&quot;,B2)))</formula></cfRule>\
+                 </conditionalFormatting>"
+            ),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains(
+                "<conditionalFormatting sqref=\"A2:A4\"><cfRule type=\"cellIs\" dxfId=\"0\" \
+                 priority=\"2\" operator=\"equal\">\
+                 <formula>&quot;a &quot;&quot;b&quot;&quot;&quot;</formula></cfRule>\
+                 </conditionalFormatting>"
+            ),
+            "{sheet}"
+        );
+
+        let styles = read_part(&bytes, "xl/styles.xml");
+        assert!(
+            styles.contains(
+                "<dxfs count=\"1\"><dxf><font><b/></font><fill><patternFill patternType=\"solid\">\
+                 <fgColor rgb=\"FFFFC7CE\"/><bgColor rgb=\"FFFFC7CE\"/></patternFill></fill>\
+                 </dxf></dxfs>"
+            ),
+            "{styles}"
+        );
+    }
+
+    #[test]
+    fn write_rows_matches_a_write_row_loop() {
+        let rows: Vec<Vec<&[u8]>> = vec![
+            vec![b"Name", b"Age"],
+            vec![b"Ada", b"36"],
+            vec![b"Alan", b"41"],
+        ];
+        let types = vec![TYPE_STRING, TYPE_NUMBER];
+
+        let batch = write_sheet(|worksheet| worksheet.write_rows(&rows, &types));
+        let looped = write_sheet(|worksheet| {
+            for row in &rows {
+                worksheet.write_row(row.clone(), &types)?;
+            }
+            Ok(())
+        });
+        assert_eq!(sheet_xml(&batch), sheet_xml(&looped));
+        assert_eq!(
+            read_cells(&batch, "Sheet 1")[2][1],
+            ParsedCell::Number(41.0)
+        );
+    }
+
+    #[test]
+    fn corner_freeze_selects_in_all_four_panes() {
+        assert_eq!(
+            pane_xml(2, 1),
+            concat!(
+                r#"<pane xSplit="1" ySplit="2" topLeftCell="B3" activePane="bottomRight" state="frozen"/>"#,
+                r#"<selection activeCell="A1" sqref="A1"/>"#,
+                r#"<selection pane="topRight" activeCell="B1" sqref="B1"/>"#,
+                r#"<selection pane="bottomLeft" activeCell="A3" sqref="A3"/>"#,
+                r#"<selection pane="bottomRight" activeCell="B3" sqref="B3"/>"#
+            )
+        );
+
+        // freeze_top_row is the same as a one-row freeze
+        let xml = sheet_xml(&write_sheet(|worksheet| {
+            worksheet.freeze_top_row();
+            worksheet.write_row(vec![b"Name"], &vec![])
+        }));
+        assert!(xml.contains(&pane_xml(1, 0)), "{xml}");
+    }
+
+    #[test]
+    fn blanks_in_a_date_column_are_empty_cells() {
+        for (mode, date) in [
+            (DateMode::Serial, ParsedCell::Date(45293.0)),
+            (
+                DateMode::IsoTyped,
+                ParsedCell::IsoDate(String::from("2024-01-02")),
+            ),
+        ] {
+            let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+            workbook.set_date_mode(mode);
+            let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+            let types = vec![TYPE_STRING, TYPE_DATE];
+            for row in [
+                vec![b"Name" as &[u8], b"Joined"],
+                vec![b"Ada", b""],
+                vec![b"Alan", b"2024-01-02"],
+            ] {
+                worksheet.write_row(row, &types).unwrap();
+            }
+            worksheet.close().unwrap();
+            let bytes = workbook.finish().unwrap().into_inner();
+
+            let sheet = sheet_xml(&bytes);
+            assert!(sheet.contains(r#"<c r="B2"/>"#), "{sheet}");
+            let cells = read_cells(&bytes, "Sheet 1");
+            assert_eq!(cells[1][1], ParsedCell::Empty);
+            assert_eq!(cells[2][1], date);
+        }
+    }
+
+    #[test]
+    fn serials_count_excels_leap_day() {
+        assert_eq!(to_excel_serial(1900, 1, 1, 0.0), Some(1.0));
+        assert_eq!(to_excel_serial(1900, 2, 28, 0.0), Some(59.0));
+        assert_eq!(to_excel_serial(1900, 3, 1, 0.0), Some(61.0));
+        assert_eq!(to_excel_serial(2023, 1, 1, 21600.0), Some(44927.25));
+        assert_eq!(to_excel_serial(1899, 12, 31, 0.0), None);
+        assert_eq!(to_excel_serial(1900, 2, 29, 0.0), None);
+        assert_eq!(to_excel_serial(2023, 2, 30, 0.0), None);
+
+        assert_eq!(from_excel_serial(1.0), Some((1900, 1, 1, 0)));
+        assert_eq!(from_excel_serial(60.0), Some((1900, 2, 29, 0)));
+        assert_eq!(from_excel_serial(61.0), Some((1900, 3, 1, 0)));
+        assert_eq!(from_excel_serial(44927.25), Some((2023, 1, 1, 21600)));
+
+        assert_eq!(date_serial(b"2023-01-01 06:00", TYPE_DATE), Some(44927.25));
+        assert_eq!(date_serial(b"31/1/2024", TYPE_DATE_DMY), Some(45322.0));
+        assert_eq!(date_serial(b"1/31/2024", TYPE_DATE_MDY), Some(45322.0));
+        assert_eq!(date_serial(b"1/31/2024", TYPE_DATE_DMY), None);
+        assert_eq!(date_serial(b"2024-01-31", TYPE_NUMBER), None);
+        assert_eq!(from_excel_serial(0.5), None);
+
+        // TYPE_DATE cells go through the same conversion
+        let bytes = write_sheet(|worksheet| {
+            let types = vec![TYPE_DATE];
+            worksheet.write_row(vec![b"Date"], &types)?;
+            worksheet.write_row(vec![b"1900-02-28"], &types)?;
+            worksheet.write_row(vec![b"1900-03-01"], &types)?;
+            worksheet.write_row(vec![b"1899-12-31"], &types)
+        });
+        let sheet = sheet_xml(&bytes);
+        assert!(
+            sheet.contains(r#"<c r="A2" t="n" s="1"><v>59</v></c>"#),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains(r#"<c r="A3" t="n" s="1"><v>61</v></c>"#),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains(r#"<c r="A4" t="str"><v>1899-12-31</v></c>"#),
+            "{sheet}"
+        );
+    }
+
+    #[test]
+    fn more_types_than_cells_is_an_error_after_the_header() {
+        let types = vec![TYPE_STRING, TYPE_NUMBER, TYPE_NUMBER];
+        let bytes = write_sheet(|worksheet| {
+            worksheet.write_row(vec![b"Name", b"Age"], &types)?;
+            let e = worksheet
+                .write_row(vec![b"Ada", b"36"], &types)
+                .unwrap_err();
+            assert_eq!(e.to_string(), "row 2 has more types (3) than cells (2)");
+            worksheet.write_row(vec![b"Alan", b"41"], &types[..2].to_vec())
+        });
+        assert_eq!(
+            read_cells(&bytes, "Sheet 1"),
+            [
+                vec![
+                    ParsedCell::String(String::from("Name")),
+                    ParsedCell::String(String::from("Age"))
+                ],
+                vec![
+                    ParsedCell::String(String::from("Alan")),
+                    ParsedCell::Number(41.0)
+                ]
+            ]
+        );
+
+        // Without a header, the first row is checked too
+        write_sheet(|worksheet| {
+            worksheet.set_no_header();
+            let e = worksheet
+                .write_row(vec![b"Ada", b"36"], &types)
+                .unwrap_err();
+            assert_eq!(e.to_string(), "row 1 has more types (3) than cells (2)");
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn grouped_header_merges_each_group_over_its_subheaders() {
+        let bytes = write_sheet(|worksheet| {
+            worksheet.set_autofilter();
+            let subheaders = ["Name", "Jan", "Feb", "Mar", "Apr", "May"];
+            worksheet.write_grouped_header(&[("", 1), ("Q1", 3), ("Q2", 2)], &subheaders)?;
+            let types = vec![TYPE_STRING, TYPE_NUMBER, TYPE_NUMBER];
+            worksheet.write_row(vec![b"Ada", b"1", b"2"], &types)
+        });
+
+        let sheet = sheet_xml(&bytes);
+        assert!(
+            sheet.contains(
+                "<mergeCells count=\"2\"><mergeCell ref=\"B1:D1\"/><mergeCell ref=\"E1:F1\"/>\
+                 </mergeCells>"
+            ),
+            "{sheet}"
+        );
+        assert!(sheet.contains("<autoFilter ref=\"A2:F3\"/>"), "{sheet}");
+        assert!(sheet.contains(&pane_xml(2, 0)), "{sheet}");
+
+        let cells = read_cells(&bytes, "Sheet 1");
+        let text = |x: &str| ParsedCell::String(String::from(x));
+        // Each label sits over the first sub-header of its group
+        assert_eq!(
+            cells[0],
+            [
+                text(""),
+                text("Q1"),
+                text(""),
+                text(""),
+                text("Q2"),
+                text("")
+            ]
+        );
+        assert_eq!(cells[1][1], text("Jan"));
+        assert_eq!(cells[1][4], text("Apr"));
+        assert_eq!(cells[2][1], ParsedCell::Number(1.0));
+    }
+
+    #[test]
+    fn grouped_header_spans_must_match_the_subheaders() {
+        write_sheet(|worksheet| {
+            let e = worksheet
+                .write_grouped_header(&[("Q1", 3)], &["Jan", "Feb"])
+                .unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                "the header groups span 3 columns but there are 2 sub-headers"
+            );
+            worksheet.write_row(vec![b"Name"], &vec![])?;
+            let e = worksheet
+                .write_grouped_header(&[("Q1", 1)], &["Jan"])
+                .unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                "the grouped header must be written before any other row"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn gridline_color_refers_to_its_palette_entry() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        for (name, color) in [
+            ("Blue", [0x12, 0x34, 0x56]),
+            ("Red", [0xC0, 0x00, 0x00]),
+            ("Also blue", [0x12, 0x34, 0x56]),
+        ] {
+            let mut worksheet = workbook.get_typed_worksheet(String::from(name));
+            worksheet.set_gridline_color(color).unwrap();
+            worksheet.close().unwrap();
+        }
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let styles = read_part(&bytes, "xl/styles.xml");
+        let palette: Vec<&str> = styles
+            .split("<rgbColor rgb=\"")
+            .skip(1)
+            .map(|x| &x[..8])
+            .collect();
+        assert_eq!(palette.len(), 64);
+
+        for (sheet, color) in [(1, "FF123456"), (2, "FFC00000"), (3, "FF123456")] {
+            let xml = read_part(&bytes, &format!("xl/worksheets/sheet{sheet}.xml"));
+            let id = xml.split("colorId=\"").nth(1).unwrap();
+            let id: usize = id[..id.find('"').unwrap()].parse().unwrap();
+            assert!(
+                xml.contains("<sheetView defaultGridColor=\"0\" colorId="),
+                "{xml}"
+            );
+            assert_eq!(palette[id], color, "sheet{sheet}");
+        }
+    }
+
+    #[test]
+    fn gridline_colors_past_the_palette_are_an_error() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        for i in 0..MAX_CUSTOM_COLORS as u8 {
+            let mut worksheet = workbook.get_typed_worksheet(format!("Sheet {i}"));
+            worksheet.set_gridline_color([i, 0, 0]).unwrap();
+            worksheet.close().unwrap();
+        }
+
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Full"));
+        // Colours already in the palette are reused
+        worksheet.set_gridline_color([3, 0, 0]).unwrap();
+        let e = worksheet.set_gridline_color([0xC0, 0, 0]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "can't use C00000 for gridlines, the palette only has room for 56 custom colours"
+        );
+        worksheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let xml = read_part(&bytes, "xl/worksheets/sheet57.xml");
+        assert!(xml.contains("colorId=\"60\""), "{xml}");
+    }
+
+    #[test]
+    fn note_shape_ids_are_unique_past_1024_notes() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        for (name, count) in [("Many", 1100), ("Few", 2), ("Exactly", 1024), ("Last", 1)] {
+            let mut worksheet = workbook.get_typed_worksheet(String::from(name));
+            for row in 0..count {
+                worksheet.add_note(row, 0, "note");
+            }
+            worksheet.close().unwrap();
+        }
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let mut ids = std::collections::HashSet::new();
+        for (sheet, blocks) in [(1, "1,2"), (2, "3"), (3, "4,5"), (4, "6")] {
+            let vml = read_part(&bytes, &format!("xl/drawings/vmlDrawing{sheet}.vml"));
+            assert!(vml.contains(&format!("data=\"{blocks}\"")), "sheet{sheet}");
+
+            // Every id falls in one of the sheet's blocks
+            let blocks: Vec<usize> = blocks.split(',').map(|x| x.parse().unwrap()).collect();
+            for id in vml.split("<v:shape id=\"_x0000_s").skip(1) {
+                let id: usize = id[..id.find('"').unwrap()].parse().unwrap();
+                assert!(blocks.contains(&(id / 1024)), "sheet{sheet}: {id}");
+                assert!(ids.insert(id), "sheet{sheet}: {id} is used twice");
+            }
+        }
+        assert_eq!(ids.len(), 1100 + 2 + 1024 + 1);
+    }
+
+    #[test]
+    fn custom_inference_is_called_once_per_field() {
+        let locale = NumberLocale::default();
+        let row: Vec<&[u8]> = vec![b"ID-2024", b"02134", b"2024-01-02", b"3.5"];
+        assert_eq!(
+            infer_row_types_with(&row, &locale, None),
+            infer_row_types(&row, &locale)
+        );
+
+        let seen = std::cell::RefCell::new(Vec::new());
+        let infer: Box<InferFn> = Box::new(|x| {
+            seen.borrow_mut().push(x.to_vec());
+            match x {
+                x if x.starts_with(b"ID-") => TYPE_STRING,
+                // Leading zeros would be lost as a number
+                [b'0', _, ..] => TYPE_STRING,
+                x => infer_type(x, &locale),
+            }
+        });
+        assert_eq!(
+            infer_row_types_with(&row, &locale, Some(infer.as_ref())),
+            [TYPE_STRING, TYPE_STRING, TYPE_DATE_YMD, TYPE_NUMBER]
+        );
+        assert_eq!(infer_type(b"02134", &locale), TYPE_NUMBER);
+        drop(infer);
+        assert_eq!(seen.into_inner(), row);
+    }
+
+    #[test]
+    fn only_filtered_sheets_get_filter_mode() {
+        let write = |criteria: bool| {
+            sheet_xml(&write_sheet(|worksheet| {
+                worksheet.freeze_top_row();
+                worksheet.set_autofilter();
+                if criteria {
+                    worksheet.set_filter_criteria(0, &["Ada"]);
+                }
+                worksheet.add_text_rule(0, 1, 2, TextOp::Equal, "Ada", &Format::default());
+                worksheet.write_row(vec![b"Name"], &vec![])?;
+                worksheet.write_row(vec![b"Ada"], &vec![])?;
+                worksheet.write_row(vec![b"Alan"], &vec![])
+            }))
+        };
+
+        let sheet = write(true);
+        // The schema puts sheetPr first in the worksheet
+        let pr = sheet.find("<sheetPr filterMode=\"1\"/>").unwrap();
+        assert!(pr < sheet.find("<sheetViews>").unwrap(), "{sheet}");
+        assert!(sheet.contains("<row r=\"3\" hidden=\"1\">"), "{sheet}");
+
+        let sheet = write(false);
+        assert!(!sheet.contains("<sheetPr"), "{sheet}");
+        assert!(sheet.contains("<conditionalFormatting"), "{sheet}");
+    }
+
+    #[test]
+    fn rtl_sheets_keep_their_frozen_panes() {
+        let xml = sheet_xml(&write_sheet(|worksheet| {
+            worksheet.set_rtl(true);
+            worksheet.freeze_panes(1, 1);
+            worksheet.write_row(vec![b"Name", b"Age"], &vec![])
+        }));
+        assert!(
+            xml.contains(&format!(
+                "<sheetViews><sheetView rightToLeft=\"1\" workbookViewId=\"0\">{}</sheetView>",
+                pane_xml(1, 1)
+            )),
+            "{xml}"
+        );
+
+        let xml = sheet_xml(&write_sheet(|worksheet| {
+            worksheet.set_rtl(true);
+            worksheet.write_row(vec![b"Name"], &vec![])
+        }));
+        assert!(
+            xml.contains("<sheetViews><sheetView rightToLeft=\"1\" workbookViewId=\"0\"></sheetView></sheetViews>"),
+            "{xml}"
+        );
+
+        // Left to right is the default, and needs no view
+        let xml = sheet_xml(&write_sheet(|worksheet| {
+            worksheet.set_rtl(false);
+            worksheet.write_row(vec![b"Name"], &vec![])
+        }));
+        assert!(!xml.contains("<sheetView"), "{xml}");
+    }
+
+    #[test]
+    fn dates_are_serial_numbers_in_the_builtin_date_style() {
+        let bytes = write_sheet(|worksheet| {
+            let types = vec![TYPE_DATE];
+            worksheet.write_row(vec![b"Joined"], &types)?;
+            worksheet.write_row(vec![b"2023-01-01"], &types)?;
+            worksheet.write_row(vec![b"2024-02-30"], &types)?;
+            worksheet.write_row(vec![b"1899-06-01"], &types)
+        });
 
-        &self.col_num_to_letter[col]
+        let sheet = sheet_xml(&bytes);
+        assert!(!sheet.contains(r#"t="d""#), "{sheet}");
+        assert!(
+            sheet.contains(r#"<c r="A2" t="n" s="1"><v>44927</v></c>"#),
+            "{sheet}"
+        );
+        assert_eq!(num_fmt_id(&bytes, "A2"), NUM_FMT_DATE.to_string());
+        assert_eq!(NUM_FMT_DATE, 14);
+        // Text that isn't a date, or has no serial, is kept as text
+        assert!(
+            sheet.contains(r#"<c r="A3" t="str"><v>2024-02-30</v></c>"#),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains(r#"<c r="A4" t="str"><v>1899-06-01</v></c>"#),
+            "{sheet}"
+        );
     }
 }
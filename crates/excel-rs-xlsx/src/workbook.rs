@@ -1,14 +1,45 @@
-use super::format::XlsxFormatter;
-use std::{io::Seek, io::Write};
-use anyhow::Result;
-use zip::ZipWriter;
+use super::format::{BookView, SheetEntry, XlsxFormatter};
+use std::{io::Read, io::Seek, io::Write};
+use anyhow::{bail, Result};
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
 
+use super::encoding::XmlEncoding;
+
+use super::columnar_sheet::ColumnarSheet;
 use super::sheet::Sheet;
-use super::typed_sheet::TypedSheet;
+use super::typed_sheet::{DateMode, SharedStrings, TypedSheet};
+
+/// When Excel recalculates formulas, written as `calcMode` in `xl/workbook.xml`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalcMode {
+    /// Recalculate whenever a value changes, Excel's default
+    Auto,
+    /// Like `Auto`, but leave data tables until asked
+    AutoNoTable,
+    /// Only recalculate when asked, e.g. with F9
+    Manual,
+}
+
+impl CalcMode {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            CalcMode::Auto => "auto",
+            CalcMode::AutoNoTable => "autoNoTable",
+            CalcMode::Manual => "manual",
+        }
+    }
+}
 
 pub struct WorkBook<W: Write + Seek> {
     formatter: XlsxFormatter<W>,
     num_of_sheets: u16,
+    sheets: Vec<SheetEntry>,
+    toc_sheet: Option<String>,
+    default_sheet: Option<String>,
+    book_view: Option<BookView>,
+    date_mode: DateMode,
+    omit_cell_refs: bool,
+    skip_empty_cells: bool,
 }
 
 impl<W: Write + Seek> WorkBook<W> {
@@ -18,21 +49,796 @@ impl<W: Write + Seek> WorkBook<W> {
         WorkBook {
             formatter: XlsxFormatter::new(zip_writer),
             num_of_sheets: 0,
+            sheets: Vec::new(),
+            toc_sheet: None,
+            default_sheet: None,
+            book_view: None,
+            date_mode: DateMode::Serial,
+            omit_cell_refs: false,
+            skip_empty_cells: false,
         }
     }
 
-    pub fn get_worksheet(&mut self, name: String) -> Sheet<W> {
-        self.num_of_sheets += 1;
-        Sheet::new(name, self.num_of_sheets, &mut self.formatter.zip_writer)
+    /// Adds a table of contents as the first sheet when the workbook is finished, linking
+    /// to every other sheet.
+    pub fn add_toc_sheet(&mut self, name: String) {
+        self.toc_sheet = Some(name);
+    }
+
+    /// Writes an empty sheet called `name` when the workbook is finished without any
+    /// sheets. Without one, finishing an empty workbook is an error, as Excel can't open
+    /// a workbook with no sheets.
+    pub fn set_default_sheet(&mut self, name: String) {
+        self.default_sheet = Some(name);
     }
 
-    pub fn get_typed_worksheet(&mut self, name: String) -> TypedSheet<W> {
+    /// Sets when Excel recalculates the workbook's formulas, e.g. `CalcMode::Manual` so a
+    /// workbook with heavy formulas opens without recalculating. Without it no mode is
+    /// written and Excel calculates automatically.
+    pub fn set_calc_mode(&mut self, mode: CalcMode) {
+        self.formatter.calc_mode = Some(mode);
+    }
+
+    /// Opens the workbook on sheet `active_tab`, counted from 0 in the order sheets were
+    /// added, in a window of `window_width` by `window_height` (in twips, 1/20 of a
+    /// point). Without it Excel opens on the first sheet in its default window.
+    pub fn set_book_view(&mut self, active_tab: usize, window_width: u32, window_height: u32) {
+        self.book_view = Some(BookView {
+            active_tab,
+            window_width,
+            window_height,
+        });
+    }
+
+    /// Sets how `TYPE_DATE` cells are stored in typed sheets added after this call.
+    /// Defaults to `DateMode::Serial`.
+    pub fn set_date_mode(&mut self, mode: DateMode) {
+        self.date_mode = mode;
+    }
+
+    /// Names the program that made the file in `docProps/app.xml`, shown by Excel under
+    /// File > Info. Defaults to `excel-rs` and its version, e.g. `excel-rs v0.5.3`.
+    pub fn set_application_name(&mut self, name: &str) {
+        self.formatter.application = String::from(name);
+    }
+
+    /// Sets the text property `name` to `value` in `docProps/custom.xml`, replacing any
+    /// value it already has. Excel shows custom properties under File > Info >
+    /// Properties > Advanced Properties.
+    pub fn set_custom_property(&mut self, name: &str, value: &str) {
+        let properties = &mut self.formatter.custom_properties;
+        match properties.iter_mut().find(|(x, _)| x == name) {
+            Some((_, old)) => *old = String::from(value),
+            None => properties.push((String::from(name), String::from(value))),
+        }
+    }
+
+    /// Stores `jpeg` as the workbook's thumbnail in `docProps/thumbnail.jpeg`, shown as a
+    /// preview by some file managers and SharePoint. The bytes are written as they are,
+    /// so they must be a JPEG image.
+    pub fn set_thumbnail(&mut self, jpeg: &[u8]) {
+        self.formatter.thumbnail = Some(jpeg.to_vec());
+    }
+
+    /// Sets the zip archive's comment, e.g. to mark which pipeline produced the file.
+    /// Excel ignores it, but `unzip -z` and other zip tools show it.
+    pub fn set_archive_comment(&mut self, comment: &str) {
+        self.formatter.zip_writer.set_comment(comment);
+    }
+
+    /// Leaves out the `<?xml ...?>` declaration that starts each part, and the line breaks
+    /// and indentation between elements, to save space in workbooks with many sheets.
+    /// The declaration is optional for UTF-8 XML, so readers don't need it. Applies to
+    /// sheets added after this call and every part written by `finish`. Defaults to off.
+    pub fn minimal_xml(&mut self, minimal: bool) {
+        self.formatter.minimal_xml = minimal;
+    }
+
+    /// Leaves out the `r` attribute of cells that directly follow the previous cell in
+    /// their row, which readers then place by position, to shrink large sheets. Blank
+    /// cells without a style are left out too, and the cell after such a gap keeps its
+    /// reference. Applies to typed sheets added after this call. Defaults to off.
+    pub fn omit_cell_refs(&mut self, omit: bool) {
+        self.omit_cell_refs = omit;
+    }
+
+    /// Leaves out cells with empty text, and blank cells, unless they have a style, to
+    /// shrink sparse sheets. Readers see no cell there rather than one holding `""`.
+    /// Applies to sheets added after this call. Defaults to off.
+    pub fn skip_empty_cells(&mut self, skip: bool) {
+        self.skip_empty_cells = skip;
+    }
+
+    /// Writes text cells as indexes into one table of unique strings,
+    /// `xl/sharedStrings.xml`, rather than each with its own copy of the text. Files with
+    /// text repeated across many rows get smaller, at the cost of holding every unique
+    /// string in memory until `finish`. Applies to sheets added after this call, headers
+    /// included. Defaults to off.
+    pub fn enable_shared_strings(&mut self) {
+        self.formatter
+            .shared_strings
+            .get_or_insert_with(SharedStrings::default);
+    }
+
+    fn add_sheet(&mut self, name: &str) -> u16 {
         self.num_of_sheets += 1;
-        TypedSheet::new(name, self.num_of_sheets, &mut self.formatter.zip_writer)
+        self.sheets.push(SheetEntry {
+            id: self.num_of_sheets,
+            name: String::from(name),
+        });
+        self.num_of_sheets
+    }
+
+    /// Adds a sheet called `name` that writes every cell as text. Sheets are numbered
+    /// and listed in the workbook in the order they're added, so a workbook can hold
+    /// several: close each one before adding the next, as only one can be written at a
+    /// time.
+    pub fn get_worksheet(&mut self, name: String) -> Sheet<'_, W> {
+        let id = self.add_sheet(&name);
+        let mut sheet = Sheet::new(
+            name,
+            id,
+            &mut self.formatter.zip_writer,
+            self.formatter.minimal_xml,
+            &mut self.formatter.open_sheet,
+        );
+        sheet.skip_empty_cells = self.skip_empty_cells;
+        sheet.shared_strings = self.formatter.shared_strings.as_mut();
+        sheet
+    }
+
+    /// Adds a sheet called `name` whose cells are written with the types given to
+    /// `write_row`. Like `get_worksheet`, it can be called again for each further sheet
+    /// once the one before it is closed.
+    ///
+    /// ```ignore
+    /// for name in ["Sheet 1", "Sheet 2"] {
+    ///     let mut sheet = workbook.get_typed_worksheet(String::from(name));
+    ///     sheet.write_row(vec![b"Total", b"3"], &vec![TYPE_STRING, TYPE_NUMBER])?;
+    ///     sheet.close()?;
+    /// }
+    /// workbook.finish()?;
+    /// ```
+    pub fn get_typed_worksheet(&mut self, name: String) -> TypedSheet<'_, W> {
+        let id = self.add_sheet(&name);
+        let mut sheet = TypedSheet::new(
+            name,
+            id,
+            &mut self.formatter.zip_writer,
+            &mut self.formatter.styles,
+            &mut self.formatter.sheet_buffers,
+            &mut self.formatter.drawings,
+            &mut self.formatter.open_sheet,
+        );
+        sheet.date_mode = self.date_mode;
+        sheet.minimal_xml = self.formatter.minimal_xml;
+        sheet.omit_cell_refs = self.omit_cell_refs;
+        sheet.skip_empty_cells = self.skip_empty_cells;
+        sheet.shared_strings = self.formatter.shared_strings.as_mut();
+        sheet
     }
-    
-    pub fn finish(self) -> Result<W> {
-        let result = self.formatter.finish(self.num_of_sheets)?;
+
+    pub fn get_columnar_worksheet(&mut self, name: String) -> ColumnarSheet<'_, W> {
+        ColumnarSheet::new(self.get_typed_worksheet(name))
+    }
+
+    /// Writes the workbook's own parts and returns the writer. Fails if a sheet was
+    /// added but not closed, as its part would be cut off.
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(id) = self.formatter.open_sheet {
+            let name = self
+                .sheets
+                .iter()
+                .find(|x| x.id == id)
+                .map(|x| x.name.as_str());
+            bail!(
+                "Sheet {:?} was not closed, close it before finishing the workbook",
+                name.unwrap_or_default()
+            );
+        }
+
+        if self.sheets.is_empty() {
+            match self.default_sheet.take() {
+                Some(name) => self.get_typed_worksheet(name).close()?,
+                None => bail!("The workbook has no sheets, add one before finishing it"),
+            }
+        }
+
+        if let Some(view) = &mut self.book_view {
+            if view.active_tab >= self.sheets.len() {
+                bail!(
+                    "The active tab {} is out of range, the workbook has {} sheets",
+                    view.active_tab,
+                    self.sheets.len()
+                );
+            }
+            // The table of contents goes in front of every other sheet
+            if self.toc_sheet.is_some() {
+                view.active_tab += 1;
+            }
+        }
+
+        if let Some(name) = self.toc_sheet.take() {
+            self.num_of_sheets += 1;
+            self.formatter
+                .write_toc_sheet(self.num_of_sheets, &self.sheets)?;
+            self.sheets.insert(
+                0,
+                SheetEntry {
+                    id: self.num_of_sheets,
+                    name,
+                },
+            );
+        }
+
+        let result = self
+            .formatter
+            .finish(&self.sheets, self.book_view.as_ref())?;
         Ok(result)
     }
 }
+
+impl<W: Read + Write + Seek> WorkBook<W> {
+    /// Finishes the workbook like `finish`, then copies it to `out` with its parts in
+    /// canonical OPC order: `[Content_Types].xml` first, then the relationships, the
+    /// workbook, its sheets and other parts, and the document properties last. Some
+    /// strict validators reject files whose content types don't come first, which
+    /// `finish` writes last as sheets are streamed. Parts are copied without being
+    /// compressed again, so the workbook is best written to an in-memory `Cursor`.
+    pub fn finish_strict_ordering<O: Write + Seek>(self, out: O) -> Result<O> {
+        let mut staged = ZipArchive::new(self.finish()?)?;
+
+        let mut order: Vec<(u8, usize)> = staged
+            .file_names()
+            .map(part_rank)
+            .enumerate()
+            .map(|(i, rank)| (rank, i))
+            .collect();
+        order.sort();
+
+        let mut writer = ZipWriter::new(out);
+        writer.set_raw_comment(staged.comment().into());
+        for (_, i) in order {
+            writer.raw_copy_file(staged.by_index_raw(i)?)?;
+        }
+        Ok(writer.finish()?)
+    }
+
+    /// Finishes the workbook like `finish`, then copies it to `out` with every XML part
+    /// transcoded to `encoding` and declared as such, for importers that only read a
+    /// legacy code page. Fails on the first character the encoding can't hold, apart
+    /// from the East Asian font names in the built-in theme, which are written as
+    /// character references. OPC only
+    /// allows UTF-8 and UTF-16 parts, so strict readers may reject the result; only use
+    /// it for systems that require it.
+    pub fn finish_with_encoding<O: Write + Seek>(self, out: O, encoding: XmlEncoding) -> Result<O> {
+        let mut staged = ZipArchive::new(self.finish()?)?;
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(Some(1));
+
+        let mut writer = ZipWriter::new(out);
+        writer.set_raw_comment(staged.comment().into());
+        for i in 0..staged.len() {
+            let name = staged.name_for_index(i).unwrap_or_default().to_owned();
+            if !is_xml_part(&name) {
+                writer.raw_copy_file(staged.by_index_raw(i)?)?;
+                continue;
+            }
+
+            let mut xml = String::new();
+            staged.by_index(i)?.read_to_string(&mut xml)?;
+            writer.start_file(name.as_str(), options)?;
+            let escape = name == "xl/theme/theme1.xml";
+            writer.write_all(&encoding.encode_part(&name, &xml, escape)?)?;
+        }
+        Ok(writer.finish()?)
+    }
+}
+
+fn is_xml_part(name: &str) -> bool {
+    name.ends_with(".xml") || name.ends_with(".rels") || name.ends_with(".vml")
+}
+
+fn part_rank(name: &str) -> u8 {
+    match name {
+        "[Content_Types].xml" => 0,
+        "_rels/.rels" => 1,
+        _ if name.ends_with(".rels") => 2,
+        "xl/workbook.xml" => 3,
+        _ if name.starts_with("docProps/") => 5,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::sheet::{validate_position, MAX_COLS, MAX_ROWS};
+    use crate::testing::{read_cells, read_part, ParsedCell};
+    use crate::typed_sheet::{TYPE_BLANK, TYPE_NUMBER, TYPE_STRING};
+
+    fn workbook_xml(mode: Option<CalcMode>) -> String {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        if let Some(mode) = mode {
+            workbook.set_calc_mode(mode);
+        }
+        workbook
+            .get_typed_worksheet(String::from("Sheet 1"))
+            .close()
+            .unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let mut xml = String::new();
+        ZipArchive::new(Cursor::new(bytes))
+            .unwrap()
+            .by_name("xl/workbook.xml")
+            .unwrap()
+            .read_to_string(&mut xml)
+            .unwrap();
+        xml
+    }
+
+    #[test]
+    fn calc_mode_is_written_for_each_mode() {
+        for (mode, name) in [
+            (CalcMode::Auto, "auto"),
+            (CalcMode::AutoNoTable, "autoNoTable"),
+            (CalcMode::Manual, "manual"),
+        ] {
+            let xml = workbook_xml(Some(mode));
+            assert!(
+                xml.contains(&format!("<calcPr calcMode=\"{name}\"/>")),
+                "{xml}"
+            );
+        }
+    }
+
+    #[test]
+    fn no_calc_mode_is_written_by_default() {
+        assert!(!workbook_xml(None).contains("calcPr"));
+    }
+
+    #[test]
+    fn toc_links_resolve_to_each_sheet() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook.add_toc_sheet(String::from("Contents"));
+        for name in ["Sales", "Bob's & Co"] {
+            let mut worksheet = workbook.get_typed_worksheet(String::from(name));
+            worksheet
+                .write_row(vec![name.as_bytes()], &vec!["str"])
+                .unwrap();
+            worksheet.close().unwrap();
+        }
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let workbook_xml = read_part(&bytes, "xl/workbook.xml");
+        let contents = workbook_xml.find("name=\"Contents\"").unwrap();
+        assert!(contents < workbook_xml.find("name=\"Sales\"").unwrap());
+
+        // The table of contents is written last, after the sheets it lists
+        let toc = read_part(&bytes, "xl/worksheets/sheet3.xml");
+        let cells = read_cells(&bytes, "Contents");
+        let links: Vec<&str> = toc.split("<hyperlink ").skip(1).collect();
+        assert_eq!(links.len(), 2);
+
+        for (i, link) in links.into_iter().enumerate() {
+            let name = match &cells[i + 1][0] {
+                ParsedCell::String(name) => name,
+                cell => panic!("{cell:?}"),
+            };
+            assert!(link.starts_with(&format!("ref=\"A{}\"", i + 2)), "{link}");
+
+            let location = link.split("location=\"").nth(1).unwrap();
+            let location = &location[..location.find('"').unwrap()];
+            let target = location
+                .replace("&apos;", "'")
+                .replace("&amp;", "&")
+                .strip_prefix('\'')
+                .and_then(|x| x.strip_suffix("'!A1"))
+                .map(|x| x.replace("''", "'"))
+                .unwrap();
+            assert_eq!(&target, name);
+
+            // The linked sheet exists and is the one its row names
+            assert_eq!(
+                read_cells(&bytes, &target)[0][0],
+                ParsedCell::String(target.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn finishing_without_sheets_is_an_error() {
+        let workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let err = workbook.finish().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The workbook has no sheets, add one before finishing it"
+        );
+    }
+
+    #[test]
+    fn default_sheet_is_written_without_sheets() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook.set_default_sheet(String::from("Empty"));
+        let bytes = workbook.finish().unwrap().into_inner();
+        assert_eq!(read_cells(&bytes, "Empty"), Vec::<Vec<ParsedCell>>::new());
+
+        // Only when there are no other sheets
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook.set_default_sheet(String::from("Empty"));
+        workbook
+            .get_typed_worksheet(String::from("Data"))
+            .close()
+            .unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+        let xml = read_part(&bytes, "xl/workbook.xml");
+        assert!(xml.contains("name=\"Data\""));
+        assert!(!xml.contains("name=\"Empty\""));
+    }
+
+    fn finish_with_book_view(toc: bool, active_tab: usize) -> Result<Vec<u8>> {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        if toc {
+            workbook.add_toc_sheet(String::from("Contents"));
+        }
+        for name in ["A", "B", "C"] {
+            workbook.get_typed_worksheet(String::from(name)).close()?;
+        }
+        workbook.set_book_view(active_tab, 28800, 17000);
+        Ok(workbook.finish()?.into_inner())
+    }
+
+    // The name of the sheet at `activeTab` in the order workbook.xml lists them
+    fn active_sheet(bytes: &[u8]) -> String {
+        let xml = read_part(bytes, "xl/workbook.xml");
+        let tab = xml.split("activeTab=\"").nth(1).unwrap();
+        let tab: usize = tab[..tab.find('"').unwrap()].parse().unwrap();
+        let name = xml.split("<sheet name=\"").nth(tab + 1).unwrap();
+        String::from(&name[..name.find('"').unwrap()])
+    }
+
+    #[test]
+    fn book_view_opens_on_the_active_tab() {
+        let bytes = finish_with_book_view(false, 2).unwrap();
+        let xml = read_part(&bytes, "xl/workbook.xml");
+        assert!(
+            xml.contains(concat!(
+                "<bookViews><workbookView windowWidth=\"28800\" windowHeight=\"17000\" ",
+                "activeTab=\"2\"/></bookViews>"
+            )),
+            "{xml}"
+        );
+        assert_eq!(active_sheet(&bytes), "C");
+
+        // The table of contents goes first, and the tab moves with the sheet
+        let bytes = finish_with_book_view(true, 2).unwrap();
+        assert!(read_part(&bytes, "xl/workbook.xml").contains("activeTab=\"3\""));
+        assert_eq!(active_sheet(&bytes), "C");
+    }
+
+    #[test]
+    fn book_view_is_left_out_by_default() {
+        assert!(!workbook_xml(None).contains("bookViews"));
+    }
+
+    #[test]
+    fn active_tab_out_of_range_is_an_error() {
+        let err = finish_with_book_view(false, 3).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The active tab 3 is out of range, the workbook has 3 sheets"
+        );
+    }
+
+    #[test]
+    fn application_name_is_written_to_app_xml() {
+        let finish = |workbook: WorkBook<Cursor<Vec<u8>>>| {
+            let bytes = workbook.finish().unwrap().into_inner();
+            read_part(&bytes, "docProps/app.xml")
+        };
+
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook.set_default_sheet(String::from("Sheet 1"));
+        let default = format!(
+            "<Application>excel-rs v{}</Application>",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(finish(workbook).contains(&default));
+
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook.set_default_sheet(String::from("Sheet 1"));
+        workbook.set_application_name("Nightly export & co");
+        assert!(finish(workbook).contains("<Application>Nightly export &amp; co</Application>"));
+    }
+
+    #[test]
+    fn archive_comment_is_written_to_the_zip() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook.set_default_sheet(String::from("Sheet 1"));
+        workbook.set_archive_comment("Generated by the nightly export");
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.comment(), b"Generated by the nightly export");
+    }
+
+    fn part_names(bytes: Vec<u8>) -> Vec<String> {
+        let archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        archive.file_names().map(String::from).collect()
+    }
+
+    #[test]
+    fn strict_ordering_puts_content_types_first() {
+        let write = || {
+            let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+            workbook.set_archive_comment("ordered");
+            for name in ["A", "B"] {
+                let mut worksheet = workbook.get_typed_worksheet(String::from(name));
+                worksheet.write_row(vec![b"x"], &vec!["str"]).unwrap();
+                worksheet.close().unwrap();
+            }
+            workbook
+        };
+
+        // Streamed sheets go first otherwise
+        let streamed = part_names(write().finish().unwrap().into_inner());
+        assert_ne!(streamed[0], "[Content_Types].xml");
+
+        let out = write()
+            .finish_strict_ordering(Cursor::new(Vec::new()))
+            .unwrap();
+        let bytes = out.into_inner();
+        let ordered = part_names(bytes.clone());
+        assert_eq!(ordered[0], "[Content_Types].xml");
+        assert_eq!(ordered[1], "_rels/.rels");
+        let position = |name: &str| ordered.iter().position(|x| x == name).unwrap();
+        assert!(position("xl/workbook.xml") < position("xl/worksheets/sheet1.xml"));
+        assert!(position("xl/worksheets/sheet1.xml") < position("docProps/app.xml"));
+
+        // Nothing is lost in the copy
+        let mut sorted = streamed.clone();
+        sorted.sort();
+        let mut copied = ordered.clone();
+        copied.sort();
+        assert_eq!(sorted, copied);
+        assert_eq!(
+            ZipArchive::new(Cursor::new(&bytes)).unwrap().comment(),
+            b"ordered"
+        );
+        assert_eq!(
+            read_cells(&bytes, "B"),
+            vec![vec![ParsedCell::String(String::from("x"))]]
+        );
+    }
+
+    fn encoded(text: &str, encoding: XmlEncoding) -> Result<Vec<u8>> {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        worksheet.write_row(vec![text.as_bytes()], &vec![]).unwrap();
+        worksheet.close().unwrap();
+        Ok(workbook
+            .finish_with_encoding(Cursor::new(Vec::new()), encoding)?
+            .into_inner())
+    }
+
+    #[test]
+    fn parts_are_written_in_the_chosen_code_page() {
+        let bytes = encoded("Café €5 ‘q’", XmlEncoding::Windows1252).unwrap();
+
+        let mut sheet = Vec::new();
+        ZipArchive::new(Cursor::new(bytes))
+            .unwrap()
+            .by_name("xl/worksheets/sheet1.xml")
+            .unwrap()
+            .read_to_end(&mut sheet)
+            .unwrap();
+        let declaration = b"<?xml version=\"1.0\" encoding=\"windows-1252\" standalone=\"yes\"?>\n";
+        assert!(sheet.starts_with(declaration));
+        let value = b"<v>Caf\xE9 \x805 \x91q\x92</v>";
+        assert!(sheet.windows(value.len()).any(|x| x == value));
+    }
+
+    #[test]
+    fn characters_outside_the_code_page_are_an_error() {
+        let e = encoded("€5", XmlEncoding::Iso8859_1).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "xl/worksheets/sheet1.xml has '€' (U+20AC) on line 4, which can't be written in ISO-8859-1"
+        );
+    }
+
+    #[test]
+    fn closed_sheets_refuse_rows() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Typed"));
+        worksheet.write_row(vec![b"Name"], &vec![]).unwrap();
+        worksheet.close().unwrap();
+        let e = worksheet.write_row(vec![b"Ada"], &vec![]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "sheet \"Typed\" is closed, rows can't be written to it"
+        );
+        let e = worksheet.close().unwrap_err();
+        assert_eq!(e.to_string(), "sheet \"Typed\" is already closed");
+
+        let mut worksheet = workbook.get_worksheet(String::from("Plain"));
+        worksheet.write_row(vec![b"Name"]).unwrap();
+        worksheet.close().unwrap();
+        assert!(worksheet.write_row(vec![b"Ada"]).is_err());
+        assert!(worksheet.close().is_err());
+
+        // Neither sheet was changed by the rows refused
+        let bytes = workbook.finish().unwrap().into_inner();
+        for sheet in ["Typed", "Plain"] {
+            assert_eq!(
+                read_cells(&bytes, sheet),
+                vec![vec![ParsedCell::String(String::from("Name"))]]
+            );
+        }
+    }
+
+    #[test]
+    fn finishing_with_an_open_sheet_is_an_error() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook
+            .get_typed_worksheet(String::from("Closed"))
+            .close()
+            .unwrap();
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Open"));
+        worksheet.write_row(vec![b"Name"], &vec![]).unwrap();
+        drop(worksheet);
+
+        let e = workbook.finish().err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "Sheet \"Open\" was not closed, close it before finishing the workbook"
+        );
+    }
+
+    #[test]
+    fn omitted_cell_refs_are_kept_after_a_gap() {
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook.omit_cell_refs(true);
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        let types = vec![TYPE_STRING, TYPE_BLANK, TYPE_NUMBER];
+        worksheet
+            .write_row(vec![b"Name", b"Note", b"Age"], &types)
+            .unwrap();
+        worksheet
+            .write_row(
+                vec![b"Ada", b"x", b"36"],
+                &vec![TYPE_STRING, TYPE_STRING, TYPE_NUMBER],
+            )
+            .unwrap();
+        worksheet
+            .write_row(vec![b"Alan", b"", b"41"], &types)
+            .unwrap();
+        worksheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let sheet = read_part(&bytes, "xl/worksheets/sheet1.xml");
+        assert!(
+            sheet.contains(
+                "<row r=\"2\"><c t=\"str\"><v>Ada</v></c><c t=\"str\"><v>x</v></c>\
+                 <c t=\"n\"><v>36</v></c></row>"
+            ),
+            "{sheet}"
+        );
+        assert!(
+            sheet.contains(
+                "<row r=\"3\"><c t=\"str\"><v>Alan</v></c><c r=\"C3\" t=\"n\"><v>41</v></c></row>"
+            ),
+            "{sheet}"
+        );
+
+        let cells = read_cells(&bytes, "Sheet 1");
+        assert_eq!(
+            cells[1],
+            [
+                ParsedCell::String(String::from("Ada")),
+                ParsedCell::String(String::from("x")),
+                ParsedCell::Number(36.0)
+            ]
+        );
+        assert_eq!(
+            cells[2],
+            [
+                ParsedCell::String(String::from("Alan")),
+                ParsedCell::Empty,
+                ParsedCell::Number(41.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn thumbnail_is_a_package_part_linked_from_the_root_rels() {
+        // Only the bytes matter to the package, so any will do for a JPEG
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, 0xFF, 0xD9];
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook.set_thumbnail(&jpeg);
+        workbook.set_custom_property("Source", "test");
+        let mut sheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        sheet.write_row(vec![b"Name"], &vec![TYPE_STRING]).unwrap();
+        sheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+
+        let mut stored = Vec::new();
+        ZipArchive::new(Cursor::new(&bytes))
+            .unwrap()
+            .by_name("docProps/thumbnail.jpeg")
+            .unwrap()
+            .read_to_end(&mut stored)
+            .unwrap();
+        assert_eq!(stored, jpeg);
+
+        let rels = read_part(&bytes, "_rels/.rels");
+        assert!(rels.contains(r#"<Relationship Id="rId5" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/thumbnail" Target="docProps/thumbnail.jpeg"/>"#), "{rels}");
+        assert!(rels.contains(r#"Id="rId4""#), "{rels}");
+        let types = read_part(&bytes, "[Content_Types].xml");
+        assert!(
+            types.contains(r#"<Default Extension="jpeg" ContentType="image/jpeg"/>"#),
+            "{types}"
+        );
+        assert_eq!(
+            read_cells(&bytes, "Sheet 1"),
+            vec![vec![ParsedCell::String(String::from("Name"))]]
+        );
+
+        // Without one there is no part to link
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        workbook
+            .get_typed_worksheet(String::from("Sheet 1"))
+            .close()
+            .unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+        assert!(!read_part(&bytes, "_rels/.rels").contains("thumbnail"));
+        assert!(!read_part(&bytes, "[Content_Types].xml").contains("jpeg"));
+    }
+
+    #[test]
+    fn positions_one_past_the_last_row_or_column_are_an_error() {
+        validate_position(0, 0).unwrap();
+        validate_position(MAX_COLS - 1, MAX_ROWS - 1).unwrap();
+        let err = validate_position(MAX_COLS, 0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "column 16385 is past the last column of a sheet (16384, XFD)"
+        );
+        let err = validate_position(0, MAX_ROWS).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "row 1048577 is past the last row of a sheet (1048576)"
+        );
+    }
+
+    #[test]
+    fn rows_wider_than_a_sheet_are_an_error() {
+        let cells = vec![&b"x"[..]; MAX_COLS];
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut sheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        sheet.write_row(cells.clone(), &vec![]).unwrap();
+        let mut wider = cells.clone();
+        wider.push(b"x");
+        assert!(sheet.write_row(wider.clone(), &vec![]).is_err());
+        sheet.close().unwrap();
+        let bytes = workbook.finish().unwrap().into_inner();
+        let xml = read_part(&bytes, "xl/worksheets/sheet1.xml");
+        // The widest row fits, ending at the last column
+        assert!(
+            xml.contains(r#"<c r="XFD1" t="str"><v>x</v></c></row>"#),
+            "{xml}"
+        );
+        assert!(!xml.contains(r#"r="2""#), "{xml}");
+
+        let mut workbook = WorkBook::new(Cursor::new(Vec::new()));
+        let mut sheet = workbook.get_worksheet(String::from("Sheet 1"));
+        sheet.write_row(cells).unwrap();
+        assert!(sheet.write_row(wider).is_err());
+    }
+}
@@ -1,2 +1,3 @@
-from .df_to_xlsx import df_to_xlsx, csv_to_xlsx, pg_to_xlsx
-from .postgres import ExcelPostgresBuilder, OrderBy
\ No newline at end of file
+from .df_to_xlsx import df_to_xlsx, array_to_xlsx, csv_to_xlsx, csv_file_to_xlsx, pg_to_xlsx
+from .postgres import ExcelPostgresBuilder, OrderBy
+from ._excel_rs import XlsxWriter, PyPostgresClient
@@ -1,41 +1,122 @@
 import pandas as pd
 import numpy as np
+from datetime import date, datetime
 from enum import Enum
+from typing import Dict, List, Optional, Union
 
 from py_excel_rs import _excel_rs
 
 from pandas.api.types import is_datetime64_any_dtype as is_datetime
 from pandas.api.types import is_numeric_dtype as is_numeric
 
+def _is_date_only(col: pd.Series) -> bool:
+    values = col.dropna()
+    return len(values) > 0 and all(isinstance(x, date) and not isinstance(x, datetime) for x in values)
+
 class CellTypes(Enum):
-    Date = "n\" s=\"1"
+    Date = "date"
     String = "str"
     Number = "n"
     Formula = "str"
     Boolean = "b"
 
-def csv_to_xlsx(buf: bytes) -> bytes:
-    return _excel_rs.csv_to_xlsx(buf)
+def csv_to_xlsx(buf: bytes, delimiter: str = ",") -> bytes:
+    return _excel_rs.csv_to_xlsx(buf, delimiter)
+
+def csv_file_to_xlsx(csv_path: str, xlsx_path: str) -> None:
+    _excel_rs.csv_file_to_xlsx(csv_path, xlsx_path)
+
+# Converting a DataFrame raises MemoryError past this many bytes, see df_to_xlsx
+DEFAULT_MAX_MEMORY = 8 * 1024 ** 3
+
+def _collect_notes(df: pd.DataFrame, notes: Dict[str, str]):
+    for target, source in notes.items():
+        for name in (target, source):
+            if name not in df.columns:
+                raise ValueError(f"notes refers to column {name!r}, which isn't in the DataFrame")
+        if source in notes:
+            raise ValueError(f"column {source!r} can't both hold notes and have them")
+
+    # Source columns aren't written, so targets move left past any before them
+    written = df.drop(columns=list(set(notes.values())))
+    cells = []
+    for target, source in notes.items():
+        col = written.columns.get_loc(target)
+        for row, text in enumerate(df[source]):
+            if pd.isna(text) or str(text) == "":
+                continue
+            cells.append((row + 1, col, str(text)))
+    return written, cells
+
+def df_to_xlsx(df: pd.DataFrame, should_infer_types: bool = False, max_memory: Optional[int] = DEFAULT_MAX_MEMORY, notes: Optional[Dict[str, str]] = None, float_format: Union[str, int] = "shortest") -> bytes:
 
-def df_to_xlsx(df: pd.DataFrame, should_infer_types: bool = False) -> bytes:
+    note_cells = None
+    if notes:
+        df, note_cells = _collect_notes(df, notes)
 
     py_list = np.vstack((df.keys().to_numpy(), df.to_numpy(dtype='object')))
 
     if should_infer_types:
         df_types = []
-        for x in df.dtypes:
-            if is_datetime(x):
+        for i, x in enumerate(df.dtypes):
+            if is_datetime(x) or (x == object and _is_date_only(df.iloc[:, i])):
                 df_types.append(CellTypes.Date)
             elif is_numeric(x):
                 df_types.append(CellTypes.Number)
             else:
                 df_types.append(CellTypes.String)
-        return _excel_rs.typed_py_2d_to_xlsx(py_list, list(map(lambda x : x.value, df_types)))
-    return _excel_rs.py_2d_to_xlsx(py_list)
+        return _excel_rs.typed_py_2d_to_xlsx(py_list, list(map(lambda x : x.value, df_types)), max_memory, notes=note_cells, float_format=float_format)
+    return _excel_rs.py_2d_to_xlsx(py_list, max_memory, notes=note_cells, float_format=float_format)
+
+def _dtype_cell_type(dtype: np.dtype) -> CellTypes:
+    if dtype.kind == 'b':
+        return CellTypes.Boolean
+    if dtype.kind in 'iuf':
+        return CellTypes.Number
+    if dtype.kind == 'M':
+        return CellTypes.Date
+    return CellTypes.String
+
+def _to_objects(values: np.ndarray) -> np.ndarray:
+    if values.dtype.kind == 'M':
+        # Dates convert to date objects, anything finer has to go through microseconds
+        # to convert to datetime objects rather than ints
+        if np.datetime_data(values.dtype)[0] == 'D':
+            return values.astype(object)
+        return values.astype('datetime64[us]').astype(object)
+    if values.dtype.kind == 'S':
+        return values.astype(str).astype(object)
+    return values.astype(object)
+
+def array_to_xlsx(arr: np.ndarray, headers: Optional[List[str]] = None, max_memory: Optional[int] = DEFAULT_MAX_MEMORY, float_format: Union[str, int] = "shortest") -> bytes:
+    if arr.dtype.names is not None:
+        if arr.ndim != 1:
+            raise ValueError("a structured array must be 1-dimensional, one record per row")
+        names = arr.dtype.names
+        types = [_dtype_cell_type(arr.dtype[x]) for x in names]
+        columns = [_to_objects(arr[x]) for x in names]
+        rows = np.empty((len(arr), len(names)), dtype=object)
+        for i, column in enumerate(columns):
+            rows[:, i] = column
+        if headers is None:
+            headers = list(names)
+    else:
+        if arr.ndim != 2:
+            raise ValueError("array_to_xlsx takes a 2D or structured array")
+        types = [_dtype_cell_type(arr.dtype)] * arr.shape[1]
+        rows = _to_objects(arr)
+
+    if headers is not None:
+        if len(headers) != len(types):
+            raise ValueError(f"got {len(headers)} headers for {len(types)} columns")
+        rows = np.vstack((np.array(headers, dtype=object), rows))
+
+    cell_types = [x.value for x in types]
+    return _excel_rs.typed_py_2d_to_xlsx(rows, cell_types, max_memory, headers is not None, float_format=float_format)
 
-def pg_to_xlsx(query: str, conn_string: str) -> bytes:
+def pg_to_xlsx(query: str, conn_string: str, fetch_size: int = 10000, numeric_as_number: bool = False) -> bytes:
     
     client = _excel_rs.PyPostgresClient.new(conn_string)
-    xlsx = client.get_xlsx_from_query(query)
+    xlsx = client.get_xlsx_from_query(query, fetch_size=fetch_size, numeric_as_number=numeric_as_number)
     client.close()
     return xlsx
\ No newline at end of file
@@ -1,135 +1,177 @@
 mod postgres;
 mod utils;
+mod writer;
 
-use std::io::Cursor;
+use std::{
+    fs::File,
+    io::{BufWriter, Cursor},
+};
 
-use chrono::NaiveDateTime;
-use excel_rs_csv::{bytes_to_csv, get_headers, get_next_record};
-use excel_rs_xlsx::WorkBook;
-use numpy::PyReadonlyArray2;
+use chrono::{NaiveDate, NaiveDateTime};
+use excel_rs_csv::{bytes_to_csv_with_delimiter, read_headers, read_next_record, CsvReader};
+use excel_rs_xlsx::{
+    typed_sheet::{TYPE_BOOLEAN, TYPE_DATE, TYPE_NUMBER, TYPE_STRING},
+    WorkBook,
+};
+use numpy::{ndarray::ArrayView2, PyReadonlyArray2};
 use postgres::PyPostgresClient;
-use utils::chrono_to_xlsx_date;
-use pyo3::{prelude::*, types::{PyBytes, PyList}};
+use utils::{FloatFormat, ISO_DATE_TIME};
+use writer::PyXlsxWriter;
+use pyo3::{
+    exceptions::{PyIOError, PyMemoryError, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyFloat, PyList},
+};
+
+// The most memory converting an array may take before it raises MemoryError, unless the
+// caller passes its own max_memory
+const DEFAULT_MAX_MEMORY: u64 = 8 << 30;
+
+// Cells converted to estimate the text length of the whole array
+const SAMPLED_CELLS: usize = 1000;
+
+// The cell types `CellTypes` in df_to_xlsx.py can produce
+const VALID_TYPES: [&str; 4] = [TYPE_STRING, TYPE_NUMBER, TYPE_BOOLEAN, TYPE_DATE];
 
 #[pymodule]
 fn _excel_rs<'py>(m: &Bound<'py, PyModule>) -> PyResult<()> {
     #[pyfn(m)]
-    #[pyo3(name = "csv_to_xlsx")]
-    fn csv_to_xlsx<'py>(py: Python<'py>, buf: Bound<'py, PyBytes>) -> Bound<'py, PyBytes> {
+    #[pyo3(name = "csv_to_xlsx", signature = (buf, delimiter = ','))]
+    fn csv_to_xlsx<'py>(py: Python<'py>, buf: Bound<'py, PyBytes>, delimiter: char) -> PyResult<Bound<'py, PyBytes>> {
+        if !delimiter.is_ascii() || matches!(delimiter, '"' | '\r' | '\n') {
+            return Err(PyValueError::new_err(format!("can't split fields on {:?}", delimiter)));
+        }
         let x = buf.as_bytes();
 
         let output_buffer = vec![];
         let mut workbook = WorkBook::new(Cursor::new(output_buffer));
         let mut worksheet = workbook.get_worksheet(String::from("Sheet 1"));
 
-        let mut reader = bytes_to_csv(x);
-        let headers = get_headers(&mut reader);
+        let mut reader = bytes_to_csv_with_delimiter(x, delimiter as u8);
+        let headers = read_headers(&mut reader)?;
+        let headers_to_bytes = headers.iter().to_owned().collect();
+        worksheet.write_row(headers_to_bytes).map_err(to_py_err)?;
 
-        if headers.is_some() {
-            let headers_to_bytes = headers.unwrap().iter().to_owned().collect();
-            if let Err(e) = worksheet.write_row(headers_to_bytes) {
-                panic!("{e}");
-            }
-        }
-
-        while let Some(record) = get_next_record(&mut reader) {
+        while let Some(record) = read_next_record(&mut reader)? {
             let row_data = record.iter().to_owned().collect();
-            if let Err(e) = worksheet.write_row(row_data) {
-                panic!("{e}");
-            }
+            worksheet.write_row(row_data).map_err(to_py_err)?;
         }
 
-        if let Err(e) = worksheet.close() {
-            panic!("{e}");
+        worksheet.close().map_err(to_py_err)?;
+
+        let final_buffer = workbook.finish().map_err(to_py_err)?;
+
+        Ok(PyBytes::new_bound(py, &final_buffer.into_inner()))
+    }
+
+    // Rows go from one file to the other as they are read, so neither is held in memory
+    #[pyfn(m)]
+    #[pyo3(name = "csv_file_to_xlsx")]
+    fn csv_file_to_xlsx(csv_path: &str, xlsx_path: &str) -> PyResult<()> {
+        let mut reader = CsvReader::from_reader(File::open(csv_path)?);
+        let writer = BufWriter::new(File::create(xlsx_path)?);
+
+        let mut workbook = WorkBook::new(writer);
+        let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+
+        // Cells without a type are text, as in csv_to_xlsx
+        let types = Vec::new();
+        let headers = reader.headers()?;
+        worksheet
+            .write_row(headers.iter().collect(), &types)
+            .map_err(to_py_err)?;
+
+        for record in reader {
+            let record = record?;
+            worksheet
+                .write_row(record.iter().collect(), &types)
+                .map_err(to_py_err)?;
         }
 
-        let final_buffer = workbook.finish().ok().unwrap();
+        worksheet.close().map_err(to_py_err)?;
 
-        PyBytes::new_bound(py, &final_buffer.into_inner())
+        workbook
+            .finish()
+            .map_err(to_py_err)?
+            .into_inner()
+            .map_err(|e| e.into_error())?;
+        Ok(())
     }
 
     #[pyfn(m)]
-    #[pyo3(name = "py_2d_to_xlsx")]
+    #[pyo3(name = "py_2d_to_xlsx", signature = (list, max_memory = Some(DEFAULT_MAX_MEMORY), notes = None, float_format = FloatFormat::Shortest))]
     fn py_2d_to_xlsx<'py>(
         py: Python<'py>,
         list: PyReadonlyArray2<'py, PyObject>,
-    ) -> Bound<'py, PyBytes> {
+        max_memory: Option<u64>,
+        notes: Option<Vec<(u32, usize, String)>>,
+        float_format: FloatFormat,
+    ) -> PyResult<Bound<'py, PyBytes>> {
         let ndarray = list.as_array();
 
-        let ndarray_str = ndarray.mapv(|x| {
-            if let Ok(inner_str) = x.extract::<String>(py) {
-                inner_str
-            } else {
-                if let Ok(inner_num) = x.extract::<f64>(py) {
-                    if inner_num.is_nan() {
-                        String::from("")
-                    } else {
-                        inner_num.to_string()
-                    }
-                } else {
-                    if let Ok(inner_date) = x.extract::<NaiveDateTime>(py) {
-                        format!("{}", inner_date.format("%Y-%m-%d %r"))
-                    } else {
-                        String::from("")
-                    }
-                }
-            }
-        });
+        check_memory(&ndarray, max_memory, |x| to_text(py, x, float_format))?;
+        let ndarray_str = ndarray.mapv(|x| to_text(py, &x, float_format));
 
         let output_buffer = vec![];
         let mut workbook = WorkBook::new(Cursor::new(output_buffer));
-        let mut worksheet = workbook.get_worksheet(String::from("Sheet 1"));
 
-        for row in ndarray_str.rows() {
-            let bytes = row.map(|x| x.as_bytes()).to_vec();
-            if let Err(e) = worksheet.write_row(bytes) {
-                panic!("{e}");
+        if let Some(notes) = notes {
+            // Only the typed sheet takes notes, so every column is typed as text
+            let types = vec![TYPE_STRING; ndarray_str.ncols()];
+            let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+            worksheet.reserve_rows(ndarray_str.nrows() as u32, ndarray_str.ncols());
+
+            for row in ndarray_str.rows() {
+                let bytes = row.map(|x| x.as_bytes()).to_vec();
+                worksheet.write_row(bytes, &types).map_err(to_py_err)?;
+            }
+            for (row, col, text) in notes {
+                worksheet.add_note(row, col, &text);
+            }
+
+            worksheet.close().map_err(to_py_err)?;
+        } else {
+            let mut worksheet = workbook.get_worksheet(String::from("Sheet 1"));
+
+            for row in ndarray_str.rows() {
+                let bytes = row.map(|x| x.as_bytes()).to_vec();
+                worksheet.write_row(bytes).map_err(to_py_err)?;
             }
-        }
 
-        if let Err(e) = worksheet.close() {
-            panic!("{e}");
+            worksheet.close().map_err(to_py_err)?;
         }
 
-        let final_buffer = workbook.finish().ok().unwrap();
+        let final_buffer = workbook.finish().map_err(to_py_err)?;
 
-        PyBytes::new_bound(py, &final_buffer.into_inner())
+        Ok(PyBytes::new_bound(py, &final_buffer.into_inner()))
     }
 
     #[pyfn(m)]
-    #[pyo3(name = "typed_py_2d_to_xlsx")]
+    #[pyo3(name = "typed_py_2d_to_xlsx", signature = (list, types, max_memory = Some(DEFAULT_MAX_MEMORY), has_header = true, notes = None, float_format = FloatFormat::Shortest))]
     fn typed_py_2d_to_xlsx<'py>(
         py: Python<'py>,
         list: PyReadonlyArray2<'py, PyObject>,
         types: Bound<'py, PyList>,
-    ) -> Bound<'py, PyBytes> {
+        max_memory: Option<u64>,
+        has_header: bool,
+        notes: Option<Vec<(u32, usize, String)>>,
+        float_format: FloatFormat,
+    ) -> PyResult<Bound<'py, PyBytes>> {
         let ndarray = list.as_array();
 
-        let ndarray_str = ndarray.mapv(|x| {
-            if let Ok(inner_str) = x.extract::<String>(py) {
-                inner_str
-            } else {
-                if let Ok(inner_num) = x.extract::<f64>(py) {
-                    if inner_num.is_nan() {
-                        String::from("")
-                    } else {
-                        inner_num.to_string()
-                    }
-                } else {
-                    if let Ok(inner_date) = x.extract::<NaiveDateTime>(py) {
-                        format!("{}", chrono_to_xlsx_date(inner_date))
-                    } else {
-                        String::from("")
-                    }
-                }
-            }
-        });
+        check_memory(&ndarray, max_memory, |x| to_typed_text(py, x, float_format))?;
+        let ndarray_str = ndarray.mapv(|x| to_typed_text(py, &x, float_format));
 
         let mut xlsx_types: Vec<String> = Vec::with_capacity(ndarray.len());
 
         for item in types.iter() {
-            let unwrapped = item.extract::<String>().unwrap_or(String::from(""));
-            xlsx_types.push(unwrapped);
+            let xlsx_type = item.extract::<String>()?;
+            if !VALID_TYPES.contains(&xlsx_type.as_str()) {
+                return Err(PyValueError::new_err(format!(
+                    "invalid cell type {xlsx_type:?}, expected one of {VALID_TYPES:?}"
+                )));
+            }
+            xlsx_types.push(xlsx_type);
         }
 
         let borrowed_xlsx_types = xlsx_types.iter().map(|x| x.as_str()).collect();
@@ -137,24 +179,131 @@ fn _excel_rs<'py>(m: &Bound<'py, PyModule>) -> PyResult<()> {
         let output_buffer = vec![];
         let mut workbook = WorkBook::new(Cursor::new(output_buffer));
         let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+        worksheet.reserve_rows(ndarray_str.nrows() as u32, ndarray_str.ncols());
+        if !has_header {
+            worksheet.set_no_header();
+        }
 
         for row in ndarray_str.rows() {
             let bytes = row.map(|x| x.as_bytes()).to_vec();
-            if let Err(e) = worksheet.write_row(bytes, &borrowed_xlsx_types) {
-                panic!("{e}");
-            }
+            worksheet.write_row(bytes, &borrowed_xlsx_types).map_err(to_py_err)?;
         }
 
-        if let Err(e) = worksheet.close() {
-            panic!("{e}");
+        // (row, col, text), with the header as row 0
+        for (row, col, text) in notes.unwrap_or_default() {
+            worksheet.add_note(row, col, &text);
         }
 
-        let final_buffer = workbook.finish().ok().unwrap();
+        worksheet.close().map_err(to_py_err)?;
 
-        PyBytes::new_bound(py, &final_buffer.into_inner())
+        let final_buffer = workbook.finish().map_err(to_py_err)?;
+
+        Ok(PyBytes::new_bound(py, &final_buffer.into_inner()))
     }
 
     m.add_class::<PyPostgresClient>()?;
+    m.add_class::<PyXlsxWriter>()?;
+
+    Ok(())
+}
+
+fn to_text(py: Python<'_>, x: &PyObject, floats: FloatFormat) -> String {
+    if let Ok(inner_str) = x.extract::<String>(py) {
+        inner_str
+    } else {
+        if let Ok(inner_num) = x.extract::<f64>(py) {
+            if inner_num.is_nan() {
+                String::from("")
+            } else if x.bind(py).is_instance_of::<PyFloat>() {
+                floats.format(inner_num)
+            } else {
+                inner_num.to_string()
+            }
+        } else {
+            // A datetime also extracts as a date, so it has to be checked first
+            if let Ok(inner_date) = x.extract::<NaiveDateTime>(py) {
+                format!("{}", inner_date.format("%Y-%m-%d %r"))
+            } else if let Ok(inner_date) = x.extract::<NaiveDate>(py) {
+                format!("{}", inner_date.format("%Y-%m-%d"))
+            } else {
+                String::from("")
+            }
+        }
+    }
+}
+
+// Like to_text, but dates are written as ISO text, which the typed writer stores as
+// serials in a date style
+fn to_typed_text(py: Python<'_>, x: &PyObject, floats: FloatFormat) -> String {
+    if let Ok(inner_str) = x.extract::<String>(py) {
+        inner_str
+    } else {
+        if let Ok(inner_num) = x.extract::<f64>(py) {
+            if inner_num.is_nan() {
+                String::from("")
+            } else if x.bind(py).is_instance_of::<PyFloat>() {
+                floats.format(inner_num)
+            } else {
+                inner_num.to_string()
+            }
+        } else {
+            // A datetime also extracts as a date, so it has to be checked first. Dates
+            // before 1900 have no serial, so the typed writer keeps them as text
+            if let Ok(inner_date) = x.extract::<NaiveDateTime>(py) {
+                inner_date.format(ISO_DATE_TIME).to_string()
+            } else if let Ok(inner_date) = x.extract::<NaiveDate>(py) {
+                inner_date.to_string()
+            } else {
+                String::from("")
+            }
+        }
+    }
+}
+
+// Every cell is copied to a String before writing, so a huge array can run out of memory
+// and take the interpreter down with it. Estimating the copy's size from a sample of
+// cells lets that be raised as a MemoryError instead.
+fn check_memory(
+    ndarray: &ArrayView2<'_, PyObject>,
+    max_memory: Option<u64>,
+    to_text: impl Fn(&PyObject) -> String,
+) -> PyResult<()> {
+    let Some(max_memory) = max_memory else {
+        return Ok(());
+    };
+    if ndarray.is_empty() {
+        return Ok(());
+    }
+
+    let step = (ndarray.len() / SAMPLED_CELLS).max(1);
+    let (sampled, text_len) = ndarray
+        .iter()
+        .step_by(step)
+        .take(SAMPLED_CELLS)
+        .fold((0, 0), |(n, len), x| (n + 1, len + to_text(x).len()));
+
+    let per_cell = (size_of::<String>() + text_len / sampled) as u64;
+    let estimate = per_cell.saturating_mul(ndarray.len() as u64);
+    if estimate > max_memory {
+        return Err(PyMemoryError::new_err(format!(
+            "converting this {}x{} array needs about {} MB, more than max_memory ({} MB). \
+             Write it in parts with XlsxWriter, or pass a larger max_memory (None for no limit)",
+            ndarray.nrows(),
+            ndarray.ncols(),
+            estimate >> 20,
+            max_memory >> 20
+        )));
+    }
 
     Ok(())
 }
+
+// I/O and zip failures are raised as IOError. Anything else is a problem with what was
+// written, e.g. a row past the sheet's limits, so it is a ValueError
+pub(crate) fn to_py_err(e: anyhow::Error) -> PyErr {
+    if e.chain().any(|x| x.is::<std::io::Error>()) {
+        PyIOError::new_err(format!("{e:#}"))
+    } else {
+        PyValueError::new_err(format!("{e:#}"))
+    }
+}
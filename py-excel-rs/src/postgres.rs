@@ -1,8 +1,10 @@
-use std::{borrow::Cow, io::Cursor};
+use std::borrow::Cow;
 
-use excel_rs_postgres::{ExcelBytes, ExcelBytesBorrowed, FallibleIterator, PostgresClient};
-use excel_rs_xlsx::WorkBook;
-use pyo3::{pyclass, pymethods, PyResult};
+use excel_rs_postgres::{FallibleIterator, PostgresClient, DEFAULT_FETCH_SIZE};
+use pyo3::{
+    exceptions::{PyIOError, PyValueError},
+    pyclass, pymethods, PyErr, PyResult,
+};
 
 #[pyclass]
 pub struct PyPostgresClient {
@@ -12,10 +14,12 @@ pub struct PyPostgresClient {
 #[pymethods]
 impl PyPostgresClient {
     #[staticmethod]
-    pub fn new(conn_string: &str) -> PyPostgresClient {
-        PyPostgresClient {
-            client: Some(PostgresClient::new(conn_string)),
-        }
+    pub fn new(conn_string: &str) -> PyResult<PyPostgresClient> {
+        let client = PostgresClient::new(conn_string).map_err(to_py_err)?;
+
+        Ok(PyPostgresClient {
+            client: Some(client),
+        })
     }
 
     pub fn get_columns(
@@ -29,12 +33,12 @@ impl PyPostgresClient {
 
         query.push_str(table_name);
 
-        if schema_name != "" {
+        if !schema_name.is_empty() {
             query.push_str("' AND TABLE_SCHEMA = '");
             query.push_str(schema_name);
         }
 
-        if excluded.len() > 0 {
+        if !excluded.is_empty() {
             query.push_str("' AND COLUMN_NAME NOT IN (");
             query.push_str(
                 &excluded
@@ -43,84 +47,54 @@ impl PyPostgresClient {
                     .collect::<Vec<String>>()
                     .join(", "), // Add commas
             );
-            query.push_str(")");
+            query.push(')');
         } else {
-            query.push_str("'");
+            query.push('\'');
         }
 
-        let res = match &mut self.client {
-            Some(client) => client.make_query(&query, vec![]),
-            None => panic!("Client not set up"),
-        };
+        let iter = self
+            .client()?
+            .make_query(&query, vec![])
+            .map_err(to_py_err)?;
 
-        let iter = match res {
-            Ok(iter) => iter,
-            Err(e) => panic!("{e}"),
-        };
-
-        let cols: Vec<String> = match iter.map(|row| Ok(row.get::<usize, String>(0))).collect() {
-            Ok(s) => s,
-            Err(e) => panic!("{e}"),
-        };
+        let cols: Vec<String> = iter
+            .map(|row| Ok(row.get::<usize, String>(0)))
+            .collect()
+            .map_err(to_py_err)?;
 
         Ok(cols)
     }
 
-    pub fn get_xlsx_from_query(&mut self, query: &str) -> PyResult<Cow<[u8]>> {
-        let res = match &mut self.client {
-            Some(client) => client.make_query(&query, vec![]),
-            None => panic!("Client not set up"),
-        };
-
-        let mut iter = match res {
-            Ok(iter) => iter,
-            Err(e) => panic!("{e}"),
-        };
-
-        let output_buffer = vec![];
-        let mut workbook = WorkBook::new(Cursor::new(output_buffer));
-        let mut worksheet = workbook.get_worksheet(String::from("Sheet 1"));
-
-        let headers = iter.next().ok().unwrap().unwrap();
-        let len = headers.len();
-
-        // Write headers
-        let mut row_vec: Vec<&[u8]> = vec![&[]; len];
-
-        for col in 0..len {
-            let column = headers.columns().get(col).unwrap();
-            let name = column.name();
-            row_vec[col] = name.as_bytes();
-        }
-
-        if let Err(e) = worksheet.write_row(row_vec) {
-            panic!("{e}");
-        }
-
-        while let Ok(Some(row)) = iter.next() {
-            let mut row_vec: Vec<Box<[u8]>> = vec![Box::from([]); len];
-
-            for col in 0..len {
-                if let Ok(bytes) = row.try_get::<usize, ExcelBytesBorrowed>(col) {
-                    row_vec[col] = Box::from(bytes.0);
-                } else if let Ok(bytes) = row.try_get::<usize, ExcelBytes>(col) {
-                    let asdasd = bytes.0;
-                    row_vec[col] = asdasd
-                }
-            }
-
-            let new_vec: Vec<&[u8]> = row_vec.iter().map(|x| x.as_ref()).collect();
-
-            if let Err(e) = worksheet.write_row(new_vec) {
-                panic!("{e}");
-            }
-        }
+    #[pyo3(signature = (query, fetch_size = DEFAULT_FETCH_SIZE, numeric_as_number = false))]
+    pub fn get_xlsx_from_query(
+        &mut self,
+        query: &str,
+        fetch_size: i32,
+        numeric_as_number: bool,
+    ) -> PyResult<Cow<'_, [u8]>> {
+        self.query_to_xlsx(query, false, fetch_size, numeric_as_number)
+    }
 
-        if let Err(e) = worksheet.close() {
-            panic!("{e}");
+    /// Runs `sql` through a server-side cursor and writes the rows straight to an xlsx,
+    /// with the column names as the header.
+    #[pyo3(signature = (sql, freeze_top_row = false, fetch_size = DEFAULT_FETCH_SIZE, numeric_as_number = false))]
+    pub fn query_to_xlsx(
+        &mut self,
+        sql: &str,
+        freeze_top_row: bool,
+        fetch_size: i32,
+        numeric_as_number: bool,
+    ) -> PyResult<Cow<'_, [u8]>> {
+        if fetch_size <= 0 {
+            return Err(PyValueError::new_err(format!(
+                "fetch_size must be positive, got {fetch_size}"
+            )));
         }
 
-        let final_buffer = workbook.finish().ok().unwrap().into_inner();
+        let final_buffer = self
+            .client()?
+            .get_xlsx_from_query(sql, fetch_size, numeric_as_number, freeze_top_row)
+            .map_err(to_py_err)?;
 
         Ok(Cow::from(final_buffer))
     }
@@ -135,3 +109,16 @@ impl PyPostgresClient {
         Ok(())
     }
 }
+
+impl PyPostgresClient {
+    fn client(&mut self) -> PyResult<&mut PostgresClient> {
+        self.client
+            .as_mut()
+            .ok_or_else(|| PyValueError::new_err("client is closed"))
+    }
+}
+
+// Connection, query and write failures all come back from the server or the socket
+fn to_py_err(e: impl std::fmt::Display) -> PyErr {
+    PyIOError::new_err(e.to_string())
+}
@@ -1,6 +1,6 @@
-use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
-
-const NANOSECONDS_IN_A_DAY: f64 = 1_000_000_000.0 * 60.0 * 60.0 * 24.0;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use excel_rs_xlsx::typed_sheet::to_excel_serial;
+use pyo3::{exceptions::PyValueError, prelude::*};
 
 // const EXCEL1900_EPOCH: &str = "1899-12-30 00:00:00";
 // const EXCEL1904_EPOCH: &str = "1904-01-01 00:00:00";
@@ -8,14 +8,168 @@ const NANOSECONDS_IN_A_DAY: f64 = 1_000_000_000.0 * 60.0 * 60.0 * 24.0;
 // const EXCEL_BUGGY_DATE_START: &str = "1900-03-01 00:00:00";
 // const DATE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
-pub fn chrono_to_xlsx_date(date: NaiveDateTime) -> f64 {
-    let start_date_only = NaiveDate::from_ymd_opt(1900, 1, 1).expect("Excel Epoch");
-    let start_date = NaiveDateTime::new(
-        start_date_only,
-        NaiveTime::from_hms_opt(0, 0, 0).expect("Time Epoch"),
-    );
-    let delta = (date - start_date).num_nanoseconds().unwrap();
+// The text datetimes are handed to TYPE_DATE cells as, with fractions of a second only
+// when there are some
+pub const ISO_DATE_TIME: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+// Dates before 1900 have no serial
+pub fn chrono_to_xlsx_date(date: NaiveDateTime) -> Option<f64> {
+    let time = date.time();
+    let seconds = time.num_seconds_from_midnight() as f64 + time.nanosecond() as f64 / 1e9;
+    to_excel_serial(date.year(), date.month(), date.day(), seconds)
+}
+
+// Whole days, so no time of day is implied
+pub fn chrono_date_to_xlsx_date(date: NaiveDate) -> Option<i64> {
+    to_excel_serial(date.year(), date.month(), date.day(), 0.0).map(|x| x as i64)
+}
+
+/// How Python floats in an array are turned into cell text. Ints are always written
+/// whole, and NaN as an empty cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// The shortest text that reads back as the same float, without an exponent:
+    /// `0.1`, `0.0000001`, `1234567.89`. Whole floats are written without `.0`.
+    Shortest,
+    /// The same text as Python's `repr`: `0.1`, `1e-07`, `1234567.89`, `3.0`.
+    Repr,
+    /// Rounded to this many decimal places, e.g. with 2: `0.10`, `0.00`, `1234567.89`.
+    Fixed(usize),
+}
+
+// Taken from Python as "shortest", "repr" or a number of decimal places
+impl<'py> FromPyObject<'py> for FloatFormat {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Ok(precision) = ob.extract::<usize>() {
+            return Ok(FloatFormat::Fixed(precision));
+        }
+        match ob.extract::<String>()?.as_str() {
+            "shortest" => Ok(FloatFormat::Shortest),
+            "repr" => Ok(FloatFormat::Repr),
+            other => Err(PyValueError::new_err(format!(
+                "invalid float_format {other:?}, expected \"shortest\", \"repr\" or a number of decimal places"
+            ))),
+        }
+    }
+}
+
+impl FloatFormat {
+    pub fn format(self, x: f64) -> String {
+        match self {
+            FloatFormat::Shortest => x.to_string(),
+            FloatFormat::Repr => python_repr(x),
+            FloatFormat::Fixed(precision) => format!("{:.*}", precision, x),
+        }
+    }
+}
+
+// Python's repr has the same shortest digits as Rust, but switches to an exponent below
+// 1e-4 and from 1e16, with at least two exponent digits
+fn python_repr(x: f64) -> String {
+    if !x.is_finite() {
+        return String::from(if x.is_nan() {
+            "nan"
+        } else if x > 0.0 {
+            "inf"
+        } else {
+            "-inf"
+        });
+    }
+
+    let exponent_form = format!("{:e}", x);
+    let (mantissa, exponent) = exponent_form.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+
+    if (-4..16).contains(&exponent) {
+        let text = x.to_string();
+        if text.contains('.') {
+            text
+        } else {
+            text + ".0"
+        }
+    } else {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_whole_serials() {
+        let date = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+        assert_eq!(chrono_date_to_xlsx_date(date), Some(44927));
+        assert_eq!(
+            chrono_to_xlsx_date(date.and_hms_opt(12, 0, 0).unwrap()),
+            Some(44927.5)
+        );
+    }
+
+    #[test]
+    fn datetimes_are_iso_text_with_fractions_only_when_set() {
+        let date = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+        let noon = date.and_hms_opt(12, 0, 0).unwrap();
+        assert_eq!(
+            noon.format(ISO_DATE_TIME).to_string(),
+            "2023-01-01T12:00:00"
+        );
+        let later = date.and_hms_micro_opt(12, 0, 0, 250_000).unwrap();
+        assert_eq!(
+            later.format(ISO_DATE_TIME).to_string(),
+            "2023-01-01T12:00:00.250"
+        );
+    }
+
+    #[test]
+    fn dates_before_1900_have_no_serial() {
+        let date = NaiveDate::from_ymd_opt(1899, 12, 31).unwrap();
+        assert_eq!(chrono_date_to_xlsx_date(date), None);
+        assert_eq!(
+            chrono_to_xlsx_date(date.and_hms_opt(0, 0, 0).unwrap()),
+            None
+        );
+        // Excel's day 1, and the day after its 29 February 1900
+        let date = NaiveDate::from_ymd_opt(1900, 1, 1).unwrap();
+        assert_eq!(chrono_date_to_xlsx_date(date), Some(1));
+        let date = NaiveDate::from_ymd_opt(1900, 3, 1).unwrap();
+        assert_eq!(chrono_date_to_xlsx_date(date), Some(61));
+    }
+
+    #[test]
+    fn floats_are_formatted_as_documented() {
+        let floats = [0.1, 1e-7, 1234567.89, 3.0, 1e16, -2.5e-5];
+        let format = |format: FloatFormat| floats.map(|x| format.format(x));
 
-    let true_delta: f64 = (delta as f64 + NANOSECONDS_IN_A_DAY + NANOSECONDS_IN_A_DAY) / NANOSECONDS_IN_A_DAY;
-    return true_delta;
+        assert_eq!(
+            format(FloatFormat::Shortest),
+            [
+                "0.1",
+                "0.0000001",
+                "1234567.89",
+                "3",
+                "10000000000000000",
+                "-0.000025"
+            ]
+        );
+        // As printed by Python's repr()
+        assert_eq!(
+            format(FloatFormat::Repr),
+            ["0.1", "1e-07", "1234567.89", "3.0", "1e+16", "-2.5e-05"]
+        );
+        assert_eq!(
+            format(FloatFormat::Fixed(2)),
+            [
+                "0.10",
+                "0.00",
+                "1234567.89",
+                "3.00",
+                "10000000000000000.00",
+                "-0.00"
+            ]
+        );
+        assert_eq!(python_repr(f64::NAN), "nan");
+        assert_eq!(python_repr(f64::NEG_INFINITY), "-inf");
+    }
 }
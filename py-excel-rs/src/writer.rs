@@ -0,0 +1,280 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime};
+use excel_rs_xlsx::{
+    typed_sheet::{TYPE_BLANK, TYPE_BOOLEAN, TYPE_DATE, TYPE_NUMBER, TYPE_STRING},
+    WorkBook,
+};
+use numpy::PyReadonlyArray2;
+use pyo3::{
+    exceptions::{PyIOError, PyValueError},
+    prelude::*,
+    types::{PyBool, PyList},
+};
+
+use crate::{
+    to_py_err,
+    utils::{chrono_date_to_xlsx_date, chrono_to_xlsx_date, ISO_DATE_TIME},
+};
+
+// Rows queued for the writing thread before write_row waits for it to catch up
+const QUEUED_ROWS: usize = 1024;
+
+// The cells, their types and whether the row is the header
+type Row = (Vec<Vec<u8>>, Vec<&'static str>, bool);
+
+/// Streams rows into an xlsx file as they are written, so the data never has to be
+/// collected in Python first. The sheet is written on its own thread, since a sheet
+/// borrows its workbook and can't be kept between calls from Python.
+#[pyclass(name = "XlsxWriter")]
+pub struct PyXlsxWriter {
+    sender: Option<SyncSender<Row>>,
+    thread: Option<JoinHandle<Result<()>>>,
+    rows_written: u64,
+    // Set by the header or the first chunk appended, which every chunk has to match
+    columns: Option<usize>,
+    // Inferred from the first row appended, blank where that row had no value
+    chunk_types: Option<Vec<&'static str>>,
+}
+
+#[pymethods]
+impl PyXlsxWriter {
+    #[new]
+    #[pyo3(signature = (path, freeze_top_row = false))]
+    pub fn new(path: &str, freeze_top_row: bool) -> PyResult<PyXlsxWriter> {
+        let file = File::create(path)?;
+        let (sender, receiver) = mpsc::sync_channel(QUEUED_ROWS);
+        let thread = thread::spawn(move || write_file(file, freeze_top_row, receiver));
+
+        Ok(PyXlsxWriter {
+            sender: Some(sender),
+            thread: Some(thread),
+            rows_written: 0,
+            columns: None,
+            chunk_types: None,
+        })
+    }
+
+    /// Writes `headers` as the header row. Must come before any other row.
+    pub fn write_header(&mut self, py: Python<'_>, headers: Vec<String>) -> PyResult<()> {
+        if self.rows_written > 0 {
+            return Err(PyValueError::new_err(
+                "write_header must be called before write_row",
+            ));
+        }
+
+        self.columns = Some(headers.len());
+        let types = vec![TYPE_STRING; headers.len()];
+        let row = headers.into_iter().map(String::into_bytes).collect();
+        self.send(py, (row, types, true))
+    }
+
+    /// Writes `values` as the next row. Strings, numbers, bools, dates and datetimes
+    /// keep their type, `None` and NaN are left blank and anything else is written as
+    /// its `str()`. Without a header, the first row is typed like the rest.
+    pub fn write_row(&mut self, py: Python<'_>, values: Bound<'_, PyList>) -> PyResult<()> {
+        let mut row = Vec::with_capacity(values.len());
+        let mut types = Vec::with_capacity(values.len());
+
+        for value in values.iter() {
+            let (datum, cell_type) = to_cell(&value)?;
+            row.push(datum);
+            types.push(cell_type);
+        }
+
+        self.send(py, (row, types, false))
+    }
+
+    /// Writes every row of `chunk`, a 2D NumPy array, e.g. a batch of results. Column
+    /// types are inferred from the first row appended and kept for every later chunk:
+    /// text columns write any value as its `str()`, and other columns raise
+    /// `ValueError` on a value of another type. Empty values are always left blank.
+    pub fn append(&mut self, py: Python<'_>, chunk: Bound<'_, PyAny>) -> PyResult<()> {
+        let chunk = to_object_array(chunk)?;
+        let array = chunk.as_array();
+
+        let columns = *self.columns.get_or_insert(array.ncols());
+        if array.ncols() != columns {
+            return Err(PyValueError::new_err(format!(
+                "chunk has {} columns, expected {}",
+                array.ncols(),
+                columns
+            )));
+        }
+
+        for row in array.rows() {
+            let mut cells = Vec::with_capacity(columns);
+            let mut types = Vec::with_capacity(columns);
+            for value in row.iter() {
+                let cell = to_cell(value.bind(py))?;
+                cells.push(cell.0);
+                types.push(cell.1);
+            }
+
+            let chunk_types = self.chunk_types.get_or_insert_with(|| types.clone());
+            for (col, (value, chunk_type)) in row.iter().zip(chunk_types.iter()).enumerate() {
+                if types[col] == TYPE_BLANK || *chunk_type == TYPE_BLANK {
+                    continue;
+                }
+                if *chunk_type == TYPE_STRING && types[col] != TYPE_STRING {
+                    cells[col] = value.bind(py).str()?.to_string().into_bytes();
+                    types[col] = TYPE_STRING;
+                } else if types[col] != *chunk_type {
+                    return Err(PyValueError::new_err(format!(
+                        "column {} was inferred from the first chunk as {}, got {}",
+                        col,
+                        type_name(chunk_type),
+                        value.bind(py).repr()?
+                    )));
+                }
+            }
+
+            self.send(py, (cells, types, false))?;
+        }
+
+        Ok(())
+    }
+
+    /// Finishes the file. Writing after this is an error, closing again does nothing.
+    pub fn close(&mut self, py: Python<'_>) -> PyResult<()> {
+        // Dropping the sender ends the thread's loop over the rows
+        self.sender = None;
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+
+        match py.allow_threads(|| thread.join()) {
+            Ok(result) => result.map_err(to_py_err),
+            Err(_) => Err(PyIOError::new_err("the xlsx writer thread panicked")),
+        }
+    }
+
+    pub fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    pub fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        exc_type: Option<PyObject>,
+        _exc_value: Option<PyObject>,
+        _traceback: Option<PyObject>,
+    ) -> PyResult<bool> {
+        let result = self.close(py);
+        // An error from closing would hide the one that ended the block
+        if exc_type.is_none() {
+            result?;
+        }
+        Ok(false)
+    }
+}
+
+impl PyXlsxWriter {
+    fn send(&mut self, py: Python<'_>, row: Row) -> PyResult<()> {
+        let Some(sender) = &self.sender else {
+            return Err(PyValueError::new_err("writer is closed"));
+        };
+
+        if py.allow_threads(|| sender.send(row)).is_err() {
+            // The thread only hangs up when writing failed, and closing returns why
+            self.close(py)?;
+            return Err(PyIOError::new_err("writer is closed"));
+        }
+
+        self.rows_written += 1;
+        Ok(())
+    }
+}
+
+fn write_file(file: File, freeze_top_row: bool, receiver: Receiver<Row>) -> Result<()> {
+    let mut workbook = WorkBook::new(BufWriter::new(file));
+    let mut worksheet = workbook.get_typed_worksheet(String::from("Sheet 1"));
+    if freeze_top_row {
+        worksheet.freeze_top_row();
+    }
+
+    for (i, (row, types, is_header)) in receiver.into_iter().enumerate() {
+        if i == 0 && !is_header {
+            worksheet.set_no_header();
+        }
+        let bytes = row.iter().map(|x| x.as_slice()).collect();
+        worksheet.write_row(bytes, &types)?;
+    }
+
+    worksheet.close()?;
+    workbook.finish()?;
+    Ok(())
+}
+
+fn to_cell(value: &Bound<'_, PyAny>) -> PyResult<(Vec<u8>, &'static str)> {
+    if value.is_none() {
+        return Ok((Vec::new(), TYPE_BLANK));
+    }
+    if let Ok(inner_str) = value.extract::<String>() {
+        return Ok((inner_str.into_bytes(), TYPE_STRING));
+    }
+    // bool is a subclass of int, so it has to be checked before numbers
+    if let Ok(inner_bool) = value.downcast::<PyBool>() {
+        let datum = if inner_bool.is_true() { "1" } else { "0" };
+        return Ok((datum.into(), TYPE_BOOLEAN));
+    }
+    // A datetime also extracts as a date, so it has to be checked first. Dates are
+    // passed on as ISO text, which the sheet writes as serials in a date style, and
+    // dates before 1900 are written as text, as Excel can't store them
+    if let Ok(inner_date) = value.extract::<NaiveDateTime>() {
+        let iso = inner_date.format(ISO_DATE_TIME).to_string().into_bytes();
+        return Ok(match chrono_to_xlsx_date(inner_date) {
+            Some(_) => (iso, TYPE_DATE),
+            None => (inner_date.to_string().into_bytes(), TYPE_STRING),
+        });
+    }
+    if let Ok(inner_date) = value.extract::<NaiveDate>() {
+        let iso = inner_date.to_string().into_bytes();
+        return Ok(match chrono_date_to_xlsx_date(inner_date) {
+            Some(_) => (iso, TYPE_DATE),
+            None => (iso, TYPE_STRING),
+        });
+    }
+    if let Ok(inner_num) = value.extract::<i64>() {
+        return Ok((inner_num.to_string().into_bytes(), TYPE_NUMBER));
+    }
+    if let Ok(inner_num) = value.extract::<f64>() {
+        if !inner_num.is_finite() {
+            return Ok((Vec::new(), TYPE_BLANK));
+        }
+        return Ok((inner_num.to_string().into_bytes(), TYPE_NUMBER));
+    }
+
+    Ok((value.str()?.to_string().into_bytes(), TYPE_STRING))
+}
+
+// Dates finer than microseconds become ints as objects, so they go through microseconds
+fn to_object_array(chunk: Bound<'_, PyAny>) -> PyResult<PyReadonlyArray2<'_, PyObject>> {
+    let dtype = chunk.getattr("dtype")?;
+    let chunk = match dtype.getattr("kind")?.extract::<String>()?.as_str() {
+        "O" => chunk,
+        "M" => chunk
+            .call_method1("astype", ("datetime64[us]",))?
+            .call_method1("astype", ("O",))?,
+        _ => chunk.call_method1("astype", ("O",))?,
+    };
+    if chunk.getattr("ndim")?.extract::<usize>()? != 2 {
+        return Err(PyValueError::new_err("append takes a 2D array"));
+    }
+    chunk.extract()
+}
+
+fn type_name(cell_type: &str) -> &'static str {
+    match cell_type {
+        TYPE_NUMBER => "a number",
+        TYPE_BOOLEAN => "a bool",
+        TYPE_DATE => "a date",
+        _ => "text",
+    }
+}
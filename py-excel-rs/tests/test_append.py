@@ -0,0 +1,47 @@
+import datetime
+import re
+
+import numpy as np
+import pytest
+
+from py_excel_rs import XlsxWriter
+from xlsx_parts import sheet_xml
+
+JAN_1 = datetime.date(2023, 1, 1)
+
+
+def chunk(start):
+    rows = [[f"row {i}", i * 1.5, JAN_1 + datetime.timedelta(days=i)] for i in range(start, start + 2)]
+    return np.array(rows, dtype=object)
+
+
+def test_chunks_are_appended_with_the_first_chunks_types(tmp_path):
+    path = tmp_path / "out.xlsx"
+    with XlsxWriter(str(path)) as w:
+        w.write_header(["Name", "Score", "Joined"])
+        w.append(chunk(0))
+        w.append(chunk(2))
+        w.append(np.array([[7, 7.5, datetime.date(2023, 2, 1)], ["x", None, None]], dtype=object))
+
+    xml = sheet_xml(path.read_bytes())
+    assert len(re.findall("<row ", xml)) == 1 + 2 + 2 + 2
+    for row in range(2, 7):
+        assert f'<c r="A{row}" t="str">' in xml
+        assert f'<c r="B{row}" t="n">' in xml
+        assert f'<c r="C{row}" t="n" s="1">' in xml
+    assert '<c r="B5" t="n"><v>4.5</v></c>' in xml
+    assert '<c r="C5" t="n" s="1"><v>44930</v></c>' in xml
+    # A number in the text column is written as its str()
+    assert '<c r="A6" t="str"><v>7</v></c>' in xml
+    assert '<c r="C6" t="n" s="1"><v>44958</v></c>' in xml
+    # Empty values are blank whatever the column's type
+    assert "B7" not in xml and "C7" not in xml
+
+
+def test_chunks_must_match_the_first(tmp_path):
+    with XlsxWriter(str(tmp_path / "out.xlsx")) as w:
+        w.append(chunk(0))
+        with pytest.raises(ValueError, match="chunk has 2 columns, expected 3"):
+            w.append(np.array([["a", 1.0]], dtype=object))
+        with pytest.raises(ValueError, match="column 1 was inferred from the first chunk as a number, got 'b'"):
+            w.append(np.array([["a", "b", JAN_1]], dtype=object))
@@ -0,0 +1,32 @@
+import numpy as np
+
+from py_excel_rs import array_to_xlsx
+from xlsx_parts import sheet_xml
+
+
+def test_structured_array_types_come_from_its_fields():
+    arr = np.array(
+        [(1, 2.5, "Ada", True, np.datetime64("2023-01-01"))],
+        dtype=[("id", "i8"), ("score", "f8"), ("name", "U10"), ("active", "?"), ("joined", "M8[D]")],
+    )
+
+    xml = sheet_xml(array_to_xlsx(arr))
+    assert '<c r="A1" t="str"><v>id</v></c>' in xml
+    assert '<c r="E1" t="str"><v>joined</v></c>' in xml
+    assert '<c r="A2" t="n"><v>1</v></c>' in xml
+    assert '<c r="B2" t="n"><v>2.5</v></c>' in xml
+    assert '<c r="C2" t="str"><v>Ada</v></c>' in xml
+    assert '<c r="D2" t="b"><v>1</v></c>' in xml
+    assert '<c r="E2" t="n" s="1"><v>44927</v></c>' in xml
+
+
+def test_2d_array_types_come_from_its_dtype():
+    xml = sheet_xml(array_to_xlsx(np.array([[1, 2], [3, 4]]), headers=["a", "b"]))
+    assert '<c r="A1" t="str"><v>a</v></c>' in xml
+    assert '<c r="B3" t="n"><v>4</v></c>' in xml
+
+    xml = sheet_xml(array_to_xlsx(np.array([["x", "y"]])))
+    assert '<c r="A1" t="str"><v>x</v></c>' in xml
+
+    xml = sheet_xml(array_to_xlsx(np.array([[True, False]])))
+    assert '<c r="A1" t="b"><v>1</v></c><c r="B1" t="b"><v>0</v></c>' in xml
@@ -0,0 +1,36 @@
+import re
+from datetime import date
+
+import pandas as pd
+
+from py_excel_rs import df_to_xlsx
+from xlsx_parts import sheet_xml
+
+
+def test_date_only_values_have_no_time():
+    df = pd.DataFrame({"day": [date(2023, 1, 1)]})
+
+    xml = sheet_xml(df_to_xlsx(df))
+    assert "<v>2023-01-01</v>" in xml
+    assert "12:00:00" not in xml
+
+    # A whole serial, in the date style without a time
+    xml = sheet_xml(df_to_xlsx(df, should_infer_types=True))
+    assert re.search(r'<c r="A2"[^>]* s="1"><v>44927</v></c>', xml), xml
+
+
+def test_times_missing_and_pre_1900_dates():
+    df = pd.DataFrame(
+        {
+            "when": pd.to_datetime(["2023-01-01 12:00", None]),
+            "day": [date(2023, 1, 1), date(1899, 6, 1)],
+        }
+    )
+
+    xml = sheet_xml(df_to_xlsx(df, should_infer_types=True))
+    # A time of day gets the date-time style rather than the date style
+    assert re.search(r'<c r="A2" t="n" s="(\d+)"><v>44927.5</v></c>', xml).group(1) != "1", xml
+    # NaT has no value, and a date Excel can't store is kept as its text
+    assert '<c r="A3"/>' in xml or 'r="A3"' not in xml, xml
+    assert '<c r="B3" t="str"><v>1899-06-01</v></c>' in xml, xml
+    assert '<c r="B2" t="n" s="1"><v>44927</v></c>' in xml, xml
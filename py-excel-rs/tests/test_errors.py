@@ -0,0 +1,32 @@
+import numpy as np
+import pytest
+
+from py_excel_rs import _excel_rs, csv_file_to_xlsx, csv_to_xlsx
+
+
+def test_invalid_type_code_raises_value_error():
+    rows = np.array([["Name"], ["Ada"]], dtype=object)
+    with pytest.raises(ValueError, match="invalid cell type"):
+        _excel_rs.typed_py_2d_to_xlsx(rows, ["not a type"])
+
+
+def test_more_types_than_cells_raises_value_error():
+    rows = np.array([["Name"], ["Ada"]], dtype=object)
+    with pytest.raises(ValueError, match="more types"):
+        _excel_rs.typed_py_2d_to_xlsx(rows, ["str", "str"])
+
+
+def test_invalid_delimiter_raises_value_error():
+    with pytest.raises(ValueError):
+        csv_to_xlsx(b"a,b\n1,2\n", delimiter='"')
+
+
+def test_missing_file_raises_os_error(tmp_path):
+    with pytest.raises(OSError):
+        csv_file_to_xlsx(str(tmp_path / "missing.csv"), str(tmp_path / "out.xlsx"))
+
+
+def test_interpreter_survives_an_error():
+    with pytest.raises(ValueError):
+        csv_to_xlsx(b"a\n", delimiter="\n")
+    assert csv_to_xlsx(b"a\n1\n").startswith(b"PK")
@@ -0,0 +1,26 @@
+import numpy as np
+import pytest
+
+from py_excel_rs import array_to_xlsx
+from xlsx_parts import sheet_xml
+
+FLOATS = [0.1, 1e-7, 1234567.89, 3.0, 1e16]
+
+
+def _values(float_format) -> list:
+    xml = sheet_xml(array_to_xlsx(np.array([FLOATS]), float_format=float_format))
+    return [cell.split("<v>")[1].split("</v>")[0] for cell in xml.split("<c ")[1:]]
+
+
+def test_floats_are_written_in_the_documented_format():
+    assert _values("shortest") == ["0.1", "0.0000001", "1234567.89", "3", "10000000000000000"]
+    assert _values(2) == ["0.10", "0.00", "1234567.89", "3.00", "10000000000000000.00"]
+
+
+def test_repr_matches_python():
+    assert _values("repr") == [repr(x) for x in FLOATS]
+
+
+def test_unknown_float_format_raises_value_error():
+    with pytest.raises(ValueError, match="float_format"):
+        array_to_xlsx(np.array([[0.1]]), float_format="str")
@@ -0,0 +1,29 @@
+import numpy as np
+import pandas as pd
+import pytest
+
+from py_excel_rs import _excel_rs, array_to_xlsx, df_to_xlsx
+from xlsx_parts import sheet_xml
+
+
+def _wide_text(rows: int = 1000, cols: int = 10) -> np.ndarray:
+    return np.full((rows, cols), "x" * 100, dtype=object)
+
+
+def test_array_past_max_memory_raises_memory_error():
+    # About 1.2 MB of cell text
+    with pytest.raises(MemoryError, match="XlsxWriter"):
+        _excel_rs.py_2d_to_xlsx(_wide_text(), 1_000_000)
+    with pytest.raises(MemoryError, match="max_memory"):
+        array_to_xlsx(_wide_text(), max_memory=1_000_000)
+    with pytest.raises(MemoryError):
+        df_to_xlsx(pd.DataFrame(_wide_text()), max_memory=1_000_000)
+
+
+def test_array_within_max_memory_is_written():
+    assert _excel_rs.py_2d_to_xlsx(_wide_text(), 2_000_000).startswith(b"PK")
+
+    # The default limit and no limit at all leave normal arrays alone
+    xml = sheet_xml(array_to_xlsx(_wide_text()))
+    assert xml.count("<row ") == 1000
+    assert sheet_xml(array_to_xlsx(_wide_text(), max_memory=None)) == xml
@@ -0,0 +1,40 @@
+import numpy as np
+import pandas as pd
+import pytest
+
+from py_excel_rs import df_to_xlsx
+from xlsx_parts import read_part, sheet_xml
+
+
+def _comments(xlsx: bytes) -> str:
+    return read_part(xlsx, "xl/comments1.xml")
+
+
+def test_notes_are_attached_to_the_annotated_cells():
+    df = pd.DataFrame({
+        "name": ["Ada", "Alan", "Grace"],
+        "reason": ["late", "", np.nan],
+        "flag": ["Y", "N", "Y"],
+    })
+
+    for infer in (False, True):
+        xlsx = df_to_xlsx(df, should_infer_types=infer, notes={"flag": "reason"})
+
+        # The reason column isn't written, so flag moves into column B
+        xml = sheet_xml(xlsx)
+        assert '<c r="B1" t="str"><v>flag</v></c>' in xml
+        assert "reason" not in xml and "late" not in xml
+        assert "C1" not in xml
+
+        comments = _comments(xlsx)
+        assert '<comment ref="B2" authorId="0"><text><t xml:space="preserve">late</t></text></comment>' in comments
+        # Empty and missing text adds no note
+        assert comments.count("<comment ") == 1
+
+
+def test_notes_must_name_columns_of_the_frame():
+    df = pd.DataFrame({"flag": ["Y"], "reason": ["late"]})
+    with pytest.raises(ValueError, match="'missing'"):
+        df_to_xlsx(df, notes={"flag": "missing"})
+    with pytest.raises(ValueError, match="both hold notes"):
+        df_to_xlsx(df, notes={"flag": "reason", "reason": "flag"})
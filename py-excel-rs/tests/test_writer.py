@@ -0,0 +1,56 @@
+import datetime
+import zipfile
+
+import pytest
+
+from py_excel_rs import XlsxWriter
+from xlsx_parts import read_part, sheet_xml
+
+
+def test_rows_are_streamed_with_their_types(tmp_path):
+    path = tmp_path / "out.xlsx"
+    with XlsxWriter(str(path), freeze_top_row=True) as w:
+        w.write_header(["Name", "Joined", "Score", "Active"])
+        w.write_row(["Ada", datetime.date(2024, 1, 2), 1.5, True])
+        w.write_row(["Alan", None, 7, False])
+
+    xml = sheet_xml(path.read_bytes())
+    assert '<pane ySplit="1"' in xml
+    assert '<c r="A1" t="str"><v>Name</v></c>' in xml
+    assert '<c r="A2" t="str"><v>Ada</v></c>' in xml
+    assert '<c r="B2" t="n" s="1"><v>45293</v></c>' in xml
+    assert '<c r="C2" t="n"><v>1.5</v></c>' in xml
+    assert '<c r="D2" t="b"><v>1</v></c>' in xml
+    assert '<c r="C3" t="n"><v>7</v></c>' in xml
+    assert '<c r="D3" t="b"><v>0</v></c>' in xml
+    assert "B3" not in xml
+
+
+def test_file_is_finished_when_the_block_raises(tmp_path):
+    path = tmp_path / "out.xlsx"
+    with pytest.raises(KeyError):
+        with XlsxWriter(str(path)) as w:
+            w.write_header(["Name"])
+            w.write_row(["Ada"])
+            raise KeyError("stop")
+
+    # The rows before the exception are in a complete, readable file
+    assert zipfile.ZipFile(path).testzip() is None
+    assert "<v>Ada</v>" in sheet_xml(path.read_bytes())
+    assert "<sheet " in read_part(path.read_bytes(), "xl/workbook.xml")
+
+
+def test_writing_after_close_raises(tmp_path):
+    w = XlsxWriter(str(tmp_path / "out.xlsx"))
+    w.write_row(["Ada"])
+    w.close()
+    w.close()
+    with pytest.raises(ValueError, match="closed"):
+        w.write_row(["Alan"])
+
+
+def test_header_after_rows_raises(tmp_path):
+    with XlsxWriter(str(tmp_path / "out.xlsx")) as w:
+        w.write_row(["Ada"])
+        with pytest.raises(ValueError, match="write_header"):
+            w.write_header(["Name"])
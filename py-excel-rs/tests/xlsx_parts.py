@@ -0,0 +1,17 @@
+"""Reads parts of the xlsx files the bindings return.
+
+The tests need the module built into the current environment, e.g. with
+`maturin develop`, and are run with `pytest py-excel-rs/tests`.
+"""
+
+import io
+import zipfile
+
+
+def read_part(xlsx: bytes, name: str) -> str:
+    with zipfile.ZipFile(io.BytesIO(xlsx)) as archive:
+        return archive.read(name).decode()
+
+
+def sheet_xml(xlsx: bytes) -> str:
+    return read_part(xlsx, "xl/worksheets/sheet1.xml")